@@ -150,6 +150,10 @@ impl Bitboard {
     pub fn is_set(&self, sq: Square) -> bool {
         self.and_to_bool(Bitboard::square_mask(sq))
     }
+    // Same as is_set(). For the library use.
+    pub fn contains(&self, sq: Square) -> bool {
+        self.is_set(sq)
+    }
     #[allow(dead_code)]
     pub fn print(self) {
         println!("{}", self.to_string());
@@ -1082,6 +1086,48 @@ fn test_rook_magic() {
     );
 }
 
+#[test]
+fn test_sliding_attacks_with_random_occupied() {
+    use rand::{Rng, SeedableRng};
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            // From the nearly empty boards to the nearly full boards.
+            for &density in [0.05, 0.2, 0.5, 0.8].iter() {
+                for _ in 0..256 {
+                    let mut occupied = Bitboard::ZERO;
+                    for &sq in Square::ALL.iter() {
+                        if rng.gen_bool(density) {
+                            occupied.set(sq);
+                        }
+                    }
+                    for &sq in Square::ALL.iter() {
+                        assert_eq!(
+                            ATTACK_TABLE.bishop.magic(sq).attack(&occupied),
+                            sliding_attacks(&AttackTable::BISHOP_DELTAS, sq, &occupied)
+                        );
+                        assert_eq!(
+                            ATTACK_TABLE.rook.magic(sq).attack(&occupied),
+                            sliding_attacks(&AttackTable::ROOK_DELTAS, sq, &occupied)
+                        );
+                        assert_eq!(
+                            ATTACK_TABLE.lance.attack(Color::BLACK, sq, &occupied),
+                            sliding_attacks(&[Square::DELTA_N], sq, &occupied)
+                        );
+                        assert_eq!(
+                            ATTACK_TABLE.lance.attack(Color::WHITE, sq, &occupied),
+                            sliding_attacks(&[Square::DELTA_S], sq, &occupied)
+                        );
+                    }
+                }
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
 #[test]
 fn test_lance_attack() {
     std::thread::Builder::new()
@@ -1268,3 +1314,36 @@ fn test_proximity_check_mask() {
         .join()
         .unwrap();
 }
+
+#[test]
+fn test_bitboard_iterator() {
+    // v[0] has the squares 11..79, and v[1] has the squares 81..99.
+    let squares = [
+        Square::SQ11,
+        Square::SQ55,
+        Square::SQ78,
+        Square::SQ79,
+        Square::SQ81,
+        Square::SQ82,
+        Square::SQ99,
+    ];
+    let mut bb = Bitboard::ZERO;
+    for &sq in squares.iter() {
+        bb.set(sq);
+    }
+    assert_eq!(
+        bb.value(0),
+        1 | (1 << Square::SQ55.0) | (1 << Square::SQ78.0) | (1 << Square::SQ79.0)
+    );
+    assert_eq!(bb.value(1), 1 | (1 << 1) | (1 << (Square::SQ99.0 - Square::SQ81.0)));
+    assert_eq!(bb.count_ones(), squares.len() as u32);
+    assert!(squares.iter().all(|&sq| bb.contains(sq)));
+    assert!(!bb.contains(Square::SQ12));
+    assert!(!bb.contains(Square::SQ98));
+    assert_eq!(bb.collect::<Vec<_>>(), squares.to_vec());
+    // The iteration doesn't change the original.
+    assert_eq!(bb.count_ones(), squares.len() as u32);
+    assert_eq!(Bitboard::ALL.count(), Square::NUM);
+    assert_eq!(Bitboard::ALL.count_ones(), Square::NUM as u32);
+    assert_eq!(Bitboard::ZERO.count(), 0);
+}
@@ -25,7 +25,7 @@ impl Book {
         set.insert(mv.to_usi(), info);
     }
     #[allow(dead_code)]
-    pub fn probe(&self, pos: &Position, rng: &mut ThreadRng) -> Option<Move> {
+    pub fn probe<R: Rng + ?Sized>(&self, pos: &Position, rng: &mut R) -> Option<Move> {
         let sfen = pos.to_sfen();
         let candidates = self.0.get(&sfen)?;
         let move_and_weights = candidates
@@ -51,13 +51,239 @@ impl Book {
     }
 }
 
+// std::mt19937_64. Apery generates the Zobrist keys for the book with the default seed.
+struct Mt64 {
+    mt: [u64; Mt64::NN],
+    index: usize,
+}
+
+impl Mt64 {
+    const NN: usize = 312;
+    const MM: usize = 156;
+    const MATRIX_A: u64 = 0xb502_6f5a_a966_19e9;
+    const UPPER_MASK: u64 = 0xffff_ffff_8000_0000;
+    const LOWER_MASK: u64 = 0x7fff_ffff;
+    const DEFAULT_SEED: u64 = 5489;
+
+    fn new(seed: u64) -> Mt64 {
+        let mut mt = [0; Mt64::NN];
+        mt[0] = seed;
+        for i in 1..Mt64::NN {
+            mt[i] = 6_364_136_223_846_793_005_u64
+                .wrapping_mul(mt[i - 1] ^ (mt[i - 1] >> 62))
+                .wrapping_add(i as u64);
+        }
+        Mt64 { mt, index: Mt64::NN }
+    }
+    fn next(&mut self) -> u64 {
+        if self.index >= Mt64::NN {
+            for i in 0..Mt64::NN {
+                let x = (self.mt[i] & Mt64::UPPER_MASK) | (self.mt[(i + 1) % Mt64::NN] & Mt64::LOWER_MASK);
+                let mut xa = x >> 1;
+                if x & 1 != 0 {
+                    xa ^= Mt64::MATRIX_A;
+                }
+                self.mt[i] = self.mt[(i + Mt64::MM) % Mt64::NN] ^ xa;
+            }
+            self.index = 0;
+        }
+        let mut x = self.mt[self.index];
+        self.index += 1;
+        x ^= (x >> 29) & 0x5555_5555_5555_5555;
+        x ^= (x << 17) & 0x71d6_7fff_eda6_0000;
+        x ^= (x << 37) & 0xfff7_eee0_0000_0000;
+        x ^= x >> 43;
+        x
+    }
+}
+
+struct AperyZobrist {
+    field: [[u64; Square::NUM]; AperyZobrist::PIECE_NUM],
+    hand: [[u64; 19]; AperyZobrist::HAND_PIECE_TYPES.len()], // 19 is max_hand_pawn + 1.
+    turn: u64,
+}
+
+impl AperyZobrist {
+    const PIECE_NUM: usize = Piece::W_DRAGON.0 as usize + 1;
+    // The order of the hand pieces in Apery.
+    const HAND_PIECE_TYPES: [PieceType; 7] = [
+        PieceType::PAWN,
+        PieceType::LANCE,
+        PieceType::KNIGHT,
+        PieceType::SILVER,
+        PieceType::GOLD,
+        PieceType::BISHOP,
+        PieceType::ROOK,
+    ];
+}
+
+static APERY_ZOBRIST: once_cell::sync::Lazy<AperyZobrist> = once_cell::sync::Lazy::new(|| {
+    let mut rng = Mt64::new(Mt64::DEFAULT_SEED);
+    let mut zobrist = AperyZobrist {
+        field: [[0; Square::NUM]; AperyZobrist::PIECE_NUM],
+        hand: [[0; 19]; AperyZobrist::HAND_PIECE_TYPES.len()],
+        turn: 0,
+    };
+    for items in zobrist.field.iter_mut() {
+        for item in items.iter_mut() {
+            *item = rng.next();
+        }
+    }
+    for items in zobrist.hand.iter_mut() {
+        for item in items.iter_mut() {
+            *item = rng.next();
+        }
+    }
+    zobrist.turn = rng.next();
+    zobrist
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AperyBookEntry {
+    key: u64,
+    from_to_pro: u16,
+    count: u16,
+    score: i32,
+}
+
+impl AperyBookEntry {
+    const SIZE: usize = 16;
+
+    fn from_bytes(bytes: &[u8]) -> AperyBookEntry {
+        debug_assert_eq!(bytes.len(), AperyBookEntry::SIZE);
+        AperyBookEntry {
+            key: u64::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]]),
+            from_to_pro: u16::from_le_bytes([bytes[8], bytes[9]]),
+            count: u16::from_le_bytes([bytes[10], bytes[11]]),
+            score: i32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+        }
+    }
+    #[cfg(test)]
+    fn to_bytes(self) -> Vec<u8> {
+        let mut v = vec![];
+        v.extend_from_slice(&self.key.to_le_bytes());
+        v.extend_from_slice(&self.from_to_pro.to_le_bytes());
+        v.extend_from_slice(&self.count.to_le_bytes());
+        v.extend_from_slice(&self.score.to_le_bytes());
+        v
+    }
+    // from_to_pro is "to" (7 bits), "from" (7 bits) and the promote flag.
+    // "from" of a drop move is 80 + the piece type dropped.
+    fn to_move(self, pos: &Position) -> Option<Move> {
+        let to = Square((self.from_to_pro & 0x7f) as i32);
+        let from = ((self.from_to_pro >> 7) & 0x7f) as i32;
+        let is_promotion = (self.from_to_pro & (1 << 14)) != 0;
+        if !to.is_ok() {
+            return None;
+        }
+        let m = if from >= Square::NUM as i32 {
+            let pt = PieceType(from - (Square::NUM as i32 - 1));
+            if !(PieceType::PAWN.0..=PieceType::GOLD.0).contains(&pt.0) {
+                return None;
+            }
+            Move::new_drop(Piece::new(pos.side_to_move(), pt), to)
+        } else {
+            let from = Square(from);
+            let pc = pos.piece_on(from);
+            if is_promotion {
+                Move::new_promote(from, to, pc)
+            } else {
+                Move::new_unpromote(from, to, pc)
+            }
+        };
+        if !pos.pseudo_legal::<NotSearchingType>(m) || !pos.legal(m) {
+            return None;
+        }
+        Some(m)
+    }
+}
+
+// Opening book in Apery's binary format. The entries are sorted by the book key of the positions.
+pub struct AperyBook {
+    entries: Vec<AperyBookEntry>,
+}
+
+impl AperyBook {
+    pub fn key(pos: &Position) -> u64 {
+        let mut key = 0;
+        for sq in pos.occupied_bb() {
+            key ^= APERY_ZOBRIST.field[pos.piece_on(sq).0 as usize][sq.0 as usize];
+        }
+        let hand = pos.hand(pos.side_to_move());
+        for (i, pt) in AperyZobrist::HAND_PIECE_TYPES.iter().enumerate() {
+            key ^= APERY_ZOBRIST.hand[i][hand.num(*pt) as usize];
+        }
+        if pos.side_to_move() == Color::WHITE {
+            key ^= APERY_ZOBRIST.turn;
+        }
+        key
+    }
+    // The whole file is read into memory. The entries are 16 bytes each.
+    pub fn from_file<P>(path: P) -> Result<AperyBook, Box<dyn std::error::Error>>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let bytes = std::fs::read(path)?;
+        if bytes.len() % AperyBookEntry::SIZE != 0 {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid book file size: {}", bytes.len()),
+            )));
+        }
+        let entries = bytes.chunks(AperyBookEntry::SIZE).map(AperyBookEntry::from_bytes).collect();
+        Ok(AperyBook { entries })
+    }
+    // Picks a move with the probability in proportion to its count.
+    pub fn probe<R: Rng + ?Sized>(&self, pos: &Position, rng: &mut R) -> Option<Move> {
+        let key = AperyBook::key(pos);
+        let first = self.entries.partition_point(|entry| entry.key < key);
+        let candidates = self.entries[first..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter_map(|entry| entry.to_move(pos).map(|m| (m, entry.count)))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return None;
+        }
+        match rand::distributions::WeightedIndex::new(candidates.iter().map(|x| x.1)) {
+            Ok(dist) => Some(candidates[dist.sample(rng)].0),
+            Err(_) => Some(candidates[0].0), // All the counts are 0.
+        }
+    }
+}
+
+// The book loaded from Book_File. The format is decided by the extension.
+pub enum OpeningBook {
+    Json(Book),
+    Apery(AperyBook),
+}
+
+impl OpeningBook {
+    pub fn from_file<P>(path: P) -> Result<OpeningBook, Box<dyn std::error::Error>>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        if path.as_ref().extension() == Some(std::ffi::OsStr::new("bin")) {
+            Ok(OpeningBook::Apery(AperyBook::from_file(path)?))
+        } else {
+            Ok(OpeningBook::Json(Book::from_file(path)?))
+        }
+    }
+    pub fn probe<R: Rng + ?Sized>(&self, pos: &Position, rng: &mut R) -> Option<Move> {
+        match self {
+            OpeningBook::Json(book) => book.probe(pos, rng),
+            OpeningBook::Apery(book) => book.probe(pos, rng),
+        }
+    }
+}
+
 #[test]
 fn test_book() {
     std::thread::Builder::new()
         .stack_size(crate::stack_size::STACK_SIZE)
         .spawn(|| {
             let sfen = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
-            let mut pos = Position::new_from_sfen(sfen).unwrap();
+            let mut pos = Position::from_sfen(sfen).unwrap();
             let mut b = Book::new();
             b.insert(
                 sfen.to_string(),
@@ -124,7 +350,7 @@ fn test_book_probe() {
         .stack_size(crate::stack_size::STACK_SIZE)
         .spawn(|| {
             let sfen = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let mut b = Book::new();
             b.insert(
                 sfen.to_string(),
@@ -198,3 +424,79 @@ fn test_book_from_file() {
         .join()
         .unwrap();
 }
+
+#[test]
+fn test_mt64() {
+    let mut rng = Mt64::new(Mt64::DEFAULT_SEED);
+    assert_eq!(rng.next(), 14_514_284_786_278_117_030);
+    for _ in 1..9999 {
+        rng.next();
+    }
+    assert_eq!(rng.next(), 9_981_545_732_273_789_042);
+}
+
+#[test]
+fn test_apery_book_probe() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::new();
+            let key = AperyBook::key(&pos);
+            let m = Move::new_from_usi_str("7g7f", &pos).unwrap();
+            let from_to_pro = (m.to().0 | (m.from().0 << 7)) as u16;
+            // The other keys surround the position to check the binary search.
+            let entries = vec![
+                AperyBookEntry {
+                    key: key - 1,
+                    from_to_pro: 0,
+                    count: 1,
+                    score: 0,
+                },
+                AperyBookEntry {
+                    key,
+                    from_to_pro,
+                    count: 10,
+                    score: 30,
+                },
+                AperyBookEntry {
+                    key: key + 1,
+                    from_to_pro: 0,
+                    count: 1,
+                    score: 0,
+                },
+            ];
+            let path = std::env::temp_dir().join(format!("apery_test_book_{}.bin", std::process::id()));
+            std::fs::write(&path, entries.iter().flat_map(|entry| entry.to_bytes()).collect::<Vec<u8>>()).unwrap();
+            let book = OpeningBook::from_file(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let mut rng = rand::thread_rng();
+            assert_eq!(book.probe(&pos, &mut rng), Some(m));
+
+            let pos = Position::from_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2").unwrap();
+            assert!(AperyBook::key(&pos) != key);
+            assert_eq!(book.probe(&pos, &mut rng), None);
+
+            // drop and promotion.
+            let pos = Position::from_sfen("4k4/9/9/7P1/9/9/9/9/4K4 b G 1").unwrap();
+            let promote = AperyBookEntry {
+                key: AperyBook::key(&pos),
+                from_to_pro: (Square::SQ23.0 | (Square::SQ24.0 << 7) | (1 << 14)) as u16,
+                count: 1,
+                score: 0,
+            };
+            assert_eq!(promote.to_move(&pos).unwrap().to_usi_string(), "2d2c+");
+            let drop = AperyBookEntry {
+                from_to_pro: (Square::SQ55.0 | ((80 + PieceType::GOLD.0) << 7)) as u16,
+                ..promote
+            };
+            assert_eq!(drop.to_move(&pos).unwrap().to_usi_string(), "G*5e");
+
+            let path = std::env::temp_dir().join(format!("apery_test_invalid_book_{}.bin", std::process::id()));
+            std::fs::write(&path, [0_u8; 15]).unwrap();
+            assert!(AperyBook::from_file(&path).is_err());
+            std::fs::remove_file(&path).unwrap();
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
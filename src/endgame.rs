@@ -0,0 +1,598 @@
+use crate::movetypes::*;
+use crate::position::*;
+use crate::types::*;
+
+// Distance to mate of the trivial endings by the retrograde analysis.
+// The attacker has the king and only one piece (on the board or in hand), and the defender has the lone king.
+// The tables are made of all the positions of the signature on a background thread started by init().
+// probe() skips the tables until they are built.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Rook,
+    Bishop,
+    Gold,
+}
+
+impl Kind {
+    fn new(pt: PieceType) -> Option<(Kind, bool)> {
+        match pt {
+            PieceType::ROOK => Some((Kind::Rook, false)),
+            PieceType::DRAGON => Some((Kind::Rook, true)),
+            PieceType::BISHOP => Some((Kind::Bishop, false)),
+            PieceType::HORSE => Some((Kind::Bishop, true)),
+            PieceType::GOLD => Some((Kind::Gold, false)),
+            _ => None,
+        }
+    }
+    fn piece_type(self) -> PieceType {
+        match self {
+            Kind::Rook => PieceType::ROOK,
+            Kind::Bishop => PieceType::BISHOP,
+            Kind::Gold => PieceType::GOLD,
+        }
+    }
+    fn can_promote(self) -> bool {
+        self != Kind::Gold
+    }
+}
+
+const ATTACKER: usize = 0;
+const DEFENDER: usize = 1;
+const SQUARES: usize = 81;
+// 0..81: the unpromoted piece on the square, 81..162: the promoted piece on the square, 162: in hand.
+const HAND: usize = 2 * SQUARES;
+const PIECE_STATES: usize = HAND + 1;
+const STATES: usize = 2 * SQUARES * SQUARES * PIECE_STATES;
+const UNKNOWN: u8 = u8::MAX;
+
+const ORTHOGONAL: [(i32, i32); 4] = [(0, -1), (0, 1), (-1, 0), (1, 0)];
+const DIAGONAL: [(i32, i32); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+// The attacker is always black in the tables. Black moves to the rank 1.
+const GOLD: [(i32, i32); 6] = [(0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0), (0, 1)];
+
+type Directions = &'static [(i32, i32)];
+
+// The squares are the same as Square. The square of the file f and the rank r is f * 9 + r.
+fn shift(sq: usize, (df, dr): (i32, i32)) -> Option<usize> {
+    let f = (sq / 9) as i32 + df;
+    let r = (sq % 9) as i32 + dr;
+    if (0..9).contains(&f) && (0..9).contains(&r) {
+        Some((f * 9 + r) as usize)
+    } else {
+        None
+    }
+}
+
+fn bit(sq: usize) -> u128 {
+    1 << sq
+}
+
+fn is_adjacent(sq0: usize, sq1: usize) -> bool {
+    let df = (sq0 / 9) as i32 - (sq1 / 9) as i32;
+    let dr = (sq0 % 9) as i32 - (sq1 % 9) as i32;
+    df.abs() <= 1 && dr.abs() <= 1 && sq0 != sq1
+}
+
+fn neighbours(sq: usize) -> impl Iterator<Item = usize> {
+    ORTHOGONAL.iter().chain(DIAGONAL.iter()).filter_map(move |&d| shift(sq, d))
+}
+
+fn is_promotion_zone(sq: usize) -> bool {
+    sq % 9 < 3
+}
+
+// The squares attacked by the piece. If reverse is true, the squares from which the piece reaches sq.
+fn attacks(kind: Kind, promoted: bool, sq: usize, occupied: u128, reverse: bool) -> u128 {
+    let mut bb = 0;
+    let mut slide = |directions: &[(i32, i32)]| {
+        for &d in directions {
+            let mut s = sq;
+            while let Some(next) = shift(s, d) {
+                bb |= bit(next);
+                if occupied & bit(next) != 0 {
+                    break;
+                }
+                s = next;
+            }
+        }
+    };
+    let (slides, steps): (Directions, Directions) = match (kind, promoted) {
+        (Kind::Rook, false) => (&ORTHOGONAL, &[]),
+        (Kind::Rook, true) => (&ORTHOGONAL, &DIAGONAL),
+        (Kind::Bishop, false) => (&DIAGONAL, &[]),
+        (Kind::Bishop, true) => (&DIAGONAL, &ORTHOGONAL),
+        (Kind::Gold, _) => (&[], &GOLD),
+    };
+    slide(slides);
+    for &(df, dr) in steps {
+        let d = if reverse { (-df, -dr) } else { (df, dr) };
+        if let Some(s) = shift(sq, d) {
+            bb |= bit(s);
+        }
+    }
+    bb
+}
+
+// Same as attacks(kind, promoted, from, bit(blocker) | bit(to), false) & bit(to) != 0, but faster.
+fn attacks_square(kind: Kind, promoted: bool, from: usize, to: usize, blocker: usize) -> bool {
+    let (df, dr) = ((to / 9) as i32 - (from / 9) as i32, (to % 9) as i32 - (from % 9) as i32);
+    let is_between = |sq: usize| {
+        let (bf, br) = ((sq / 9) as i32 - (from / 9) as i32, (sq % 9) as i32 - (from % 9) as i32);
+        // The blocker is on the segment if it's in the same direction and nearer.
+        bf * dr == br * df && bf * df + br * dr > 0 && bf.abs().max(br.abs()) < df.abs().max(dr.abs())
+    };
+    let is_orthogonal = (df == 0) != (dr == 0);
+    let is_diagonal = df != 0 && df.abs() == dr.abs();
+    let is_step = from != to && df.abs() <= 1 && dr.abs() <= 1;
+    match (kind, promoted) {
+        (Kind::Rook, _) if is_orthogonal && !is_between(blocker) => true,
+        (Kind::Rook, true) => is_step,
+        (Kind::Bishop, _) if is_diagonal && !is_between(blocker) => true,
+        (Kind::Bishop, true) => is_step,
+        (Kind::Gold, _) => is_step && GOLD.contains(&(df, dr)),
+        _ => false,
+    }
+}
+
+fn squares(mut bb: u128) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bb == 0 {
+            None
+        } else {
+            let sq = bb.trailing_zeros() as usize;
+            bb &= bb - 1;
+            Some(sq)
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    side_to_move: usize,
+    attacker_king: usize,
+    defender_king: usize,
+    piece: usize,
+}
+
+impl State {
+    fn index(self) -> usize {
+        ((self.side_to_move * SQUARES + self.attacker_king) * SQUARES + self.defender_king) * PIECE_STATES + self.piece
+    }
+    fn from_index(i: usize) -> State {
+        State {
+            piece: i % PIECE_STATES,
+            defender_king: i / PIECE_STATES % SQUARES,
+            attacker_king: i / PIECE_STATES / SQUARES % SQUARES,
+            side_to_move: i / PIECE_STATES / SQUARES / SQUARES,
+        }
+    }
+    fn piece_square(self) -> Option<(usize, bool)> {
+        if self.piece == HAND {
+            None
+        } else {
+            Some((self.piece % SQUARES, self.piece >= SQUARES))
+        }
+    }
+    fn with(self, side_to_move: usize, attacker_king: usize, defender_king: usize, piece: usize) -> State {
+        debug_assert!(self.side_to_move != side_to_move);
+        State {
+            side_to_move,
+            attacker_king,
+            defender_king,
+            piece,
+        }
+    }
+    fn occupied(self) -> u128 {
+        let bb = bit(self.attacker_king) | bit(self.defender_king);
+        match self.piece_square() {
+            Some((sq, _)) => bb | bit(sq),
+            None => bb,
+        }
+    }
+    fn is_defender_in_check(self, kind: Kind) -> bool {
+        match self.piece_square() {
+            Some((sq, promoted)) => attacks_square(kind, promoted, sq, self.defender_king, self.attacker_king),
+            None => false,
+        }
+    }
+    fn is_valid(self, kind: Kind) -> bool {
+        if is_adjacent(self.attacker_king, self.defender_king) || self.attacker_king == self.defender_king {
+            return false;
+        }
+        if let Some((sq, promoted)) = self.piece_square() {
+            if sq == self.attacker_king || sq == self.defender_king || (promoted && !kind.can_promote()) {
+                return false;
+            }
+        }
+        // The defender can't leave the king in check.
+        self.side_to_move == DEFENDER || !self.is_defender_in_check(kind)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transition {
+    To(State),
+    Capture, // The defender captures the piece. The result isn't in the table.
+}
+
+// The legal moves of the defender king.
+fn defender_moves(state: State, kind: Kind) -> impl Iterator<Item = (usize, Transition)> {
+    neighbours(state.defender_king).filter_map(move |to| {
+        if to == state.attacker_king || is_adjacent(to, state.attacker_king) {
+            return None;
+        }
+        match state.piece_square() {
+            Some((sq, _)) if sq == to => Some((to, Transition::Capture)),
+            Some((sq, promoted)) => {
+                if attacks_square(kind, promoted, sq, to, state.attacker_king) {
+                    None
+                } else {
+                    Some((to, Transition::To(state.with(ATTACKER, state.attacker_king, to, state.piece))))
+                }
+            }
+            None => Some((to, Transition::To(state.with(ATTACKER, state.attacker_king, to, state.piece)))),
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttackerMove {
+    King(usize, usize),
+    Piece(usize, usize, bool), // from, to, is_promotion
+    Drop(usize),
+}
+
+fn attacker_moves(state: State, kind: Kind) -> Vec<(AttackerMove, State)> {
+    let mut v = vec![];
+    let occupied = state.occupied();
+    for to in neighbours(state.attacker_king) {
+        if occupied & bit(to) == 0 && !is_adjacent(to, state.defender_king) {
+            let next = state.with(DEFENDER, to, state.defender_king, state.piece);
+            v.push((AttackerMove::King(state.attacker_king, to), next));
+        }
+    }
+    match state.piece_square() {
+        Some((from, promoted)) => {
+            let targets = attacks(kind, promoted, from, occupied, false) & !occupied;
+            for to in squares(targets) {
+                let piece = to + if promoted { SQUARES } else { 0 };
+                let next = state.with(DEFENDER, state.attacker_king, state.defender_king, piece);
+                v.push((AttackerMove::Piece(from, to, false), next));
+                if kind.can_promote() && !promoted && (is_promotion_zone(from) || is_promotion_zone(to)) {
+                    let next = state.with(DEFENDER, state.attacker_king, state.defender_king, to + SQUARES);
+                    v.push((AttackerMove::Piece(from, to, true), next));
+                }
+            }
+        }
+        None => {
+            for to in (0..SQUARES).filter(|&sq| occupied & bit(sq) == 0) {
+                let next = state.with(DEFENDER, state.attacker_king, state.defender_king, to);
+                v.push((AttackerMove::Drop(to), next));
+            }
+        }
+    }
+    v
+}
+
+// The attacker-to-move positions from which the attacker reaches the defender-to-move state.
+fn attacker_predecessors(state: State, kind: Kind, v: &mut Vec<State>) {
+    v.clear();
+    let occupied = state.occupied();
+    for from in neighbours(state.attacker_king) {
+        if occupied & bit(from) == 0 {
+            v.push(state.with(ATTACKER, from, state.defender_king, state.piece));
+        }
+    }
+    if let Some((to, promoted)) = state.piece_square() {
+        if !promoted {
+            v.push(state.with(ATTACKER, state.attacker_king, state.defender_king, HAND));
+        }
+        let sources = attacks(kind, promoted, to, occupied, true) & !occupied;
+        for from in squares(sources) {
+            let piece = from + if promoted { SQUARES } else { 0 };
+            v.push(state.with(ATTACKER, state.attacker_king, state.defender_king, piece));
+        }
+        if promoted {
+            let sources = attacks(kind, false, to, occupied, true) & !occupied;
+            for from in squares(sources).filter(|&from| is_promotion_zone(from) || is_promotion_zone(to)) {
+                v.push(state.with(ATTACKER, state.attacker_king, state.defender_king, from));
+            }
+        }
+    }
+    v.retain(|s| s.is_valid(kind));
+}
+
+// The defender-to-move positions from which the defender reaches the attacker-to-move state.
+fn defender_predecessors(state: State, kind: Kind, v: &mut Vec<State>) {
+    v.clear();
+    let occupied = state.occupied();
+    for from in neighbours(state.defender_king) {
+        if occupied & bit(from) == 0 {
+            v.push(state.with(DEFENDER, state.attacker_king, from, state.piece));
+        }
+    }
+    v.retain(|s| s.is_valid(kind));
+}
+
+struct EndgameTable {
+    kind: Kind,
+    // The plies to mate for the attacker to move, the plies to be mated for the defender to move.
+    // UNKNOWN means no forced mate.
+    dtm: Vec<u8>,
+}
+
+impl EndgameTable {
+    fn new(kind: Kind) -> EndgameTable {
+        let mut dtm = vec![UNKNOWN; STATES];
+        // The number of the defender moves not proven to lose yet.
+        let mut counts = vec![0u8; STATES];
+        let mut queue = std::collections::VecDeque::new();
+        for i in 0..STATES {
+            let state = State::from_index(i);
+            if state.side_to_move != DEFENDER || !state.is_valid(kind) {
+                continue;
+            }
+            counts[i] = defender_moves(state, kind).count() as u8;
+            // No legal moves is a loss even if the king isn't in check.
+            if counts[i] == 0 {
+                dtm[i] = 0;
+                queue.push_back(i);
+            }
+        }
+        let mut predecessors = vec![];
+        while let Some(i) = queue.pop_front() {
+            let state = State::from_index(i);
+            let d = dtm[i];
+            if state.side_to_move == DEFENDER {
+                attacker_predecessors(state, kind, &mut predecessors);
+                for p in predecessors.iter().map(|p| p.index()) {
+                    if dtm[p] == UNKNOWN {
+                        dtm[p] = d + 1;
+                        queue.push_back(p);
+                    }
+                }
+            } else {
+                defender_predecessors(state, kind, &mut predecessors);
+                for p in predecessors.iter().map(|p| p.index()) {
+                    if dtm[p] == UNKNOWN {
+                        counts[p] -= 1;
+                        if counts[p] == 0 {
+                            dtm[p] = d + 1;
+                            queue.push_back(p);
+                        }
+                    }
+                }
+            }
+        }
+        EndgameTable { kind, dtm }
+    }
+    fn dtm(&self, state: State) -> Option<u8> {
+        match self.dtm[state.index()] {
+            UNKNOWN => None,
+            d => Some(d),
+        }
+    }
+    // The optimal move and the next state. The attacker mates fastest, and the defender delays the mate.
+    fn best_transition(&self, state: State) -> Option<(TableMove, State)> {
+        let d = self.dtm(state)?;
+        if state.side_to_move == ATTACKER {
+            attacker_moves(state, self.kind)
+                .into_iter()
+                .find(|&(_, next)| self.dtm(next) == Some(d - 1))
+                .map(|(m, next)| (TableMove::Attacker(m), next))
+        } else {
+            defender_moves(state, self.kind)
+                .filter_map(|(to, t)| match t {
+                    Transition::To(next) => Some((to, next)),
+                    Transition::Capture => None,
+                })
+                .max_by_key(|&(_, next)| self.dtm(next))
+                .map(|(to, next)| (TableMove::Defender(state.defender_king, to), next))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TableMove {
+    Attacker(AttackerMove),
+    Defender(usize, usize),
+}
+
+// The tables take about a second to build, so they are built on a background thread and probe() skips them until then.
+static ROOK_TABLE: once_cell::sync::OnceCell<EndgameTable> = once_cell::sync::OnceCell::new();
+static BISHOP_TABLE: once_cell::sync::OnceCell<EndgameTable> = once_cell::sync::OnceCell::new();
+static GOLD_TABLE: once_cell::sync::OnceCell<EndgameTable> = once_cell::sync::OnceCell::new();
+
+#[derive(Debug, Clone)]
+pub struct EndgameResult {
+    pub best_move: Move,
+    pub score: Value, // From the side to move.
+    pub pv: Vec<Move>,
+}
+
+// The state of the position and the color of the attacker, if the position matches a signature.
+fn recognize(pos: &Position) -> Option<(Kind, State, Color)> {
+    let mut kings = [None; Color::NUM];
+    let mut piece = None;
+    for i in 0..SQUARES {
+        let pc = pos.piece_on(Square(i as i32));
+        if pc == Piece::EMPTY {
+            continue;
+        }
+        let c = Color::new(pc);
+        let pt = PieceType::new(pc);
+        if pt == PieceType::KING {
+            kings[c.0 as usize] = Some(i);
+        } else if piece.is_some() {
+            return None;
+        } else {
+            let (kind, promoted) = Kind::new(pt)?;
+            piece = Some((c, kind, promoted, i));
+        }
+    }
+    let in_hand = |c: Color| {
+        [Kind::Rook, Kind::Bishop, Kind::Gold]
+            .iter()
+            .find(|kind| pos.hand(c).num(kind.piece_type()) == 1)
+            .copied()
+    };
+    let (attacker, kind, piece_state) = match piece {
+        Some((c, kind, promoted, sq)) => {
+            if pos.hand(Color::BLACK).0 != 0 || pos.hand(Color::WHITE).0 != 0 {
+                return None;
+            }
+            // The attacker is black in the tables.
+            let sq = if c == Color::BLACK { sq } else { SQUARES - 1 - sq };
+            (c, kind, sq + if promoted { SQUARES } else { 0 })
+        }
+        None => {
+            let c = if pos.hand(Color::BLACK).0 != 0 {
+                Color::BLACK
+            } else {
+                Color::WHITE
+            };
+            let kind = in_hand(c)?;
+            let mut hand = pos.hand(c);
+            hand.minus_one(kind.piece_type());
+            if hand.0 != 0 || pos.hand(c.inverse()).0 != 0 {
+                return None;
+            }
+            (c, kind, HAND)
+        }
+    };
+    let normalize = |sq: usize| if attacker == Color::BLACK { sq } else { SQUARES - 1 - sq };
+    let state = State {
+        side_to_move: if pos.side_to_move() == attacker { ATTACKER } else { DEFENDER },
+        attacker_king: normalize(kings[attacker.0 as usize]?),
+        defender_king: normalize(kings[attacker.inverse().0 as usize]?),
+        piece: piece_state,
+    };
+    if state.is_valid(kind) {
+        Some((kind, state, attacker))
+    } else {
+        None
+    }
+}
+
+fn table(kind: Kind) -> &'static once_cell::sync::OnceCell<EndgameTable> {
+    match kind {
+        Kind::Rook => &ROOK_TABLE,
+        Kind::Bishop => &BISHOP_TABLE,
+        Kind::Gold => &GOLD_TABLE,
+    }
+}
+
+// It blocks until all the tables are built.
+pub fn build_tables() {
+    for &kind in [Kind::Rook, Kind::Bishop, Kind::Gold].iter() {
+        table(kind).get_or_init(|| EndgameTable::new(kind));
+    }
+}
+
+// Starts building the tables on a background thread at the first call.
+pub fn init() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        std::thread::spawn(build_tables);
+    });
+}
+
+// Some if the position matches a signature, the table is built and there is a forced mate.
+pub fn probe(pos: &Position) -> Option<EndgameResult> {
+    let (kind, state, attacker) = recognize(pos)?;
+    let table = table(kind).get()?;
+    let d = table.dtm(state)?;
+    if d == 0 {
+        // The side to move is already mated.
+        return None;
+    }
+    let square = |sq: usize| {
+        let sq = if attacker == Color::BLACK { sq } else { SQUARES - 1 - sq };
+        Square(sq as i32)
+    };
+    let is_attacker_to_move = state.side_to_move == ATTACKER;
+    let mut pos = Position::from_sfen(&pos.to_sfen()).ok()?;
+    let mut state = state;
+    let mut pv = vec![];
+    while let Some((m, next)) = table.best_transition(state) {
+        let m = match m {
+            TableMove::Attacker(AttackerMove::King(from, to))
+            | TableMove::Defender(from, to)
+            | TableMove::Attacker(AttackerMove::Piece(from, to, false)) => {
+                Move::new_unpromote(square(from), square(to), pos.piece_on(square(from)))
+            }
+            TableMove::Attacker(AttackerMove::Piece(from, to, true)) => {
+                Move::new_promote(square(from), square(to), pos.piece_on(square(from)))
+            }
+            TableMove::Attacker(AttackerMove::Drop(to)) => Move::new_drop(Piece::new(attacker, kind.piece_type()), square(to)),
+        };
+        debug_assert!(pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m));
+        let gives_check = pos.gives_check(m);
+        pos.do_move(m, gives_check);
+        pv.push(m);
+        state = next;
+    }
+    let plies = i32::from(d);
+    Some(EndgameResult {
+        best_move: pv[0],
+        score: if is_attacker_to_move {
+            Value::mate_in(plies)
+        } else {
+            Value::mated_in(plies)
+        },
+        pv,
+    })
+}
+
+#[test]
+fn test_endgame_probe() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            use crate::movegen::*;
+            build_tables();
+            let assert_mate = |sfen: &str| -> EndgameResult {
+                let result = probe(&Position::from_sfen(sfen).unwrap()).unwrap();
+                assert_eq!(result.best_move, result.pv[0]);
+                let mut pos = Position::from_sfen(sfen).unwrap();
+                for &m in result.pv.iter() {
+                    assert!(pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m));
+                    let gives_check = pos.gives_check(m);
+                    pos.do_move(m, gives_check);
+                }
+                let mut mlist = MoveList::new();
+                mlist.generate::<LegalAllType>(&pos, 0);
+                // No legal moves is a loss even if the king isn't in check.
+                assert_eq!(mlist.size, 0);
+                result
+            };
+
+            // mate in 1. R*1a
+            let result = assert_mate("k8/9/1K7/9/9/9/9/9/9 b R 1");
+            assert_eq!(result.score, Value::mate_in(1));
+
+            // K + rook in hand vs lone K.
+            let result = assert_mate("4k4/9/9/9/9/9/9/9/4K4 b R 1");
+            assert!(Value::MATE_IN_MAX_PLY <= result.score);
+            assert_eq!(Value::mate_in(result.pv.len() as i32), result.score);
+
+            // The same position rotated. The attacker is white.
+            let rotated = assert_mate("4k4/9/9/9/9/9/9/9/4K4 w r 1");
+            assert_eq!(rotated.score, result.score);
+
+            // The defender to move delays the mate.
+            let result = assert_mate("4k4/9/9/9/9/4R4/9/9/4K4 w - 1");
+            assert!(result.score <= Value::MATED_IN_MAX_PLY);
+            assert_eq!(Value::mated_in(result.pv.len() as i32), result.score);
+
+            // The defender captures the rook.
+            assert!(probe(&Position::from_sfen("4k4/4R4/9/9/9/9/9/9/4K4 w - 1").unwrap()).is_none());
+            // Not a signature.
+            assert!(probe(&Position::new()).is_none());
+            assert!(probe(&Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 b RP 1").unwrap()).is_none());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
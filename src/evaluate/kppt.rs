@@ -221,6 +221,18 @@ static INVERSE_EVAL_INDEX_TABLE: once_cell::sync::Lazy<[EvalIndex; EvalIndex::FE
     buf
 });
 
+// An evaluation file of another version has a different size, and it must not be loaded.
+fn check_file_size(file: &std::fs::File, expected: usize) -> std::io::Result<()> {
+    let actual = file.metadata()?.len();
+    if actual != expected as u64 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid file size. expected: {} bytes, actual: {} bytes", expected, actual),
+        ));
+    }
+    Ok(())
+}
+
 pub struct Evaluator {
     pub kpp: *const [[[[i16; 2]; EvalIndex::FE_END.0]; EvalIndex::FE_END.0]; Square::NUM],
     pub kkp: *const [[[[i16; 2]; EvalIndex::FE_END.0]; Square::NUM]; Square::NUM],
@@ -229,9 +241,10 @@ pub struct Evaluator {
 impl Evaluator {
     fn load_kpp(&mut self, path: &str) -> std::io::Result<()> {
         let mut file = std::fs::File::open(path)?;
+        let size = 2 * 2 * EvalIndex::FE_END.0 * EvalIndex::FE_END.0 * Square::NUM;
+        check_file_size(&file, size)?;
         let ptr = BUFFER_KPP.lock().unwrap().as_mut_ptr() as *mut u8;
-        let slice =
-            unsafe { std::slice::from_raw_parts_mut(ptr, 2 * 2 * EvalIndex::FE_END.0 * EvalIndex::FE_END.0 * Square::NUM) };
+        let slice = unsafe { std::slice::from_raw_parts_mut(ptr, size) };
         file.read_exact(slice)?;
         self.kpp =
             BUFFER_KPP.lock().unwrap().as_mut_ptr() as *mut [[[[i16; 2]; EvalIndex::FE_END.0]; EvalIndex::FE_END.0]; Square::NUM];
@@ -239,8 +252,10 @@ impl Evaluator {
     }
     fn load_kkp(&mut self, path: &str) -> std::io::Result<()> {
         let mut file = std::fs::File::open(path)?;
+        let size = 2 * 2 * EvalIndex::FE_END.0 * Square::NUM * Square::NUM;
+        check_file_size(&file, size)?;
         let ptr = BUFFER_KKP.lock().unwrap().as_mut_ptr() as *mut u8;
-        let slice = unsafe { std::slice::from_raw_parts_mut(ptr, 2 * 2 * EvalIndex::FE_END.0 * Square::NUM * Square::NUM) };
+        let slice = unsafe { std::slice::from_raw_parts_mut(ptr, size) };
         file.read_exact(slice)?;
         self.kkp = BUFFER_KKP.lock().unwrap().as_mut_ptr() as *mut [[[[i16; 2]; EvalIndex::FE_END.0]; Square::NUM]; Square::NUM];
         Ok(())
@@ -268,6 +283,10 @@ impl Evaluator {
         unsafe { *(*self.kpp).get_unchecked(sq.0 as usize).get_unchecked(i.0).get_unchecked(j.0) }
     }
     #[inline]
+    fn kpp_row(&self, sq: Square, i: EvalIndex) -> &[[i16; 2]; EvalIndex::FE_END.0] {
+        unsafe { (*self.kpp).get_unchecked(sq.0 as usize).get_unchecked(i.0) }
+    }
+    #[inline]
     pub fn kkp(&self, sq0: Square, sq1: Square, i: EvalIndex) -> [i16; 2] {
         unsafe {
             *(*self.kkp)
@@ -286,16 +305,12 @@ impl Evaluator {
         for i in 0..LIST_NUM {
             let k0 = list.get(i, Color::BLACK);
             let k1 = list.get(i, Color::WHITE);
-            for j in 0..i {
-                let l0 = list.get(j, Color::BLACK);
-                let l1 = list.get(j, Color::WHITE);
-                let board_and_turn_0 = self.kpp(sq_bk, k0, l0);
-                sum.val[0][0] += i32::from(board_and_turn_0[0]);
-                sum.val[0][1] += i32::from(board_and_turn_0[1]);
-                let board_and_turn_1 = self.kpp(sq_wk_inv, k1, l1);
-                sum.val[1][0] += i32::from(board_and_turn_1[0]);
-                sum.val[1][1] += i32::from(board_and_turn_1[1]);
-            }
+            let board_and_turn_0 = sum_kpp_row(self.kpp_row(sq_bk, k0), &list.0[..i], Color::BLACK);
+            sum.val[0][0] += board_and_turn_0[0];
+            sum.val[0][1] += board_and_turn_0[1];
+            let board_and_turn_1 = sum_kpp_row(self.kpp_row(sq_wk_inv, k1), &list.0[..i], Color::WHITE);
+            sum.val[1][0] += board_and_turn_1[0];
+            sum.val[1][1] += board_and_turn_1[1];
             let board_and_turn = self.kkp(sq_bk, sq_wk, k0);
             sum.val[2][0] += i32::from(board_and_turn[0]);
             sum.val[2][1] += i32::from(board_and_turn[1]);
@@ -312,50 +327,34 @@ impl Evaluator {
         eval_sum.val[2][1] = i32::from(board_and_turn[1]);
         let inv_sq_wk = sq_wk.inverse();
         let inv_eval_index = eval_index.inverse();
-        for item in pos.eval_list().0.iter() {
-            let board_and_turn_0 = self.kpp(sq_bk, eval_index, item[0]);
-            eval_sum.val[0][0] += i32::from(board_and_turn_0[0]);
-            eval_sum.val[0][1] += i32::from(board_and_turn_0[1]);
-            let board_and_turn_1 = self.kpp(inv_sq_wk, inv_eval_index, item[1]);
-            eval_sum.val[1][0] += i32::from(board_and_turn_1[0]);
-            eval_sum.val[1][1] += i32::from(board_and_turn_1[1]);
-        }
+        let list = &pos.eval_list().0;
+        eval_sum.val[0] = sum_kpp_row(self.kpp_row(sq_bk, eval_index), list, Color::BLACK);
+        eval_sum.val[1] = sum_kpp_row(self.kpp_row(inv_sq_wk, inv_eval_index), list, Color::WHITE);
         eval_sum
     }
     fn doablack(&self, pos: &Position, eval_index: EvalIndex) -> [i32; 2] {
         let sq_bk = pos.king_square(Color::BLACK);
-        let mut sum = [0, 0];
-        for item in pos.eval_list().0.iter() {
-            let board_and_turn = self.kpp(sq_bk, eval_index, item[0]);
-            sum[0] += i32::from(board_and_turn[0]);
-            sum[1] += i32::from(board_and_turn[1]);
-        }
-        sum
+        sum_kpp_row(self.kpp_row(sq_bk, eval_index), &pos.eval_list().0, Color::BLACK)
     }
     fn doawhite(&self, pos: &Position, inv_eval_index: EvalIndex) -> [i32; 2] {
         let inv_sq_wk = pos.king_square(Color::WHITE).inverse();
-        let mut sum = [0, 0];
-        for item in pos.eval_list().0.iter() {
-            let board_and_turn = self.kpp(inv_sq_wk, inv_eval_index, item[1]);
-            sum[0] += i32::from(board_and_turn[0]);
-            sum[1] += i32::from(board_and_turn[1]);
-        }
-        sum
+        sum_kpp_row(self.kpp_row(inv_sq_wk, inv_eval_index), &pos.eval_list().0, Color::WHITE)
     }
     fn evaluate_difference_calc(&self, pos: &mut Position, stack: &mut [Stack], ehash: *mut EvalHash) -> Value {
         if get_stack(stack, 0).static_eval_raw.is_not_evaluated() {
             debug_assert!(!get_stack(stack, -1).static_eval_raw.is_not_evaluated());
-            debug_assert!(get_stack(stack, -1).current_move.unwrap_unchecked() != Move::NULL);
+            debug_assert!(get_stack(stack, -1).current_move.unwrap_unchecked_move() != Move::NULL);
             let key_exclude_turn = Key(pos.key().0 >> 1);
-            let mut entry = unsafe { (*ehash).get(key_exclude_turn) };
-            entry.decode();
-            if entry.key == key_exclude_turn {
-                get_stack_mut(stack, 0).static_eval_raw = entry;
-                debug_assert_eq!(entry.sum(pos.side_to_move()), self.evaluate_debug(pos));
-                return entry.sum(pos.side_to_move()) / FV_SCALE;
+            if let Some(mut entry) = unsafe { (*ehash).get(key_exclude_turn) } {
+                entry.decode();
+                if entry.key == key_exclude_turn {
+                    get_stack_mut(stack, 0).static_eval_raw = entry;
+                    debug_assert_eq!(entry.sum(pos.side_to_move()), self.evaluate_debug(pos));
+                    return entry.sum(pos.side_to_move()) / FV_SCALE;
+                }
             }
 
-            let last_move = get_stack(stack, -1).current_move.unwrap_unchecked();
+            let last_move = get_stack(stack, -1).current_move.unwrap_unchecked_move();
             let sq_bk = pos.king_square(Color::BLACK);
             let sq_wk = pos.king_square(Color::WHITE);
             if PieceType::new(last_move.piece_moved_before_move()) == PieceType::KING {
@@ -369,11 +368,9 @@ impl Evaluator {
                     let inv_sq_wk = sq_wk.inverse();
                     let eval_list = pos.eval_list();
                     for (i, item) in eval_list.0.iter().enumerate() {
-                        for item_inner in eval_list.0.iter().take(i) {
-                            let board_and_turn = self.kpp(inv_sq_wk, item[1], item_inner[1]);
-                            sum.val[1][0] += i32::from(board_and_turn[0]);
-                            sum.val[1][1] += i32::from(board_and_turn[1]);
-                        }
+                        let board_and_turn = sum_kpp_row(self.kpp_row(inv_sq_wk, item[1]), &eval_list.0[..i], Color::WHITE);
+                        sum.val[1][0] += board_and_turn[0];
+                        sum.val[1][1] += board_and_turn[1];
                         let board_and_turn = self.kkp(inv_sq_wk, sq_bk.inverse(), item[1]);
                         sum.val[2][0] -= i32::from(board_and_turn[0]);
                         sum.val[2][1] += i32::from(board_and_turn[1]);
@@ -398,11 +395,9 @@ impl Evaluator {
                     sum.val[0][1] = 0;
                     let eval_list = pos.eval_list();
                     for (i, item) in eval_list.0.iter().enumerate() {
-                        for item_inner in eval_list.0.iter().take(i) {
-                            let board_and_turn = self.kpp(sq_bk, item[0], item_inner[0]);
-                            sum.val[0][0] += i32::from(board_and_turn[0]);
-                            sum.val[0][1] += i32::from(board_and_turn[1]);
-                        }
+                        let board_and_turn = sum_kpp_row(self.kpp_row(sq_bk, item[0]), &eval_list.0[..i], Color::BLACK);
+                        sum.val[0][0] += board_and_turn[0];
+                        sum.val[0][1] += board_and_turn[1];
                         let board_and_turn = self.kkp(sq_bk, sq_wk, item[0]);
                         sum.val[2][0] += i32::from(board_and_turn[0]);
                         sum.val[2][1] += i32::from(board_and_turn[1]);
@@ -556,6 +551,63 @@ impl Evaluator {
     }
 }
 
+// Sums row[item[c]] for all the items of list as [board, turn].
+// The AVX2 path is used if the CPU has it. It has exactly the same result as the scalar path because the sums are integers.
+#[inline]
+fn sum_kpp_row(row: &[[i16; 2]; EvalIndex::FE_END.0], list: &[[EvalIndex; 2]], c: Color) -> [i32; 2] {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { sum_kpp_row_avx2(row, list, c) };
+        }
+    }
+    sum_kpp_row_scalar(row, list, c)
+}
+
+#[inline]
+fn sum_kpp_row_scalar(row: &[[i16; 2]; EvalIndex::FE_END.0], list: &[[EvalIndex; 2]], c: Color) -> [i32; 2] {
+    let mut sum = [0, 0];
+    for item in list.iter() {
+        let board_and_turn = unsafe { row.get_unchecked(item[c.0 as usize].0) };
+        sum[0] += i32::from(board_and_turn[0]);
+        sum[1] += i32::from(board_and_turn[1]);
+    }
+    sum
+}
+
+// The caller must check that the CPU has AVX2.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn sum_kpp_row_avx2(row: &[[i16; 2]; EvalIndex::FE_END.0], list: &[[EvalIndex; 2]], c: Color) -> [i32; 2] {
+    use std::arch::x86_64::*;
+    const LANES: usize = 8;
+    // [board, turn] is gathered as an i32. board is the low 16 bits.
+    let base = row.as_ptr() as *const i32;
+    let mut board = _mm256_setzero_si256();
+    let mut turn = _mm256_setzero_si256();
+    let chunks = list.chunks_exact(LANES);
+    let mut sum = sum_kpp_row_scalar(row, chunks.remainder(), c);
+    for chunk in chunks {
+        let mut indices = [0i32; LANES];
+        for (index, item) in indices.iter_mut().zip(chunk.iter()) {
+            *index = item[c.0 as usize].0 as i32;
+        }
+        let v = _mm256_i32gather_epi32(base, _mm256_loadu_si256(indices.as_ptr() as *const __m256i), 4);
+        board = _mm256_add_epi32(board, _mm256_srai_epi32(_mm256_slli_epi32(v, 16), 16));
+        turn = _mm256_add_epi32(turn, _mm256_srai_epi32(v, 16));
+    }
+    #[target_feature(enable = "avx2")]
+    unsafe fn horizontal_sum(v: __m256i) -> i32 {
+        let v = _mm_add_epi32(_mm256_castsi256_si128(v), _mm256_extracti128_si256(v, 1));
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32(v, 0b01_00_11_10));
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32(v, 0b10_11_00_01));
+        _mm_cvtsi128_si32(v)
+    }
+    sum[0] += horizontal_sum(board);
+    sum[1] += horizontal_sum(turn);
+    sum
+}
+
 static BUFFER_KPP: once_cell::sync::Lazy<std::sync::Mutex<Vec<i16>>> = once_cell::sync::Lazy::new(|| {
     std::sync::Mutex::new(Vec::<i16>::with_capacity(
         2 * EvalIndex::FE_END.0 * EvalIndex::FE_END.0 * Square::NUM,
@@ -610,6 +662,13 @@ pub fn evaluate_at_root(pos: &Position, stack: &mut [Stack]) -> Value {
     unsafe { EVALUATOR.evaluate_at_root(pos, stack) }
 }
 
+// The evaluation calculated from scratch without the differences.
+// In debug builds, evaluate() asserts that the difference calculation always has the same value as this.
+#[cfg(test)]
+pub fn evaluate_full(pos: &Position) -> Value {
+    unsafe { (*std::ptr::addr_of!(EVALUATOR)).evaluate_debug(pos) / FV_SCALE }
+}
+
 #[repr(align(256))]
 #[derive(Clone, Copy)]
 pub struct EvalSum {
@@ -705,18 +764,26 @@ impl EvalHash {
             *x = unsafe { std::mem::zeroed() };
         });
     }
-    pub fn get(&self, key: Key) -> EvalSum {
+    // None if the eval hash is disabled by the size 0.
+    pub fn get(&self, key: Key) -> Option<EvalSum> {
+        if self.value.is_empty() {
+            return None;
+        }
         let mask = self.value.len() - 1;
         let index = key.0 as usize & mask;
-        unsafe { *self.value.get_unchecked(index) }
+        Some(unsafe { *self.value.get_unchecked(index) })
     }
     pub fn set(&mut self, key: Key, entry: &EvalSum) {
+        if self.value.is_empty() {
+            return;
+        }
         let mask = self.value.len() - 1;
         let index = key.0 as usize & mask;
         unsafe {
             *self.value.get_unchecked_mut(index) = *entry;
         }
     }
+    // mega_byte_size 0 disables the eval hash, and all the nodes are evaluated without it.
     pub fn resize(&mut self, mega_byte_size: usize, thread_pool: &mut ThreadPool) {
         thread_pool.wait_for_search_finished();
         let mega_byte_size = (mega_byte_size + 1).next_power_of_two() >> 1;
@@ -731,6 +798,71 @@ impl EvalHash {
     }
 }
 
+#[test]
+fn test_eval_hash() {
+    let mut thread_pool = ThreadPool::new();
+    let mut ehash = EvalHash::new();
+    let mut entry = EvalSum::new();
+    entry.val[0][0] = 1;
+    entry.key = Key(12345);
+
+    ehash.resize(0, &mut thread_pool);
+    ehash.set(entry.key, &entry);
+    assert!(ehash.get(entry.key).is_none());
+
+    ehash.resize(1, &mut thread_pool);
+    ehash.set(entry.key, &entry);
+    let stored = ehash.get(entry.key).unwrap();
+    assert!(stored.key == entry.key);
+    assert_eq!(stored.val[0][0], 1);
+}
+
+// Run it by "cargo test -- --ignored" with the evaluation function binaries.
+#[test]
+#[ignore = "needs the evaluation function binaries"]
+fn test_evaluate_difference_calc() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let usi_options = crate::usioption::UsiOptions::new();
+            load_evaluate_files(&usi_options.get_string(crate::usioption::UsiOptions::EVAL_DIR)).unwrap();
+            let mut thread_pool = ThreadPool::new();
+            let mut ehash = EvalHash::new();
+            ehash.resize(0, &mut thread_pool);
+            let mut pos = Position::new();
+            let mut stack = vec![Stack::new(); CURRENT_STACK_INDEX + 16];
+            assert_eq!(evaluate_at_root(&pos, &mut stack), evaluate_full(&pos));
+            // promotion, capturing the promoted piece, dropping it back, king moves and a promoted piece capture.
+            let moves = [
+                "7g7f", "3c3d", "8h2b+", "3a2b", "B*4e", "5a4b", "4e3d", "2b3c", "3d4c+", "4b4c", "5i4h", "B*5e",
+            ];
+            for (i, m) in moves.iter().enumerate() {
+                let m = Move::new_from_usi_str(m, &pos).unwrap();
+                stack[CURRENT_STACK_INDEX + i].current_move = Some(m);
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+                stack[CURRENT_STACK_INDEX + i + 1].static_eval_raw.set_not_evaluated();
+                assert_eq!(evaluate(&mut pos, &mut stack[i + 1..], &mut ehash), evaluate_full(&pos));
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_load_evaluate_files_error() {
+    let err = load_evaluate_files("no_such_directory").unwrap_err();
+    assert!(err.contains("KPP.bin"));
+
+    let dir = std::env::temp_dir().join(format!("apery_test_load_evaluate_files_error_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("KPP.bin"), [0u8; 4]).unwrap();
+    let err = load_evaluate_files(dir.to_str().unwrap()).unwrap_err();
+    assert!(err.contains("Invalid file size") && err.contains("KPP.bin"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
 #[test]
 fn test_eval_index_new() {
     assert_eq!(EvalIndex::F_PAWN, EvalIndex::new_board(Piece::B_PAWN));
@@ -907,3 +1039,46 @@ fn test_eval_index_inverse() {
         EvalIndex(EvalIndex::F_DRAGON.0 + Square::SQ98.0 as usize)
     );
 }
+
+#[test]
+fn test_sum_kpp_row() {
+    use rand::{Rng, SeedableRng};
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            let mut row = Box::new([[0i16; 2]; EvalIndex::FE_END.0]);
+            for item in row.iter_mut() {
+                *item = [rng.gen(), rng.gen()];
+            }
+            // The eval lists of the random positions.
+            for _ in 0..64 {
+                let mut pos = Position::new();
+                for _ in 0..rng.gen_range(0..128) {
+                    let moves = pos.legal_moves();
+                    if moves.is_empty() {
+                        break;
+                    }
+                    let m = moves[rng.gen_range(0..moves.len())];
+                    let gives_check = pos.gives_check(m);
+                    pos.do_move(m, gives_check);
+                }
+                let list = &pos.eval_list().0;
+                for i in 0..=LIST_NUM {
+                    for &c in Color::ALL.iter() {
+                        let expected = sum_kpp_row_scalar(&row, &list[..i], c);
+                        assert_eq!(sum_kpp_row(&row, &list[..i], c), expected);
+                        #[cfg(target_arch = "x86_64")]
+                        {
+                            if is_x86_feature_detected!("avx2") {
+                                assert_eq!(unsafe { sum_kpp_row_avx2(&row, &list[..i], c) }, expected);
+                            }
+                        }
+                    }
+                }
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -1,11 +1,35 @@
+use crate::evaluate::*;
 use crate::position::*;
 use crate::search::*;
 use crate::types::*;
 
 pub fn evaluate(pos: &mut Position, _stack: &mut [Stack]) -> Value {
-    pos.material()
+    material_value(pos)
 }
 
 pub fn evaluate_at_root(pos: &Position, _stack: &mut [Stack]) -> Value {
-    pos.material()
+    material_value(pos)
+}
+
+#[test]
+fn test_evaluate_from_side_to_move() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            // Black has the rook more than white.
+            let mut black_to_move = Position::from_sfen("4k4/9/9/9/9/9/9/8R/4K4 b - 1").unwrap();
+            let mut white_to_move = Position::from_sfen("4k4/9/9/9/9/9/9/8R/4K4 w - 1").unwrap();
+            assert!(black_to_move.material() > Value::ZERO);
+            assert_eq!(black_to_move.material(), white_to_move.material());
+
+            let score = evaluate(&mut black_to_move, &mut []);
+            assert_eq!(score, black_to_move.material());
+            assert_eq!(evaluate_at_root(&black_to_move, &mut []), score);
+            // The side to move is behind by the rook.
+            assert_eq!(evaluate(&mut white_to_move, &mut []), -score);
+            assert_eq!(evaluate_at_root(&white_to_move, &mut []), -score);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
 }
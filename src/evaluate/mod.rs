@@ -2,3 +2,64 @@
 pub mod kppt;
 #[cfg(feature = "material")]
 pub mod material;
+#[cfg(feature = "nnue")]
+pub mod nnue;
+
+use crate::position::*;
+use crate::types::*;
+
+// "full" is the evaluation of the build. "material" is only the piece values, for comparing with the full evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalMode {
+    Full,
+    Material,
+}
+
+impl std::str::FromStr for EvalMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<EvalMode, String> {
+        match s {
+            "full" => Ok(EvalMode::Full),
+            "material" => Ok(EvalMode::Material),
+            _ => Err(format!(r#"expected: "full" or "material". found: "{}""#, s)),
+        }
+    }
+}
+
+// The sum of the piece values on the board and in hand from the side to move.
+pub fn material_value(pos: &Position) -> Value {
+    // pos.material() is from the black side.
+    if pos.side_to_move() == Color::BLACK {
+        pos.material()
+    } else {
+        -pos.material()
+    }
+}
+
+#[test]
+fn test_material_value() {
+    use crate::movetypes::*;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::new();
+            assert_eq!(material_value(&pos), Value::ZERO);
+            let mut pos = Position::from_sfen("4k4/9/9/9/4r4/9/9/4R4/4K4 b - 1").unwrap();
+            assert_eq!(material_value(&pos), Value::ZERO);
+            let m = Move::new_from_usi_str("5h5e", &pos).unwrap();
+            let gives_check = pos.gives_check(m);
+            pos.do_move(m, gives_check);
+            // The rook is lost on the board and gained in hand.
+            let values = pos.piece_values();
+            assert_eq!(material_value(&pos), -values.capture_piece_type_value(PieceType::ROOK));
+            assert_eq!(
+                values.capture_piece_type_value(PieceType::ROOK),
+                values.piece_type_value(PieceType::ROOK) * 2
+            );
+            assert_eq!("material".parse::<EvalMode>(), Ok(EvalMode::Material));
+            assert!("kppt".parse::<EvalMode>().is_err());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -0,0 +1,610 @@
+use crate::movetypes::*;
+use crate::position::*;
+use crate::search::*;
+use crate::types::*;
+use std::io::prelude::*;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+// HalfKP(Friend)[125388->256x2]->32->32->1 network, which is the common .nnue format of shogi engines.
+// The feature index is (king square) * FE_END + (piece index) from the point of view of each side.
+// The piece index layout is the same as the other shogi NNUE engines.
+const F_HAND_PAWN: usize = 1;
+const E_HAND_PAWN: usize = F_HAND_PAWN + 19;
+const F_HAND_LANCE: usize = E_HAND_PAWN + 19;
+const E_HAND_LANCE: usize = F_HAND_LANCE + 5;
+const F_HAND_KNIGHT: usize = E_HAND_LANCE + 5;
+const E_HAND_KNIGHT: usize = F_HAND_KNIGHT + 5;
+const F_HAND_SILVER: usize = E_HAND_KNIGHT + 5;
+const E_HAND_SILVER: usize = F_HAND_SILVER + 5;
+const F_HAND_GOLD: usize = E_HAND_SILVER + 5;
+const E_HAND_GOLD: usize = F_HAND_GOLD + 5;
+const F_HAND_BISHOP: usize = E_HAND_GOLD + 5;
+const E_HAND_BISHOP: usize = F_HAND_BISHOP + 3;
+const F_HAND_ROOK: usize = E_HAND_BISHOP + 3;
+const E_HAND_ROOK: usize = F_HAND_ROOK + 3;
+const FE_HAND_END: usize = E_HAND_ROOK + 3;
+
+const F_PAWN: usize = FE_HAND_END;
+const E_PAWN: usize = F_PAWN + 81;
+const F_LANCE: usize = E_PAWN + 81;
+const E_LANCE: usize = F_LANCE + 81;
+const F_KNIGHT: usize = E_LANCE + 81;
+const E_KNIGHT: usize = F_KNIGHT + 81;
+const F_SILVER: usize = E_KNIGHT + 81;
+const E_SILVER: usize = F_SILVER + 81;
+const F_GOLD: usize = E_SILVER + 81;
+const E_GOLD: usize = F_GOLD + 81;
+const F_BISHOP: usize = E_GOLD + 81;
+const E_BISHOP: usize = F_BISHOP + 81;
+const F_HORSE: usize = E_BISHOP + 81;
+const E_HORSE: usize = F_HORSE + 81;
+const F_ROOK: usize = E_HORSE + 81;
+const E_ROOK: usize = F_ROOK + 81;
+const F_DRAGON: usize = E_ROOK + 81;
+const E_DRAGON: usize = F_DRAGON + 81;
+const FE_END: usize = E_DRAGON + 81;
+
+pub const HALFKP_DIMENSIONS: usize = Square::NUM * FE_END;
+pub const TRANSFORMED_DIMENSIONS: usize = 256;
+const HIDDEN_DIMENSIONS: usize = 32;
+const WEIGHT_SCALE_BITS: i32 = 6;
+const FV_SCALE: i32 = 16;
+const VERSION: u32 = 0x7AF3_2F16;
+
+// Indexed by the black piece, or the white piece of which color is inverted.
+const TABLE_OF_BOARD_INDEX: [usize; Piece::NUM] = [
+    0, // Piece::EMPTY
+    F_PAWN, F_LANCE, F_KNIGHT, F_SILVER, F_BISHOP, F_ROOK, F_GOLD, 0, // Piece::B_KING
+    F_GOLD, F_GOLD, F_GOLD, F_GOLD, F_HORSE, F_DRAGON, 0, 0, E_PAWN, E_LANCE, E_KNIGHT, E_SILVER, E_BISHOP, E_ROOK, E_GOLD,
+    0, // Piece::W_KING
+    E_GOLD, E_GOLD, E_GOLD, E_GOLD, E_HORSE, E_DRAGON,
+];
+// [PieceType][is_enemy]
+const TABLE_OF_HAND_INDEX: [[usize; 2]; PieceType::HAND_NUM] = [
+    [0, 0],
+    [F_HAND_PAWN, E_HAND_PAWN],
+    [F_HAND_LANCE, E_HAND_LANCE],
+    [F_HAND_KNIGHT, E_HAND_KNIGHT],
+    [F_HAND_SILVER, E_HAND_SILVER],
+    [F_HAND_BISHOP, E_HAND_BISHOP],
+    [F_HAND_ROOK, E_HAND_ROOK],
+    [F_HAND_GOLD, E_HAND_GOLD],
+];
+
+fn board_index(perspective: Color, pc: Piece, sq: Square) -> usize {
+    debug_assert!(pc != Piece::EMPTY && !pc.is_king());
+    if perspective == Color::BLACK {
+        TABLE_OF_BOARD_INDEX[pc.0 as usize] + sq.0 as usize
+    } else {
+        TABLE_OF_BOARD_INDEX[pc.inverse().0 as usize] + sq.inverse().0 as usize
+    }
+}
+
+// num is the number of the pieces in the hand including this piece.
+fn hand_index(perspective: Color, c: Color, pt: PieceType, num: u32) -> usize {
+    debug_assert!(num > 0);
+    TABLE_OF_HAND_INDEX[pt.0 as usize][(c != perspective) as usize] + num as usize - 1
+}
+
+fn king_square(pos: &Position, perspective: Color) -> Square {
+    if perspective == Color::BLACK {
+        pos.king_square(Color::BLACK)
+    } else {
+        pos.king_square(Color::WHITE).inverse()
+    }
+}
+
+fn feature_index(king_sq: Square, index: usize) -> usize {
+    king_sq.0 as usize * FE_END + index
+}
+
+const fn affine_transform_hash(prev_hash: u32, output_dimensions: u32) -> u32 {
+    let mut hash = 0xCC03_DAE4u32.wrapping_add(output_dimensions);
+    hash ^= prev_hash >> 1;
+    hash ^= prev_hash << 31;
+    hash
+}
+
+const fn clipped_relu_hash(prev_hash: u32) -> u32 {
+    0x538D_24C7u32.wrapping_add(prev_hash)
+}
+
+fn feature_transformer_hash(transformed_dimensions: usize) -> u32 {
+    // HalfKP(Friend)
+    0x5D69_D5B8 ^ (transformed_dimensions * 2) as u32
+}
+
+fn network_hash(transformed_dimensions: usize, hidden_dimensions: usize) -> u32 {
+    let input_slice_hash = 0xEC42_E90D ^ (transformed_dimensions * 2) as u32;
+    let hash = clipped_relu_hash(affine_transform_hash(input_slice_hash, hidden_dimensions as u32));
+    let hash = clipped_relu_hash(affine_transform_hash(hash, hidden_dimensions as u32));
+    affine_transform_hash(hash, 1)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_i16_vec<R: Read>(reader: &mut R, len: usize) -> std::io::Result<Vec<i16>> {
+    let mut buf = vec![0; len * 2];
+    reader.read_exact(&mut buf)?;
+    Ok(buf.chunks_exact(2).map(|x| i16::from_le_bytes([x[0], x[1]])).collect())
+}
+
+fn read_i32_vec<R: Read>(reader: &mut R, len: usize) -> std::io::Result<Vec<i32>> {
+    let mut buf = vec![0; len * 4];
+    reader.read_exact(&mut buf)?;
+    Ok(buf
+        .chunks_exact(4)
+        .map(|x| i32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect())
+}
+
+fn check_hash(name: &str, expected: u32, actual: u32) -> std::io::Result<()> {
+    if expected != actual {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} hash mismatch. expected: {:#010x}, actual: {:#010x}",
+                name, expected, actual
+            ),
+        ));
+    }
+    Ok(())
+}
+
+struct AffineTransform {
+    input_dimensions: usize,
+    padded_input_dimensions: usize,
+    output_dimensions: usize,
+    biases: Vec<i32>,
+    weights: Vec<i8>,
+}
+
+impl AffineTransform {
+    fn read<R: Read>(reader: &mut R, input_dimensions: usize, output_dimensions: usize) -> std::io::Result<AffineTransform> {
+        let padded_input_dimensions = input_dimensions.div_ceil(32) * 32;
+        let biases = read_i32_vec(reader, output_dimensions)?;
+        let mut buf = vec![0u8; output_dimensions * padded_input_dimensions];
+        reader.read_exact(&mut buf)?;
+        let weights = buf.into_iter().map(|x| x as i8).collect();
+        Ok(AffineTransform {
+            input_dimensions,
+            padded_input_dimensions,
+            output_dimensions,
+            biases,
+            weights,
+        })
+    }
+    fn propagate(&self, input: &[u8], output: &mut [i32]) {
+        debug_assert_eq!(input.len(), self.input_dimensions);
+        debug_assert_eq!(output.len(), self.output_dimensions);
+        for (i, out) in output.iter_mut().enumerate() {
+            let row = &self.weights[i * self.padded_input_dimensions..i * self.padded_input_dimensions + self.input_dimensions];
+            *out = self.biases[i]
+                + row
+                    .iter()
+                    .zip(input.iter())
+                    .map(|(&w, &x)| i32::from(w) * i32::from(x))
+                    .sum::<i32>();
+        }
+    }
+}
+
+fn clipped_relu(input: &[i32], output: &mut [u8]) {
+    for (out, &x) in output.iter_mut().zip(input.iter()) {
+        *out = num::clamp(x >> WEIGHT_SCALE_BITS, 0, 127) as u8;
+    }
+}
+
+// A piece which is removed or added by a move. The hand piece belongs to the side which made the move.
+#[derive(Clone, Copy)]
+enum ChangedPiece {
+    Board(Piece, Square),
+    Hand(PieceType, u32),
+}
+
+// The outputs of the feature transformer for each side. They are stored on the search stack,
+// and the child node's accumulator is made from the parent's one.
+#[repr(align(32))]
+#[derive(Clone, Copy)]
+pub struct Accumulator {
+    pub val: [[i16; TRANSFORMED_DIMENSIONS]; Color::NUM],
+    computed: bool,
+}
+
+impl Accumulator {
+    pub fn new() -> Accumulator {
+        Accumulator {
+            val: [[0; TRANSFORMED_DIMENSIONS]; Color::NUM],
+            computed: false,
+        }
+    }
+    pub fn set_not_evaluated(&mut self) {
+        self.computed = false;
+    }
+    pub fn is_not_evaluated(&self) -> bool {
+        !self.computed
+    }
+}
+
+pub struct NnueEvaluator {
+    architecture: String,
+    transformed_dimensions: usize,
+    feature_transformer_biases: Vec<i16>,
+    feature_transformer_weights: Vec<i16>, // [HALFKP_DIMENSIONS][transformed_dimensions]
+    hidden1: AffineTransform,
+    hidden2: AffineTransform,
+    output: AffineTransform,
+}
+
+impl NnueEvaluator {
+    pub fn load(path: &std::path::Path) -> std::io::Result<NnueEvaluator> {
+        let file = std::fs::File::open(path)?;
+        let mut reader = std::io::BufReader::new(file);
+        NnueEvaluator::read(&mut reader, TRANSFORMED_DIMENSIONS, HIDDEN_DIMENSIONS)
+    }
+    // The layer sizes are parameters only for testing with small networks.
+    // transformed_dimensions must not be larger than TRANSFORMED_DIMENSIONS.
+    fn read<R: Read>(reader: &mut R, transformed_dimensions: usize, hidden_dimensions: usize) -> std::io::Result<NnueEvaluator> {
+        debug_assert!(transformed_dimensions <= TRANSFORMED_DIMENSIONS);
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unsupported NNUE version: {:#010x}", version),
+            ));
+        }
+        let ft_hash = feature_transformer_hash(transformed_dimensions);
+        let net_hash = network_hash(transformed_dimensions, hidden_dimensions);
+        check_hash("NNUE", ft_hash ^ net_hash, read_u32(reader)?)?;
+        let architecture_size = read_u32(reader)? as usize;
+        let mut architecture = vec![0; architecture_size];
+        reader.read_exact(&mut architecture)?;
+        let architecture = String::from_utf8_lossy(&architecture).into_owned();
+
+        check_hash("Feature transformer", ft_hash, read_u32(reader)?)?;
+        let feature_transformer_biases = read_i16_vec(reader, transformed_dimensions)?;
+        let feature_transformer_weights = read_i16_vec(reader, HALFKP_DIMENSIONS * transformed_dimensions)?;
+
+        check_hash("Network", net_hash, read_u32(reader)?)?;
+        let hidden1 = AffineTransform::read(reader, transformed_dimensions * 2, hidden_dimensions)?;
+        let hidden2 = AffineTransform::read(reader, hidden_dimensions, hidden_dimensions)?;
+        let output = AffineTransform::read(reader, hidden_dimensions, 1)?;
+        Ok(NnueEvaluator {
+            architecture,
+            transformed_dimensions,
+            feature_transformer_biases,
+            feature_transformer_weights,
+            hidden1,
+            hidden2,
+            output,
+        })
+    }
+    #[allow(dead_code)]
+    pub fn architecture(&self) -> &str {
+        &self.architecture
+    }
+    fn add_feature(&self, acc: &mut [i16; TRANSFORMED_DIMENSIONS], index: usize) {
+        let n = self.transformed_dimensions;
+        let weights = &self.feature_transformer_weights[index * n..(index + 1) * n];
+        for (a, &w) in acc.iter_mut().zip(weights.iter()) {
+            *a = a.wrapping_add(w);
+        }
+    }
+    fn remove_feature(&self, acc: &mut [i16; TRANSFORMED_DIMENSIONS], index: usize) {
+        let n = self.transformed_dimensions;
+        let weights = &self.feature_transformer_weights[index * n..(index + 1) * n];
+        for (a, &w) in acc.iter_mut().zip(weights.iter()) {
+            *a = a.wrapping_sub(w);
+        }
+    }
+    fn refresh(&self, pos: &Position, perspective: Color, acc: &mut [i16; TRANSFORMED_DIMENSIONS]) {
+        *acc = [0; TRANSFORMED_DIMENSIONS];
+        acc[..self.transformed_dimensions].copy_from_slice(&self.feature_transformer_biases);
+        let king_sq = king_square(pos, perspective);
+        for sq in Square::ALL.iter() {
+            let pc = pos.piece_on(*sq);
+            if pc != Piece::EMPTY && !pc.is_king() {
+                self.add_feature(acc, feature_index(king_sq, board_index(perspective, pc, *sq)));
+            }
+        }
+        for &c in Color::ALL.iter() {
+            let hand = pos.hand(c);
+            for &pt in PieceType::ALL_HAND.iter() {
+                for num in 1..=hand.num(pt) {
+                    self.add_feature(acc, feature_index(king_sq, hand_index(perspective, c, pt, num)));
+                }
+            }
+        }
+    }
+    fn refresh_accumulator(&self, pos: &Position, acc: &mut Accumulator) {
+        for &c in Color::ALL.iter() {
+            self.refresh(pos, c, &mut acc.val[c.0 as usize]);
+        }
+        acc.computed = true;
+    }
+    // Make the accumulator of the current position from the one before the last move m.
+    fn update_accumulator(&self, pos: &Position, m: Move, prev: &Accumulator, acc: &mut Accumulator) {
+        let us = pos.side_to_move().inverse();
+        let mut removed = arrayvec::ArrayVec::<[ChangedPiece; 2]>::new();
+        let mut added = arrayvec::ArrayVec::<[ChangedPiece; 2]>::new();
+        if m.is_drop() {
+            let pt = m.piece_type_dropped();
+            removed.push(ChangedPiece::Hand(pt, pos.hand(us).num(pt) + 1));
+            added.push(ChangedPiece::Board(Piece::new(us, pt), m.to()));
+        } else {
+            let moved_piece = m.piece_moved_before_move();
+            if !moved_piece.is_king() {
+                removed.push(ChangedPiece::Board(moved_piece, m.from()));
+                added.push(ChangedPiece::Board(m.piece_moved_after_move(), m.to()));
+            }
+            let captured_piece = pos.captured_piece();
+            if captured_piece != Piece::EMPTY {
+                let pt = PieceType::new(captured_piece).to_demote_if_possible();
+                removed.push(ChangedPiece::Board(captured_piece, m.to()));
+                added.push(ChangedPiece::Hand(pt, pos.hand(us).num(pt)));
+            }
+        }
+        for &perspective in Color::ALL.iter() {
+            let val = &mut acc.val[perspective.0 as usize];
+            if m.piece_moved_before_move().is_king() && perspective == us {
+                self.refresh(pos, perspective, val);
+                continue;
+            }
+            *val = prev.val[perspective.0 as usize];
+            let king_sq = king_square(pos, perspective);
+            let index = |item: &ChangedPiece| match *item {
+                ChangedPiece::Board(pc, sq) => feature_index(king_sq, board_index(perspective, pc, sq)),
+                ChangedPiece::Hand(pt, num) => feature_index(king_sq, hand_index(perspective, us, pt, num)),
+            };
+            for item in removed.iter() {
+                self.remove_feature(val, index(item));
+            }
+            for item in added.iter() {
+                self.add_feature(val, index(item));
+            }
+        }
+        acc.computed = true;
+    }
+    fn propagate(&self, pos: &Position, acc: &Accumulator) -> Value {
+        let n = self.transformed_dimensions;
+        let us = pos.side_to_move();
+        let mut transformed = [0u8; TRANSFORMED_DIMENSIONS * 2];
+        for (i, &c) in [us, us.inverse()].iter().enumerate() {
+            for (out, &x) in transformed[i * n..(i + 1) * n].iter_mut().zip(acc.val[c.0 as usize].iter()) {
+                *out = num::clamp(x, 0, 127) as u8;
+            }
+        }
+        let hidden_dimensions = self.hidden1.output_dimensions;
+        let mut buf = [0i32; HIDDEN_DIMENSIONS];
+        let mut hidden1_out = [0u8; HIDDEN_DIMENSIONS];
+        let mut hidden2_out = [0u8; HIDDEN_DIMENSIONS];
+        self.hidden1.propagate(&transformed[..n * 2], &mut buf[..hidden_dimensions]);
+        clipped_relu(&buf[..hidden_dimensions], &mut hidden1_out[..hidden_dimensions]);
+        self.hidden2
+            .propagate(&hidden1_out[..hidden_dimensions], &mut buf[..hidden_dimensions]);
+        clipped_relu(&buf[..hidden_dimensions], &mut hidden2_out[..hidden_dimensions]);
+        let mut output = [0i32; 1];
+        self.output.propagate(&hidden2_out[..hidden_dimensions], &mut output);
+        Value(output[0] / FV_SCALE)
+    }
+    fn evaluate(&self, pos: &Position, stack: &mut [Stack]) -> Value {
+        if get_stack(stack, 0).static_eval_raw.is_not_evaluated() {
+            let prev = get_stack(stack, -1).static_eval_raw;
+            match get_stack(stack, -1).current_move {
+                Some(m) if m != Move::NULL && !prev.is_not_evaluated() => {
+                    self.update_accumulator(pos, m, &prev, &mut get_stack_mut(stack, 0).static_eval_raw)
+                }
+                _ => self.refresh_accumulator(pos, &mut get_stack_mut(stack, 0).static_eval_raw),
+            }
+        }
+        self.propagate(pos, &get_stack(stack, 0).static_eval_raw)
+    }
+    fn evaluate_at_root(&self, pos: &Position, stack: &mut [Stack]) -> Value {
+        self.refresh_accumulator(pos, &mut get_stack_mut(stack, 0).static_eval_raw);
+        self.propagate(pos, &get_stack(stack, 0).static_eval_raw)
+    }
+}
+
+// The network is replaced only at "isready", when no search thread is running.
+static EVALUATOR: AtomicPtr<NnueEvaluator> = AtomicPtr::new(std::ptr::null_mut());
+
+fn evaluator() -> &'static NnueEvaluator {
+    let ptr = EVALUATOR.load(Ordering::Acquire);
+    debug_assert!(!ptr.is_null());
+    unsafe { &*ptr }
+}
+
+pub fn load_evaluate_files(eval_dir: &str) -> Result<(), String> {
+    let mut path = std::path::PathBuf::from(eval_dir);
+    path.push("nn.bin");
+    match NnueEvaluator::load(&path) {
+        Ok(evaluator) => {
+            let old = EVALUATOR.swap(Box::into_raw(Box::new(evaluator)), Ordering::AcqRel);
+            if !old.is_null() {
+                unsafe { drop(Box::from_raw(old)) };
+            }
+            Ok(())
+        }
+        Err(err) => Err(format!("{}\nFile name: {}", err, path.display())),
+    }
+}
+
+pub fn evaluate(pos: &mut Position, stack: &mut [Stack]) -> Value {
+    evaluator().evaluate(pos, stack)
+}
+
+pub fn evaluate_at_root(pos: &Position, stack: &mut [Stack]) -> Value {
+    evaluator().evaluate_at_root(pos, stack)
+}
+
+#[cfg(test)]
+fn write_test_network<W: Write>(writer: &mut W, transformed_dimensions: usize, hidden_dimensions: usize, seed: u64) {
+    let mut state = seed;
+    let mut next = |range: i64| -> i64 {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        ((state >> 33) as i64 % (2 * range + 1)) - range
+    };
+    let ft_hash = feature_transformer_hash(transformed_dimensions);
+    let net_hash = network_hash(transformed_dimensions, hidden_dimensions);
+    let architecture = b"test network";
+    writer.write_all(&VERSION.to_le_bytes()).unwrap();
+    writer.write_all(&(ft_hash ^ net_hash).to_le_bytes()).unwrap();
+    writer.write_all(&(architecture.len() as u32).to_le_bytes()).unwrap();
+    writer.write_all(architecture).unwrap();
+    writer.write_all(&ft_hash.to_le_bytes()).unwrap();
+    for _ in 0..transformed_dimensions {
+        writer.write_all(&(next(64) as i16).to_le_bytes()).unwrap();
+    }
+    for _ in 0..HALFKP_DIMENSIONS * transformed_dimensions {
+        writer.write_all(&(next(16) as i16).to_le_bytes()).unwrap();
+    }
+    writer.write_all(&net_hash.to_le_bytes()).unwrap();
+    for &(input_dimensions, output_dimensions) in [
+        (transformed_dimensions * 2, hidden_dimensions),
+        (hidden_dimensions, hidden_dimensions),
+        (hidden_dimensions, 1),
+    ]
+    .iter()
+    {
+        for _ in 0..output_dimensions {
+            writer.write_all(&(next(2000) as i32).to_le_bytes()).unwrap();
+        }
+        let padded_input_dimensions = input_dimensions.div_ceil(32) * 32;
+        for _ in 0..output_dimensions {
+            for i in 0..padded_input_dimensions {
+                let w = if i < input_dimensions { next(127) as i8 } else { 0 };
+                writer.write_all(&w.to_le_bytes()).unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_nnue_hash() {
+    // The well-known hash of the HalfKP 256x2-32-32 networks.
+    assert_eq!(
+        feature_transformer_hash(TRANSFORMED_DIMENSIONS) ^ network_hash(TRANSFORMED_DIMENSIONS, HIDDEN_DIMENSIONS),
+        0x3E5A_A6EE
+    );
+}
+
+#[test]
+fn test_nnue_load_error() {
+    let mut buf = vec![];
+    write_test_network(&mut buf, 8, 4, 1);
+    // wrong layer sizes.
+    assert!(NnueEvaluator::read(&mut buf.as_slice(), 8, 8).is_err());
+    // truncated.
+    assert!(NnueEvaluator::read(&mut &buf[..buf.len() - 1], 8, 4).is_err());
+    assert!(NnueEvaluator::load(std::path::Path::new("no_such_directory/nn.bin")).is_err());
+}
+
+#[test]
+fn test_nnue_evaluate() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            const TRANSFORMED: usize = 8;
+            const HIDDEN: usize = 4;
+            let mut buf = vec![];
+            write_test_network(&mut buf, TRANSFORMED, HIDDEN, 1);
+            let evaluator = NnueEvaluator::read(&mut buf.as_slice(), TRANSFORMED, HIDDEN).unwrap();
+            assert_eq!(evaluator.architecture(), "test network");
+
+            // Scalar reference computation from the raw file contents.
+            let reference = |pos: &Position| -> Value {
+                let read_i16 = |offset: usize| i64::from(i16::from_le_bytes([buf[offset], buf[offset + 1]]));
+                let read_i32 = |offset: usize| {
+                    i64::from(i32::from_le_bytes([
+                        buf[offset],
+                        buf[offset + 1],
+                        buf[offset + 2],
+                        buf[offset + 3],
+                    ]))
+                };
+                let ft_offset = 4 * 3 + "test network".len() + 4;
+                let ft_weights_offset = ft_offset + TRANSFORMED * 2;
+                let mut features = [vec![], vec![]];
+                for &c in Color::ALL.iter() {
+                    let king_sq = king_square(pos, c);
+                    for sq in Square::ALL.iter() {
+                        let pc = pos.piece_on(*sq);
+                        if pc != Piece::EMPTY && !pc.is_king() {
+                            features[c.0 as usize].push(feature_index(king_sq, board_index(c, pc, *sq)));
+                        }
+                    }
+                    for &owner in Color::ALL.iter() {
+                        for &pt in PieceType::ALL_HAND.iter() {
+                            for num in 1..=pos.hand(owner).num(pt) {
+                                features[c.0 as usize].push(feature_index(king_sq, hand_index(c, owner, pt, num)));
+                            }
+                        }
+                    }
+                }
+                let us = pos.side_to_move();
+                let mut input = vec![];
+                for &c in [us, us.inverse()].iter() {
+                    for j in 0..TRANSFORMED {
+                        let mut sum = read_i16(ft_offset + j * 2);
+                        for &f in features[c.0 as usize].iter() {
+                            sum += read_i16(ft_weights_offset + (f * TRANSFORMED + j) * 2);
+                        }
+                        input.push(sum.max(0).min(127));
+                    }
+                }
+                let mut offset = ft_weights_offset + HALFKP_DIMENSIONS * TRANSFORMED * 2 + 4;
+                for &(output_dimensions, last) in [(HIDDEN, false), (HIDDEN, false), (1, true)].iter() {
+                    let padded = input.len().div_ceil(32) * 32;
+                    let weights_offset = offset + output_dimensions * 4;
+                    let mut output = vec![];
+                    for i in 0..output_dimensions {
+                        let mut sum = read_i32(offset + i * 4);
+                        for (j, &x) in input.iter().enumerate() {
+                            sum += i64::from(buf[weights_offset + i * padded + j] as i8) * x;
+                        }
+                        output.push(if last {
+                            sum
+                        } else {
+                            (sum >> WEIGHT_SCALE_BITS).max(0).min(127)
+                        });
+                    }
+                    offset = weights_offset + output_dimensions * padded;
+                    input = output;
+                }
+                Value((input[0] / i64::from(FV_SCALE)) as i32)
+            };
+
+            let mut pos = Position::from_sfen("l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1").unwrap();
+            let mut stack = vec![Stack::new(); CURRENT_STACK_INDEX + 17];
+            assert_eq!(evaluator.evaluate_at_root(&pos, &mut stack), reference(&pos));
+            // incremental updates with captures, drops, promotions and king moves.
+            for i in 0..16 {
+                let mut mlist = crate::movegen::MoveList::new();
+                mlist.generate::<crate::movegen::LegalAllType>(&pos, 0);
+                let moves = mlist.slice(0).iter().map(|x| x.mv).collect::<Vec<_>>();
+                let m = *moves
+                    .iter()
+                    .find(|m| match i % 4 {
+                        0 => m.is_capture(&pos),
+                        1 => m.is_drop(),
+                        2 => m.is_promotion(),
+                        _ => m.piece_moved_before_move().is_king(),
+                    })
+                    .unwrap_or(&moves[i % moves.len()]);
+                stack[CURRENT_STACK_INDEX + i].current_move = Some(m);
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+                stack[CURRENT_STACK_INDEX + i + 1].static_eval_raw.set_not_evaluated();
+                assert_eq!(evaluator.evaluate(&pos, &mut stack[i + 1..]), reference(&pos));
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
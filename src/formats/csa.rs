@@ -0,0 +1,333 @@
+use crate::movetypes::*;
+use crate::position::*;
+use crate::sfen::*;
+use crate::types::*;
+
+#[derive(Debug)]
+pub enum CsaError {
+    InvalidBoardLine { line: String },
+    InvalidPieceCharactors { chars: String },
+    InvalidSquareCharactors { chars: String },
+    InvalidHandPiece { chars: String },
+    NoPieceToRemove { chars: String },
+    SideToMoveIsNothing,
+    InvalidPosition { err: SfenError },
+    IllegalMove { chars: String },
+}
+
+impl std::fmt::Display for CsaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CsaError::InvalidBoardLine { line } => write!(f, "Invalid board line: {}", line),
+            CsaError::InvalidPieceCharactors { chars } => write!(f, "Invalid piece: {}", chars),
+            CsaError::InvalidSquareCharactors { chars } => write!(f, "Invalid square: {}", chars),
+            CsaError::InvalidHandPiece { chars } => write!(f, "Invalid hand piece: {}", chars),
+            CsaError::NoPieceToRemove { chars } => write!(f, "No piece to remove: {}", chars),
+            CsaError::SideToMoveIsNothing => write!(f, "Side to move is nothing"),
+            CsaError::InvalidPosition { err } => write!(f, "Invalid position: {:?}", err),
+            CsaError::IllegalMove { chars } => write!(f, "Illegal move: {}", chars),
+        }
+    }
+}
+
+// The number of each piece type in a set, used by "00AL".
+const ALL_PIECES: [(PieceType, u32); 7] = [
+    (PieceType::ROOK, 2),
+    (PieceType::BISHOP, 2),
+    (PieceType::GOLD, 4),
+    (PieceType::SILVER, 4),
+    (PieceType::KNIGHT, 4),
+    (PieceType::LANCE, 4),
+    (PieceType::PAWN, 18),
+];
+
+struct CsaBoard {
+    board: [Piece; Square::NUM],
+    hands: [[u32; PieceType::NUM]; Color::NUM],
+}
+
+impl CsaBoard {
+    fn new() -> CsaBoard {
+        CsaBoard {
+            board: [Piece::EMPTY; Square::NUM],
+            hands: [[0; PieceType::NUM]; Color::NUM],
+        }
+    }
+    fn new_hirate() -> CsaBoard {
+        let pos = Position::new();
+        let mut csa_board = CsaBoard::new();
+        for sq in Square::ALL.iter() {
+            csa_board.board[sq.0 as usize] = pos.piece_on(*sq);
+        }
+        csa_board
+    }
+    // The number of pieces of this type not yet placed on the board or in hands.
+    fn rest(&self, pt: PieceType, total: u32) -> u32 {
+        let on_board = self
+            .board
+            .iter()
+            .filter(|&&pc| pc != Piece::EMPTY && !pc.is_king() && PieceType::new(pc).to_demote_if_possible() == pt)
+            .count() as u32;
+        let in_hands = self.hands[Color::BLACK.0 as usize][pt.0 as usize] + self.hands[Color::WHITE.0 as usize][pt.0 as usize];
+        total.saturating_sub(on_board + in_hands)
+    }
+    fn to_sfen(&self, us: Color) -> String {
+        let mut s = "".to_string();
+        for rank in Rank::ALL_FROM_UPPER.iter() {
+            let mut empty_squares = 0;
+            if !s.is_empty() {
+                s += "/";
+            }
+            for file in File::ALL_FROM_LEFT.iter() {
+                let pc = self.board[Square::new(*file, *rank).0 as usize];
+                if pc == Piece::EMPTY {
+                    empty_squares += 1;
+                } else {
+                    if empty_squares != 0 {
+                        s += &empty_squares.to_string();
+                    }
+                    s += pc.to_usi_str();
+                    empty_squares = 0;
+                }
+            }
+            if empty_squares != 0 {
+                s += &empty_squares.to_string();
+            }
+        }
+        s += if us == Color::BLACK { " b " } else { " w " };
+        let mut hand = "".to_string();
+        for c in Color::ALL_FROM_BLACK.iter() {
+            for pt in PieceType::ALL_HAND_FOR_SFEN.iter() {
+                let num = self.hands[c.0 as usize][pt.0 as usize];
+                if 2 <= num {
+                    hand += &num.to_string();
+                }
+                if num != 0 {
+                    hand += Piece::new(*c, *pt).to_usi_str();
+                }
+            }
+        }
+        if hand.is_empty() {
+            hand += "-";
+        }
+        s += &hand;
+        s += " 1";
+        s
+    }
+}
+
+fn parse_square(v: &[char]) -> Option<Square> {
+    let file = File::new_from_csa_char(v[0])?;
+    let rank = Rank::new_from_csa_char(v[1])?;
+    Some(Square::new(file, rank))
+}
+
+fn parse_color(c: char) -> Option<Color> {
+    match c {
+        '+' => Some(Color::BLACK),
+        '-' => Some(Color::WHITE),
+        _ => None,
+    }
+}
+
+// Parses the position part of a CSA file. Header lines ("V", "N", "$", "'") and the move lines are skipped.
+// The board is given by "PI" (with optional pieces removed for handicap games), "P1".."P9" or "P+"/"P-" lines,
+// and the first "+" or "-" line is the side to move.
+pub fn parse_csa_position(s: &str) -> Result<Position, CsaError> {
+    let mut csa_board = CsaBoard::new();
+    let mut side_to_move = None;
+    for line in s.lines().flat_map(|line| line.split(',')) {
+        let line = line.trim_end();
+        let v: Vec<char> = line.chars().collect();
+        if side_to_move.is_some() {
+            break;
+        }
+        match v.as_slice() {
+            ['+'] | ['-'] => side_to_move = parse_color(v[0]),
+            ['P', 'I', ..] => {
+                csa_board = CsaBoard::new_hirate();
+                let chunks = v[2..].chunks_exact(4);
+                if !chunks.remainder().is_empty() {
+                    return Err(CsaError::InvalidBoardLine { line: line.to_string() });
+                }
+                for chunk in chunks {
+                    let chars = chunk.iter().collect::<String>();
+                    let sq = parse_square(chunk).ok_or_else(|| CsaError::InvalidSquareCharactors { chars: chars.clone() })?;
+                    let pt = PieceType::new_from_csa_str(&chunk[2..].iter().collect::<String>())
+                        .ok_or_else(|| CsaError::InvalidPieceCharactors { chars: chars.clone() })?;
+                    let pc = csa_board.board[sq.0 as usize];
+                    if pc == Piece::EMPTY || PieceType::new(pc) != pt {
+                        return Err(CsaError::NoPieceToRemove { chars });
+                    }
+                    csa_board.board[sq.0 as usize] = Piece::EMPTY;
+                }
+            }
+            ['P', c @ '1'..='9', ..] => {
+                let rank = Rank::new_from_csa_char(*c).unwrap();
+                if v.len() > 2 + 3 * File::NUM {
+                    return Err(CsaError::InvalidBoardLine { line: line.to_string() });
+                }
+                let mut cells = v[2..].to_vec();
+                cells.resize(3 * File::NUM, ' ');
+                for (file, cell) in File::ALL_FROM_LEFT.iter().zip(cells.chunks(3)) {
+                    let sq = Square::new(*file, rank);
+                    let chars = cell.iter().collect::<String>();
+                    csa_board.board[sq.0 as usize] = match chars.as_str() {
+                        " * " | "   " => Piece::EMPTY,
+                        _ => {
+                            let c =
+                                parse_color(cell[0]).ok_or_else(|| CsaError::InvalidPieceCharactors { chars: chars.clone() })?;
+                            let pt = PieceType::new_from_csa_str(&cell[1..].iter().collect::<String>())
+                                .ok_or_else(|| CsaError::InvalidPieceCharactors { chars: chars.clone() })?;
+                            Piece::new(c, pt)
+                        }
+                    };
+                }
+            }
+            ['P', c @ '+', ..] | ['P', c @ '-', ..] => {
+                let us = parse_color(*c).unwrap();
+                let chunks = v[2..].chunks_exact(4);
+                if !chunks.remainder().is_empty() {
+                    return Err(CsaError::InvalidBoardLine { line: line.to_string() });
+                }
+                for chunk in chunks {
+                    let chars = chunk.iter().collect::<String>();
+                    let pt_str = chunk[2..].iter().collect::<String>();
+                    if chunk[0] == '0' && chunk[1] == '0' {
+                        if pt_str == "AL" {
+                            for (pt, total) in ALL_PIECES.iter() {
+                                let rest = csa_board.rest(*pt, *total);
+                                csa_board.hands[us.0 as usize][pt.0 as usize] += rest;
+                            }
+                            continue;
+                        }
+                        match PieceType::new_from_csa_str(&pt_str) {
+                            Some(pt) if ALL_PIECES.iter().any(|(hand_pt, _)| *hand_pt == pt) => {
+                                csa_board.hands[us.0 as usize][pt.0 as usize] += 1;
+                            }
+                            _ => return Err(CsaError::InvalidHandPiece { chars }),
+                        }
+                    } else {
+                        let sq = parse_square(chunk).ok_or_else(|| CsaError::InvalidSquareCharactors { chars: chars.clone() })?;
+                        let pt = PieceType::new_from_csa_str(&pt_str).ok_or(CsaError::InvalidPieceCharactors { chars })?;
+                        csa_board.board[sq.0 as usize] = Piece::new(us, pt);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let us = side_to_move.ok_or(CsaError::SideToMoveIsNothing)?;
+    Position::from_sfen(&csa_board.to_sfen(us)).map_err(|err| CsaError::InvalidPosition { err })
+}
+
+// Move in CSA notation with the side to move such as "+7776FU".
+pub fn move_to_csa(m: Move, pos: &Position) -> String {
+    let c = if pos.side_to_move() == Color::BLACK { "+" } else { "-" };
+    c.to_string() + &m.to_csa_string(pos)
+}
+
+// The piece type in CSA notation is the one after the move, so "+2423TO" is a promotion of the pawn on 2d.
+// The leading "+" or "-" is optional, but it must be the side to move if exists.
+pub fn move_from_csa(s: &str, pos: &Position) -> Result<Move, CsaError> {
+    let illegal = || CsaError::IllegalMove { chars: s.to_string() };
+    let body = match s.chars().next().and_then(parse_color) {
+        Some(c) if c != pos.side_to_move() => return Err(illegal()),
+        Some(_) => &s[1..],
+        None => s,
+    };
+    if body.chars().count() != 6 {
+        return Err(illegal());
+    }
+    Move::new_from_csa_str(body, pos).ok_or_else(illegal)
+}
+
+#[test]
+fn test_parse_csa_position() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let csa = "V2.2
+N+sente
+N-gote
+$EVENT:test
+'comment
+PI
++
++7776FU
+-3334FU
+";
+            let pos = parse_csa_position(csa).unwrap();
+            assert_eq!(pos.to_sfen(), START_SFEN);
+
+            // 2-piece handicap.
+            let pos = parse_csa_position("PI82HI22KA\n-\n").unwrap();
+            assert_eq!(pos.to_sfen(), "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1");
+
+            let csa = "P1 *  *  *  *  *  *  *  * -OU
+P2 *  *  *  *  *  *  *  *  * 
+P3 *  *  *  *  *  *  * +TO * 
+P4
+P5
+P6
+P7
+P8
+P9+OU
+P+00HI00FU00FU
+P-00AL
+-
+";
+            let pos = parse_csa_position(csa).unwrap();
+            assert_eq!(pos.to_sfen(), "8k/9/7+P1/9/9/9/9/9/K8 w R2Pr2b4g4s4n4l15p 1");
+
+            assert!(matches!(parse_csa_position("PI\n"), Err(CsaError::SideToMoveIsNothing)));
+            assert!(matches!(
+                parse_csa_position("PI11FU\n+\n"),
+                Err(CsaError::NoPieceToRemove { .. })
+            ));
+            assert!(matches!(
+                parse_csa_position("P+00OU\n+\n"),
+                Err(CsaError::InvalidHandPiece { .. })
+            ));
+            assert!(matches!(
+                parse_csa_position("P1-OU\n+\n"),
+                Err(CsaError::InvalidPosition { .. })
+            ));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_csa_move() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::new();
+            let m = move_from_csa("+7776FU", &pos).unwrap();
+            assert_eq!(m.to_usi_string(), "7g7f");
+            assert_eq!(move_to_csa(m, &pos), "+7776FU");
+            assert!(move_from_csa("-3334FU", &pos).is_err());
+            assert!(move_from_csa("+7775FU", &pos).is_err());
+
+            let pos = Position::from_sfen("4k4/9/9/7P1/9/9/9/9/4K4 b - 1").unwrap();
+            let m = move_from_csa("+2423TO", &pos).unwrap();
+            assert!(m.is_promotion());
+            assert_eq!(m.to_usi_string(), "2d2c+");
+            assert_eq!(move_to_csa(m, &pos), "+2423TO");
+            let m = move_from_csa("2423FU", &pos).unwrap();
+            assert!(!m.is_promotion());
+            assert_eq!(move_to_csa(m, &pos), "+2423FU");
+
+            let pos = Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 w G 1").unwrap();
+            assert!(move_from_csa("-0055KI", &pos).is_err());
+            let pos = Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 w g 1").unwrap();
+            let m = move_from_csa("-0055KI", &pos).unwrap();
+            assert_eq!(m.to_usi_string(), "G*5e");
+            assert_eq!(move_to_csa(m, &pos), "-0055KI");
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
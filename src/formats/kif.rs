@@ -0,0 +1,305 @@
+use crate::movetypes::*;
+use crate::position::*;
+use crate::sfen::*;
+use crate::types::*;
+
+#[derive(Debug)]
+pub enum KifError {
+    UnsupportedHandicap { chars: String },
+    InvalidMove { index: usize, chars: String },
+    IllegalMove { index: usize, chars: String },
+    AmbiguousMove { index: usize, chars: String },
+}
+
+impl std::fmt::Display for KifError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            KifError::UnsupportedHandicap { chars } => write!(f, "Unsupported handicap: {}", chars),
+            KifError::InvalidMove { index, chars } => write!(f, "Invalid move at {}: {}", index, chars),
+            KifError::IllegalMove { index, chars } => write!(f, "Illegal move at {}: {}", index, chars),
+            KifError::AmbiguousMove { index, chars } => write!(f, "Ambiguous move at {}: {}", index, chars),
+        }
+    }
+}
+
+const HANDICAPS: [(&str, &str); 5] = [
+    ("平手", START_SFEN),
+    ("香落ち", "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("角落ち", "lnsgkgsnl/1r7/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("飛車落ち", "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("二枚落ち", "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+];
+
+// The game ends at these words. The moves after them are ignored.
+const TERMINATIONS: [&str; 11] = [
+    "投了",
+    "中断",
+    "千日手",
+    "詰み",
+    "持将棋",
+    "切れ負け",
+    "反則勝ち",
+    "反則負け",
+    "入玉勝ち",
+    "不戦勝",
+    "不戦敗",
+];
+
+// Two characters names must be checked before one character names.
+const PIECE_NAMES: [(&str, PieceType); 17] = [
+    ("成香", PieceType::PRO_LANCE),
+    ("成桂", PieceType::PRO_KNIGHT),
+    ("成銀", PieceType::PRO_SILVER),
+    ("歩", PieceType::PAWN),
+    ("香", PieceType::LANCE),
+    ("桂", PieceType::KNIGHT),
+    ("銀", PieceType::SILVER),
+    ("金", PieceType::GOLD),
+    ("角", PieceType::BISHOP),
+    ("飛", PieceType::ROOK),
+    ("玉", PieceType::KING),
+    ("王", PieceType::KING),
+    ("と", PieceType::PRO_PAWN),
+    ("馬", PieceType::HORSE),
+    ("龍", PieceType::DRAGON),
+    ("竜", PieceType::DRAGON),
+    ("杏", PieceType::PRO_LANCE),
+];
+
+// The remaining one characters names of the promoted pieces.
+const PROMOTED_NAMES: [(char, PieceType); 2] = [('圭', PieceType::PRO_KNIGHT), ('全', PieceType::PRO_SILVER)];
+
+// A move in the movelist such as "７六歩(77)", "同　銀(31)", "２二角成" or "４五角打".
+struct KifMove {
+    to: Option<Square>, // None if "同".
+    pt: PieceType,      // piece type before the move.
+    promote: Option<bool>,
+    is_drop: bool,
+    from: Option<Square>,
+}
+
+fn parse_kif_move(s: &str) -> Option<KifMove> {
+    let mut s = s;
+    let to = if let Some(rest) = s.strip_prefix('同') {
+        s = rest.trim_start();
+        None
+    } else {
+        let mut chars = s.chars();
+        let (file_char, rank_char) = (chars.next()?, chars.next()?);
+        let file_idx = "９８７６５４３２１".chars().position(|c| c == file_char)?;
+        let rank_idx = "一二三四五六七八九".chars().position(|c| c == rank_char)?;
+        s = chars.as_str();
+        Some(Square::new(File::ALL_FROM_LEFT[file_idx], Rank::ALL_FROM_UPPER[rank_idx]))
+    };
+    let pt = if let Some((name, pt)) = PIECE_NAMES.iter().find(|(name, _)| s.starts_with(name)) {
+        s = &s[name.len()..];
+        *pt
+    } else {
+        let c = s.chars().next()?;
+        let (_, pt) = PROMOTED_NAMES.iter().find(|(name, _)| *name == c)?;
+        s = &s[c.len_utf8()..];
+        *pt
+    };
+    let mut promote = None;
+    let mut is_drop = false;
+    if let Some(rest) = s.strip_prefix("不成") {
+        s = rest;
+        promote = Some(false);
+    } else if let Some(rest) = s.strip_prefix('成') {
+        s = rest;
+        promote = Some(true);
+    } else if let Some(rest) = s.strip_prefix('打') {
+        s = rest;
+        is_drop = true;
+    }
+    let from = if let Some(rest) = s.strip_prefix('(') {
+        let v: Vec<char> = rest.chars().take(3).collect();
+        if v.len() != 3 || v[2] != ')' {
+            return None;
+        }
+        let file = File::new_from_usi_char(v[0])?;
+        let rank = Rank::new_from_csa_char(v[1])?;
+        Some(Square::new(file, rank))
+    } else {
+        None
+    };
+    Some(KifMove {
+        to,
+        pt,
+        promote,
+        is_drop,
+        from,
+    })
+}
+
+fn resolve_kif_move(kif_move: &KifMove, to: Square, pos: &Position) -> Result<Move, bool> {
+    let us = pos.side_to_move();
+    let is_legal = |m: Move| pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m);
+    let drop = || {
+        let m = Move::new_drop(Piece::new(us, kif_move.pt), to);
+        if kif_move.promote.is_none() && pos.hand(us).num(kif_move.pt) != 0 && is_legal(m) {
+            Ok(m)
+        } else {
+            Err(false)
+        }
+    };
+    if kif_move.is_drop {
+        return drop();
+    }
+    if let Some(from) = kif_move.from {
+        let pc = pos.piece_on(from);
+        if pc == Piece::EMPTY || PieceType::new(pc) != kif_move.pt {
+            return Err(false);
+        }
+        let m = if kif_move.promote == Some(true) {
+            Move::new_promote(from, to, pc)
+        } else {
+            Move::new_unpromote(from, to, pc)
+        };
+        return if is_legal(m) { Ok(m) } else { Err(false) };
+    }
+    // Without the from square, the move is resolved by the piece type.
+    // Err(true) means ambiguous. The relative position words such as "右" or "上" aren't supported.
+    let candidates = pos
+        .legal_moves()
+        .into_iter()
+        .filter(|m| {
+            !m.is_drop()
+                && m.to() == to
+                && PieceType::new(m.piece_moved_before_move()) == kif_move.pt
+                && m.is_promotion() == (kif_move.promote == Some(true))
+        })
+        .collect::<Vec<_>>();
+    match candidates.len() {
+        0 => drop(),
+        1 => Ok(candidates[0]),
+        _ => Err(true),
+    }
+}
+
+// Parses a KIF game record and returns the initial position and the moves of the main line.
+// Each move is resolved against the position after the previous moves, so the moves are guaranteed to be legal.
+pub fn parse_game(s: &str) -> Result<(Position, Vec<Move>), KifError> {
+    let mut sfen = START_SFEN;
+    let mut pos = None;
+    let mut moves = vec![];
+    let mut prev_to = None;
+    for line in s.lines() {
+        let line = line.trim();
+        if let Some(handicap) = line.strip_prefix("手合割：") {
+            let handicap = handicap.trim();
+            sfen = HANDICAPS
+                .iter()
+                .find(|(name, _)| *name == handicap)
+                .ok_or_else(|| KifError::UnsupportedHandicap {
+                    chars: handicap.to_string(),
+                })?
+                .1;
+            continue;
+        }
+        if line.starts_with("変化") {
+            break;
+        }
+        if !line.starts_with(|c: char| c.is_ascii_digit()) {
+            // Header, comment or the other non-move lines.
+            continue;
+        }
+        let pos = pos.get_or_insert_with(|| Position::from_sfen(sfen).unwrap());
+        let index = moves.len() + 1;
+        let text = line.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start();
+        if TERMINATIONS.iter().any(|t| text.starts_with(t)) {
+            break;
+        }
+        let invalid = || KifError::InvalidMove {
+            index,
+            chars: text.to_string(),
+        };
+        let kif_move = parse_kif_move(text).ok_or_else(invalid)?;
+        let to = kif_move.to.or(prev_to).ok_or_else(invalid)?;
+        let m = resolve_kif_move(&kif_move, to, pos).map_err(|is_ambiguous| {
+            let chars = text.to_string();
+            if is_ambiguous {
+                KifError::AmbiguousMove { index, chars }
+            } else {
+                KifError::IllegalMove { index, chars }
+            }
+        })?;
+        let gives_check = pos.gives_check(m);
+        pos.do_move(m, gives_check);
+        moves.push(m);
+        prev_to = Some(to);
+    }
+    Ok((Position::from_sfen(sfen).unwrap(), moves))
+}
+
+#[test]
+fn test_parse_kif_game() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let kif = "# ---- Kifu for Windows 棋譜ファイル ----
+開始日時：2020/01/01
+手合割：平手　　
+先手：sente
+後手：gote
+手数----指手---------消費時間--
+   1 ７六歩(77)   ( 0:01/00:00:01)
+   2 ３四歩(33)   ( 0:01/00:00:01)
+*comment
+   3 ２二角成(88)   ( 0:01/00:00:02)
+   4 同　銀(31)   ( 0:01/00:00:02)
+   5 ４五角打   ( 0:01/00:00:03)
+   6 投了
+まで5手で先手の勝ち
+";
+            let (mut pos, moves) = parse_game(kif).unwrap();
+            assert_eq!(pos.to_sfen(), START_SFEN);
+            assert_eq!(
+                moves.iter().map(|m| m.to_usi_string()).collect::<Vec<_>>(),
+                vec!["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e"]
+            );
+            for &m in moves.iter() {
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+            }
+            assert_eq!(
+                pos.to_sfen(),
+                "lnsgkg1nl/1r5s1/pppppp1pp/6p2/5B3/2P6/PP1PPPPPP/7R1/LNSGKGSNL w b 6"
+            );
+
+            // Without the from squares.
+            let kif = "1 ７六歩\n2 ３四歩\n3 ２二角不成\n4 同銀\n5 ４五角\n6 ８四歩\n7 ６三角成\n";
+            let (_, moves) = parse_game(kif).unwrap();
+            assert_eq!(
+                moves.iter().map(|m| m.to_usi_string()).collect::<Vec<_>>(),
+                vec!["7g7f", "3c3d", "8h2b", "3a2b", "B*4e", "8c8d", "4e6c+"]
+            );
+            let (_, moves) = parse_game("手合割：角落ち\n1 ３四歩(33)\n").unwrap();
+            assert_eq!(moves[0].to_usi_string(), "3c3d");
+
+            assert!(matches!(
+                parse_game("1 ７六歩\n2 ７五歩\n"),
+                Err(KifError::IllegalMove { index: 2, .. })
+            ));
+            assert!(matches!(
+                parse_game("1 ７六歩\n2 ３四歩\n3 ９九桂\n"),
+                Err(KifError::IllegalMove { index: 3, .. })
+            ));
+            assert!(matches!(
+                parse_game("1 同　歩\n"),
+                Err(KifError::InvalidMove { index: 1, .. })
+            ));
+            assert!(matches!(parse_game("1 ７六x\n"), Err(KifError::InvalidMove { index: 1, .. })));
+            assert!(matches!(
+                parse_game("1 ５八金\n"),
+                Err(KifError::AmbiguousMove { index: 1, .. })
+            ));
+            assert!(matches!(
+                parse_game("手合割：八枚落ち\n"),
+                Err(KifError::UnsupportedHandicap { .. })
+            ));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -0,0 +1,2 @@
+pub mod csa;
+pub mod kif;
@@ -1,14 +1,11 @@
 use crate::bitboard::*;
-#[cfg(feature = "kppt")]
-use crate::evaluate::kppt::*;
 use crate::file_to_vec::*;
 use crate::huffman_code::*;
 use crate::movegen::*;
 use crate::movetypes::*;
 use crate::position::*;
 use crate::search::*;
-use crate::thread::*;
-use crate::tt::*;
+use crate::searcher::Searcher;
 use crate::types::*;
 use crate::usi::*;
 use crate::usioption::*;
@@ -147,47 +144,20 @@ pub fn generate_teachers(args: &[&str]) {
         let count_teachers = count_teachers.clone();
         let worker = move || {
             let mut rng = rand::thread_rng();
-            let mut thread_pool = ThreadPool::new();
-            let mut tt = TranspositionTable::new();
-            #[cfg(feature = "kppt")]
-            let mut ehash = EvalHash::new(); // todo: All threads use same ehash.
-            let mut breadcrumbs = Breadcrumbs::new();
-            let mut reductions = Reductions::new(1);
-            thread_pool.set(
-                1,
-                &mut tt,
-                #[cfg(feature = "kppt")]
-                &mut ehash,
-                &mut breadcrumbs,
-                &mut reductions,
-            );
+            let mut searcher = Searcher::new_unloaded(UsiOptions::new());
             let mut is_ready = false;
-            let usi_options = {
-                let mut u = UsiOptions::new();
-                [
-                    (UsiOptions::MULTI_PV, "1"),
-                    (UsiOptions::THREADS, "1"),
-                    (UsiOptions::USI_HASH, "1024"),
-                    #[cfg(feature = "kppt")]
-                    (UsiOptions::EVAL_HASH, "256"),
-                    (UsiOptions::BOOK_ENABLE, "false"),
-                ]
-                .iter()
-                .for_each(|(name, value)| {
-                    setoption(
-                        &["name", name, "value", value],
-                        &mut u,
-                        &mut thread_pool,
-                        &mut tt,
-                        #[cfg(feature = "kppt")]
-                        &mut ehash,
-                        &mut breadcrumbs,
-                        &mut reductions,
-                        &mut is_ready,
-                    );
-                });
-                u
-            };
+            [
+                (UsiOptions::MULTI_PV, "1"),
+                (UsiOptions::THREADS, "1"),
+                (UsiOptions::USI_HASH, "1024"),
+                #[cfg(feature = "kppt")]
+                (UsiOptions::EVAL_HASH, "256"),
+                (UsiOptions::BOOK_ENABLE, "false"),
+            ]
+            .iter()
+            .for_each(|(name, value)| {
+                setoption(&["name", name, "value", value], &mut searcher, &mut is_ready);
+            });
             let limits = {
                 let mut l = LimitsType::new();
                 l.start_time = Some(std::time::Instant::now());
@@ -230,9 +200,16 @@ pub fn generate_teachers(args: &[&str]) {
                         });
                         break;
                     }
-                    thread_pool.start_thinking(&pos, &mut tt, limits.clone(), &usi_options, ponder_mode, hide_all_output);
-                    thread_pool.wait_for_search_finished();
-                    let rm = thread_pool.last_best_root_move.lock().unwrap();
+                    searcher.thread_pool.start_thinking(
+                        &pos,
+                        &mut searcher.tt,
+                        limits.clone(),
+                        &searcher.usi_options,
+                        ponder_mode,
+                        hide_all_output,
+                    );
+                    searcher.thread_pool.wait_for_search_finished();
+                    let rm = searcher.thread_pool.last_best_root_move.lock().unwrap();
                     let rm = rm.as_ref().unwrap();
                     const RESIGN_THRESH: Value = Value(4000);
                     if rm.score.abs() <= RESIGN_THRESH {
@@ -8,21 +8,28 @@ extern crate custom_derive;
 extern crate derive_more;
 #[macro_use]
 extern crate enum_derive;
+#[macro_use]
+mod usi_log;
 mod authors;
 mod bitboard;
 mod book;
+mod endgame;
 mod engine_name;
 mod evaluate;
 mod file_to_vec;
+pub mod formats;
 mod hand;
 mod huffman_code;
 mod learn;
+mod mate;
 mod movegen;
 mod movepick;
 mod movetypes;
 mod piecevalue;
 mod position;
 mod search;
+pub mod searcher;
+mod selfplay;
 mod sfen;
 pub mod stack_size;
 mod thread;
@@ -0,0 +1,222 @@
+use crate::movegen::*;
+use crate::movetypes::*;
+use crate::position::*;
+use crate::types::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug)]
+pub enum MateResult {
+    Mate(Vec<Move>),
+    NoMate,
+    Timeout,
+}
+
+impl MateResult {
+    pub fn to_usi_string(&self) -> String {
+        match self {
+            MateResult::Mate(pv) => format!(
+                "checkmate {}",
+                pv.iter().map(|m| m.to_usi_string()).collect::<Vec<_>>().join(" ")
+            ),
+            MateResult::NoMate => "checkmate nomate".to_string(),
+            MateResult::Timeout => "checkmate timeout".to_string(),
+        }
+    }
+}
+
+// Tsume shogi solver for "go mate".
+// The attacker (side to move at the root) plays only checking moves, and the defender plays all evasions.
+pub struct MateSearcher<'a> {
+    stop: &'a AtomicBool,
+    start_time: std::time::Instant,
+    time_limit: Option<std::time::Duration>,
+    calls_count: i32,
+    timed_out: bool,
+    depth_cut: bool,     // true if the depth limit cut off some branches in the current iteration.
+    path_keys: Vec<Key>, // for detecting repetitions on the current path.
+}
+
+impl<'a> MateSearcher<'a> {
+    pub fn new(
+        stop: &'a AtomicBool,
+        start_time: std::time::Instant,
+        time_limit: Option<std::time::Duration>,
+    ) -> MateSearcher<'a> {
+        MateSearcher {
+            stop,
+            start_time,
+            time_limit,
+            calls_count: 0,
+            timed_out: false,
+            depth_cut: false,
+            path_keys: vec![],
+        }
+    }
+    // Iterative deepening on odd plies. The first found mate is the shortest one.
+    // If no branch was cut off by the depth limit, all the checking sequences are exhausted and there is no mate.
+    pub fn search(&mut self, pos: &mut Position) -> MateResult {
+        for depth in (1..MAX_PLY).step_by(2) {
+            self.depth_cut = false;
+            let mut pv = vec![];
+            if self.attack(pos, depth, &mut pv) {
+                return MateResult::Mate(pv);
+            }
+            if self.is_stopped() {
+                return MateResult::Timeout;
+            }
+            if !self.depth_cut {
+                return MateResult::NoMate;
+            }
+        }
+        MateResult::NoMate
+    }
+    fn is_stopped(&mut self) -> bool {
+        if self.timed_out {
+            return true;
+        }
+        self.calls_count -= 1;
+        if self.calls_count > 0 {
+            return false;
+        }
+        self.calls_count = 1024;
+        if self.stop.load(Ordering::Relaxed)
+            || (self.time_limit.is_some() && self.start_time.elapsed() >= self.time_limit.unwrap())
+        {
+            self.timed_out = true;
+        }
+        self.timed_out
+    }
+    fn is_repetition_on_path(&self, key: Key) -> bool {
+        // The same side to move appears every 2 plies.
+        self.path_keys.iter().rev().skip(1).step_by(2).any(|&k| k == key)
+    }
+    // true if the side to move can mate within depth plies.
+    fn attack(&mut self, pos: &mut Position, depth: i32, pv: &mut Vec<Move>) -> bool {
+        debug_assert!(depth % 2 == 1);
+        if self.is_stopped() {
+            return false;
+        }
+        let mut mlist = MoveList::new();
+        mlist.generate_checks(pos, 0);
+        for i in 0..mlist.size {
+            let m = mlist.ext_moves[i].mv;
+            pos.do_move(m, true);
+            let key = pos.key();
+            let mated = if self.is_repetition_on_path(key) {
+                false
+            } else {
+                self.path_keys.push(key);
+                let mut child_pv = vec![];
+                let mated = self.defend(pos, depth - 1, &mut child_pv);
+                self.path_keys.pop();
+                if mated {
+                    pv.clear();
+                    pv.push(m);
+                    pv.extend(child_pv);
+                }
+                mated
+            };
+            pos.undo_move(m);
+            if mated {
+                return true;
+            }
+            if self.is_stopped() {
+                return false;
+            }
+        }
+        false
+    }
+    // true if the side to move is mated within depth plies.
+    fn defend(&mut self, pos: &mut Position, depth: i32, pv: &mut Vec<Move>) -> bool {
+        debug_assert!(pos.in_check());
+        let mut mlist = MoveList::new();
+        mlist.generate::<LegalAllType>(pos, 0);
+        if mlist.size == 0 {
+            // Drop pawn mate isn't generated at attack(), so this is a legal checkmate.
+            return true;
+        }
+        if depth == 0 {
+            self.depth_cut = true;
+            return false;
+        }
+        let mut longest_pv = vec![];
+        for i in 0..mlist.size {
+            let m = mlist.ext_moves[i].mv;
+            let gives_check = pos.gives_check(m);
+            pos.do_move(m, gives_check);
+            let key = pos.key();
+            let mated = if self.is_repetition_on_path(key) {
+                false
+            } else {
+                self.path_keys.push(key);
+                let mut child_pv = vec![];
+                let mated = self.attack(pos, depth - 1, &mut child_pv);
+                self.path_keys.pop();
+                if mated && longest_pv.len() <= child_pv.len() {
+                    longest_pv.clear();
+                    longest_pv.push(m);
+                    longest_pv.extend(child_pv);
+                }
+                mated
+            };
+            pos.undo_move(m);
+            if !mated {
+                return false;
+            }
+        }
+        *pv = longest_pv;
+        true
+    }
+}
+
+#[test]
+fn test_mate_search() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let stop = AtomicBool::new(false);
+            let solve = |sfen: &str| {
+                let mut pos = Position::from_sfen(sfen).unwrap();
+                let mut searcher = MateSearcher::new(&stop, std::time::Instant::now(), None);
+                searcher.search(&mut pos)
+            };
+
+            let assert_mate = |sfen: &str, result: &MateResult, plies: usize| match result {
+                MateResult::Mate(pv) => {
+                    assert_eq!(pv.len(), plies);
+                    let mut pos = Position::from_sfen(sfen).unwrap();
+                    for &m in pv.iter() {
+                        assert!(pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m));
+                        let gives_check = pos.gives_check(m);
+                        pos.do_move(m, gives_check);
+                    }
+                    let mut mlist = MoveList::new();
+                    mlist.generate::<LegalAllType>(&pos, 0);
+                    assert!(pos.in_check());
+                    assert_eq!(mlist.size, 0);
+                }
+                _ => panic!("{} must be mate.", sfen),
+            };
+
+            // mate in 1.
+            let sfen = "7nk/9/7K1/9/9/9/9/9/9 b G 1";
+            assert_mate(sfen, &solve(sfen), 1);
+
+            // mate in 3. R*1i G2a1b S*2b
+            let sfen = "7gk/9/7P1/9/9/9/9/9/K8 b RS 1";
+            assert_mate(sfen, &solve(sfen), 3);
+
+            // drop pawn mate is the only check. it's illegal.
+            let result = solve("7nk/9/7K1/9/9/9/9/9/9 b P 1");
+            assert_eq!(result.to_usi_string(), "checkmate nomate");
+
+            // timeout.
+            let stop = AtomicBool::new(true);
+            let mut pos = Position::from_sfen("7nk/9/7K1/9/9/9/9/9/9 b G 1").unwrap();
+            let mut searcher = MateSearcher::new(&stop, std::time::Instant::now(), None);
+            assert_eq!(searcher.search(&mut pos).to_usi_string(), "checkmate timeout");
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -86,6 +86,8 @@ impl AllowMovesTrait for LegalAllType {
     const ALLOW_PSEUDO_LEGAL: bool = false;
 }
 
+// The fixed size array for the maximum number of the moves. The move generation doesn't allocate,
+// and each node of the search has its own list in the move picker on the stack.
 pub struct MoveList {
     pub ext_moves: [ExtMove; ExtMove::MAX_LEGAL_MOVES],
     pub size: usize,
@@ -239,12 +241,7 @@ impl MoveList {
         if hand.exist(PieceType::PAWN) {
             // avoid two pawns.
             let rank = Rank::new_from_color_and_rank_as_black(us, RankAsBlack::RANK1);
-            let mut to_bb = *target & !Bitboard::rank_mask(rank);
-            let pawns_bb = pos.pieces_cp(us, PieceType::PAWN);
-            for pawn_sq in pawns_bb {
-                let pawn_file = File::new(pawn_sq);
-                to_bb &= !Bitboard::file_mask(pawn_file);
-            }
+            let mut to_bb = *target & !Bitboard::rank_mask(rank) & !pos.pawn_files_bb(us);
 
             // avoid drop pawn mate.
             let them = us.inverse();
@@ -716,6 +713,20 @@ impl MoveList {
             }
         }
     }
+    // legal checking moves including unpromotions. drop pawn mate is excluded.
+    pub fn generate_checks(&mut self, pos: &Position, current_size: usize) {
+        self.generate_legals::<True>(pos, current_size);
+        let mut i = current_size;
+        while i != self.size {
+            let m = self.ext_moves[i].mv;
+            if pos.gives_check(m) {
+                i += 1;
+            } else {
+                self.size -= 1;
+                self.ext_moves[i].mv = self.ext_moves[self.size].mv;
+            }
+        }
+    }
     pub fn generate<AMT: AllowMovesTrait>(&mut self, pos: &Position, current_size: usize) {
         if AMT::LEGALS {
             if AMT::ALL {
@@ -782,7 +793,7 @@ fn test_move_piece_moved() {
 fn test_generate_for_piece() {
     let sfen = "4k4/9/9/9/9/9/4l4/4bp3/4KP3 b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let us = pos.side_to_move();
     let target = pos.pieces_c(us.inverse());
     mlist.generate_for_piece::<KingType, CaptureOrPawnPromotionsType>(&pos, &target);
@@ -792,7 +803,7 @@ fn test_generate_for_piece() {
 
     let sfen = "4k4/9/9/9/9/9/4l4/4bp3/4KP3 b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let target = pos.empty_bb();
     mlist.generate_for_piece::<KingType, QuietsWithoutPawnPromotionsType>(&pos, &target);
     assert_eq!(mlist.size, 2);
@@ -801,7 +812,7 @@ fn test_generate_for_piece() {
 
     let sfen = "4k4/7p1/9/9/4BB3/5P3/9/9/s3K4 b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let target = pos.pieces_c(us.inverse());
     mlist.generate_for_piece::<BishopType, CaptureOrPawnPromotionsType>(&pos, &target);
     assert_eq!(mlist.size, 2);
@@ -811,7 +822,7 @@ fn test_generate_for_piece() {
     let sfen = "4k4/7p1/9/9/4BB3/5P3/9/9/s3K4 b - 1";
     let mut mlist = MoveList::new();
     let target = pos.empty_bb();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     mlist.generate_for_piece::<BishopType, QuietsWithoutPawnPromotionsType>(&pos, &target);
     assert_eq!(mlist.size, 23);
     assert!(mlist.contains(Move::new_promote(Square::SQ55, Square::SQ33, Piece::B_BISHOP)));
@@ -840,7 +851,7 @@ fn test_generate_for_piece() {
 
     let sfen = "4k4/4l4/9/9/5B3/9/9/9/4K4 b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let target = Bitboard::between_mask(Square::SQ52, Square::SQ59) | Bitboard::square_mask(Square::SQ52);
     mlist.generate_for_piece::<BishopType, EvasionsType>(&pos, &target);
     assert_eq!(mlist.size, 2);
@@ -856,7 +867,7 @@ fn test_generate_for_piece() {
     ];
     for &(sfen, pc) in sfens.iter() {
         let mut mlist = MoveList::new();
-        let pos = Position::new_from_sfen(sfen).unwrap();
+        let pos = Position::from_sfen(sfen).unwrap();
         let us = pos.side_to_move();
         let target = pos.pieces_c(us.inverse());
         mlist.generate_for_piece::<GoldType, CaptureOrPawnPromotionsType>(&pos, &target);
@@ -873,7 +884,7 @@ fn test_generate_for_piece() {
     ];
     for &(sfen, pc) in sfens.iter() {
         let mut mlist = MoveList::new();
-        let pos = Position::new_from_sfen(sfen).unwrap();
+        let pos = Position::from_sfen(sfen).unwrap();
         let target = pos.empty_bb();
         mlist.generate_for_piece::<GoldType, QuietsWithoutPawnPromotionsType>(&pos, &target);
         assert_eq!(mlist.size, 10);
@@ -891,7 +902,7 @@ fn test_generate_for_piece() {
 
     let sfen = "8k/1pP6/1S7/5S3/9/9/S8/9/8K b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let us = pos.side_to_move();
     let target = pos.pieces_c(us.inverse());
     mlist.generate_for_piece::<SilverType, CaptureOrPawnPromotionsType>(&pos, &target);
@@ -901,7 +912,7 @@ fn test_generate_for_piece() {
 
     let sfen = "8k/1pP6/1S7/5S3/9/9/S8/9/8K b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let target = pos.empty_bb();
     mlist.generate_for_piece::<SilverType, QuietsWithoutPawnPromotionsType>(&pos, &target);
     assert_eq!(mlist.size, 17);
@@ -925,7 +936,7 @@ fn test_generate_for_piece() {
 
     let sfen = "p7k/1p7/1Np6/2N6/3N5/9/9/9/8K b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let us = pos.side_to_move();
     let target = pos.pieces_c(us.inverse());
     mlist.generate_for_piece::<KnightType, CaptureOrPawnPromotionsType>(&pos, &target);
@@ -937,7 +948,7 @@ fn test_generate_for_piece() {
 
     let sfen = "8k/9/9/9/3n5/2n6/1nP6/1P7/P7K w - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let us = pos.side_to_move();
     let target = pos.pieces_c(us.inverse());
     mlist.generate_for_piece::<KnightType, CaptureOrPawnPromotionsType>(&pos, &target);
@@ -949,7 +960,7 @@ fn test_generate_for_piece() {
 
     let sfen = "p7k/1p7/1Np6/2N6/3N5/9/9/9/8K b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let target = pos.empty_bb();
     mlist.generate_for_piece::<KnightType, QuietsWithoutPawnPromotionsType>(&pos, &target);
     assert_eq!(mlist.size, 4);
@@ -960,7 +971,7 @@ fn test_generate_for_piece() {
 
     let sfen = "8k/9/9/9/3n5/2n6/1nP6/1P7/P7K w - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let target = pos.empty_bb();
     mlist.generate_for_piece::<KnightType, QuietsWithoutPawnPromotionsType>(&pos, &target);
     assert_eq!(mlist.size, 4);
@@ -971,7 +982,7 @@ fn test_generate_for_piece() {
 
     let sfen = "p7k/1p7/2p6/9/LLLL5/9/9/9/8K b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let us = pos.side_to_move();
     let target = pos.pieces_c(us.inverse());
     mlist.generate_for_piece::<LanceType, CaptureOrPawnPromotionsType>(&pos, &target);
@@ -983,7 +994,7 @@ fn test_generate_for_piece() {
 
     let sfen = "p7k/1p7/2p6/9/LLLL5/9/9/9/8K b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let target = pos.empty_bb();
     mlist.generate_for_piece::<LanceType, QuietsWithoutPawnPromotionsType>(&pos, &target);
     assert_eq!(mlist.size, 10);
@@ -1000,7 +1011,7 @@ fn test_generate_for_piece() {
 
     let sfen = "p7k/PPp6/2PPp4/4PPp2/6PP1/9/9/9/8K b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let us = pos.side_to_move();
     let target = pos.pieces_c(us.inverse()) | (pos.empty_bb() & Bitboard::opponent_field_mask(us));
     mlist.generate_for_piece::<PawnType, CaptureOrPawnPromotionsType>(&pos, &target);
@@ -1015,7 +1026,7 @@ fn test_generate_for_piece() {
 
     let sfen = "p7k/PPp6/2PPp4/4PPp2/6PP1/9/9/9/8K b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let us = pos.side_to_move();
     let target = pos.empty_bb() & !Bitboard::opponent_field_mask(us);
     mlist.generate_for_piece::<PawnType, QuietsWithoutPawnPromotionsType>(&pos, &target);
@@ -1024,7 +1035,7 @@ fn test_generate_for_piece() {
 
     let sfen = "4k4/7p1/9/9/4+B+B3/5P3/9/9/s3K4 b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let us = pos.side_to_move();
     let target = pos.pieces_c(us.inverse());
     mlist.generate_for_piece::<HorseType, CaptureOrPawnPromotionsType>(&pos, &target);
@@ -1034,7 +1045,7 @@ fn test_generate_for_piece() {
 
     let sfen = "4k4/7p1/9/9/4+B+B3/5P3/9/9/s3K4 b - 1";
     let mut mlist = MoveList::new();
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let target = pos.empty_bb();
     mlist.generate_for_piece::<HorseType, QuietsWithoutPawnPromotionsType>(&pos, &target);
     assert_eq!(mlist.size, 28);
@@ -1070,7 +1081,7 @@ fn test_generate_for_piece() {
 #[test]
 fn test_generate_recaptures() {
     let sfen = "k1B1R1+B2/9/4p+R3/3SPG3/3N5/9/9/9/K8 b p 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let mut mlist = MoveList::new();
     let capture_square = Square::SQ53;
     mlist.generate_recaptures(&pos, capture_square);
@@ -1138,14 +1149,14 @@ fn test_generate_recaptures() {
 #[test]
 fn test_generate_drop() {
     let sfen = "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w GR5pnsg 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let mut mlist = MoveList::new();
     let target = pos.empty_bb();
     mlist.generate_drop::<QuietsWithoutPawnPromotionsType>(&pos, &target);
     assert_eq!(mlist.size, 167);
 
     let sfen = "l5+R2/1k2r2p1/1sngn4/l1ppp2P1/5pp2/lPPPP4/1KSG4P/1SSB5/1N1G4+b w GLPn5p 130";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let mut mlist = MoveList::new();
     let target = pos.empty_bb();
     mlist.generate_drop::<QuietsWithoutPawnPromotionsType>(&pos, &target);
@@ -1157,7 +1168,7 @@ fn test_generate_drop() {
     assert!(Move::new_from_csa_str(&"0081FU", &pos).is_some());
 
     let sfen = "ln3G2l/7k1/3pgsn2/2p2bpp1/p4p3/3sSbn1P/P2P1GPP1/2+r3S1K/L3RG1NL w P6p 106";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let mut mlist = MoveList::new();
     let target = pos.empty_bb();
     mlist.generate_drop::<QuietsWithoutPawnPromotionsType>(&pos, &target);
@@ -1171,7 +1182,7 @@ fn test_generate_drop() {
 #[test]
 fn test_generate_evasion() {
     let sfen = "9/4k4/r8/3b5/4L4/9/9/9/4K4 w pnsg 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
 
     let mut mlist = MoveList::new();
     mlist.generate::<EvasionsType>(&pos, 0);
@@ -1206,7 +1217,7 @@ fn test_generate_evasion() {
 #[test]
 fn test_generate_all() {
     let sfen = "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w GR5pnsg 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
 
     let mut mlist = MoveList::new();
     mlist.generate_all::<NonEvasionsType>(&pos, 0);
@@ -1224,7 +1235,7 @@ fn test_generate_all() {
 #[test]
 fn test_move_new_from_csa_str() {
     let sfen = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
 
     let m_str = "7776FU";
     if let Some(m) = Move::new_from_csa_str(m_str, &pos) {
@@ -1239,7 +1250,7 @@ fn test_move_new_from_csa_str() {
 #[test]
 fn test_pawn_drop_mate() {
     let sfen = "kl7/1n7/K8/9/9/9/9/9/9 b P 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let mut mlist = MoveList::new();
     mlist.generate_all::<NonEvasionsType>(&pos, 0);
     assert!(mlist
@@ -1249,6 +1260,83 @@ fn test_pawn_drop_mate() {
         .is_none());
 }
 
+#[test]
+fn test_pawn_drop_check_legality() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let contains = |sfen: &str, to: Square| {
+                let pos = Position::from_sfen(sfen).unwrap();
+                let m = Move::new_drop(Piece::new(pos.side_to_move(), PieceType::PAWN), to);
+                let mut mlist = MoveList::new();
+                mlist.generate::<LegalType>(&pos, 0);
+                let in_legal_moves = mlist.slice(0).iter().any(|x| x.mv == m);
+                assert_eq!(
+                    in_legal_moves,
+                    pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m),
+                    "{}",
+                    sfen
+                );
+                in_legal_moves
+            };
+            // The pawn drop mate is excluded. The king can't capture the pawn protected by the other king.
+            assert!(!contains("kl7/1n7/K8/9/9/9/9/9/9 b P 1", Square::SQ92));
+            // Also for white.
+            assert!(!contains("9/9/9/9/9/9/k8/1N7/KL7 w p 1", Square::SQ98));
+            // The king can escape. It's a legal check.
+            assert!(contains("4k4/9/4G4/9/9/9/9/9/4K4 b P 1", Square::SQ52));
+            // The king can capture the pawn.
+            assert!(contains("4k4/9/9/9/9/9/9/9/4K4 b P 1", Square::SQ52));
+            // The pawn can be captured by the other piece.
+            assert!(contains("ks7/1n7/K8/9/9/9/9/9/9 b P 1", Square::SQ92));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_two_pawns() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pawn_drop_files = |sfen: &str| {
+                let pos = Position::from_sfen(sfen).unwrap();
+                let mut mlist = MoveList::new();
+                mlist.generate::<LegalType>(&pos, 0);
+                let mut files = mlist
+                    .slice(0)
+                    .iter()
+                    .filter(|x| x.mv.is_drop() && x.mv.piece_type_dropped() == PieceType::PAWN)
+                    .map(|x| File::new(x.mv.to()).to_usi_char())
+                    .collect::<Vec<_>>();
+                files.sort_unstable();
+                files.dedup();
+                files
+            };
+            // The black pawn is on the file 5. The white pawn doesn't matter.
+            assert_eq!(
+                pawn_drop_files("4k4/9/9/9/4p4/4P4/9/9/4K4 b P 1"),
+                vec!['1', '2', '3', '4', '6', '7', '8', '9']
+            );
+            assert_eq!(
+                pawn_drop_files("4k4/9/9/9/4p4/4P4/9/9/4K4 w p 1"),
+                vec!['1', '2', '3', '4', '6', '7', '8', '9']
+            );
+            // The promoted pawn doesn't count.
+            assert_eq!(
+                pawn_drop_files("4k4/9/9/9/9/4+P4/9/9/4K4 b P 1"),
+                vec!['1', '2', '3', '4', '5', '6', '7', '8', '9']
+            );
+            let pos = Position::from_sfen("4k4/9/9/9/9/4+P4/9/9/3PK4 b P 1").unwrap();
+            assert!(pos.pawn_files_bb(Color::BLACK) == Bitboard::file_mask(File::FILE6));
+            assert!(pos.pawn_files_bb(Color::WHITE) == Bitboard::ZERO);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
 #[test]
 fn test_is_normal_move() {
     assert!(!None.is_normal_move());
@@ -1258,3 +1346,75 @@ fn test_is_normal_move() {
     assert!(Some(Move::new_unpromote(Square::SQ11, Square::SQ12, Piece::W_PAWN)).is_normal_move());
     assert!(Some(Move::new_drop(Piece::B_PAWN, Square::SQ12)).is_normal_move());
 }
+
+#[test]
+fn test_move_list_max_legal_moves() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let sfens = [
+                // maximum number of legal moves.
+                "R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1",
+                "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            ];
+            for sfen in sfens.iter() {
+                let pos = Position::from_sfen(sfen).unwrap();
+                // The pseudo legal moves before the legality check are in the list too.
+                let mut mlist = MoveList::new();
+                mlist.generate_all::<NonEvasionsAllType>(&pos, 0);
+                assert!(mlist.size <= ExtMove::MAX_LEGAL_MOVES);
+                let mut mlist = MoveList::new();
+                mlist.generate::<LegalAllType>(&pos, 0);
+                assert_eq!(mlist.slice(0).iter().map(|x| x.mv).collect::<Vec<_>>(), pos.legal_moves());
+
+                // All the moves on the board.
+                let us = pos.side_to_move();
+                let mut expected = vec![];
+                for &from in Square::ALL.iter() {
+                    let pc = pos.piece_on(from);
+                    if pc == Piece::EMPTY || Color::new(pc) != us {
+                        continue;
+                    }
+                    for &to in Square::ALL.iter() {
+                        let mut moves = vec![Move::new_unpromote(from, to, pc)];
+                        if PieceType::new(pc).is_promotable() {
+                            moves.push(Move::new_promote(from, to, pc));
+                        }
+                        expected.extend(
+                            moves
+                                .into_iter()
+                                .filter(|&m| pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m)),
+                        );
+                    }
+                }
+                for &pt in PieceType::ALL_HAND.iter() {
+                    for &to in Square::ALL.iter() {
+                        let m = Move::new_drop(Piece::new(us, pt), to);
+                        // pseudo_legal() doesn't check the ranks where the dropped piece can't move.
+                        let rank_as_black = if us == Color::BLACK {
+                            Rank::new(to)
+                        } else {
+                            Rank::new(to).inverse()
+                        };
+                        let min_rank_as_black = match pt {
+                            PieceType::PAWN | PieceType::LANCE => Rank::RANK2,
+                            PieceType::KNIGHT => Rank::RANK3,
+                            _ => Rank::RANK1,
+                        };
+                        if rank_as_black.0 >= min_rank_as_black.0 && pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m) {
+                            expected.push(m);
+                        }
+                    }
+                }
+                let mut actual = mlist.slice(0).iter().map(|x| x.mv).collect::<Vec<_>>();
+                assert_eq!(actual.len(), expected.len(), "{}", sfen);
+                actual.sort_by_key(|m| m.0);
+                expected.sort_by_key(|m| m.0);
+                assert_eq!(actual, expected, "{}", sfen);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -1,7 +1,7 @@
 use crate::movegen::*;
 use crate::movetypes::*;
-use crate::piecevalue::*;
 use crate::position::*;
+use crate::search::*;
 use crate::types::*;
 
 fn partial_insertion_sort(move_list: &mut [ExtMove], limit: i32) {
@@ -209,7 +209,7 @@ custom_derive! {
 custom_derive! {
     #[derive(Debug, NextVariant)]
     enum StagesForQSearch {
-        QSearchTT, QCaptureInit, QCapture, /*QCheckInit, QCheck,*/
+        QSearchTT, QCaptureInit, QCapture, QCheckInit, QCheck,
         QRecaptureTT, QRecaptureInit, QRecapture,
         EvasionTT, EvasionInit, Evasion,
     }
@@ -237,7 +237,7 @@ fn select_next_refutation(
     for &mv in list {
         *current_index += 1;
         if let Some(m) = mv {
-            if m != tt_move.unwrap_unchecked() && !m.is_capture(pos) && pos.pseudo_legal::<SearchingType>(m) {
+            if m != tt_move.unwrap_unchecked_move() && !m.is_capture(pos) && pos.pseudo_legal::<SearchingType>(m) {
                 return Some(m);
             }
         }
@@ -257,7 +257,7 @@ fn select_best_good_capture(
         let m = pick_best(&mut ext_moves[*current_index..ext_moves_size]);
         let score = ext_moves[*current_index].score;
         *current_index += 1;
-        if m != tt_move.unwrap_unchecked() {
+        if m != tt_move.unwrap_unchecked_move() {
             if pos.see_ge(m, Value(-55 * score / 1024)) {
                 return Some(m);
             } else {
@@ -278,11 +278,7 @@ fn select_next_quiet(
     for ext_move in list {
         *current_index += 1;
         let m = ext_move.mv;
-        if m != tt_move.unwrap_unchecked()
-            && m != refutations[0].unwrap_unchecked()
-            && m != refutations[1].unwrap_unchecked()
-            && m != refutations[2].unwrap_unchecked()
-        {
+        if m != tt_move.unwrap_unchecked_move() && !refutations.contains(&Some(m)) {
             return Some(m);
         }
     }
@@ -293,7 +289,7 @@ fn select_next_bad_capture(list: &[ExtMove], current_index: &mut usize, tt_move:
     for ext_move in list {
         *current_index += 1;
         let m = ext_move.mv;
-        if m != tt_move.unwrap_unchecked() {
+        if m != tt_move.unwrap_unchecked_move() {
             return Some(m);
         }
     }
@@ -304,7 +300,7 @@ fn select_best_evasion(list: &mut [ExtMove], current_index: &mut usize, tt_move:
     for i in 0..list.len() {
         let m = pick_best(&mut list[i..]);
         *current_index += 1;
-        if m != tt_move.unwrap_unchecked() {
+        if m != tt_move.unwrap_unchecked_move() {
             return Some(m);
         }
     }
@@ -315,7 +311,7 @@ fn select_best_qcapture(list: &mut [ExtMove], current_index: &mut usize, tt_move
     for i in 0..list.len() {
         let m = pick_best(&mut list[i..]);
         *current_index += 1;
-        if m != tt_move.unwrap_unchecked() {
+        if m != tt_move.unwrap_unchecked_move() {
             return Some(m);
         }
     }
@@ -326,7 +322,7 @@ fn select_best_qrecapture(list: &mut [ExtMove], current_index: &mut usize, tt_mo
     for i in 0..list.len() {
         let m = pick_best(&mut list[i..]);
         *current_index += 1;
-        if m != tt_move.unwrap_unchecked() {
+        if m != tt_move.unwrap_unchecked_move() {
             return Some(m);
         }
     }
@@ -343,7 +339,7 @@ fn select_best_probcut(
     for i in 0..list.len() {
         let m = pick_best(&mut list[i..]);
         *current_index += 1;
-        if m != tt_move.unwrap_unchecked() && pos.see_ge(m, threshold) {
+        if m != tt_move.unwrap_unchecked_move() && pos.see_ge(m, threshold) {
             return Some(m);
         }
     }
@@ -356,15 +352,17 @@ fn score_captures(move_list: &mut [ExtMove], pos: &Position, capture_history: *c
         let to = m.to();
         let pc_to = pos.piece_on(to);
         let pt_to = PieceType::new(pc_to);
-        ext_move.score =
-            capture_piece_type_value(pt_to).0 + unsafe { (*capture_history).get(m.piece_moved_after_move(), to, pt_to) };
+        ext_move.score = pos.piece_values().capture_piece_type_value(pt_to).0
+            + unsafe { (*capture_history).get(m.piece_moved_after_move(), to, pt_to) };
     }
 }
 
 fn score_recaptures(move_list: &mut [ExtMove], pos: &Position) {
     for ext_move in move_list {
         let m = ext_move.mv;
-        ext_move.score = (capture_piece_value(pos.piece_on(m.to())) - lva_value(PieceType::new(m.piece_moved_before_move()))).0;
+        ext_move.score = (pos.piece_values().capture_piece_value(pos.piece_on(m.to()))
+            - pos.piece_values().lva_value(PieceType::new(m.piece_moved_before_move())))
+        .0;
     }
 }
 
@@ -403,8 +401,9 @@ fn score_evasion(
     for ext_move in move_list {
         let m = ext_move.mv;
         if m.is_capture(pos) {
-            ext_move.score =
-                (capture_piece_value(pos.piece_on(m.to())) - lva_value(PieceType::new(m.piece_moved_before_move()))).0;
+            ext_move.score = (pos.piece_values().capture_piece_value(pos.piece_on(m.to()))
+                - pos.piece_values().lva_value(PieceType::new(m.piece_moved_before_move())))
+            .0;
         } else {
             let piece_moved = m.piece_moved_after_move();
             ext_move.score = unsafe { (*main_history).get(pos.side_to_move(), m) }
@@ -423,7 +422,7 @@ pub struct MovePickerForMainSearch<'a> {
     end_bad_captures: usize,
     stage: StagesForMainSearch,
     tt_move: Option<Move>,
-    refutations: [Option<Move>; 3],
+    refutations: [Option<Move>; MAX_KILLER_SLOTS + 1], // The killers and the counter move.
     refutations_size: usize,
     depth: Depth,
     move_list: MoveList,
@@ -443,6 +442,10 @@ impl<'a> MovePickerForMainSearch<'a> {
         killers: &[Option<Move>],
         ply: i32,
     ) -> MovePickerForMainSearch<'a> {
+        debug_assert!(killers.len() <= MAX_KILLER_SLOTS);
+        let mut refutations = [None; MAX_KILLER_SLOTS + 1];
+        refutations[..killers.len()].copy_from_slice(killers);
+        refutations[killers.len()] = cm;
         let mut stage = if pos.in_check() {
             StagesForMainSearch::EvasionTT
         } else {
@@ -464,8 +467,8 @@ impl<'a> MovePickerForMainSearch<'a> {
             end_bad_captures: 0,
             stage,
             tt_move,
-            refutations: [killers[0], killers[1], cm],
-            refutations_size: 3,
+            refutations,
+            refutations_size: killers.len() + 1,
             depth,
             move_list: MoveList::new(),
             ply,
@@ -497,8 +500,9 @@ impl<'a> MovePickerForMainSearch<'a> {
                     self.cur = 0;
 
                     // if countermove == killer, skip it.
-                    if self.refutations[0] == self.refutations[2] || self.refutations[1] == self.refutations[2] {
-                        self.refutations_size = 2;
+                    let counter_move_index = self.refutations_size - 1;
+                    if self.refutations[..counter_move_index].contains(&self.refutations[counter_move_index]) {
+                        self.refutations_size = counter_move_index;
                     }
                     self.stage = self.stage.next_variant().unwrap();
                 }
@@ -558,6 +562,13 @@ impl<'a> MovePickerForMainSearch<'a> {
     }
 }
 
+// depth is 0 at the first ply of qsearch.
+#[derive(Clone, Copy)]
+pub struct QSearchLimits {
+    pub depth: Depth,
+    pub checks: bool, // The quiet checks after the captures.
+}
+
 pub struct MovePickerForQSearch<'a> {
     main_history: *const ButterflyHistory,
     capture_history: *const CapturePieceToHistory,
@@ -566,6 +577,7 @@ pub struct MovePickerForQSearch<'a> {
     recapture_square: Square,
     stage: StagesForQSearch,
     tt_move: Option<Move>,
+    checks: bool,
     move_list: MoveList,
 }
 
@@ -577,8 +589,9 @@ impl<'a> MovePickerForQSearch<'a> {
         pos: &Position,
         recapture_square: Square,
         ttm: Option<Move>,
-        depth: Depth,
+        limits: QSearchLimits,
     ) -> MovePickerForQSearch<'a> {
+        let depth = limits.depth;
         let mut stage = if pos.in_check() {
             StagesForQSearch::EvasionTT
         } else if depth > Depth::QS_RECAPTURES {
@@ -606,6 +619,7 @@ impl<'a> MovePickerForQSearch<'a> {
             recapture_square,
             stage,
             tt_move,
+            checks: limits.checks,
             move_list: MoveList::new(),
         }
     }
@@ -629,20 +643,37 @@ impl<'a> MovePickerForQSearch<'a> {
                 }
                 StagesForQSearch::QCapture => {
                     let m = select_best_qcapture(self.move_list.slice_mut(self.cur), &mut self.cur, self.tt_move);
-                    return m;
-                    //if m != Move::NONE {
-                    //    return m;
-                    //}
-                    //if self.depth != Depth::QS_CHECKS {
-                    //    return Move::NONE;
-                    //}
-                    //self.stage = self.stage.next_variant().unwrap();
+                    if m.is_some() || !self.checks {
+                        return m;
+                    }
+                    self.stage = self.stage.next_variant().unwrap();
+                }
+                StagesForQSearch::QCheckInit => {
+                    self.cur = 0;
+                    self.move_list.size = 0;
+                    self.move_list.generate::<QuietsWithoutPawnPromotionsType>(pos, 0);
+                    let mut i = 0;
+                    while i != self.move_list.size {
+                        let m = self.move_list.ext_moves[i].mv;
+                        if pos.gives_check(m) && m != self.tt_move.unwrap_unchecked_move() {
+                            i += 1;
+                        } else {
+                            self.move_list.size -= 1;
+                            self.move_list.ext_moves[i].mv = self.move_list.ext_moves[self.move_list.size].mv;
+                        }
+                    }
+                    self.stage = self.stage.next_variant().unwrap();
+                }
+                StagesForQSearch::QCheck => {
+                    if self.cur == self.move_list.size {
+                        return None;
+                    }
+                    self.cur += 1;
+                    return Some(self.move_list.ext_moves[self.cur - 1].mv);
                 }
                 StagesForQSearch::QRecapture => {
                     return select_best_qrecapture(self.move_list.slice_mut(self.cur), &mut self.cur, self.tt_move);
                 }
-                //StagesForQSearch::QCheckInit => {}
-                //StagesForQSearch::QCheck => {}
                 StagesForQSearch::EvasionInit => {
                     self.cur = 0;
                     self.move_list.generate::<EvasionsType>(pos, 0);
@@ -771,7 +802,7 @@ fn test_butterfly_history() {
 #[test]
 fn test_move_list_select_best() {
     let sfen = "k8/9/3b1l3/4s4/5pg2/4GP3/5RN2/9/K4L3 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let mut mlist = MoveList::new();
     mlist.generate::<CaptureOrPawnPromotionsType>(&pos, 0);
     let capture_history = CapturePieceToHistory::new();
@@ -782,7 +813,7 @@ fn test_move_list_select_best() {
     assert_eq!(m.unwrap().to_csa_string(&pos), "4645FU");
 
     let sfen = "k8/lpppppp2/rbgsnlp2/+RPPPPPP2/9/9/9/9/K8 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let mut mlist = MoveList::new();
     mlist.generate::<CaptureOrPawnPromotionsType>(&pos, 0);
     let capture_history = CapturePieceToHistory::new();
@@ -796,7 +827,7 @@ fn test_move_list_select_best() {
 #[test]
 fn test_move_picker_for_main_search_next_move() {
     let sfen = "k8/9/9/5b3/9/l8/p8/1B7/1K7 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let tt_move = Some(Move::new_unpromote(Square::SQ88, Square::SQ66, Piece::B_BISHOP));
     let mh = ButterflyHistory::new();
     let lph = LowPlyHistory::new();
@@ -865,10 +896,96 @@ fn test_move_picker_for_main_search_next_move() {
     assert!(m.is_none());
 }
 
+#[test]
+fn test_move_picker_for_main_search_capture_order() {
+    // The gold and the pawn on 2e are free. The pawn on 8e is protected by the silver.
+    let sfen = "8k/9/4g4/2s6/1p2R2p1/9/9/9/K8 b - 1";
+    let pos = Position::from_sfen(sfen).unwrap();
+    let usi_move = |s: &str| Move::new_from_usi_str(s, &pos).unwrap();
+    let tt_move = Some(usi_move("9i8h"));
+    let mh = ButterflyHistory::new();
+    let lph = LowPlyHistory::new();
+    let cph = CapturePieceToHistory::new();
+    let ch = [
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+    ];
+    let ch = ch.iter().map(|x| x as *const PieceToHistory).collect::<Vec<_>>();
+    let killers = [Some(usi_move("5e5d")), Some(usi_move("5e4e"))];
+    let cm = Some(usi_move("5e5f"));
+    let skip_quiets = false;
+    let mut mp = MovePickerForMainSearch::new(&pos, tt_move, Depth(5), &mh, &lph, &cph, &ch, cm, &killers, 0);
+    let mut move_vec = vec![];
+    while let Some(m) = mp.next_move(&pos, skip_quiets) {
+        move_vec.push(m.to_usi_string());
+    }
+    assert_eq!(move_vec[0], "9i8h"); // MainTT
+    assert_eq!(move_vec[1], "5e5c+"); // GoodCapture. The more valuable piece first.
+    assert_eq!(move_vec[2], "5e2e"); // GoodCapture
+    assert_eq!(move_vec[3..6], ["5e5d", "5e4e", "5e5f"]); // Refutation
+    assert_eq!(move_vec.last().unwrap(), "5e8e"); // BadCapture
+    assert_eq!(move_vec.iter().filter(|&m| m == "5e8e" || m == "9i8h").count(), 2);
+}
+
+#[test]
+fn test_move_picker_for_main_search_killer_slots() {
+    let sfen = "8k/9/4g4/2s6/1p2R2p1/9/9/9/K8 b - 1";
+    let pos = Position::from_sfen(sfen).unwrap();
+    let usi_move = |s: &str| Move::new_from_usi_str(s, &pos).unwrap();
+    let tt_move = Some(usi_move("9i8h"));
+    let mut mh = ButterflyHistory::new();
+    mh.update(Color::BLACK, usi_move("5e5g"), 1000);
+    let lph = LowPlyHistory::new();
+    let cph = CapturePieceToHistory::new();
+    let ch = [
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+        PieceToHistory::new(),
+    ];
+    let ch = ch.iter().map(|x| x as *const PieceToHistory).collect::<Vec<_>>();
+    let mut ss = Stack::new();
+    let killer_slots = 3;
+    for &m in ["5e5d", "5e4e", "5e6e"].iter() {
+        ss.update_killers(usi_move(m), killer_slots);
+    }
+    let cm = Some(usi_move("5e5f"));
+    let skip_quiets = false;
+    let mut mp = MovePickerForMainSearch::new(
+        &pos,
+        tt_move,
+        Depth(5),
+        &mh,
+        &lph,
+        &cph,
+        &ch,
+        cm,
+        &ss.killers[..killer_slots],
+        0,
+    );
+    let mut move_vec = vec![];
+    while let Some(m) = mp.next_move(&pos, skip_quiets) {
+        move_vec.push(m.to_usi_string());
+    }
+    assert_eq!(move_vec[..3], ["9i8h", "5e5c+", "5e2e"]); // MainTT, GoodCapture
+    assert_eq!(move_vec[3..7], ["5e6e", "5e4e", "5e5d", "5e5f"]); // Refutation. The most recent killer first.
+    assert_eq!(move_vec[7], "5e5g"); // Quiet. The best history first.
+    assert_eq!(move_vec.last().unwrap(), "5e8e"); // BadCapture
+    for m in move_vec[..7].iter() {
+        assert_eq!(move_vec.iter().filter(|&x| x == m).count(), 1);
+    }
+}
+
 #[test]
 fn test_move_picker_for_main_search_next_move_evasion() {
     let sfen = "k8/9/9/5b3/6K2/l8/p8/1B7/9 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let tt_move = Some(Move::new_unpromote(Square::SQ35, Square::SQ24, Piece::B_KING));
     let mh = ButterflyHistory::new();
     let lph = LowPlyHistory::new();
@@ -934,7 +1051,7 @@ fn test_move_picker_for_main_search_next_move_evasion() {
 #[test]
 fn test_move_picker_for_qsearch_next_move() {
     let sfen = "k8/9/9/5b3/9/l8/p8/1B7/1K7 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let tt_move = Some(Move::new_unpromote(Square::SQ88, Square::SQ66, Piece::B_BISHOP));
     let mh = ButterflyHistory::new();
     let cph = CapturePieceToHistory::new();
@@ -946,7 +1063,11 @@ fn test_move_picker_for_qsearch_next_move() {
     ];
     let ch = ch.iter().map(|x| x as *const PieceToHistory).collect::<Vec<_>>();
     let recapture_square = Square::SQ97;
-    let mut mp = MovePickerForQSearch::new(&mh, &cph, &ch, &pos, recapture_square, tt_move, Depth(0));
+    let limits = QSearchLimits {
+        depth: Depth(0),
+        checks: false,
+    };
+    let mut mp = MovePickerForQSearch::new(&mh, &cph, &ch, &pos, recapture_square, tt_move, limits);
     let m = mp.next_move(&pos);
     assert_eq!(m.unwrap(), tt_move.unwrap()); // QSearchTT
     let m = mp.next_move(&pos);
@@ -960,7 +1081,7 @@ fn test_move_picker_for_qsearch_next_move() {
 #[test]
 fn test_move_picker_for_qsearch_next_move_evasion() {
     let sfen = "k8/9/9/5b3/6K2/l8/p8/1B7/9 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let tt_move = Some(Move::new_unpromote(Square::SQ35, Square::SQ24, Piece::B_KING));
     let mh = ButterflyHistory::new();
     let cph = CapturePieceToHistory::new();
@@ -972,7 +1093,11 @@ fn test_move_picker_for_qsearch_next_move_evasion() {
     ];
     let ch = ch.iter().map(|x| x as *const PieceToHistory).collect::<Vec<_>>();
     let recapture_square = Square::SQ97;
-    let mut mp = MovePickerForQSearch::new(&mh, &cph, &ch, &pos, recapture_square, tt_move, Depth(0));
+    let limits = QSearchLimits {
+        depth: Depth(0),
+        checks: false,
+    };
+    let mut mp = MovePickerForQSearch::new(&mh, &cph, &ch, &pos, recapture_square, tt_move, limits);
     let moves_size;
     {
         let mut mlist = MoveList::new();
@@ -1020,7 +1145,7 @@ fn test_move_picker_for_qsearch_next_move_evasion() {
 #[test]
 fn test_move_picker_for_qsearch_next_move_recapture() {
     let sfen = "k8/9/9/5b3/9/l8/p8/1B7/1K7 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let tt_move = Some(Move::new_unpromote(Square::SQ88, Square::SQ66, Piece::B_BISHOP)); // to_square is not recapture_square. tt_move is not used.
     let mh = ButterflyHistory::new();
     let cph = CapturePieceToHistory::new();
@@ -1032,7 +1157,11 @@ fn test_move_picker_for_qsearch_next_move_recapture() {
     ];
     let ch = ch.iter().map(|x| x as *const PieceToHistory).collect::<Vec<_>>();
     let recapture_square = Square::SQ97;
-    let mut mp = MovePickerForQSearch::new(&mh, &cph, &ch, &pos, recapture_square, tt_move, Depth::QS_RECAPTURES);
+    let limits = QSearchLimits {
+        depth: Depth::QS_RECAPTURES,
+        checks: false,
+    };
+    let mut mp = MovePickerForQSearch::new(&mh, &cph, &ch, &pos, recapture_square, tt_move, limits);
     let m = mp.next_move(&pos);
     assert_eq!(m.unwrap(), Move::new_unpromote(Square::SQ88, Square::SQ97, Piece::B_BISHOP)); // QRecapture
     let m = mp.next_move(&pos);
@@ -1042,7 +1171,7 @@ fn test_move_picker_for_qsearch_next_move_recapture() {
 #[test]
 fn test_move_picker_for_prob_cut_next_move() {
     let sfen = "k8/9/9/5b3/9/l8/p8/1B7/1K7 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     let tt_move = Some(Move::new_unpromote(Square::SQ88, Square::SQ66, Piece::B_BISHOP));
     let cph = CapturePieceToHistory::new();
     let mut mp = MovePickerForProbCut::new(&pos, tt_move, Value(0), &cph);
@@ -15,6 +15,16 @@ impl UsiMove {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CsaMove(String);
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum MoveParseError {
+    InvalidString { chars: String },
+    OutOfRange { chars: String },
+    EmptySquare { sq: Square },
+    NotPromotable,
+    NotInHand { pt: PieceType },
+    Illegal,
+}
+
 // xxxxxxxx xxxxxxxx xxxxxxxx x1111111  to
 // xxxxxxxx xxxxxxxx xxxxxxxx 1xxxxxxx  promote flag
 // xxxxxxxx xxxxxxxx xxxxxxx1 xxxxxxxx  drop flag
@@ -57,50 +67,66 @@ impl Move {
         })
     }
     pub fn new_from_usi_str(s: &str, pos: &Position) -> Option<Move> {
+        Self::from_usi(pos, s).ok()
+    }
+    // The moved piece is the piece on the from square. (The same as TTEntry::mv().)
+    pub fn from_usi(pos: &Position, s: &str) -> Result<Move, MoveParseError> {
         let m;
         let v: Vec<char> = s.chars().collect();
+        let invalid_string = || MoveParseError::InvalidString { chars: s.to_string() };
+        let square = |file: char, rank: char| match (File::new_from_usi_char(file), Rank::new_from_usi_char(rank)) {
+            (Some(file), Some(rank)) => Ok(Square::new(file, rank)),
+            _ if file.is_ascii_digit() && rank.is_ascii_lowercase() => Err(MoveParseError::OutOfRange {
+                chars: format!("{}{}", file, rank),
+            }),
+            _ => Err(invalid_string()),
+        };
         if v.len() < 4 {
             // Any move is illegal.
-            return None;
+            return Err(invalid_string());
         }
         if let Some(pt) = PieceType::new_from_str_for_drop_move(&v[0].to_string()) {
             let pc = Piece::new(pos.side_to_move(), pt);
             // Drop move.
             if v[1] != '*' {
-                return None;
+                return Err(invalid_string());
             }
-            if v.len() != 4 {
-                return None;
+            if v.len() != 4 || pt == PieceType::KING {
+                return Err(invalid_string());
+            }
+            let to = square(v[2], v[3])?;
+            if !pos.hand(pos.side_to_move()).exist(pt) {
+                return Err(MoveParseError::NotInHand { pt });
             }
-            let file = File::new_from_usi_char(v[2])?;
-            let rank = Rank::new_from_usi_char(v[3])?;
-            let to = Square::new(file, rank);
             m = Move::new_drop(pc, to);
         } else {
             // Not drop move.
-            let file_from = File::new_from_usi_char(v[0])?;
-            let rank_from = Rank::new_from_usi_char(v[1])?;
-            let file_to = File::new_from_usi_char(v[2])?;
-            let rank_to = Rank::new_from_usi_char(v[3])?;
-            let from = Square::new(file_from, rank_from);
-            let to = Square::new(file_to, rank_to);
+            let from = square(v[0], v[1])?;
+            let to = square(v[2], v[3])?;
             let pc = pos.piece_on(from);
+            if pc == Piece::EMPTY {
+                return Err(MoveParseError::EmptySquare { sq: from });
+            }
             if v.len() == 4 {
                 // Unpromote move.
                 m = Move::new_unpromote(from, to, pc);
             } else if v.len() == 5 {
                 if v[4] != '+' {
-                    return None;
+                    return Err(invalid_string());
+                }
+                let us = pos.side_to_move();
+                if !pc.is_promotable() || !(Rank::new(from).is_opponent_field(us) || Rank::new(to).is_opponent_field(us)) {
+                    return Err(MoveParseError::NotPromotable);
                 }
                 m = Move::new_promote(from, to, pc);
             } else {
-                return None;
+                return Err(invalid_string());
             }
         }
         if !pos.pseudo_legal::<NotSearchingType>(m) || !pos.legal(m) {
-            return None;
+            return Err(MoveParseError::Illegal);
         }
-        Some(m)
+        Ok(m)
     }
     pub fn new_from_usi(usi_move: &UsiMove, pos: &Position) -> Option<Move> {
         Self::new_from_usi_str(usi_move.as_str(), pos)
@@ -136,20 +162,14 @@ impl Move {
                 let rank_from = Rank::new_from_csa_char(v[1])?;
                 Square::new(file_from, rank_from)
             };
-            let is_promote = {
-                let pc_from = pos.piece_on(from);
-                if pc_from == pc {
-                    false
-                } else if pc_from.is_promotable() && pc_from.to_promote() == pc {
-                    true
-                } else {
-                    return None;
-                }
-            };
-            if is_promote {
-                m = Move::new_promote(from, to, pc);
+            // The piece type in CSA notation is the one after the move.
+            let pc_from = pos.piece_on(from);
+            if pc_from == pc {
+                m = Move::new_unpromote(from, to, pc_from);
+            } else if pc_from.is_promotable() && pc_from.to_promote() == pc {
+                m = Move::new_promote(from, to, pc_from);
             } else {
-                m = Move::new_unpromote(from, to, pc);
+                return None;
             }
         }
 
@@ -159,7 +179,9 @@ impl Move {
 
         Some(m)
     }
+    // The drops have no reverse moves.
     pub fn reverse(self) -> Move {
+        debug_assert!(!self.is_drop());
         let pc = Piece(((self.0.get() & Move::MOVED_PIECE_MASK) >> Move::MOVED_PIECE_SHIFT) as i32);
         Move::new_unpromote(self.to(), self.to(), pc)
     }
@@ -228,6 +250,7 @@ impl Move {
         }
         UsiMove(s)
     }
+    // "7g7f", "7g7f+" or "P*5e".
     pub fn to_usi_string(self) -> String {
         self.to_usi().0
     }
@@ -254,13 +277,18 @@ impl Move {
 }
 
 pub trait UnwrapUnchecked {
-    fn unwrap_unchecked(self) -> Move;
+    fn unwrap_unchecked_move(self) -> Move;
 }
 
 impl UnwrapUnchecked for Option<Move> {
+    // None is the move which has no bits but the unused highest one. Its squares and pieces are 0.
+    // (The transmute of None to Move is undefined behaviour, and the optimizer assumes that it never happens.)
     #[inline]
-    fn unwrap_unchecked(self) -> Move {
-        unsafe { std::mem::transmute::<Option<Move>, Move>(self) }
+    fn unwrap_unchecked_move(self) -> Move {
+        match self {
+            Some(m) => m,
+            None => Move(unsafe { std::num::NonZeroU32::new_unchecked(1 << 31) }),
+        }
     }
 }
 
@@ -270,7 +298,7 @@ pub trait IsNormalMove {
 
 impl IsNormalMove for Option<Move> {
     fn is_normal_move(&self) -> bool {
-        let val = self.unwrap_unchecked().0.get();
+        let val = self.map_or(0, |m| m.0.get());
         let ret = (val & 0x1ff) != (val >> 9);
         debug_assert_eq!(
             ret,
@@ -317,3 +345,157 @@ impl Clone for ExtMove {
         }
     }
 }
+
+#[test]
+fn test_move_from_usi() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            // The moves have the same moved piece bits as the generated moves.
+            let sfens = [
+                crate::sfen::START_SFEN,
+                "lnsgkg1nl/1r5s1/pppppp1pp/6p2/4B4/2P6/PP1PPPPPP/7R1/LNSGKGSNL w b 6",
+                "l4S2l/4g1gs1/5p1p1/pr2N1pkp/4Gn3/PP3PPPP/2GPP4/1K7/L3r+s2L w BS2N5Pb 1",
+                "6n1l/2+S1k4/2lp4p/1np1B2b1/3PP4/1N1S3rP/1P2+pPP+p1/1p1G5/3KG2r1 b GSN2L4Pgs2p 1",
+            ];
+            for sfen in sfens.iter() {
+                let pos = Position::from_sfen(sfen).unwrap();
+                let moves = pos.legal_moves();
+                assert!(!moves.is_empty());
+                for &m in moves.iter() {
+                    assert_eq!(Move::from_usi(&pos, &m.to_usi_string()), Ok(m));
+                }
+            }
+
+            let mut pos = Position::new();
+            for s in ["7g7f", "3c3d", "8h2b+", "3a2b"].iter() {
+                let m = Move::from_usi(&pos, s).unwrap();
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+            }
+            let m = Move::from_usi(&pos, "7g7f").err();
+            assert_eq!(m, Some(MoveParseError::EmptySquare { sq: Square::SQ77 }));
+            let m = Move::from_usi(&pos, "B*5e").unwrap();
+            assert!(m.is_drop());
+            assert_eq!(m.piece_dropped(), Piece::B_BISHOP);
+            assert_eq!(m.to(), Square::SQ55);
+            let m = Move::from_usi(&pos, "2b3c").err();
+            assert_eq!(m, Some(MoveParseError::Illegal)); // The opponent's piece.
+            let pos = Position::from_sfen("4k4/9/9/9/9/4P4/9/9/4K4 b - 1").unwrap();
+            let m = Move::from_usi(&pos, "5f5e").unwrap();
+            assert_eq!(m.piece_moved_before_move(), Piece::B_PAWN);
+            assert!(!m.is_promotion());
+            let pos = Position::from_sfen("4k4/9/9/4P4/9/9/9/9/4K4 b - 1").unwrap();
+            let m = Move::from_usi(&pos, "5d5c+").unwrap();
+            assert!(m.is_promotion());
+            assert_eq!(m.piece_moved_after_move(), Piece::B_PRO_PAWN);
+
+            // Malformed strings.
+            let pos = Position::new();
+            let cases = [
+                ("", MoveParseError::InvalidString { chars: "".to_string() }),
+                (
+                    "7g7",
+                    MoveParseError::InvalidString {
+                        chars: "7g7".to_string(),
+                    },
+                ),
+                (
+                    "7g7f=",
+                    MoveParseError::InvalidString {
+                        chars: "7g7f=".to_string(),
+                    },
+                ),
+                (
+                    "7g7f++",
+                    MoveParseError::InvalidString {
+                        chars: "7g7f++".to_string(),
+                    },
+                ),
+                (
+                    "P-5e",
+                    MoveParseError::InvalidString {
+                        chars: "P-5e".to_string(),
+                    },
+                ),
+                (
+                    "P*5e+",
+                    MoveParseError::InvalidString {
+                        chars: "P*5e+".to_string(),
+                    },
+                ),
+                (
+                    "K*5e",
+                    MoveParseError::InvalidString {
+                        chars: "K*5e".to_string(),
+                    },
+                ),
+                ("0g7f", MoveParseError::OutOfRange { chars: "0g".to_string() }),
+                ("7g7j", MoveParseError::OutOfRange { chars: "7j".to_string() }),
+                ("P*5z", MoveParseError::OutOfRange { chars: "5z".to_string() }),
+                ("P*5e", MoveParseError::NotInHand { pt: PieceType::PAWN }),
+                ("5i5h+", MoveParseError::NotPromotable),
+                ("7g7f+", MoveParseError::NotPromotable),
+                ("7g7e", MoveParseError::Illegal),
+            ];
+            for (s, err) in cases.iter() {
+                assert_eq!(&Move::from_usi(&pos, s).unwrap_err(), err);
+                assert_eq!(Move::new_from_usi_str(s, &pos), None);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_move_to_usi_string() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            assert_eq!(
+                Move::new_unpromote(Square::SQ77, Square::SQ76, Piece::B_PAWN).to_usi_string(),
+                "7g7f"
+            );
+            assert_eq!(
+                Move::new_promote(Square::SQ88, Square::SQ22, Piece::B_BISHOP).to_usi_string(),
+                "8h2b+"
+            );
+            assert_eq!(Move::new_drop(Piece::W_PAWN, Square::SQ55).to_usi_string(), "P*5e");
+            assert_eq!(Move::new_drop(Piece::B_GOLD, Square::SQ11).to_usi_string(), "G*1a");
+
+            let mut pos = Position::new();
+            for (s, is_drop, is_promotion) in [
+                ("7g7f", false, false),
+                ("3c3d", false, false),
+                ("8h2b+", false, true),
+                ("3a2b", false, false),
+                ("B*5e", true, false),
+            ]
+            .iter()
+            {
+                let m = Move::from_usi(&pos, s).unwrap();
+                assert_eq!(m.is_drop(), *is_drop);
+                assert_eq!(m.is_promotion(), *is_promotion);
+                assert_eq!(&m.to_usi_string(), s);
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_unwrap_unchecked_move() {
+    let m = Move::new_unpromote(Square::SQ77, Square::SQ76, Piece::B_PAWN);
+    assert_eq!(Some(m).unwrap_unchecked_move(), m);
+    assert!(Some(m).is_normal_move());
+    // The squares of None are 0 as the previous square of the search.
+    let none = None.unwrap_unchecked_move();
+    assert_eq!(none.to(), Square(0));
+    assert_eq!(none.from(), Square(0));
+    assert!(!None.is_normal_move());
+    assert!(!Some(Move::NULL).is_normal_move());
+}
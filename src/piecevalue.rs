@@ -15,6 +15,207 @@ const HORSE_VALUE: i32 = 1050 * 9 / 10;
 const DRAGON_VALUE: i32 = 1550 * 9 / 10;
 const KING_VALUE: i32 = 0; // see_ge() needs that KING_VALUE == 0.
 
+// The sum of the values of the pieces on the board at the start position.
+pub const START_PHASE: i32 =
+    18 * PAWN_VALUE + 4 * (LANCE_VALUE + KNIGHT_VALUE + SILVER_VALUE + GOLD_VALUE) + 2 * (BISHOP_VALUE + ROOK_VALUE);
+
+// The values of the piece types without the king for experiments.
+// The pieces in hand have the values of the piece types on the board.
+#[cfg(feature = "tune")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceValues {
+    pub pawn: i32,
+    pub lance: i32,
+    pub knight: i32,
+    pub silver: i32,
+    pub bishop: i32,
+    pub rook: i32,
+    pub gold: i32,
+    pub pro_pawn: i32,
+    pub pro_lance: i32,
+    pub pro_knight: i32,
+    pub pro_silver: i32,
+    pub horse: i32,
+    pub dragon: i32,
+}
+
+#[cfg(feature = "tune")]
+impl PieceValues {
+    pub const DEFAULT: PieceValues = PieceValues {
+        pawn: PAWN_VALUE,
+        lance: LANCE_VALUE,
+        knight: KNIGHT_VALUE,
+        silver: SILVER_VALUE,
+        bishop: BISHOP_VALUE,
+        rook: ROOK_VALUE,
+        gold: GOLD_VALUE,
+        pro_pawn: PRO_PAWN_VALUE,
+        pro_lance: PRO_LANCE_VALUE,
+        pro_knight: PRO_KNIGHT_VALUE,
+        pro_silver: PRO_SILVER_VALUE,
+        horse: HORSE_VALUE,
+        dragon: DRAGON_VALUE,
+    };
+    fn value_mut(&mut self, name: &str) -> Option<&mut i32> {
+        Some(match name {
+            "Pawn" => &mut self.pawn,
+            "Lance" => &mut self.lance,
+            "Knight" => &mut self.knight,
+            "Silver" => &mut self.silver,
+            "Bishop" => &mut self.bishop,
+            "Rook" => &mut self.rook,
+            "Gold" => &mut self.gold,
+            "ProPawn" => &mut self.pro_pawn,
+            "ProLance" => &mut self.pro_lance,
+            "ProKnight" => &mut self.pro_knight,
+            "ProSilver" => &mut self.pro_silver,
+            "Horse" => &mut self.horse,
+            "Dragon" => &mut self.dragon,
+            _ => return None,
+        })
+    }
+    // Returns false if name isn't a piece type such as "Pawn" or "ProPawn".
+    pub fn set(&mut self, name: &str, value: i32) -> bool {
+        match self.value_mut(name) {
+            Some(v) => {
+                *v = value;
+                true
+            }
+            None => false,
+        }
+    }
+    fn piece_type_value(&self, pt: PieceType) -> i32 {
+        match pt {
+            PieceType::PAWN => self.pawn,
+            PieceType::LANCE => self.lance,
+            PieceType::KNIGHT => self.knight,
+            PieceType::SILVER => self.silver,
+            PieceType::BISHOP => self.bishop,
+            PieceType::ROOK => self.rook,
+            PieceType::GOLD => self.gold,
+            PieceType::PRO_PAWN => self.pro_pawn,
+            PieceType::PRO_LANCE => self.pro_lance,
+            PieceType::PRO_KNIGHT => self.pro_knight,
+            PieceType::PRO_SILVER => self.pro_silver,
+            PieceType::HORSE => self.horse,
+            PieceType::DRAGON => self.dragon,
+            _ => KING_VALUE,
+        }
+    }
+}
+
+// The lookup tables of the piece values read by the search and the evaluation.
+// Each position refers to one. The tune builds make it from the PieceValues of the searcher, the other builds only have DEFAULT.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PieceValueTable {
+    piece: [i32; Piece::NUM],
+    capture: [i32; Piece::NUM],
+    promote: [i32; 7],
+    lva: [i32; PieceType::NUM],
+}
+
+impl PieceValueTable {
+    pub const DEFAULT: PieceValueTable = PieceValueTable {
+        piece: PIECE_VALUES,
+        capture: CAPTURE_PIECE_VALUES,
+        promote: PROMOTE_PIECE_VALUES,
+        lva: LVA_VALUES,
+    };
+    #[cfg(feature = "tune")]
+    pub fn new(values: &PieceValues) -> PieceValueTable {
+        let mut table = PieceValueTable {
+            piece: [0; Piece::NUM],
+            capture: [0; Piece::NUM],
+            promote: [0; 7],
+            lva: [0; PieceType::NUM],
+        };
+        for i in 0..Piece::NUM {
+            // The black pieces and the white pieces have the same piece types at i and i + 16.
+            let pt = PieceType((i % 16) as i32);
+            // The empty square, the king (KING_VALUE == 0) and the unused indices are 0.
+            if pt.0 == 0 || pt == PieceType::KING || pt.0 >= PieceType::NUM as i32 {
+                continue;
+            }
+            table.piece[i] = values.piece_type_value(pt);
+            table.capture[i] = values.piece_type_value(pt) + values.piece_type_value(pt.to_demote_if_possible());
+        }
+        for i in 1..table.promote.len() {
+            let pt = PieceType(i as i32);
+            table.promote[i] = values.piece_type_value(pt.to_promote()) - values.piece_type_value(pt);
+        }
+        // The ranks of the values. The piece types of the same value keep the order of LVA_VALUES.
+        // The default values give LVA_VALUES.
+        let rank_key = |pt: PieceType| (values.piece_type_value(pt), LVA_VALUES[pt.0 as usize]);
+        for i in 1..PieceType::NUM {
+            let pt = PieceType(i as i32);
+            if pt == PieceType::KING {
+                table.lva[i] = LVA_VALUES[i];
+                continue;
+            }
+            let mut lower_keys = (1..PieceType::NUM)
+                .map(|j| PieceType(j as i32))
+                .filter(|&other| other != PieceType::KING && rank_key(other) < rank_key(pt))
+                .map(rank_key)
+                .collect::<Vec<_>>();
+            lower_keys.sort_unstable();
+            lower_keys.dedup();
+            table.lva[i] = lower_keys.len() as i32 + 1;
+        }
+        table
+    }
+    #[inline]
+    fn get(values: &[i32], i: usize) -> Value {
+        debug_assert!(i < values.len());
+        unsafe { Value(*values.get_unchecked(i)) }
+    }
+    #[inline]
+    pub fn piece_value(&self, pc: Piece) -> Value {
+        debug_assert!(0 <= pc.0);
+        Self::get(&self.piece, pc.0 as usize)
+    }
+    #[inline]
+    pub fn piece_type_value(&self, pt: PieceType) -> Value {
+        debug_assert!(0 <= pt.0 && (pt.0 as usize) < PieceType::NUM);
+        Self::get(&self.piece, pt.0 as usize)
+    }
+    #[inline]
+    pub fn capture_piece_value(&self, pc: Piece) -> Value {
+        debug_assert!(0 <= pc.0);
+        Self::get(&self.capture, pc.0 as usize)
+    }
+    #[inline]
+    pub fn capture_piece_type_value(&self, pt: PieceType) -> Value {
+        debug_assert!(0 <= pt.0 && (pt.0 as usize) < PieceType::NUM);
+        Self::get(&self.capture, pt.0 as usize)
+    }
+    #[inline]
+    pub fn promote_piece_type_value(&self, pt: PieceType) -> Value {
+        debug_assert!(0 <= pt.0);
+        Self::get(&self.promote, pt.0 as usize)
+    }
+    // The order of the attackers for MVV-LVA. The less valuable attacker is smaller.
+    #[inline]
+    pub fn lva_value(&self, pt: PieceType) -> Value {
+        debug_assert!(0 < pt.0 && (pt.0 as usize) < PieceType::NUM);
+        Self::get(&self.lva, pt.0 as usize)
+    }
+}
+
+// The weight of the piece type for the game phase.
+// It is the default value in every build, so the phase doesn't move with the tuned piece values.
+#[inline]
+pub fn phase_value(pt: PieceType) -> i32 {
+    PieceValueTable::DEFAULT.piece_type_value(pt).0
+}
+
+#[cfg(not(feature = "tune"))]
+pub static DEFAULT_PIECE_VALUE_TABLE: PieceValueTable = PieceValueTable::DEFAULT;
+
+// The positions share it until the piece values of the searcher are set.
+#[cfg(feature = "tune")]
+pub static DEFAULT_PIECE_VALUE_TABLE: once_cell::sync::Lazy<std::sync::Arc<PieceValueTable>> =
+    once_cell::sync::Lazy::new(|| std::sync::Arc::new(PieceValueTable::DEFAULT));
+
 const PIECE_VALUES: [i32; Piece::NUM] = [
     0,
     PAWN_VALUE,
@@ -49,18 +250,6 @@ const PIECE_VALUES: [i32; Piece::NUM] = [
     DRAGON_VALUE,
 ];
 
-#[allow(dead_code)]
-pub fn piece_value(pc: Piece) -> Value {
-    debug_assert!(0 <= pc.0);
-    debug_assert!((pc.0 as usize) < Piece::NUM);
-    unsafe { Value(*PIECE_VALUES.get_unchecked(pc.0 as usize)) }
-}
-pub fn piece_type_value(pt: PieceType) -> Value {
-    debug_assert!(0 <= pt.0);
-    debug_assert!((pt.0 as usize) < PieceType::NUM);
-    unsafe { Value(*PIECE_VALUES.get_unchecked(pt.0 as usize)) }
-}
-
 const CAPTURE_PAWN_VALUE: i32 = PAWN_VALUE * 2;
 const CAPTURE_LANCE_VALUE: i32 = LANCE_VALUE * 2;
 const CAPTURE_KNIGHT_VALUE: i32 = KNIGHT_VALUE * 2;
@@ -110,17 +299,6 @@ const CAPTURE_PIECE_VALUES: [i32; Piece::NUM] = [
     CAPTURE_DRAGON_VALUE,
 ];
 
-pub fn capture_piece_value(pc: Piece) -> Value {
-    debug_assert!(0 <= pc.0);
-    debug_assert!((pc.0 as usize) < Piece::NUM);
-    unsafe { Value(*CAPTURE_PIECE_VALUES.get_unchecked(pc.0 as usize)) }
-}
-pub fn capture_piece_type_value(pt: PieceType) -> Value {
-    debug_assert!(0 <= pt.0);
-    debug_assert!((pt.0 as usize) < PieceType::NUM);
-    unsafe { Value(*CAPTURE_PIECE_VALUES.get_unchecked(pt.0 as usize)) }
-}
-
 const PROMOTE_PAWN_VALUE: i32 = PRO_PAWN_VALUE - PAWN_VALUE;
 const PROMOTE_LANCE_VALUE: i32 = PRO_LANCE_VALUE - LANCE_VALUE;
 const PROMOTE_KNIGHT_VALUE: i32 = PRO_KNIGHT_VALUE - KNIGHT_VALUE;
@@ -138,142 +316,185 @@ const PROMOTE_PIECE_VALUES: [i32; 7] = [
     PROMOTE_ROOK_VALUE,
 ];
 
-pub fn promote_piece_type_value(pt: PieceType) -> Value {
-    debug_assert!(0 <= pt.0);
-    debug_assert!((pt.0 as usize) < PROMOTE_PIECE_VALUES.len());
-    unsafe { Value(*PROMOTE_PIECE_VALUES.get_unchecked(pt.0 as usize)) }
-}
+const LVA_VALUES: [i32; PieceType::NUM] = [
+    0, 1,     // PAWN
+    2,     // LANCE
+    3,     // KNIGHT
+    4,     // SILVER
+    7,     // BISHOP
+    9,     // ROOK
+    6,     // GOLD
+    10000, // KING
+    5,     // PRO_PAWN
+    5,     // PRO_LANCE
+    5,     // PRO_KNIGHT
+    5,     // PRO_SILVER
+    8,     // HORSE
+    10,    // DRAGON
+];
 
-pub fn lva_value(pt: PieceType) -> Value {
-    match pt {
-        PieceType::PAWN => Value(1),
-        PieceType::LANCE => Value(2),
-        PieceType::KNIGHT => Value(3),
-        PieceType::SILVER => Value(4),
-        PieceType::PRO_SILVER => Value(5),
-        PieceType::PRO_KNIGHT => Value(5),
-        PieceType::PRO_LANCE => Value(5),
-        PieceType::PRO_PAWN => Value(5),
-        PieceType::GOLD => Value(6),
-        PieceType::BISHOP => Value(7),
-        PieceType::HORSE => Value(8),
-        PieceType::ROOK => Value(9),
-        PieceType::DRAGON => Value(10),
-        PieceType::KING => Value(10000),
-        _ => unreachable!(),
-    }
-}
+#[cfg(test)]
+const TABLE: PieceValueTable = PieceValueTable::DEFAULT;
 
 #[test]
 fn test_piece_value() {
-    assert_eq!(piece_type_value(PieceType::PAWN), Value(PAWN_VALUE));
-    assert_eq!(piece_type_value(PieceType::LANCE), Value(LANCE_VALUE));
-    assert_eq!(piece_type_value(PieceType::KNIGHT), Value(KNIGHT_VALUE));
-    assert_eq!(piece_type_value(PieceType::SILVER), Value(SILVER_VALUE));
-    assert_eq!(piece_type_value(PieceType::BISHOP), Value(BISHOP_VALUE));
-    assert_eq!(piece_type_value(PieceType::ROOK), Value(ROOK_VALUE));
-    assert_eq!(piece_type_value(PieceType::GOLD), Value(GOLD_VALUE));
-    assert_eq!(piece_type_value(PieceType::KING), Value(KING_VALUE));
-    assert_eq!(piece_type_value(PieceType::PRO_PAWN), Value(PRO_PAWN_VALUE));
-    assert_eq!(piece_type_value(PieceType::PRO_LANCE), Value(PRO_LANCE_VALUE));
-    assert_eq!(piece_type_value(PieceType::PRO_KNIGHT), Value(PRO_KNIGHT_VALUE));
-    assert_eq!(piece_type_value(PieceType::PRO_SILVER), Value(PRO_SILVER_VALUE));
-    assert_eq!(piece_type_value(PieceType::HORSE), Value(HORSE_VALUE));
-    assert_eq!(piece_type_value(PieceType::DRAGON), Value(DRAGON_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::PAWN), Value(PAWN_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::LANCE), Value(LANCE_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::KNIGHT), Value(KNIGHT_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::SILVER), Value(SILVER_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::BISHOP), Value(BISHOP_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::ROOK), Value(ROOK_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::GOLD), Value(GOLD_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::KING), Value(KING_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::PRO_PAWN), Value(PRO_PAWN_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::PRO_LANCE), Value(PRO_LANCE_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::PRO_KNIGHT), Value(PRO_KNIGHT_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::PRO_SILVER), Value(PRO_SILVER_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::HORSE), Value(HORSE_VALUE));
+    assert_eq!(TABLE.piece_type_value(PieceType::DRAGON), Value(DRAGON_VALUE));
 
-    assert_eq!(piece_value(Piece::EMPTY), Value(0));
-    assert_eq!(piece_value(Piece::B_PAWN), Value(PAWN_VALUE));
-    assert_eq!(piece_value(Piece::B_LANCE), Value(LANCE_VALUE));
-    assert_eq!(piece_value(Piece::B_KNIGHT), Value(KNIGHT_VALUE));
-    assert_eq!(piece_value(Piece::B_SILVER), Value(SILVER_VALUE));
-    assert_eq!(piece_value(Piece::B_BISHOP), Value(BISHOP_VALUE));
-    assert_eq!(piece_value(Piece::B_ROOK), Value(ROOK_VALUE));
-    assert_eq!(piece_value(Piece::B_GOLD), Value(GOLD_VALUE));
-    assert_eq!(piece_value(Piece::B_KING), Value(KING_VALUE));
-    assert_eq!(piece_value(Piece::B_PRO_PAWN), Value(PRO_PAWN_VALUE));
-    assert_eq!(piece_value(Piece::B_PRO_LANCE), Value(PRO_LANCE_VALUE));
-    assert_eq!(piece_value(Piece::B_PRO_KNIGHT), Value(PRO_KNIGHT_VALUE));
-    assert_eq!(piece_value(Piece::B_PRO_SILVER), Value(PRO_SILVER_VALUE));
-    assert_eq!(piece_value(Piece::B_HORSE), Value(HORSE_VALUE));
-    assert_eq!(piece_value(Piece::B_DRAGON), Value(DRAGON_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::EMPTY), Value(0));
+    assert_eq!(TABLE.piece_value(Piece::B_PAWN), Value(PAWN_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_LANCE), Value(LANCE_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_KNIGHT), Value(KNIGHT_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_SILVER), Value(SILVER_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_BISHOP), Value(BISHOP_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_ROOK), Value(ROOK_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_GOLD), Value(GOLD_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_KING), Value(KING_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_PRO_PAWN), Value(PRO_PAWN_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_PRO_LANCE), Value(PRO_LANCE_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_PRO_KNIGHT), Value(PRO_KNIGHT_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_PRO_SILVER), Value(PRO_SILVER_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_HORSE), Value(HORSE_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::B_DRAGON), Value(DRAGON_VALUE));
 
-    assert_eq!(piece_value(Piece::W_PAWN), Value(PAWN_VALUE));
-    assert_eq!(piece_value(Piece::W_LANCE), Value(LANCE_VALUE));
-    assert_eq!(piece_value(Piece::W_KNIGHT), Value(KNIGHT_VALUE));
-    assert_eq!(piece_value(Piece::W_SILVER), Value(SILVER_VALUE));
-    assert_eq!(piece_value(Piece::W_BISHOP), Value(BISHOP_VALUE));
-    assert_eq!(piece_value(Piece::W_ROOK), Value(ROOK_VALUE));
-    assert_eq!(piece_value(Piece::W_GOLD), Value(GOLD_VALUE));
-    assert_eq!(piece_value(Piece::W_KING), Value(KING_VALUE));
-    assert_eq!(piece_value(Piece::W_PRO_PAWN), Value(PRO_PAWN_VALUE));
-    assert_eq!(piece_value(Piece::W_PRO_LANCE), Value(PRO_LANCE_VALUE));
-    assert_eq!(piece_value(Piece::W_PRO_KNIGHT), Value(PRO_KNIGHT_VALUE));
-    assert_eq!(piece_value(Piece::W_PRO_SILVER), Value(PRO_SILVER_VALUE));
-    assert_eq!(piece_value(Piece::W_HORSE), Value(HORSE_VALUE));
-    assert_eq!(piece_value(Piece::W_DRAGON), Value(DRAGON_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_PAWN), Value(PAWN_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_LANCE), Value(LANCE_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_KNIGHT), Value(KNIGHT_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_SILVER), Value(SILVER_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_BISHOP), Value(BISHOP_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_ROOK), Value(ROOK_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_GOLD), Value(GOLD_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_KING), Value(KING_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_PRO_PAWN), Value(PRO_PAWN_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_PRO_LANCE), Value(PRO_LANCE_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_PRO_KNIGHT), Value(PRO_KNIGHT_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_PRO_SILVER), Value(PRO_SILVER_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_HORSE), Value(HORSE_VALUE));
+    assert_eq!(TABLE.piece_value(Piece::W_DRAGON), Value(DRAGON_VALUE));
 }
 
 #[test]
 fn test_capture_piece_value() {
-    assert_eq!(capture_piece_type_value(PieceType::PAWN), Value(CAPTURE_PAWN_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::LANCE), Value(CAPTURE_LANCE_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::KNIGHT), Value(CAPTURE_KNIGHT_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::SILVER), Value(CAPTURE_SILVER_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::BISHOP), Value(CAPTURE_BISHOP_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::ROOK), Value(CAPTURE_ROOK_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::GOLD), Value(CAPTURE_GOLD_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::KING), Value(CAPTURE_KING_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::PRO_PAWN), Value(CAPTURE_PRO_PAWN_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::PRO_LANCE), Value(CAPTURE_PRO_LANCE_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::PAWN), Value(CAPTURE_PAWN_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::LANCE), Value(CAPTURE_LANCE_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::KNIGHT), Value(CAPTURE_KNIGHT_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::SILVER), Value(CAPTURE_SILVER_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::BISHOP), Value(CAPTURE_BISHOP_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::ROOK), Value(CAPTURE_ROOK_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::GOLD), Value(CAPTURE_GOLD_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::KING), Value(CAPTURE_KING_VALUE));
+    assert_eq!(
+        TABLE.capture_piece_type_value(PieceType::PRO_PAWN),
+        Value(CAPTURE_PRO_PAWN_VALUE)
+    );
     assert_eq!(
-        capture_piece_type_value(PieceType::PRO_KNIGHT),
+        TABLE.capture_piece_type_value(PieceType::PRO_LANCE),
+        Value(CAPTURE_PRO_LANCE_VALUE)
+    );
+    assert_eq!(
+        TABLE.capture_piece_type_value(PieceType::PRO_KNIGHT),
         Value(CAPTURE_PRO_KNIGHT_VALUE)
     );
     assert_eq!(
-        capture_piece_type_value(PieceType::PRO_SILVER),
+        TABLE.capture_piece_type_value(PieceType::PRO_SILVER),
         Value(CAPTURE_PRO_SILVER_VALUE)
     );
-    assert_eq!(capture_piece_type_value(PieceType::HORSE), Value(CAPTURE_HORSE_VALUE));
-    assert_eq!(capture_piece_type_value(PieceType::DRAGON), Value(CAPTURE_DRAGON_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::HORSE), Value(CAPTURE_HORSE_VALUE));
+    assert_eq!(TABLE.capture_piece_type_value(PieceType::DRAGON), Value(CAPTURE_DRAGON_VALUE));
 
-    assert_eq!(capture_piece_value(Piece::EMPTY), Value(0));
-    assert_eq!(capture_piece_value(Piece::B_PAWN), Value(CAPTURE_PAWN_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_LANCE), Value(CAPTURE_LANCE_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_KNIGHT), Value(CAPTURE_KNIGHT_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_SILVER), Value(CAPTURE_SILVER_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_BISHOP), Value(CAPTURE_BISHOP_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_ROOK), Value(CAPTURE_ROOK_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_GOLD), Value(CAPTURE_GOLD_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_KING), Value(CAPTURE_KING_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_PRO_PAWN), Value(CAPTURE_PRO_PAWN_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_PRO_LANCE), Value(CAPTURE_PRO_LANCE_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_PRO_KNIGHT), Value(CAPTURE_PRO_KNIGHT_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_PRO_SILVER), Value(CAPTURE_PRO_SILVER_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_HORSE), Value(CAPTURE_HORSE_VALUE));
-    assert_eq!(capture_piece_value(Piece::B_DRAGON), Value(CAPTURE_DRAGON_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::EMPTY), Value(0));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_PAWN), Value(CAPTURE_PAWN_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_LANCE), Value(CAPTURE_LANCE_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_KNIGHT), Value(CAPTURE_KNIGHT_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_SILVER), Value(CAPTURE_SILVER_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_BISHOP), Value(CAPTURE_BISHOP_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_ROOK), Value(CAPTURE_ROOK_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_GOLD), Value(CAPTURE_GOLD_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_KING), Value(CAPTURE_KING_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_PRO_PAWN), Value(CAPTURE_PRO_PAWN_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_PRO_LANCE), Value(CAPTURE_PRO_LANCE_VALUE));
+    assert_eq!(
+        TABLE.capture_piece_value(Piece::B_PRO_KNIGHT),
+        Value(CAPTURE_PRO_KNIGHT_VALUE)
+    );
+    assert_eq!(
+        TABLE.capture_piece_value(Piece::B_PRO_SILVER),
+        Value(CAPTURE_PRO_SILVER_VALUE)
+    );
+    assert_eq!(TABLE.capture_piece_value(Piece::B_HORSE), Value(CAPTURE_HORSE_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::B_DRAGON), Value(CAPTURE_DRAGON_VALUE));
 
-    assert_eq!(capture_piece_value(Piece::W_PAWN), Value(CAPTURE_PAWN_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_LANCE), Value(CAPTURE_LANCE_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_KNIGHT), Value(CAPTURE_KNIGHT_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_SILVER), Value(CAPTURE_SILVER_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_BISHOP), Value(CAPTURE_BISHOP_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_ROOK), Value(CAPTURE_ROOK_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_GOLD), Value(CAPTURE_GOLD_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_KING), Value(CAPTURE_KING_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_PRO_PAWN), Value(CAPTURE_PRO_PAWN_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_PRO_LANCE), Value(CAPTURE_PRO_LANCE_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_PRO_KNIGHT), Value(CAPTURE_PRO_KNIGHT_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_PRO_SILVER), Value(CAPTURE_PRO_SILVER_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_HORSE), Value(CAPTURE_HORSE_VALUE));
-    assert_eq!(capture_piece_value(Piece::W_DRAGON), Value(CAPTURE_DRAGON_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_PAWN), Value(CAPTURE_PAWN_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_LANCE), Value(CAPTURE_LANCE_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_KNIGHT), Value(CAPTURE_KNIGHT_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_SILVER), Value(CAPTURE_SILVER_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_BISHOP), Value(CAPTURE_BISHOP_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_ROOK), Value(CAPTURE_ROOK_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_GOLD), Value(CAPTURE_GOLD_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_KING), Value(CAPTURE_KING_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_PRO_PAWN), Value(CAPTURE_PRO_PAWN_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_PRO_LANCE), Value(CAPTURE_PRO_LANCE_VALUE));
+    assert_eq!(
+        TABLE.capture_piece_value(Piece::W_PRO_KNIGHT),
+        Value(CAPTURE_PRO_KNIGHT_VALUE)
+    );
+    assert_eq!(
+        TABLE.capture_piece_value(Piece::W_PRO_SILVER),
+        Value(CAPTURE_PRO_SILVER_VALUE)
+    );
+    assert_eq!(TABLE.capture_piece_value(Piece::W_HORSE), Value(CAPTURE_HORSE_VALUE));
+    assert_eq!(TABLE.capture_piece_value(Piece::W_DRAGON), Value(CAPTURE_DRAGON_VALUE));
 }
 
 #[test]
 fn test_promote_piece_value() {
-    assert_eq!(promote_piece_type_value(PieceType::PAWN), Value(PROMOTE_PAWN_VALUE));
-    assert_eq!(promote_piece_type_value(PieceType::LANCE), Value(PROMOTE_LANCE_VALUE));
-    assert_eq!(promote_piece_type_value(PieceType::KNIGHT), Value(PROMOTE_KNIGHT_VALUE));
-    assert_eq!(promote_piece_type_value(PieceType::SILVER), Value(PROMOTE_SILVER_VALUE));
-    assert_eq!(promote_piece_type_value(PieceType::BISHOP), Value(PROMOTE_BISHOP_VALUE));
-    assert_eq!(promote_piece_type_value(PieceType::ROOK), Value(PROMOTE_ROOK_VALUE));
+    assert_eq!(TABLE.promote_piece_type_value(PieceType::PAWN), Value(PROMOTE_PAWN_VALUE));
+    assert_eq!(TABLE.promote_piece_type_value(PieceType::LANCE), Value(PROMOTE_LANCE_VALUE));
+    assert_eq!(TABLE.promote_piece_type_value(PieceType::KNIGHT), Value(PROMOTE_KNIGHT_VALUE));
+    assert_eq!(TABLE.promote_piece_type_value(PieceType::SILVER), Value(PROMOTE_SILVER_VALUE));
+    assert_eq!(TABLE.promote_piece_type_value(PieceType::BISHOP), Value(PROMOTE_BISHOP_VALUE));
+    assert_eq!(TABLE.promote_piece_type_value(PieceType::ROOK), Value(PROMOTE_ROOK_VALUE));
+}
+
+#[test]
+fn test_lva_value() {
+    let lva = |pt| TABLE.lva_value(pt);
+    assert!(lva(PieceType::PAWN) < lva(PieceType::LANCE));
+    assert!(lva(PieceType::SILVER) < lva(PieceType::PRO_PAWN));
+    assert_eq!(lva(PieceType::PRO_PAWN), lva(PieceType::PRO_SILVER));
+    assert!(lva(PieceType::PRO_SILVER) < lva(PieceType::GOLD));
+    assert!(lva(PieceType::ROOK) < lva(PieceType::DRAGON));
+    assert!(lva(PieceType::DRAGON) < lva(PieceType::KING));
+}
+
+#[cfg(feature = "tune")]
+#[test]
+fn test_piece_value_table() {
+    assert_eq!(PieceValueTable::new(&PieceValues::DEFAULT), PieceValueTable::DEFAULT);
+
+    let mut values = PieceValues::DEFAULT;
+    assert!(values.set("ProPawn", 1000));
+    assert!(!values.set("King", 1000));
+    assert_eq!(values.pro_pawn, 1000);
+    let table = PieceValueTable::new(&values);
+    assert_eq!(table.piece_value(Piece::W_PRO_PAWN), Value(1000));
+    assert_eq!(table.capture_piece_value(Piece::W_PRO_PAWN), Value(1000 + PAWN_VALUE));
+    assert_eq!(table.promote_piece_type_value(PieceType::PAWN), Value(1000 - PAWN_VALUE));
+    // The attackers are ordered by the values.
+    assert!(table.lva_value(PieceType::PRO_PAWN) > table.lva_value(PieceType::HORSE));
+    assert!(table.lva_value(PieceType::PRO_PAWN) < table.lva_value(PieceType::DRAGON));
+    assert!(table.lva_value(PieceType::PRO_LANCE) < table.lva_value(PieceType::GOLD));
+    assert!(table.lva_value(PieceType::DRAGON) < table.lva_value(PieceType::KING));
 }
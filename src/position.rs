@@ -3,12 +3,14 @@ use crate::bitboard::*;
 use crate::evaluate::kppt::*;
 use crate::hand::*;
 use crate::huffman_code::*;
+use crate::movegen::*;
 use crate::movetypes::*;
 use crate::piecevalue::*;
 use crate::sfen::*;
 use crate::types::*;
 use rand::prelude::*;
 use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
 use std::convert::TryFrom;
 use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
@@ -27,14 +29,16 @@ impl IsSearchingTrait for NotSearchingType {
     const IS_SEARCHING: bool = false;
 }
 
+// The result of is_repetition() from the point of view of the side to move.
+// Only the positions with the same side to move are compared, and Draw, Win and Lose require the same hands too.
 #[derive(Debug, PartialEq, Eq)]
 pub enum Repetition {
     Not,
     Draw,
-    Win,
-    Lose,
-    Superior,
-    Inferior,
+    Win,      // the opponent gave check on every move of the repetition (perpetual check).
+    Lose,     // we gave check on every move of the repetition.
+    Superior, // the same board, and our hand is equal or superior to the earlier one.
+    Inferior, // the same board, and our hand is inferior to the earlier one.
 }
 
 #[derive(Clone)]
@@ -240,6 +244,7 @@ impl EvalIndexToEvalListIndex {
 #[derive(Clone)]
 pub struct StateInfo {
     material: Value,
+    phase: i32, // The sum of the values of the non-king pieces on the board.
     plies_from_null: i32,
     continuous_checks: [i32; Color::NUM],
     board_key: Key,
@@ -258,6 +263,7 @@ impl StateInfo {
     fn new() -> StateInfo {
         StateInfo {
             material: Value(0),
+            phase: 0,
             plies_from_null: 0,
             continuous_checks: [0, 0],
             board_key: Key(0),
@@ -275,6 +281,7 @@ impl StateInfo {
     unsafe fn new_from_old_state(old_state: &StateInfo) -> StateInfo {
         StateInfo {
             material: old_state.material,
+            phase: old_state.phase,
             plies_from_null: old_state.plies_from_null,
             continuous_checks: old_state.continuous_checks,
             board_key: std::mem::MaybeUninit::uninit().assume_init(),
@@ -289,12 +296,13 @@ impl StateInfo {
             changed_eval_index_captured: std::mem::MaybeUninit::uninit().assume_init(),
         }
     }
-    fn new_from_position(pos: &PositionBase) -> StateInfo {
+    fn new_from_position(pos: &PositionBase, table: &PieceValueTable) -> StateInfo {
         let us = pos.side_to_move();
         let them = us.inverse();
         let king_sq = pos.king_square(us);
         StateInfo {
-            material: StateInfo::new_material(pos),
+            material: StateInfo::new_material(pos, table),
+            phase: StateInfo::new_phase(pos),
             plies_from_null: 0,
             continuous_checks: [0, 0],
             board_key: StateInfo::new_board_key(pos),
@@ -309,7 +317,7 @@ impl StateInfo {
             changed_eval_index_captured: ChangedEvalIndex::ZERO,
         }
     }
-    fn new_material(pos: &PositionBase) -> Value {
+    fn new_material(pos: &PositionBase, table: &PieceValueTable) -> Value {
         let mut val = Value(0);
         for &pt in [
             PieceType::PAWN,
@@ -329,14 +337,19 @@ impl StateInfo {
         .iter()
         {
             let num = pos.pieces_cp(Color::BLACK, pt).count_ones() as i32 - pos.pieces_cp(Color::WHITE, pt).count_ones() as i32;
-            val += Value(num * piece_type_value(pt).0);
+            val += Value(num * table.piece_type_value(pt).0);
         }
         for &pt in PieceType::ALL_HAND.iter() {
             let num = pos.hand(Color::BLACK).num(pt) as i32 - pos.hand(Color::WHITE).num(pt) as i32;
-            val += Value(num * piece_type_value(pt).0);
+            val += Value(num * table.piece_type_value(pt).0);
         }
         val
     }
+    fn new_phase(pos: &PositionBase) -> i32 {
+        pos.occupied_bb()
+            .map(|sq| phase_value(PieceType::new(pos.piece_on(sq))))
+            .sum()
+    }
     fn new_board_key(pos: &PositionBase) -> Key {
         let mut key = Key(0);
         for sq in pos.occupied_bb() {
@@ -377,6 +390,7 @@ impl StateInfo {
     #[allow(dead_code)]
     pub const ZERO: StateInfo = StateInfo {
         material: Value(0),
+        phase: 0,
         plies_from_null: 0,
         continuous_checks: [0, 0],
         board_key: Key(0),
@@ -435,6 +449,12 @@ impl PositionBase {
             let rank_str = rank_str_vec[rank_idx as usize];
             let mut file_idx: usize = 0;
             let re = regex::Regex::new(r"(\d+|\+?[[:alpha:]])").unwrap();
+            let unmatched = re.replace_all(rank_str, "");
+            if !unmatched.is_empty() {
+                return Err(SfenError::InvalidPieceCharactors {
+                    chars: unmatched.to_string(),
+                });
+            }
             for cap in re.captures_iter(rank_str) {
                 if file_idx >= File::NUM {
                     return Err(SfenError::InvalidNumberOfFiles { files: file_idx });
@@ -460,6 +480,9 @@ impl PositionBase {
                     });
                 }
             }
+            if file_idx != File::NUM {
+                return Err(SfenError::InvalidNumberOfFiles { files: file_idx });
+            }
         }
         pos.set_golds_bb();
         for c in Color::ALL.iter() {
@@ -468,6 +491,9 @@ impl PositionBase {
                 Some(sq) => pos.king_squares[c.0 as usize] = sq,
                 None => return Err(SfenError::KingIsNothing { c: *c }),
             }
+            if bb.to_bool() {
+                return Err(SfenError::TooManyKings { c: *c });
+            }
         }
         match side_to_move_str {
             "b" => pos.side_to_move = Color::BLACK,
@@ -481,6 +507,12 @@ impl PositionBase {
         if hands_str != "-" {
             let mut hand_num: i64 = 1;
             let re = regex::Regex::new(r"(\d+|[[:alpha:]])").unwrap();
+            let unmatched = re.replace_all(hands_str, "");
+            if !unmatched.is_empty() {
+                return Err(SfenError::InvalidHandPieceCharactors {
+                    chars: unmatched.to_string(),
+                });
+            }
             for cap in re.captures_iter(hands_str) {
                 let token: &str = &cap[0];
                 if let Ok(digit) = token.to_string().parse::<i64>() {
@@ -719,6 +751,32 @@ impl PositionBase {
             self.by_color_bb.get_unchecked_mut(c.0 as usize).xor(sq);
         }
     }
+    // The board is moved by square_fn. If flip_colors is true, the colors of the pieces, the hands and the side to move are swapped.
+    fn transform(&self, square_fn: fn(Square) -> Square, flip_colors: bool) -> PositionBase {
+        let mut base = PositionBase {
+            board: [Piece::EMPTY; Square::NUM],
+            by_type_bb: [Bitboard::ZERO; PieceType::NUM],
+            by_color_bb: [Bitboard::ZERO; Color::NUM],
+            golds_bb: Bitboard::ZERO,
+            hands: self.hands,
+            game_ply: self.game_ply,
+            king_squares: [Square(0), Square(0)],
+            side_to_move: self.side_to_move,
+        };
+        for sq in self.occupied_bb() {
+            let pc = self.piece_on(sq);
+            base.put_piece(if flip_colors { pc.inverse() } else { pc }, square_fn(sq));
+        }
+        if flip_colors {
+            base.hands.swap(0, 1);
+            base.side_to_move = self.side_to_move.inverse();
+        }
+        for &c in Color::ALL.iter() {
+            base.king_squares[c.0 as usize] = base.pieces_cp(c, PieceType::KING).lsb_unchecked();
+        }
+        base.set_golds_bb();
+        base
+    }
     fn put_piece(&mut self, pc: Piece, sq: Square) {
         debug_assert!(!self.pieces_p(PieceType::new(pc)).is_set(sq));
         debug_assert!(!self.pieces_c(Color::new(pc)).is_set(sq));
@@ -869,6 +927,106 @@ impl PositionBase {
         s += if self.side_to_move == Color::BLACK { "+\n" } else { "-\n" };
         s
     }
+    // Hand pieces in USI notation such as "R2P". "-" if nothing in hand.
+    fn hand_to_usi_string(&self, c: Color) -> String {
+        let mut s = "".to_string();
+        for pt in PieceType::ALL_HAND_FOR_SFEN.iter() {
+            let num = self.hand(c).num(*pt);
+            if 2 <= num {
+                s += &num.to_string();
+            }
+            if num != 0 {
+                s += pt.to_usi_str();
+            }
+        }
+        if s.is_empty() {
+            s += "-";
+        }
+        s
+    }
+    pub fn to_board_string(&self) -> String {
+        let mut s: String = "".to_string();
+        s += "  9  8  7  6  5  4  3  2  1\n";
+        for rank in Rank::ALL_FROM_UPPER.iter() {
+            for file in File::ALL_FROM_LEFT.iter() {
+                let sq = Square::new(*file, *rank);
+                let pc = self.piece_on(sq);
+                s += &format!("{:>3}", if pc == Piece::EMPTY { "." } else { pc.to_usi_str() });
+            }
+            s += " ";
+            s.push(rank.to_usi_char());
+            s += "\n";
+        }
+        s += &format!("Black hand: {}\n", self.hand_to_usi_string(Color::BLACK));
+        s += &format!("White hand: {}\n", self.hand_to_usi_string(Color::WHITE));
+        s += if self.side_to_move == Color::BLACK {
+            "Side to move: Black\n"
+        } else {
+            "Side to move: White\n"
+        };
+        s
+    }
+    // BOD format used in KIF files. The hand counts are written in kanji numerals.
+    pub fn to_kif_board(&self) -> String {
+        fn kanji_number(num: u32) -> String {
+            const DIGITS: [&str; 10] = ["", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+            let mut s = "".to_string();
+            if 10 <= num {
+                if 20 <= num {
+                    s += DIGITS[(num / 10) as usize];
+                }
+                s += "十";
+            }
+            s += DIGITS[(num % 10) as usize];
+            s
+        }
+        let hand_to_kif_string = |c: Color| -> String {
+            let mut s = "".to_string();
+            for pt in PieceType::ALL_HAND_FOR_SFEN.iter() {
+                let num = self.hand(c).num(*pt);
+                if num != 0 {
+                    s += pt.to_kif_str();
+                    if 2 <= num {
+                        s += &kanji_number(num);
+                    }
+                    s += "　";
+                }
+            }
+            if s.is_empty() {
+                s += "なし";
+            }
+            s
+        };
+        const RANKS: [&str; Rank::NUM] = ["一", "二", "三", "四", "五", "六", "七", "八", "九"];
+        let mut s: String = "".to_string();
+        s += &format!("後手の持駒：{}\n", hand_to_kif_string(Color::WHITE));
+        s += "  ９ ８ ７ ６ ５ ４ ３ ２ １\n";
+        s += "+---------------------------+\n";
+        for (i, rank) in Rank::ALL_FROM_UPPER.iter().enumerate() {
+            s += "|";
+            for file in File::ALL_FROM_LEFT.iter() {
+                let sq = Square::new(*file, *rank);
+                let pc = self.piece_on(sq);
+                if pc == Piece::EMPTY {
+                    s += " ・";
+                } else {
+                    s += if Color::new(pc) == Color::BLACK { " " } else { "v" };
+                    s += PieceType::new(pc).to_kif_str();
+                }
+            }
+            s += "|";
+            s += RANKS[i];
+            s += "\n";
+        }
+        s += "+---------------------------+\n";
+        s += &format!("先手の持駒：{}\n", hand_to_kif_string(Color::BLACK));
+        s += if self.side_to_move == Color::BLACK {
+            "先手番\n"
+        } else {
+            "後手番\n"
+        };
+        s
+    }
     pub fn print(&self) {
         println!("{}", self.to_csa_string());
     }
@@ -923,6 +1081,18 @@ impl PositionBase {
     }
 }
 
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.base.to_board_string())
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Position {
     pub base: PositionBase,
     #[cfg(feature = "kppt")]
@@ -930,20 +1100,30 @@ pub struct Position {
     #[cfg(feature = "kppt")]
     eval_index_to_eval_list_index: EvalIndexToEvalListIndex,
     states: Vec<StateInfo>,
-    nodes: Arc<AtomicI64>,
+    nodes: Arc<AtomicI64>, // nodes_local published for the other threads.
+    nodes_local: u64,
+    #[cfg(feature = "tune")]
+    piece_values: Arc<PieceValueTable>,
 }
 
 impl Position {
+    pub const MAX_GAME_PHASE: i32 = 128;
+    const NODES_PUBLISH_INTERVAL: u64 = 1024; // power of 2
+
     pub fn new() -> Position {
-        Position::new_from_sfen(START_SFEN).unwrap()
+        Position::from_sfen(START_SFEN).unwrap()
     }
-    pub fn new_from_sfen(sfen: &str) -> Result<Position, SfenError> {
+    pub fn handicap(kind: Handicap) -> Position {
+        Position::from_sfen(&kind.to_sfen()).unwrap()
+    }
+    // The inverse of to_sfen().
+    pub fn from_sfen(sfen: &str) -> Result<Position, SfenError> {
         Self::new_from_sfen_args(sfen.split_whitespace().collect::<Vec<&str>>().as_slice())
     }
     pub fn new_from_sfen_args(sfen_slice: &[&str]) -> Result<Position, SfenError> {
         match PositionBase::new_from_sfen_args(sfen_slice) {
             Ok(base) => {
-                let state = StateInfo::new_from_position(&base);
+                let state = StateInfo::new_from_position(&base, &PieceValueTable::DEFAULT);
                 #[cfg(feature = "kppt")]
                 let eval_list = EvalList::new(&base);
                 #[cfg(feature = "kppt")]
@@ -956,8 +1136,12 @@ impl Position {
                     eval_index_to_eval_list_index,
                     states: Vec::new(),
                     nodes: Arc::new(AtomicI64::new(0)),
+                    nodes_local: 0,
+                    #[cfg(feature = "tune")]
+                    piece_values: DEFAULT_PIECE_VALUE_TABLE.clone(),
                 };
                 pos.init_states_and_push(state);
+                pos.validate()?;
                 debug_assert!(pos.is_ok());
                 Ok(pos)
             }
@@ -967,7 +1151,7 @@ impl Position {
     pub fn new_from_huffman_coded_position(hcp: &HuffmanCodedPosition) -> Result<Position, u32> {
         match PositionBase::new_from_huffman_coded_position(hcp) {
             Ok(base) => {
-                let state = StateInfo::new_from_position(&base);
+                let state = StateInfo::new_from_position(&base, &PieceValueTable::DEFAULT);
                 #[cfg(feature = "kppt")]
                 let eval_list = EvalList::new(&base);
                 #[cfg(feature = "kppt")]
@@ -980,6 +1164,9 @@ impl Position {
                     eval_index_to_eval_list_index,
                     states: Vec::new(),
                     nodes: Arc::new(AtomicI64::new(0)),
+                    nodes_local: 0,
+                    #[cfg(feature = "tune")]
+                    piece_values: DEFAULT_PIECE_VALUE_TABLE.clone(),
                 };
                 pos.init_states_and_push(state);
                 debug_assert!(pos.is_ok());
@@ -988,6 +1175,85 @@ impl Position {
             Err(err) => Err(err),
         }
     }
+    // The number of the kings, the king of the side not to move, the pieces that can't move and the number of the pieces.
+    pub fn validate(&self) -> Result<(), IllegalPosition> {
+        for &c in [Color::BLACK, Color::WHITE].iter() {
+            match self.pieces_cp(c, PieceType::KING).count_ones() {
+                0 => return Err(IllegalPosition::KingIsNothing { c }),
+                1 => {}
+                _ => return Err(IllegalPosition::TooManyKings { c }),
+            }
+        }
+        let them = self.side_to_move().inverse();
+        if self
+            .attackers_to(self.side_to_move(), self.king_square(them), &self.occupied_bb())
+            .to_bool()
+        {
+            return Err(IllegalPosition::KingCanBeCaptured { c: them });
+        }
+        for &c in [Color::BLACK, Color::WHITE].iter() {
+            let rank1 = Bitboard::rank_mask(Rank::new_from_color_and_rank_as_black(c, RankAsBlack::RANK1));
+            let rank2 = Bitboard::rank_mask(Rank::new_from_color_and_rank_as_black(c, RankAsBlack::RANK2));
+            let mut bb = (self.pieces_cpp(c, PieceType::PAWN, PieceType::LANCE) & rank1)
+                | (self.pieces_cp(c, PieceType::KNIGHT) & (rank1 | rank2));
+            if let Some(sq) = bb.pop_lsb() {
+                return Err(IllegalPosition::PieceCannotMove {
+                    pc: self.piece_on(sq),
+                    sq,
+                });
+            }
+        }
+        let supplies: [(&[PieceType], i64); 7] = [
+            (&[PieceType::PAWN, PieceType::PRO_PAWN], 18),
+            (&[PieceType::LANCE, PieceType::PRO_LANCE], 4),
+            (&[PieceType::KNIGHT, PieceType::PRO_KNIGHT], 4),
+            (&[PieceType::SILVER, PieceType::PRO_SILVER], 4),
+            (&[PieceType::GOLD], 4),
+            (&[PieceType::BISHOP, PieceType::HORSE], 2),
+            (&[PieceType::ROOK, PieceType::DRAGON], 2),
+        ];
+        for &(pts, max) in supplies.iter() {
+            let number = i64::from(
+                pts.iter().fold(0, |sum, &pt| sum + self.pieces_p(pt).count_ones())
+                    + self.hand(Color::BLACK).num(pts[0])
+                    + self.hand(Color::WHITE).num(pts[0]),
+            );
+            if number > max {
+                return Err(IllegalPosition::TooManyPieces { pt: pts[0], number });
+            }
+        }
+        Ok(())
+    }
+    fn new_from_base(base: PositionBase) -> Position {
+        let state = StateInfo::new_from_position(&base, &PieceValueTable::DEFAULT);
+        #[cfg(feature = "kppt")]
+        let eval_list = EvalList::new(&base);
+        #[cfg(feature = "kppt")]
+        let eval_index_to_eval_list_index = EvalIndexToEvalListIndex::new(&eval_list);
+        let mut pos = Position {
+            base,
+            #[cfg(feature = "kppt")]
+            eval_list,
+            #[cfg(feature = "kppt")]
+            eval_index_to_eval_list_index,
+            states: Vec::new(),
+            nodes: Arc::new(AtomicI64::new(0)),
+            nodes_local: 0,
+            #[cfg(feature = "tune")]
+            piece_values: DEFAULT_PIECE_VALUE_TABLE.clone(),
+        };
+        pos.init_states_and_push(state);
+        debug_assert!(pos.is_ok());
+        pos
+    }
+    // The left-right mirror. The file 1 and the file 9 are swapped. The history of the moves isn't kept.
+    pub fn mirror_files(&self) -> Position {
+        Self::new_from_base(self.base.transform(Square::inverse_file, false))
+    }
+    // The board is rotated 180 degrees, and black and white are swapped. The history of the moves isn't kept.
+    pub fn flip_colors(&self) -> Position {
+        Self::new_from_base(self.base.transform(Square::inverse, true))
+    }
     pub fn new_from_position(pos: &Position, nodes: Arc<AtomicI64>) -> Position {
         let mut p = Position {
             base: pos.base.clone(),
@@ -997,10 +1263,31 @@ impl Position {
             eval_index_to_eval_list_index: pos.eval_index_to_eval_list_index.clone(),
             states: pos.states.clone(),
             nodes,
+            nodes_local: 0,
+            #[cfg(feature = "tune")]
+            piece_values: pos.piece_values.clone(),
         };
         p.reserve_states();
         p
     }
+    #[cfg(not(feature = "tune"))]
+    #[inline]
+    pub fn piece_values(&self) -> &PieceValueTable {
+        &DEFAULT_PIECE_VALUE_TABLE
+    }
+    #[cfg(feature = "tune")]
+    #[inline]
+    pub fn piece_values(&self) -> &PieceValueTable {
+        &self.piece_values
+    }
+    // The material of the current state is made again by the values. The previous states keep the old one.
+    // The phase doesn't depend on the values.
+    #[cfg(feature = "tune")]
+    pub fn set_piece_values(&mut self, values: &PieceValues) {
+        self.piece_values = Arc::new(PieceValueTable::new(values));
+        let material = StateInfo::new_material(&self.base, &self.piece_values);
+        self.st_mut().material = material;
+    }
     #[inline]
     pub fn pieces_c(&self, c: Color) -> Bitboard {
         self.base.pieces_c(c)
@@ -1013,6 +1300,12 @@ impl Position {
     pub fn pieces_cp(&self, c: Color, pt: PieceType) -> Bitboard {
         self.base.pieces_cp(c, pt)
     }
+    // The files of the unpromoted pawns of c. The pawns can't be dropped there (nifu). The promoted pawns don't count.
+    pub fn pawn_files_bb(&self, c: Color) -> Bitboard {
+        self.pieces_cp(c, PieceType::PAWN)
+            .into_iter()
+            .fold(Bitboard::ZERO, |bb, sq| bb | Bitboard::file_mask(File::new(sq)))
+    }
     #[inline]
     pub fn pieces_pp(&self, pt0: PieceType, pt1: PieceType) -> Bitboard {
         self.base.pieces_pp(pt0, pt1)
@@ -1090,6 +1383,10 @@ impl Position {
     pub fn attackers_to_both_color(&self, to: Square, occupied: &Bitboard) -> Bitboard {
         self.base.attackers_to_both_color(to, occupied)
     }
+    // All the pieces of both colors which attack sq in the current position.
+    pub fn attackers_to_square(&self, sq: Square) -> Bitboard {
+        self.attackers_to_both_color(sq, &self.occupied_bb())
+    }
     #[allow(dead_code)]
     pub fn init_states(&mut self) {
         self.states.truncate(0);
@@ -1125,6 +1422,10 @@ impl Position {
     pub fn pinners_for_king(&self, color_of_king: Color) -> Bitboard {
         self.st().check_info.pinners_for_king(color_of_king)
     }
+    // The pieces of c which are pinned to the king of c.
+    pub fn pinned_pieces(&self, c: Color) -> Bitboard {
+        self.blockers_for_king(c) & self.pieces_c(c)
+    }
     pub fn pseudo_legal<T: IsSearchingTrait>(&self, m: Move) -> bool {
         let us = self.side_to_move();
         let to;
@@ -1264,6 +1565,114 @@ impl Position {
         }
         true
     }
+    // All the legal moves including drops, promotions and unpromotions.
+    // Two pawns on a file (nifu) and drop pawn mate (uchifuzume) are excluded.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = vec![];
+        self.legal_moves_into(&mut moves);
+        moves
+    }
+    // Same as legal_moves(), but reuses buf to avoid allocation.
+    pub fn legal_moves_into(&self, buf: &mut Vec<Move>) {
+        let mut mlist = MoveList::new();
+        mlist.generate::<LegalAllType>(self, 0);
+        buf.clear();
+        buf.extend(mlist.slice(0).iter().map(|x| x.mv));
+    }
+    // The king moves, the captures of the checker and the interpositions. Only the king moves at double check.
+    // The moves of the pinned pieces aren't removed, so check legal() before do_move().
+    pub fn generate_evasions(&self, buf: &mut MoveList) {
+        debug_assert!(self.in_check());
+        buf.generate::<EvasionsAllType>(self, 0);
+    }
+    // In check and no legal moves.
+    // The side to move also loses if it has no legal moves without being in check.
+    pub fn is_mate(&self) -> bool {
+        self.in_check() && self.has_no_legal_moves()
+    }
+    pub fn has_no_legal_moves(&self) -> bool {
+        let mut mlist = MoveList::new();
+        mlist.generate::<LegalType>(self, 0);
+        mlist.size == 0
+    }
+    // perft() is our utility to verify move generation. All the leaf nodes up
+    // to the given depth are generated and counted, and the sum is returned.
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let mut mlist = MoveList::new();
+        mlist.generate::<LegalAllType>(self, 0);
+        if depth == 1 {
+            return mlist.size as u64;
+        }
+        let mut nodes = 0;
+        for i in 0..mlist.size {
+            let m = mlist.ext_moves[i].mv;
+            let gives_check = self.gives_check(m);
+            self.do_move(m, gives_check);
+            nodes += self.perft(depth - 1);
+            self.undo_move(m);
+        }
+        nodes
+    }
+    // perft() with the root moves split across the threads.
+    pub fn perft_parallel(&self, depth: u32, threads: usize) -> u64 {
+        self.perft_parallel_per_thread(depth, threads).iter().sum()
+    }
+    // The leaf node counts searched by each thread of perft_parallel().
+    fn perft_parallel_per_thread(&self, depth: u32, threads: usize) -> Vec<u64> {
+        let mut nodes_per_thread = vec![0; std::cmp::max(threads, 1)];
+        if depth <= 1 {
+            let mut pos = Position::new_from_position(self, Arc::new(AtomicI64::new(0)));
+            nodes_per_thread[0] = pos.perft(depth);
+            return nodes_per_thread;
+        }
+        let mut mlist = MoveList::new();
+        mlist.generate::<LegalAllType>(self, 0);
+        let moves = mlist.slice(0).iter().map(|x| x.mv).collect::<Vec<_>>();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(nodes_per_thread.len())
+            .stack_size(crate::stack_size::STACK_SIZE)
+            .build()
+            .unwrap();
+        let results = pool.install(|| {
+            moves
+                .par_iter()
+                .map(|&m| {
+                    // Each thread has its own copy of the position.
+                    let mut pos = Position::new_from_position(self, Arc::new(AtomicI64::new(0)));
+                    let gives_check = pos.gives_check(m);
+                    pos.do_move(m, gives_check);
+                    (rayon::current_thread_index().unwrap(), pos.perft(depth - 1))
+                })
+                .collect::<Vec<_>>()
+        });
+        for (thread_index, nodes) in results {
+            nodes_per_thread[thread_index] += nodes;
+        }
+        nodes_per_thread
+    }
+    // The leaf node counts of perft() for each root move. depth 0 is treated as depth 1.
+    pub fn perft_divide(&mut self, depth: u32) -> Vec<(Move, u64)> {
+        let mut mlist = MoveList::new();
+        mlist.generate::<LegalAllType>(self, 0);
+        let mut result = Vec::with_capacity(mlist.size);
+        for i in 0..mlist.size {
+            let m = mlist.ext_moves[i].mv;
+            let cnt = if depth <= 1 {
+                1
+            } else {
+                let gives_check = self.gives_check(m);
+                self.do_move(m, gives_check);
+                let cnt = self.perft(depth - 1);
+                self.undo_move(m);
+                cnt
+            };
+            result.push((m, cnt));
+        }
+        result
+    }
     pub fn legal(&self, m: Move) -> bool {
         // Repetition king check is illegal, but this function return legal.
         // Repetition king check is judged illegal(mated) in search functions.
@@ -1281,9 +1690,12 @@ impl Position {
         !self.blockers_for_king(us).is_set(from)
             || is_aligned_and_sq2_is_not_between_sq0_and_sq1(from, m.to(), self.king_square(us))
     }
+    // Static exchange evaluation. true if the exchange on m.to() with the least valuable attackers gains threshold or more.
+    // The sliders behind the captured pieces join the exchange as x-ray attackers.
     pub fn see_ge(&self, m: Move, threshold: Value) -> bool {
+        let table = self.piece_values();
         let to = m.to();
-        let mut swap = capture_piece_value(self.piece_on(to)) - threshold;
+        let mut swap = table.capture_piece_value(self.piece_on(to)) - threshold;
         if swap < Value::ZERO {
             return false;
         }
@@ -1293,7 +1705,7 @@ impl Position {
         } else {
             PieceType::new(self.piece_on(m.from()))
         };
-        swap = capture_piece_type_value(next_victim) - swap;
+        swap = table.capture_piece_type_value(next_victim) - swap;
         // in case next_victim == PieceType::KING return here.
         // ( capture_piece_type_value(PieceType::KING) == Value::ZERO )
         // it is ok if this move is legal.
@@ -1331,67 +1743,67 @@ impl Position {
                 }};
             }
             if attacker_found!(PieceType::PAWN) {
-                swap = capture_piece_type_value(PieceType::PAWN) - swap;
+                swap = table.capture_piece_type_value(PieceType::PAWN) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::LANCE) {
-                swap = capture_piece_type_value(PieceType::LANCE) - swap;
+                swap = table.capture_piece_type_value(PieceType::LANCE) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::KNIGHT) {
-                swap = capture_piece_type_value(PieceType::KNIGHT) - swap;
+                swap = table.capture_piece_type_value(PieceType::KNIGHT) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::PRO_PAWN) {
-                swap = capture_piece_type_value(PieceType::PRO_PAWN) - swap;
+                swap = table.capture_piece_type_value(PieceType::PRO_PAWN) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::PRO_LANCE) {
-                swap = capture_piece_type_value(PieceType::PRO_LANCE) - swap;
+                swap = table.capture_piece_type_value(PieceType::PRO_LANCE) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::PRO_KNIGHT) {
-                swap = capture_piece_type_value(PieceType::PRO_KNIGHT) - swap;
+                swap = table.capture_piece_type_value(PieceType::PRO_KNIGHT) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::SILVER) {
-                swap = capture_piece_type_value(PieceType::SILVER) - swap;
+                swap = table.capture_piece_type_value(PieceType::SILVER) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::PRO_SILVER) {
-                swap = capture_piece_type_value(PieceType::PRO_SILVER) - swap;
+                swap = table.capture_piece_type_value(PieceType::PRO_SILVER) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::GOLD) {
-                swap = capture_piece_type_value(PieceType::GOLD) - swap;
+                swap = table.capture_piece_type_value(PieceType::GOLD) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::BISHOP) {
-                swap = capture_piece_type_value(PieceType::BISHOP) - swap;
+                swap = table.capture_piece_type_value(PieceType::BISHOP) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::HORSE) {
-                swap = capture_piece_type_value(PieceType::HORSE) - swap;
+                swap = table.capture_piece_type_value(PieceType::HORSE) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::ROOK) {
-                swap = capture_piece_type_value(PieceType::ROOK) - swap;
+                swap = table.capture_piece_type_value(PieceType::ROOK) - swap;
                 if swap < res {
                     break;
                 }
             } else if attacker_found!(PieceType::DRAGON) {
-                swap = capture_piece_type_value(PieceType::DRAGON) - swap;
+                swap = table.capture_piece_type_value(PieceType::DRAGON) - swap;
                 if swap < res {
                     break;
                 }
@@ -1468,12 +1880,16 @@ impl Position {
         true
     }
     pub fn is_repetition(&self) -> Repetition {
+        self.is_repetition_with_plies().0
+    }
+    // The plies are the number of the moves back to the repeated position. They are 0 for Repetition::Not.
+    pub fn is_repetition_with_plies(&self) -> (Repetition, i32) {
         const MAX_REPETITION_PLY: i32 = 16;
         let end = std::cmp::min(MAX_REPETITION_PLY, self.st().plies_from_null);
 
         // Repetition state takes at least 4 moves.
         if end < 4 {
-            return Repetition::Not;
+            return (Repetition::Not, 0);
         }
 
         let mut state_index = self.states.len() - 3;
@@ -1483,22 +1899,22 @@ impl Position {
             if self.key() == st.key() {
                 let us = self.side_to_move();
                 if i <= self.st().continuous_check(us) {
-                    return Repetition::Lose;
+                    return (Repetition::Lose, i);
                 }
                 if i <= self.st().continuous_check(us.inverse()) {
-                    return Repetition::Win;
+                    return (Repetition::Win, i);
                 }
-                return Repetition::Draw;
+                return (Repetition::Draw, i);
             } else if self.st().board_key == st.board_key {
                 if self.st().hand_of_side_to_move.is_equal_or_superior(st.hand_of_side_to_move) {
-                    return Repetition::Superior;
+                    return (Repetition::Superior, i);
                 }
                 if st.hand_of_side_to_move.is_equal_or_superior(self.st().hand_of_side_to_move) {
-                    return Repetition::Inferior;
+                    return (Repetition::Inferior, i);
                 }
             }
         }
-        Repetition::Not
+        (Repetition::Not, 0)
     }
     pub fn is_entering_king_win(&self) -> bool {
         // CSA rule.
@@ -1550,6 +1966,31 @@ impl Position {
     pub fn key(&self) -> Key {
         self.st().key()
     }
+    // The key after m without doing m. It's the same as key() after do_move(m).
+    pub fn key_after(&self, m: Move) -> Key {
+        let us = self.side_to_move();
+        let to = m.to();
+        let mut key = self.key() ^ Zobrist::COLOR;
+        if m.is_drop() {
+            let pt_to = PieceType::new(m.piece_dropped());
+            key ^= Zobrist::get_hand(pt_to, self.hand(us).num(pt_to), us);
+            key ^= Zobrist::get_field(pt_to, to, us);
+        } else {
+            let from = m.from();
+            let pc_from = self.piece_on(from);
+            let pc_to = if m.is_promotion() { pc_from.to_promote() } else { pc_from };
+            let captured_piece = self.piece_on(to);
+            if captured_piece != Piece::EMPTY {
+                let pt_captured = PieceType::new(captured_piece);
+                let pt_captured_demoted = pt_captured.to_demote_if_possible();
+                key ^= Zobrist::get_field(pt_captured, to, us.inverse());
+                key ^= Zobrist::get_hand(pt_captured_demoted, self.hand(us).num(pt_captured_demoted) + 1, us);
+            }
+            key ^= Zobrist::get_field(PieceType::new(pc_from), from, us);
+            key ^= Zobrist::get_field(PieceType::new(pc_to), to, us);
+        }
+        key
+    }
     #[inline]
     fn board_key(&self) -> Key {
         self.st().board_key
@@ -1562,6 +2003,10 @@ impl Position {
     pub fn material(&self) -> Value {
         self.st().material
     }
+    // The opening is Self::MAX_GAME_PHASE, and 0 means no pieces on the board except the kings.
+    pub fn game_phase(&self) -> i32 {
+        std::cmp::min(self.st().phase, START_PHASE) * Self::MAX_GAME_PHASE / START_PHASE
+    }
     pub fn material_diff(&self) -> Value {
         self.st().material - self.states[self.states.len() - 2].material
     }
@@ -1584,6 +2029,9 @@ impl Position {
     pub fn to_csa_string(&self) -> String {
         self.base.to_csa_string()
     }
+    pub fn to_kif_board(&self) -> String {
+        self.base.to_kif_board()
+    }
     #[inline]
     pub fn checkers(&self) -> Bitboard {
         self.st().checkers_bb
@@ -1592,9 +2040,13 @@ impl Position {
     pub fn in_check(&self) -> bool {
         self.checkers().to_bool()
     }
-    #[allow(dead_code)]
+    // The number of do_move() of this position. It isn't atomic because only this thread counts it.
     pub fn nodes_searched(&self) -> i64 {
-        (*self.nodes).load(Ordering::Relaxed)
+        self.nodes_local as i64
+    }
+    // The shared counter is updated without the read-modify-write. Only this position writes it.
+    pub fn publish_nodes(&self) {
+        self.nodes.store(self.nodes_local as i64, Ordering::Relaxed);
     }
     pub fn gives_check(&self, m: Move) -> bool {
         let to = m.to();
@@ -1625,7 +2077,10 @@ impl Position {
     }
     pub fn do_move(&mut self, m: Move, gives_check: bool) {
         debug_assert!(self.is_ok());
-        (*self.nodes).fetch_add(1, Ordering::Relaxed);
+        self.nodes_local += 1;
+        if self.nodes_local & (Self::NODES_PUBLISH_INTERVAL - 1) == 0 {
+            self.publish_nodes();
+        }
         let mut board_key = self.board_key() ^ Zobrist::COLOR;
         let mut hand_key = self.hand_key();
         {
@@ -1659,6 +2114,7 @@ impl Position {
             board_key ^= Zobrist::get_field(pt_to, to, us);
             self.base.hands[us.0 as usize].minus_one(pt_to);
             self.base.put_piece(pc_to, to);
+            self.st_mut().phase += phase_value(pt_to);
 
             // set golds_bb before using attackers_to_except_king.
             self.base.set_golds_bb();
@@ -1700,20 +2156,16 @@ impl Position {
 
                 board_key ^= Zobrist::get_field(pt_captured, to, them);
                 hand_key ^= Zobrist::get_hand(pt_captured_demoted, hand_num, us);
-                self.st_mut().material += if us == Color::BLACK {
-                    capture_piece_type_value(pt_captured)
-                } else {
-                    -capture_piece_type_value(pt_captured)
-                };
+                let capture_value = self.piece_values().capture_piece_type_value(pt_captured);
+                self.st_mut().material += if us == Color::BLACK { capture_value } else { -capture_value };
+                self.st_mut().phase -= phase_value(pt_captured);
             } else {
                 captured_piece = Piece::EMPTY;
             }
             let pc_to = if m.is_promotion() {
-                self.st_mut().material += if us == Color::BLACK {
-                    promote_piece_type_value(pt_from)
-                } else {
-                    -promote_piece_type_value(pt_from)
-                };
+                let promote_value = self.piece_values().promote_piece_type_value(pt_from);
+                self.st_mut().material += if us == Color::BLACK { promote_value } else { -promote_value };
+                self.st_mut().phase += phase_value(pt_from.to_promote()) - phase_value(pt_from);
                 pc_from.to_promote()
             } else {
                 pc_from
@@ -1760,6 +2212,7 @@ impl Position {
         self.st_mut().hand_of_side_to_move = self.hand(them);
         self.st_mut().captured_piece = captured_piece;
         self.st_mut().check_info = CheckInfo::new(&self.base);
+        debug_assert_eq!(self.st().phase, StateInfo::new_phase(&self.base));
         debug_assert!(self.is_ok());
     }
     pub fn undo_move(&mut self, m: Move) {
@@ -1832,8 +2285,10 @@ impl Position {
         self.states.pop();
         debug_assert!(self.is_ok());
     }
+    // Pass the turn. The repetition detection doesn't look beyond the null move because plies_from_null is reset.
     pub fn do_null_move(&mut self) {
         debug_assert!(self.is_ok());
+        debug_assert!(!self.in_check());
         {
             let state = self.st().clone();
             self.states.push(state);
@@ -2107,6 +2562,32 @@ impl Position {
         }
         None
     }
+    // All the legal checks are tried after mate_move_in_1ply(), which is faster but doesn't find some mates.
+    // The drop pawn mate isn't returned since it's illegal.
+    pub fn gives_mate_in_one(&self) -> Option<Move> {
+        if let Some(m) = self.mate_move_in_1ply() {
+            return Some(m);
+        }
+        let mut pos = Position::new_from_position(self, Arc::new(AtomicI64::new(0)));
+        let mut mlist = MoveList::new();
+        mlist.generate::<LegalAllType>(&pos, 0);
+        for m in mlist.slice(0).iter().map(|x| x.mv) {
+            if !pos.gives_check(m) {
+                continue;
+            }
+            pos.do_move(m, true);
+            let mated = {
+                let mut replies = MoveList::new();
+                replies.generate::<LegalAllType>(&pos, 0);
+                replies.size == 0
+            };
+            pos.undo_move(m);
+            if mated {
+                return Some(m);
+            }
+        }
+        None
+    }
     pub fn mate_move_in_1ply(&self) -> Option<Move> {
         let us = self.side_to_move();
         let hand = self.hand(us);
@@ -2208,6 +2689,9 @@ impl Position {
             panic!("position is ng. line: {}", line!());
         }
 
+        if self.board_key() != StateInfo::new_board_key(&self.base) || self.hand_key() != StateInfo::new_hand_key(&self.base) {
+            panic!("position is ng. line: {}", line!());
+        }
         if self.pieces_p(PieceType::KING).count_ones() != 2 {
             panic!("position is ng. line: {}", line!());
         }
@@ -2237,15 +2721,24 @@ impl Position {
             panic!("position is ng. line: {}", line!());
         }
 
-        let tmp_state = StateInfo::new_from_position(&self.base);
+        let tmp_state = StateInfo::new_from_position(&self.base, self.piece_values());
         if self.material() != tmp_state.material {
             panic!("position is ng. line: {}", line!());
         }
 
+        if self.st().phase != tmp_state.phase {
+            panic!("position is ng. line: {}", line!());
+        }
+
         if self.key() != tmp_state.key() {
             panic!("position is ng. line: {}", line!());
         }
 
+        // The checkers are updated incrementally by do_move().
+        if self.checkers() != tmp_state.checkers_bb {
+            panic!("position is ng. line: {}", line!());
+        }
+
         #[cfg(feature = "kppt")]
         {
             let mut eval_list_vec_correct = EvalList::new(&self.base)
@@ -2301,7 +2794,7 @@ fn test_position_set() {
         "6n1l/2+S1k4/2lp4p/1np1B2b1/3PP4/1N1S3rP/1P2+pPP+p1/1p1G5/3KG2r1 b GSN2L4Pgs2p 399",
     ];
     for sfen in sfens.iter() {
-        match Position::new_from_sfen(sfen) {
+        match Position::from_sfen(sfen) {
             Ok(pos) => assert_eq!(pos.to_sfen(), sfen.to_string()),
             Err(_) => assert_eq!("".to_string(), sfen.to_string()),
         }
@@ -2322,7 +2815,7 @@ fn test_position_set() {
         ),
     ];
     for &(sfen, pt_twice) in sfens.iter() {
-        match Position::new_from_sfen(sfen) {
+        match Position::from_sfen(sfen) {
             Ok(_) => assert_eq!("".to_string(), sfen.to_string()),
             Err(err) => match err {
                 SfenError::SameHandPieceTwice { pt } => {
@@ -2344,7 +2837,7 @@ fn test_position_set() {
         ),
     ];
     for &(sfen, color_of_king_nothing) in sfens.iter() {
-        match Position::new_from_sfen(sfen) {
+        match Position::from_sfen(sfen) {
             Ok(_) => assert_eq!("".to_string(), sfen.to_string()),
             Err(err) => match err {
                 SfenError::KingIsNothing { c } => {
@@ -2356,11 +2849,279 @@ fn test_position_set() {
     }
 }
 
+#[test]
+fn test_position_from_sfen_round_trip() {
+    let sfens = [
+        START_SFEN,
+        "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+        "1g1+Ps1g2/l8/+N5kgp/2p6/N2P1n1L1/PPP1P1p2/2+b2PNPP/L3G1SRL/2S3K2 b BS5Prp 87",
+        "1n2gks2/l2s3b1/pppppgppn/9/9/P1l1PRP2/1PPPK2Pp/L1GSG1r+l1/1NB6 b Psn2p 51",
+        "2s2g2l/lrgb1k3/npppp1ns1/p7p/2P4pN/5P3/PPGPPKPPP/2L5R/2S1G1SNL b 2Pb 39",
+        "2r3sll/G6b1/3k1g1p1/1KN3p1L/1p5N1/9/lP1PP2P1/p2sG1bR1/1+s1S1P1N1 b 9Pgn 99",
+    ];
+    for sfen in sfens.iter() {
+        assert_eq!(Position::from_sfen(sfen).unwrap().to_sfen(), sfen.to_string());
+    }
+}
+
+#[test]
+fn test_position_set_invalid_sfen() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let sfens = [
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/8/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNLL b - 1",
+            ];
+            for sfen in sfens.iter() {
+                match Position::from_sfen(sfen) {
+                    Err(SfenError::InvalidNumberOfFiles { .. }) => {}
+                    _ => panic!("{} must be an invalid number of files.", sfen),
+                }
+            }
+            match Position::from_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNX b - 1") {
+                Err(SfenError::InvalidPieceCharactors { chars }) => assert_eq!(chars, "X"),
+                _ => panic!(),
+            }
+            match Position::from_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSN* b - 1") {
+                Err(SfenError::InvalidPieceCharactors { chars }) => assert_eq!(chars, "*"),
+                _ => panic!(),
+            }
+            let sfens = [
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b +P 1",
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b K 1",
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b 2 1",
+            ];
+            for sfen in sfens.iter() {
+                match Position::from_sfen(sfen) {
+                    Err(SfenError::InvalidHandPieceCharactors { .. }) => {}
+                    _ => panic!("{} must be invalid hand pieces.", sfen),
+                }
+            }
+            match Position::from_sfen("k8/9/9/9/9/9/9/9/KK7 b - 1") {
+                Err(SfenError::TooManyKings { c }) => assert_eq!(c, Color::BLACK),
+                _ => panic!(),
+            }
+            // The side to move could capture the king.
+            match Position::from_sfen("4k4/4R4/9/9/9/9/9/9/4K4 b - 1") {
+                Err(SfenError::KingCanBeCaptured { c }) => assert_eq!(c, Color::WHITE),
+                _ => panic!(),
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_validate() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            assert!(Position::new().validate().is_ok());
+            let pos = Position::from_sfen("+P3k4/1+N7/9/9/9/9/9/9/+l3K4 w - 1").unwrap();
+            assert!(pos.validate().is_ok());
+            assert!(pos.flip_colors().validate().is_ok());
+            let sfens = [
+                ("P3k4/9/9/9/9/9/9/9/4K4 w - 1", Piece::B_PAWN, Square::SQ91),
+                ("4k4/9/9/9/9/9/9/9/l3K4 b - 1", Piece::W_LANCE, Square::SQ99),
+                ("4k4/N8/9/9/9/9/9/9/4K4 w - 1", Piece::B_KNIGHT, Square::SQ92),
+                ("4k4/9/9/9/9/9/9/8n/4K4 b - 1", Piece::W_KNIGHT, Square::SQ18),
+            ];
+            for &(sfen, expected_pc, expected_sq) in sfens.iter() {
+                match Position::from_sfen(sfen) {
+                    Err(SfenError::PieceCannotMove { pc, sq }) => {
+                        assert_eq!(pc, expected_pc);
+                        assert_eq!(sq, expected_sq);
+                    }
+                    _ => panic!("{} must have a piece that can't move.", sfen),
+                }
+            }
+            match Position::from_sfen("4k4/9/9/9/9/9/9/9/4K3K w - 1") {
+                Err(SfenError::TooManyKings { c }) => assert_eq!(c, Color::BLACK),
+                _ => panic!(),
+            }
+            // The side not to move is in check.
+            match Position::from_sfen("4k4/9/9/9/9/9/9/4r4/4K4 w - 1") {
+                Err(SfenError::KingCanBeCaptured { c }) => assert_eq!(c, Color::BLACK),
+                _ => panic!(),
+            }
+            match Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 b B2b 1") {
+                Err(SfenError::InvalidNumberOfBishops { number }) => assert_eq!(number, 3),
+                _ => panic!(),
+            }
+            match Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 b 10P9p 1") {
+                Err(SfenError::InvalidNumberOfPawns { number }) => assert_eq!(number, 19),
+                _ => panic!(),
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_legal_moves() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let sfens = [
+                (START_SFEN, 30),
+                // maximum number of legal moves.
+                ("R8/2K1S1SSk/4B4/9/9/9/9/9/1L1L1L3 b RBGSNLP3g3n17p 1", 593),
+                // evasions.
+                ("4k4/9/9/9/9/9/9/4r4/4K4 b - 1", 3),
+                // nifu. no pawn drop on the file 5.
+                ("4k4/9/9/9/9/9/4P4/9/4K4 b P 1", 70),
+                // uchifuzume. P*1b is illegal.
+                ("7nk/9/7K1/9/9/9/9/9/9 b P 1", 74),
+            ];
+            let mut buf = vec![];
+            for &(sfen, num) in sfens.iter() {
+                let pos = Position::from_sfen(sfen).unwrap();
+                let moves = pos.legal_moves();
+                assert_eq!(moves.len(), num, "{}", sfen);
+                assert!(moves.iter().all(|&m| pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m)));
+                pos.legal_moves_into(&mut buf);
+                assert_eq!(buf, moves);
+            }
+            let pos = Position::from_sfen("7nk/9/7K1/9/9/9/9/9/9 b P 1").unwrap();
+            assert!(!pos.legal_moves().contains(&Move::new_drop(Piece::B_PAWN, Square::SQ12)));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_perft() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut pos = Position::from_sfen(START_SFEN).unwrap();
+            let key = pos.key();
+            for &(depth, nodes) in [(1, 30), (2, 900), (3, 25470)].iter() {
+                assert_eq!(pos.perft(depth), nodes);
+                let divide = pos.perft_divide(depth);
+                assert_eq!(divide.len(), 30);
+                assert_eq!(divide.iter().map(|&(_, cnt)| cnt).sum::<u64>(), nodes);
+                assert!(pos.key() == key);
+            }
+            assert_eq!(pos.perft(0), 1);
+            assert_eq!(pos.perft(4), 719_731);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_perft_parallel() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut pos = Position::from_sfen(START_SFEN).unwrap();
+            let key = pos.key();
+            for depth in 0..4 {
+                assert_eq!(pos.perft_parallel(depth, 4), pos.perft(depth));
+            }
+            assert!(pos.key() == key);
+            let sfen = "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1";
+            let mut pos = Position::from_sfen(sfen).unwrap();
+            assert_eq!(pos.perft_parallel(3, 2), pos.perft(3));
+            assert_eq!(pos.perft_parallel(3, 1), pos.perft(3));
+
+            let pos = Position::from_sfen(START_SFEN).unwrap();
+            let nodes_per_thread = pos.perft_parallel_per_thread(4, 4);
+            assert_eq!(nodes_per_thread.iter().sum::<u64>(), 719_731);
+            if std::thread::available_parallelism().map_or(1, |n| n.get()) > 1 {
+                assert!(nodes_per_thread.iter().filter(|&&nodes| nodes != 0).count() > 1);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_null_move() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let sfen = "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1";
+            let mut pos = Position::from_sfen(sfen).unwrap();
+            let key = pos.key();
+            let moves = pos.legal_moves();
+
+            pos.do_null_move();
+            assert_eq!(pos.side_to_move(), Color::BLACK);
+            assert!(pos.key() != key);
+            assert!(pos
+                .legal_moves()
+                .iter()
+                .all(|&m| Color::new(m.piece_moved_before_move()) == Color::BLACK));
+            assert!(pos.is_repetition() == Repetition::Not);
+            pos.undo_null_move();
+
+            assert_eq!(pos.to_sfen(), sfen);
+            assert!(pos.key() == key);
+            assert_eq!(pos.legal_moves(), moves);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_attackers_to_square() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::from_sfen("k8/5+R3/3b1l3/4s4/5pg1+r/4GP3/5LN2/9/K4L3 b - 1").unwrap();
+            let mut squares = pos.attackers_to_square(Square::SQ45).collect::<Vec<_>>();
+            squares.sort_by_key(|sq| sq.0);
+            assert_eq!(
+                squares,
+                vec![
+                    Square::SQ35,
+                    Square::SQ37,
+                    Square::SQ43,
+                    Square::SQ46,
+                    Square::SQ54,
+                    Square::SQ56
+                ]
+            );
+            assert!(!pos.attackers_to_square(Square::SQ99).to_bool());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_pinned_pieces() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            // The silver on 5h is pinned by the rook on 5a. The gold on 4h is not pinned.
+            let pos = Position::from_sfen("k3r4/9/9/9/9/9/9/4SG3/4K4 b - 1").unwrap();
+            assert_eq!(pos.pinned_pieces(Color::BLACK).collect::<Vec<_>>(), vec![Square::SQ58]);
+            assert!(!pos.pinned_pieces(Color::WHITE).to_bool());
+
+            // The opponent's piece between the slider and the king is not a pinned piece.
+            let pos = Position::from_sfen("k3r4/9/9/9/9/9/9/4s4/4K4 b - 1").unwrap();
+            assert!(!pos.pinned_pieces(Color::BLACK).to_bool());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
 #[test]
 fn test_position_attackers_to() {
     let sfens = ["lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1"];
     for sfen in sfens.iter() {
-        match Position::new_from_sfen(sfen) {
+        match Position::from_sfen(sfen) {
             Ok(pos) => {
                 assert_eq!(pos.to_sfen(), sfen.to_string());
                 let attackers = pos.attackers_to(Color::WHITE, Square::SQ52, &pos.occupied_bb());
@@ -2374,7 +3135,7 @@ fn test_position_attackers_to() {
         }
     }
     let sfen = "k8/5+R3/3b1l3/4s4/5pg1+r/4GP3/5LN2/9/K4L3 b - 1";
-    match Position::new_from_sfen(sfen) {
+    match Position::from_sfen(sfen) {
         Ok(pos) => {
             let to = Square::SQ45;
             let attackers = pos.attackers_to_both_color(to, &pos.occupied_bb());
@@ -2393,7 +3154,7 @@ fn test_position_attackers_to() {
 #[test]
 fn test_position_slider_blockers() {
     let sfen = "4k4/4l4/4P4/9/4K4/9/9/9/9 b - 1";
-    match Position::new_from_sfen(sfen) {
+    match Position::from_sfen(sfen) {
         Ok(pos) => {
             assert_eq!(pos.to_sfen(), sfen.to_string());
             let blockers_and_pinners_for_king =
@@ -2408,7 +3169,7 @@ fn test_position_slider_blockers() {
 #[test]
 fn test_state_info() {
     let sfen = "4k4/4l4/4L4/9/4K4/9/9/9/9 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     assert_eq!(
         pos.st().ci().blockers_for_king(Color::BLACK),
         Bitboard::square_mask(Square::SQ53)
@@ -2427,7 +3188,7 @@ fn test_state_info() {
     );
 
     let sfen = "4k4/4r4/4R4/9/4K4/9/9/9/9 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     assert_eq!(
         pos.st().ci().blockers_for_king(Color::BLACK),
         Bitboard::square_mask(Square::SQ53)
@@ -2446,7 +3207,7 @@ fn test_state_info() {
     );
 
     let sfen = "4k4/4+r4/4+R4/9/4K4/9/9/9/9 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     assert_eq!(
         pos.st().ci().blockers_for_king(Color::BLACK),
         Bitboard::square_mask(Square::SQ53)
@@ -2465,7 +3226,7 @@ fn test_state_info() {
     );
 
     let sfen = "k8/1b7/2B6/9/4K4/9/9/9/9 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     assert_eq!(
         pos.st().ci().blockers_for_king(Color::BLACK),
         Bitboard::square_mask(Square::SQ73)
@@ -2484,7 +3245,7 @@ fn test_state_info() {
     );
 
     let sfen = "k8/1+b7/2+B6/9/4K4/9/9/9/9 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     assert_eq!(
         pos.st().ci().blockers_for_king(Color::BLACK),
         Bitboard::square_mask(Square::SQ73)
@@ -2509,16 +3270,40 @@ fn test_position_see_ge() {
         .stack_size(crate::stack_size::STACK_SIZE)
         .spawn(|| {
             let sfen = "k8/5+R3/3b1l3/4s4/6g1+r/4GP3/5LN2/9/K4L3 b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let to = Square::SQ45;
             let m = Move::new_unpromote(Square::SQ46, to, Piece::B_PAWN);
             assert_eq!(pos.see_ge(m, Value(0)), true);
 
             let sfen = "k8/9/9/9/9/l8/p8/1B7/1K7 b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let to = Square::SQ97;
             let m = Move::new_unpromote(Square::SQ88, to, Piece::B_BISHOP);
             assert_eq!(pos.see_ge(m, Value(0)), false);
+
+            // winning capture of an undefended rook.
+            let pos = Position::from_sfen("4k4/9/9/9/4r4/9/9/4R4/4K4 b - 1").unwrap();
+            let m = Move::new_from_usi_str("5h5e", &pos).unwrap();
+            assert!(pos.see_ge(m, pos.piece_values().capture_piece_value(Piece::W_ROOK)));
+            assert!(!pos.see_ge(m, pos.piece_values().capture_piece_value(Piece::W_ROOK) + Value(1)));
+
+            // losing capture of a pawn defended twice.
+            let pos = Position::from_sfen("4k4/9/3g1g3/4p4/9/9/4L4/B8/4K4 b - 1").unwrap();
+            let m = Move::new_from_usi_str("9h5d", &pos).unwrap();
+            assert!(!pos.see_ge(m, Value(0)));
+
+            // x-ray. the second lance recaptures through the square the first one left.
+            let pos = Position::from_sfen("8k/9/9/4g4/4p4/9/4L4/4L4/8K b - 1").unwrap();
+            let m = Move::new_from_usi_str("5g5e", &pos).unwrap();
+            assert!(pos.see_ge(m, Value(0)));
+            let pos = Position::from_sfen("8k/9/9/4g4/4p4/9/4L4/9/8K b - 1").unwrap();
+            let m = Move::new_from_usi_str("5g5e", &pos).unwrap();
+            assert!(!pos.see_ge(m, Value(0)));
+
+            // drops capture nothing. the dropped piece may be captured.
+            let pos = Position::from_sfen("8k/9/9/4p4/9/9/9/9/8K b S 1").unwrap();
+            assert!(!pos.see_ge(Move::new_from_usi_str("S*5e", &pos).unwrap(), Value(0)));
+            assert!(pos.see_ge(Move::new_from_usi_str("S*1e", &pos).unwrap(), Value(0)));
         })
         .unwrap()
         .join()
@@ -2541,7 +3326,7 @@ fn test_position_gives_check() {
         ("8k/9/9/9/9/9/9/8G/K7L b Rr 1", vec![("1h2h", CHECK), ("1h1g", NOT_CHECK)]),
     ];
     for (sfen, move_candidates) in array.iter() {
-        let pos = Position::new_from_sfen(sfen).unwrap();
+        let pos = Position::from_sfen(sfen).unwrap();
         for &(move_str, is_check) in move_candidates {
             let m = Move::new_from_usi_str(move_str, &pos);
             assert!(m.is_some());
@@ -2565,7 +3350,7 @@ fn test_position_do_move() {
         ),
     ];
     for (sfen, moves) in sfen_and_moves_array.iter() {
-        let mut pos = Position::new_from_sfen(sfen).unwrap();
+        let mut pos = Position::from_sfen(sfen).unwrap();
         for move_str in moves {
             let m = Move::new_from_usi_str(move_str, &pos);
             assert!(m.is_some());
@@ -2606,7 +3391,7 @@ fn test_check_info_new() {
 #[test]
 fn test_check_info_do_move() {
     let sfen = "9/4R+P2k/9/9/9/9/9/8K/9 b - 1";
-    let mut pos = Position::new_from_sfen(sfen).unwrap();
+    let mut pos = Position::from_sfen(sfen).unwrap();
     let move_str = "4b4a";
     let m = Move::new_from_usi_str(move_str, &pos).unwrap();
     let gives_check = pos.gives_check(m);
@@ -2615,9 +3400,123 @@ fn test_check_info_do_move() {
     assert!(pos.checkers().is_set(Square::SQ52));
 }
 
+#[test]
+fn test_position_checkers() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let do_move = |sfen: &str, usi_move: &str| {
+                let mut pos = Position::from_sfen(sfen).unwrap();
+                assert!(!pos.in_check());
+                let m = Move::new_from_usi_str(usi_move, &pos).unwrap();
+                let gives_check = pos.gives_check(m);
+                assert!(gives_check);
+                pos.do_move(m, gives_check);
+                assert!(pos.in_check());
+                assert!(pos.is_ok());
+                pos
+            };
+            // Direct check by the gold.
+            let pos = do_move("4k4/9/4G4/9/9/9/9/9/4K4 b - 1", "5c5b");
+            assert_eq!(pos.checkers().collect::<Vec<_>>(), vec![Square::SQ52]);
+            // Discovered check by the lance. The silver doesn't give check.
+            let pos = do_move("4k4/9/4S4/9/9/9/9/4L4/3K5 b - 1", "5c6d");
+            assert_eq!(pos.checkers().collect::<Vec<_>>(), vec![Square::SQ58]);
+            // Double check by the rook and the promoted silver.
+            let pos = do_move("4k4/9/4S4/9/4R4/9/9/9/3K5 b - 1", "5c4b+");
+            assert_eq!(pos.checkers().count_ones(), 2);
+            assert!(pos.checkers().contains(Square::SQ55));
+            assert!(pos.checkers().contains(Square::SQ42));
+            // Drop check.
+            let pos = do_move("4k4/9/9/9/9/9/9/9/4K4 b N 1", "N*4c");
+            assert_eq!(pos.checkers().collect::<Vec<_>>(), vec![Square::SQ43]);
+            let mut pos = pos;
+            let m = pos.legal_moves()[0];
+            let gives_check = pos.gives_check(m);
+            pos.do_move(m, gives_check);
+            assert!(pos.is_ok());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_generate_evasions() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let sfens = [
+                // The lance check. Interpositions by the moves and the drops.
+                "4k4/9/4l4/9/9/9/9/9/3GKS3 b GP 1",
+                // The knight check can't be interposed.
+                "4k4/9/9/9/9/9/5n3/3G5/4K4 b R 1",
+                // The bishop check with a pinned rook.
+                "4k4/9/9/9/4r4/9/2b6/4R4/4K4 b S 1",
+                // Double check by the rook and the bishop.
+                "4k4/9/9/9/4r4/9/9/3b5/4K4 b GS 1",
+            ];
+            for sfen in sfens.iter() {
+                let pos = Position::from_sfen(sfen).unwrap();
+                assert!(pos.in_check(), "{}", sfen);
+                let mut evasions = MoveList::new();
+                pos.generate_evasions(&mut evasions);
+                let mut evasions = evasions
+                    .slice(0)
+                    .iter()
+                    .map(|x| x.mv)
+                    .filter(|&m| pos.legal(m))
+                    .collect::<Vec<_>>();
+                // All the moves on the board and from the hand without the move generator.
+                let us = pos.side_to_move();
+                let mut candidates = vec![];
+                for &to in Square::ALL.iter() {
+                    for &from in Square::ALL.iter() {
+                        let pc = pos.piece_on(from);
+                        if pc != Piece::EMPTY && Color::new(pc) == us {
+                            candidates.push(Move::new_unpromote(from, to, pc));
+                            if pc.is_promotable() {
+                                candidates.push(Move::new_promote(from, to, pc));
+                            }
+                        }
+                    }
+                    for &pt in PieceType::ALL_HAND.iter() {
+                        if pos.hand(us).exist(pt) {
+                            candidates.push(Move::new_drop(Piece::new(us, pt), to));
+                        }
+                    }
+                }
+                let mut filtered = candidates
+                    .into_iter()
+                    .filter(|&m| pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m))
+                    .collect::<Vec<_>>();
+                // The evasions don't leave the king in check.
+                for &m in evasions.iter() {
+                    let mut pos = Position::new_from_position(&pos, Arc::new(AtomicI64::new(0)));
+                    let gives_check = pos.gives_check(m);
+                    pos.do_move(m, gives_check);
+                    assert!(!pos
+                        .attackers_to(pos.side_to_move(), pos.king_square(us), &pos.occupied_bb())
+                        .to_bool());
+                }
+                evasions.sort_by_key(|m| m.0.get());
+                filtered.sort_by_key(|m| m.0.get());
+                assert!(!evasions.is_empty(), "{}", sfen);
+                assert_eq!(evasions, filtered, "{}", sfen);
+                if pos.checkers().count_ones() == 2 {
+                    let ksq = pos.king_square(pos.side_to_move());
+                    assert!(evasions.iter().all(|m| !m.is_drop() && m.from() == ksq));
+                }
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
 #[test]
 fn test_huffman_code() {
-    let pos = Position::new_from_sfen(START_SFEN).unwrap();
+    let pos = Position::from_sfen(START_SFEN).unwrap();
     let hcp = HuffmanCodedPosition::from(&pos);
     match Position::new_from_huffman_coded_position(&hcp) {
         Ok(pos_from_hcp) => {
@@ -2635,65 +3534,69 @@ fn test_is_entering_king_win() {
     std::thread::Builder::new()
         .stack_size(crate::stack_size::STACK_SIZE)
         .spawn(|| {
-            let pos = Position::new_from_sfen("1p7/KRRBBPPPP/NN7/9/9/9/9/9/8k b 2P 1").unwrap();
+            let pos = Position::from_sfen("1p7/KRRBBPPPP/NN7/9/9/9/9/9/8k b 2P 1").unwrap();
             assert!(pos.is_entering_king_win());
-            let pos = Position::new_from_sfen("1p7/KRRBBPPPP/NN7/9/9/9/9/9/8k w 2P 1").unwrap();
+            let pos = Position::from_sfen("1p7/KRRBBPPPP/NN7/9/9/9/9/9/8k w 2P 1").unwrap();
             assert!(!pos.is_entering_king_win()); // opponent side is entring king position. but own side is not.
-            let pos = Position::new_from_sfen("pp7/KRRBBPPPP/NN7/9/9/9/9/9/8k b 2P 1").unwrap();
+            let pos = Position::from_sfen("pp7/KRRBBPPPP/NN7/9/9/9/9/9/8k b 2P 1").unwrap();
             assert!(!pos.is_entering_king_win()); // in_check
-            let pos = Position::new_from_sfen("1p7/1RRBBPPPP/NNN6/K8/9/9/9/9/8k b 2P 1").unwrap();
+            let pos = Position::from_sfen("1p7/1RRBBPPPP/NNN6/K8/9/9/9/9/8k b 2P 1").unwrap();
             assert!(!pos.is_entering_king_win()); // not entering king
-            let pos = Position::new_from_sfen("1p7/KRRBBPPPP/N8/9/9/9/9/9/8k b 3P 1").unwrap();
+            let pos = Position::from_sfen("1p7/KRRBBPPPP/N8/9/9/9/9/9/8k b 3P 1").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 10 own pieces on the opponent field.
-            let pos = Position::new_from_sfen("1p7/KRRBBPPPP/N8/N8/9/9/9/9/8k b 2P 1").unwrap();
+            let pos = Position::from_sfen("1p7/KRRBBPPPP/N8/N8/9/9/9/9/8k b 2P 1").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 28 point.
-            let pos = Position::new_from_sfen("1pGGGGS2/KRRB1PPPP/N8/N8/9/9/9/9/8k b 2P 1").unwrap();
+            let pos = Position::from_sfen("1pGGGGS2/KRRB1PPPP/N8/N8/9/9/9/9/8k b 2P 1").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 28 point.
 
-            let pos = Position::new_from_sfen("K8/9/9/9/9/9/nn7/krrbbpppp/1P7 w p 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/9/nn7/krrbbpppp/1P7 w p 2").unwrap();
             assert!(pos.is_entering_king_win());
-            let pos = Position::new_from_sfen("K8/9/9/9/9/9/nn7/krrbbpppp/1P7 b p 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/9/nn7/krrbbpppp/1P7 b p 2").unwrap();
             assert!(!pos.is_entering_king_win()); // opponent side is entring king position. but own side is not.
-            let pos = Position::new_from_sfen("K8/9/9/9/9/9/nn7/krrbbpppp/PP7 w p 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/9/nn7/krrbbpppp/PP7 w p 2").unwrap();
             assert!(!pos.is_entering_king_win()); // in_check
-            let pos = Position::new_from_sfen("K8/9/9/9/9/k8/nn7/1rrbbpppp/1P7 w p 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/k8/nn7/1rrbbpppp/1P7 w p 2").unwrap();
             assert!(!pos.is_entering_king_win()); // not entering king
-            let pos = Position::new_from_sfen("K8/9/9/9/9/9/n8/krrbbpppp/1P7 w 2p 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/9/n8/krrbbpppp/1P7 w 2p 2").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 10 own pieces on the opponent field.
-            let pos = Position::new_from_sfen("K8/9/9/9/9/n8/n8/krrbbpppp/1P7 w p 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/n8/n8/krrbbpppp/1P7 w p 2").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 27 point.
-            let pos = Position::new_from_sfen("K8/9/9/9/9/n8/n8/krrb1pppp/1Pggggs2 w p 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/n8/n8/krrb1pppp/1Pggggs2 w p 2").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 27 point.
 
             // check point of hand big pieces
-            let pos = Position::new_from_sfen("1p7/KRRBPPPPP/NN7/9/9/9/9/9/8k b BP 1").unwrap();
+            let pos = Position::from_sfen("1p7/KRRBPPPPP/NN7/9/9/9/9/9/8k b BP 1").unwrap();
             assert!(pos.is_entering_king_win());
-            let pos = Position::new_from_sfen("1p7/KR+RB+PPPPP/NN7/9/9/9/9/9/8k b BP 1").unwrap();
+            let pos = Position::from_sfen("1p7/KR+RB+PPPPP/NN7/9/9/9/9/9/8k b BP 1").unwrap();
             assert!(pos.is_entering_king_win());
-            let pos = Position::new_from_sfen("1p7/KRRBPPPPP/NN7/9/9/9/9/9/8k w BP 1").unwrap();
+            let pos = Position::from_sfen("1p7/KRRBPPPPP/NN7/9/9/9/9/9/8k w BP 1").unwrap();
             assert!(!pos.is_entering_king_win()); // opponent side is entring king position. but own side is not.
-            let pos = Position::new_from_sfen("pp7/KRRBPPPPP/NN7/9/9/9/9/9/8k b BP 1").unwrap();
+            let pos = Position::from_sfen("pp7/KRRBPPPPP/NN7/9/9/9/9/9/8k b BP 1").unwrap();
             assert!(!pos.is_entering_king_win()); // in_check
-            let pos = Position::new_from_sfen("1p7/1RRBPPPPP/NNN6/K8/9/9/9/9/8k b BP 1").unwrap();
+            let pos = Position::from_sfen("1p7/1RRBPPPPP/NNN6/K8/9/9/9/9/8k b BP 1").unwrap();
             assert!(!pos.is_entering_king_win()); // not entering king
-            let pos = Position::new_from_sfen("1p7/KRRBPPPPP/N8/9/9/9/9/9/8k b B2P 1").unwrap();
+            let pos = Position::from_sfen("1p7/KRRBPPPPP/N8/9/9/9/9/9/8k b B2P 1").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 10 own pieces on the opponent field.
-            let pos = Position::new_from_sfen("1pGGGGS2/KR1BPPPPP/N8/N8/9/9/9/9/8k b BP 1").unwrap();
+            let pos = Position::from_sfen("1pGGGGS2/KR1BPPPPP/N8/N8/9/9/9/9/8k b BP 1").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 28 point.
+            let pos = Position::from_sfen("1p7/KRRBPPPPP/NN7/9/9/9/9/9/8k b B 1").unwrap();
+            assert!(!pos.is_entering_king_win()); // 27 point. one point short.
+            let pos = Position::from_sfen("1p7/KRRBPPPPP/NN7/9/9/9/9/9/8k b G4LP 1").unwrap();
+            assert!(pos.is_entering_king_win()); // 28 point with small pieces in hand.
 
-            let pos = Position::new_from_sfen("K8/9/9/9/9/9/nn7/krrbppppp/1P7 w b 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/9/nn7/krrbppppp/1P7 w b 2").unwrap();
             assert!(pos.is_entering_king_win());
-            let pos = Position::new_from_sfen("K8/9/9/9/9/9/nn7/kr+rb+ppppp/1P7 w b 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/9/nn7/kr+rb+ppppp/1P7 w b 2").unwrap();
             assert!(pos.is_entering_king_win());
-            let pos = Position::new_from_sfen("K8/9/9/9/9/9/nn7/krrbppppp/1P7 b b 1").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/9/nn7/krrbppppp/1P7 b b 1").unwrap();
             assert!(!pos.is_entering_king_win()); // opponent side is entring king position. but own side is not.
-            let pos = Position::new_from_sfen("K8/9/9/9/9/9/nn7/krrbppppp/PP7 w b 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/9/nn7/krrbppppp/PP7 w b 2").unwrap();
             assert!(!pos.is_entering_king_win()); // in_check
-            let pos = Position::new_from_sfen("K8/9/9/9/9/k8/nnn6/1rrbppppp/1P w b 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/k8/nnn6/1rrbppppp/1P7 w b 2").unwrap();
             assert!(!pos.is_entering_king_win()); // not entering king
-            let pos = Position::new_from_sfen("K8/9/9/9/9/9/n8/krrbppppp/1P7 w bp 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/9/n8/krrbppppp/1P7 w bp 2").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 10 own pieces on the opponent field.
-            let pos = Position::new_from_sfen("K8/9/9/9/9/n8/n8/kr1bppppp/1Pggggs2 w b 2").unwrap();
+            let pos = Position::from_sfen("K8/9/9/9/9/n8/n8/kr1bppppp/1Pggggs2 w b 2").unwrap();
             assert!(!pos.is_entering_king_win()); // less than 27 point.
         })
         .unwrap()
@@ -2704,7 +3607,7 @@ fn test_is_entering_king_win() {
 #[test]
 fn test_pseudo_legal() {
     let sfen = "4k4/4l4/9/9/4K4/9/9/9/9 b - 1";
-    let pos = Position::new_from_sfen(sfen).unwrap();
+    let pos = Position::from_sfen(sfen).unwrap();
     assert!(!pos.pseudo_legal::<SearchingType>(Move::new_unpromote(Square::SQ55, Square::SQ56, Piece::B_KING)));
 }
 
@@ -2728,7 +3631,38 @@ fn test_is_repetition() {
                 ("3b2b", Repetition::Win),
                 ("2a3a", Repetition::Lose),
             ];
-            let mut pos = Position::new_from_sfen(sfen).unwrap();
+            let mut pos = Position::from_sfen(sfen).unwrap();
+            for (m, r) in &moves {
+                let m = Move::new_from_usi_str(m, &pos).unwrap();
+                pos.do_move(m, pos.gives_check(m));
+                assert_eq!(pos.is_repetition(), *r);
+            }
+
+            // no check.
+            let sfen = "8k/9/9/9/9/9/9/9/8K b - 1";
+            let moves = [
+                ("1i2i", Repetition::Not),
+                ("1a2a", Repetition::Not),
+                ("2i1i", Repetition::Not),
+                ("2a1a", Repetition::Draw),
+                ("1i2i", Repetition::Draw),
+            ];
+            let mut pos = Position::from_sfen(sfen).unwrap();
+            for (m, r) in &moves {
+                let m = Move::new_from_usi_str(m, &pos).unwrap();
+                pos.do_move(m, pos.gives_check(m));
+                assert_eq!(pos.is_repetition(), *r);
+            }
+
+            // checks are interrupted. it's not perpetual check.
+            let sfen = "8k/9/9/9/9/9/9/9/R7K b - 1";
+            let moves = [
+                ("9i9a", Repetition::Not),
+                ("1a2b", Repetition::Not),
+                ("9a9i", Repetition::Not),
+                ("2b1a", Repetition::Draw),
+            ];
+            let mut pos = Position::from_sfen(sfen).unwrap();
             for (m, r) in &moves {
                 let m = Move::new_from_usi_str(m, &pos).unwrap();
                 pos.do_move(m, pos.gives_check(m));
@@ -2746,154 +3680,154 @@ fn test_mate_move_in_1ply() {
         .stack_size(crate::stack_size::STACK_SIZE)
         .spawn(|| {
             let sfen = "8k/9/8P/9/9/9/9/9/8K b G 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "G*1b");
 
             let sfen = "8k/9/9/9/9/9/9/9/8K b G 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "7bk/9/8P/9/9/9/9/9/8K b G 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "6Rbk/9/8P/9/9/9/9/9/8K b G 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "G*1b");
 
             let sfen = "8k/9/8P/9/9/9/9/9/8K b L 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "7nk/7n1/8P/9/9/9/9/9/8K b L 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "L*1b");
 
             let sfen = "7nk/7n1/8P/9/9/9/9/9/8K b RL 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "R*1b"); // Rook is checked before Lance.
 
             let sfen = "7k1/R8/9/9/9/9/9/9/8K b S 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "7pk/7bp/9/9/9/9/9/9/8K b N 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "N*2c");
 
             let sfen = "7pk/7bs/9/9/9/9/9/8L/8K b N 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "N*2c");
 
             let sfen = "7pk/7bs/9/9/9/9/9/9/8K b N 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "7pk/7nn/9/9/8N/9/9/9/8K b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "1e2c");
 
             let sfen = "7pk/7nn/9/8s/8N/9/9/9/8K b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "7pk/7nn/9/8l/8N/9/9/9/8K b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "8k/7nn/9/9/8N/9/9/9/8K b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "7nk/7pn/9/9/8N/9/9/9/B7K b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "1e2c");
 
             let sfen = "8k/9/8P/8L/9/9/9/9/8K b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "1c1b+");
 
             let sfen = "7k1/9/7P1/7L1/9/9/9/9/1K7 b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "2c2b+");
 
             let sfen = "7k1/8g/7P1/7L1/9/9/9/9/1K7 b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "7k1/8b/7P1/7L1/9/9/9/9/1K7 b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "7p1/7lk/7ll/8L/9/9/9/9/8K b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "7p1/7lk/7ll/7BL/9/9/9/9/8K b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "1d1c");
 
             let sfen = "ln5nl/4g2G1/pr1p1skpp/2P2psR1/1SpPp3B/Pp4G1P/N3PbN2/2G6/L3K3L b Ps4p 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "2d2c+");
 
             let sfen = "+L7R/3pp4/1bSk5/+B2+n3n1/1K1L1s3/1PG6/2+ng1+n2P/2+p6/1+pL2+p1+p1 b R3P2g2sl7p 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "3+rn4/RP6+S/2p2+Pp1p/4P2k1/4K+P3/p1P+p2PP1/+p4+pB2/S+p1+p+n+b2P/4S3+p w 3G2N2Lgs2l 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "4l1l1p/G+P2+L4/2+P1+PpS+S+S/l3p1K2/1G+p3+P1k/+p+bp1+p2P+p/5g2+p/G2+BP2S1/1+p1+p1PN2 w RNr2n 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "2g1+Pp3/+P2+Pn1g2/1gkLK1n2/9/+P1+PS1P3/pl1P2+p2/1LP1N+s+pp1/PP2P2SL/N2S2PPg b 2R2B 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_none());
 
             let sfen = "5pp2/prp1l2+Pl/1SSpnk3/1K7/3+P+p2+P1/1P7/P1g+lpNR2/1p2PL2P/3+p1P1P1 b G2SN2b2gn 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let m = pos.mate_move_in_1ply();
             assert!(m.is_some());
             assert_eq!(&m.unwrap().to_usi_string(), "4g5e");
@@ -2909,7 +3843,7 @@ fn test_effect_bb_of_checker_where_king_cannot_escape() {
         .stack_size(crate::stack_size::STACK_SIZE)
         .spawn(|| {
             let sfen = "4k4/4l4/9/9/4K4/9/9/9/9 b - 1";
-            let pos = Position::new_from_sfen(sfen).unwrap();
+            let pos = Position::from_sfen(sfen).unwrap();
             let bb =
                 pos.effect_bb_of_checker_where_king_cannot_escape(Square::SQ52, pos.piece_on(Square::SQ52), &pos.occupied_bb());
             assert!(bb.is_set(Square::SQ56));
@@ -2919,3 +3853,323 @@ fn test_effect_bb_of_checker_where_king_cannot_escape() {
         .join()
         .unwrap();
 }
+
+#[test]
+fn test_position_display() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::new();
+            let expected = "  9  8  7  6  5  4  3  2  1
+  l  n  s  g  k  g  s  n  l a
+  .  r  .  .  .  .  .  b  . b
+  p  p  p  p  p  p  p  p  p c
+  .  .  .  .  .  .  .  .  . d
+  .  .  .  .  .  .  .  .  . e
+  .  .  .  .  .  .  .  .  . f
+  P  P  P  P  P  P  P  P  P g
+  .  B  .  .  .  .  .  R  . h
+  L  N  S  G  K  G  S  N  L i
+Black hand: -
+White hand: -
+Side to move: Black
+";
+            assert_eq!(pos.to_string(), expected);
+
+            let pos = Position::from_sfen("8k/9/7+P1/9/9/9/9/9/K8 w R2Pbg15p 1").unwrap();
+            let s = pos.to_string();
+            assert!(s.contains(" +P  . c\n"));
+            assert!(s.contains("Black hand: R2P\n"));
+            assert!(s.contains("White hand: BG15P\n"));
+            assert!(s.contains("Side to move: White\n"));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_to_kif_board() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::new();
+            let expected = "後手の持駒：なし
+  ９ ８ ７ ６ ５ ４ ３ ２ １
++---------------------------+
+|v香v桂v銀v金v玉v金v銀v桂v香|一
+| ・v飛 ・ ・ ・ ・ ・v角 ・|二
+|v歩v歩v歩v歩v歩v歩v歩v歩v歩|三
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|四
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|五
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|六
+| 歩 歩 歩 歩 歩 歩 歩 歩 歩|七
+| ・ 角 ・ ・ ・ ・ ・ 飛 ・|八
+| 香 桂 銀 金 玉 金 銀 桂 香|九
++---------------------------+
+先手の持駒：なし
+先手番
+";
+            assert_eq!(pos.to_kif_board(), expected);
+
+            let pos = Position::from_sfen("8k/9/7+P1/9/9/9/9/9/K8 w R2Pbg15p 1").unwrap();
+            let s = pos.to_kif_board();
+            assert!(s.starts_with("後手の持駒：角　金　歩十五　\n"));
+            assert!(s.contains("| ・ ・ ・ ・ ・ ・ ・ と ・|三\n"));
+            assert!(s.contains("先手の持駒：飛　歩二　\n"));
+            assert!(s.ends_with("後手番\n"));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_key_after() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            // captures, promotions, drops and the captured promoted pieces.
+            let mut pos = Position::new();
+            for usi in [
+                "7g7f", "3c3d", "8h2b+", "3a2b", "B*4e", "2b3c", "4e3d", "3c3d", "2g2f", "B*5e",
+            ]
+            .iter()
+            {
+                let m = Move::new_from_usi_str(usi, &pos).unwrap();
+                let key_after = pos.key_after(m);
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+                assert!(key_after == pos.key(), "{}", usi);
+            }
+            // all the legal moves in a complicated position.
+            let mut pos = Position::from_sfen("l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1").unwrap();
+            for m in pos.legal_moves().iter() {
+                let key_after = pos.key_after(*m);
+                let gives_check = pos.gives_check(*m);
+                pos.do_move(*m, gives_check);
+                assert!(key_after == pos.key());
+                pos.undo_move(*m);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_gives_mate_in_one() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let is_mate = |sfen: &str, m: Move| {
+                let mut pos = Position::from_sfen(sfen).unwrap();
+                assert!(pos.legal_moves().contains(&m));
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+                pos.in_check() && pos.legal_moves().is_empty()
+            };
+            for sfen in [
+                // by a drop.
+                "8k/9/8P/9/9/9/9/9/8K b G 1",
+                // by a board move. G5c5b
+                "4k4/9/4GS3/9/9/9/9/9/4K4 b - 1",
+                // for white.
+                "8k/9/9/9/9/9/8p/9/8K w g 1",
+            ]
+            .iter()
+            {
+                let pos = Position::from_sfen(sfen).unwrap();
+                let m = pos.gives_mate_in_one();
+                assert!(m.is_some(), "{}", sfen);
+                assert!(is_mate(sfen, m.unwrap()), "{}", sfen);
+            }
+            for sfen in [
+                START_SFEN,
+                // checks, but the king escapes.
+                "8k/9/9/9/9/9/9/9/8K b G 1",
+                // the drop pawn mate is illegal.
+                "kl7/1n7/K8/9/9/9/9/9/9 b P 1",
+            ]
+            .iter()
+            {
+                let pos = Position::from_sfen(sfen).unwrap();
+                assert!(pos.gives_mate_in_one().is_none(), "{}", sfen);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_game_phase() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let recomputed = |pos: &Position| Position::from_sfen(&pos.to_sfen()).unwrap().game_phase();
+            let mut pos = Position::new();
+            assert_eq!(pos.game_phase(), Position::MAX_GAME_PHASE);
+            let mut phases = vec![pos.game_phase()];
+            let mut moves = vec![];
+            for usi_move in ["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e", "2b3a"].iter() {
+                let m = Move::new_from_usi_str(usi_move, &pos).unwrap();
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+                assert_eq!(pos.game_phase(), recomputed(&pos));
+                phases.push(pos.game_phase());
+                moves.push(m);
+                if *usi_move == "3a2b" {
+                    // Two bishops are off the board.
+                    assert_eq!(pos.st().phase, START_PHASE - 2 * phase_value(PieceType::BISHOP));
+                }
+            }
+            // Quiet moves don't change the phase.
+            assert_eq!(phases[0], phases[2]);
+            // The capture of the bishop with the promotion.
+            assert!(phases[3] < phases[2]);
+            // The capture of the horse.
+            assert!(phases[4] < phases[3]);
+            // The drop.
+            assert!(phases[4] < phases[5]);
+            assert_eq!(phases[5], phases[6]);
+            for &m in moves.iter().rev() {
+                pos.undo_move(m);
+                phases.pop();
+                assert_eq!(pos.game_phase(), *phases.last().unwrap());
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[cfg(feature = "tune")]
+#[test]
+fn test_position_game_phase_with_piece_values() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut values = PieceValues::DEFAULT;
+            assert!(values.set("Pawn", 1000));
+            assert!(values.set("Horse", 10));
+            let mut pos = Position::new();
+            let mut default_pos = Position::new();
+            pos.set_piece_values(&values);
+            assert_eq!(pos.game_phase(), Position::MAX_GAME_PHASE);
+            for usi_move in ["7g7f", "3c3d", "8h2b+", "3a2b", "B*4e", "2b3a"].iter() {
+                let m = Move::new_from_usi_str(usi_move, &pos).unwrap();
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+                default_pos.do_move(m, gives_check);
+                assert_eq!(pos.st().phase, StateInfo::new_phase(&pos.base));
+                assert_eq!(pos.game_phase(), default_pos.game_phase());
+                if *usi_move == "8h2b+" {
+                    // The material follows the values.
+                    assert!(pos.material() != default_pos.material());
+                }
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_mirror_files_and_flip_colors() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let sfens = [
+                START_SFEN,
+                "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2",
+                "8l/1l+R2P3/p2pBG1pp/kps1p4/Nn1P2G2/P1P1P2PP/1PS6/1KSG3+r1/LN2+p3L w Sbgn3p 124",
+            ];
+            for sfen in sfens.iter() {
+                let pos = Position::from_sfen(sfen).unwrap();
+                let num_moves = pos.legal_moves().len();
+
+                let mirrored = pos.mirror_files();
+                assert_eq!(mirrored.side_to_move(), pos.side_to_move());
+                assert_eq!(mirrored.hand(Color::BLACK), pos.hand(Color::BLACK));
+                assert_eq!(mirrored.legal_moves().len(), num_moves);
+                assert!(mirrored.key() == Position::from_sfen(&mirrored.to_sfen()).unwrap().key());
+                for &sq in Square::ALL.iter() {
+                    assert_eq!(mirrored.piece_on(sq.inverse_file()), pos.piece_on(sq));
+                }
+                let double_mirrored = mirrored.mirror_files();
+                assert_eq!(double_mirrored.to_sfen(), pos.to_sfen());
+                assert!(double_mirrored.key() == pos.key());
+
+                let flipped = pos.flip_colors();
+                assert_eq!(flipped.side_to_move(), pos.side_to_move().inverse());
+                assert_eq!(flipped.hand(Color::BLACK), pos.hand(Color::WHITE));
+                assert_eq!(flipped.hand(Color::WHITE), pos.hand(Color::BLACK));
+                assert_eq!(flipped.legal_moves().len(), num_moves);
+                assert!(flipped.key() == Position::from_sfen(&flipped.to_sfen()).unwrap().key());
+                for &sq in Square::ALL.iter() {
+                    let pc = pos.piece_on(sq);
+                    let expected = if pc == Piece::EMPTY { pc } else { pc.inverse() };
+                    assert_eq!(flipped.piece_on(sq.inverse()), expected);
+                }
+                let double_flipped = flipped.flip_colors();
+                assert_eq!(double_flipped.to_sfen(), pos.to_sfen());
+                assert!(double_flipped.key() == pos.key());
+
+                assert_eq!(pos.mirror_files().flip_colors().legal_moves().len(), num_moves);
+                assert_eq!(pos.game_phase(), flipped.mirror_files().game_phase());
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_handicap() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            assert_eq!(
+                Position::handicap(Handicap::Lance).to_sfen(),
+                "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"
+            );
+            assert_eq!(
+                Position::handicap(Handicap::TwoPiece).to_sfen(),
+                "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"
+            );
+            assert_eq!(
+                Position::handicap(Handicap::SixPiece).to_sfen(),
+                "2sgkgs2/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"
+            );
+            assert_eq!("rook_lance".parse::<Handicap>(), Ok(Handicap::RookLance));
+            assert!("3piece".parse::<Handicap>().is_err());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_is_mate() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::new();
+            assert!(!pos.is_mate());
+            assert!(!pos.has_no_legal_moves());
+            // The gold protected by the lance.
+            let pos = Position::from_sfen("4k3l/9/9/9/9/9/9/8g/8K b - 1").unwrap();
+            assert!(pos.is_mate());
+            assert!(pos.has_no_legal_moves());
+            // Not in check, but the king can't move to 1h, 2h and 2i.
+            let pos = Position::from_sfen("4k2r1/9/9/4b4/9/9/9/9/8K b - 1").unwrap();
+            assert!(!pos.is_mate());
+            assert!(pos.has_no_legal_moves());
+            assert!(pos.legal_moves().is_empty());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
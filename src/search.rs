@@ -1,20 +1,25 @@
 #[cfg(feature = "kppt")]
 use crate::evaluate::kppt::*;
-use crate::movegen::*;
+#[cfg(feature = "nnue")]
+use crate::evaluate::nnue::*;
 use crate::movepick::*;
 use crate::movetypes::*;
+use crate::piecevalue::*;
 use crate::position::*;
 use crate::tt::*;
 use crate::types::*;
 
 pub const COUNTER_MOVE_PRUNE_THRESHOLD: i16 = 0;
 pub const CURRENT_STACK_INDEX: usize = 7;
+pub const MAX_KILLER_SLOTS: usize = 4; // The upper limit of "KillerSlots".
 pub type Pv = True;
 pub type NonPv = False;
 
 pub const TT_HIT_AVERAGE_WINDOW: u64 = 4096;
 pub const TT_HIT_AVERAGE_RESOLUTION: u64 = 1024;
 
+pub const TIME_CHECK_INTERVAL_MILLIS: i64 = 2;
+
 #[derive(Clone)]
 pub struct LimitsType {
     pub time: [std::time::Duration; 2],
@@ -23,8 +28,10 @@ pub struct LimitsType {
     pub movetime: Option<std::time::Duration>,
     pub mate: Option<u32>,
     pub perft: Option<u32>,
-    pub infinite: Option<()>, // Is bool more appropriate?
-    pub nodes: Option<u64>,
+    pub infinite: Option<()>,   // Is bool more appropriate?
+    pub nodes: Option<u64>,     // total nodes of all the threads. not per thread.
+    pub searchmoves: Vec<Move>, // If this is empty, search all the legal moves.
+    pub root_pv: Vec<Move>,     // The hint of the previous pv. The root search tries the first move first.
     pub start_time: Option<std::time::Instant>,
 }
 
@@ -40,9 +47,15 @@ impl LimitsType {
             perft: None,
             infinite: None,
             nodes: None,
+            searchmoves: vec![],
+            root_pv: vec![],
             start_time: None,
         }
     }
+    // The search of a game move. It's limited only by the clock or byoyomi ("movetime").
+    pub fn is_timed(&self) -> bool {
+        self.depth.is_none() && self.nodes.is_none() && self.mate.is_none() && self.perft.is_none() && self.infinite.is_none()
+    }
     pub fn use_time_management(&self) -> bool {
         self.mate.is_none()
             && self.movetime.is_none()
@@ -53,6 +66,12 @@ impl LimitsType {
     }
 }
 
+impl Default for LimitsType {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Clone, Eq)]
 pub struct RootMove {
     pub score: Value,
@@ -91,51 +110,121 @@ impl RootMove {
             pv: vec![m],
         }
     }
-    pub fn extract_pv_from_tt(&mut self, pos: &mut Position, tt: *mut TranspositionTable) {
-        let mut m = self.pv[0];
+    // max_len includes the root move.
+    pub fn extract_pv_from_tt(&mut self, pos: &mut Position, tt: *mut TranspositionTable, max_len: usize) {
+        let m = self.pv[0];
         debug_assert!(pos.pseudo_legal::<SearchingType>(m));
-        let mut ply = 0;
-        self.pv.clear();
-        while {
-            self.pv.push(m);
-            debug_assert!(pos.pseudo_legal::<SearchingType>(m));
-            let gives_check = pos.gives_check(m);
-            pos.do_move(m, gives_check);
-            ply += 1;
-            let key = pos.key();
-            let (tte, tt_hit) = unsafe { (*tt).probe(key) };
-            tt_hit
-                && {
-                    if let Some(tt_move) = tte.mv(pos) {
-                        m = tt_move;
-                        debug_assert!(pos.pseudo_legal::<SearchingType>(m));
-                        true
-                    } else {
-                        false
-                    }
-                }
-                && pos.legal(m)
-                && ply < MAX_PLY
-                && pos.is_repetition() == Repetition::Not
-        } {}
-        for m in self.pv[..ply as usize].iter().rev() {
-            pos.undo_move(*m);
+        let gives_check = pos.gives_check(m);
+        pos.do_move(m, gives_check);
+        let pv = unsafe { (*tt).extract_pv(pos, std::cmp::min(max_len, MAX_PLY as usize) - 1) };
+        pos.undo_move(m);
+        self.pv.truncate(1);
+        self.pv.extend(pv);
+    }
+    // The second move of the pv. None if it isn't legal after the best move.
+    pub fn ponder_move(&self, pos: &Position) -> Option<Move> {
+        let (&best_move, &ponder_move) = (self.pv.first()?, self.pv.get(1)?);
+        let mut pos = Position::new_from_position(pos, Default::default());
+        let gives_check = pos.gives_check(best_move);
+        pos.do_move(best_move, gives_check);
+        if pos.pseudo_legal::<NotSearchingType>(ponder_move) && pos.legal(ponder_move) {
+            Some(ponder_move)
+        } else {
+            None
         }
     }
 }
 
 pub type RootMoves = Vec<RootMove>;
 
+// Stores the moves of pv as the tt moves so that the search tries them first.
+// It stops at the first illegal move.
+pub fn insert_pv_in_tt(pv: &[Move], pos: &mut Position, tt: &mut TranspositionTable) {
+    let mut ply = 0;
+    for &m in pv.iter() {
+        if !pos.pseudo_legal::<NotSearchingType>(m) || !pos.legal(m) {
+            break;
+        }
+        let generation = tt.generation();
+        let (tte, tt_hit) = tt.probe(pos.key());
+        if !tt_hit || tte.mv(pos) != Some(m) {
+            tte.save(
+                pos.key(),
+                Value::NONE,
+                false,
+                false,
+                Bound::BOUND_NONE,
+                Depth::NONE,
+                Some(m),
+                Value::NONE,
+                generation,
+            );
+        }
+        let gives_check = pos.gives_check(m);
+        pos.do_move(m, gives_check);
+        ply += 1;
+    }
+    for m in pv[..ply].iter().rev() {
+        pos.undo_move(*m);
+    }
+}
+
+// Weakened play by Skill_Level. Skill::MAX_LEVEL is the full strength.
+pub struct Skill {
+    level: i32,
+}
+
+impl Skill {
+    pub const MAX_LEVEL: i32 = 20;
+    pub const MIN_MULTI_PV: usize = 4;
+
+    pub fn new(level: i32) -> Skill {
+        Skill { level }
+    }
+    pub fn enabled(&self) -> bool {
+        self.level < Skill::MAX_LEVEL
+    }
+    pub fn time_to_pick(&self, depth: Depth) -> bool {
+        depth.0 / Depth::ONE_PLY.0 == 1 + self.level
+    }
+    // The moves worse than the best one by more than this are never picked.
+    pub fn margin(&self) -> Value {
+        Value(PAWN_VALUE * (Skill::MAX_LEVEL - self.level) / 10)
+    }
+    // Picks one of the first multi_pv root moves. The lower the level is, the more the worse moves are likely to be picked.
+    // root_moves must be sorted.
+    pub fn pick_best<R: rand::Rng + ?Sized>(&self, root_moves: &[RootMove], multi_pv: usize, rng: &mut R) -> Move {
+        let top_score = root_moves[0].score;
+        let delta = std::cmp::min(top_score - root_moves[multi_pv - 1].score, Value(PAWN_VALUE));
+        let weakness = 120 - 2 * self.level;
+        let mut max_score = -Value::INFINITE;
+        let mut best = root_moves[0].pv[0];
+        for rm in root_moves.iter().take(multi_pv) {
+            if top_score - rm.score > self.margin() {
+                break;
+            }
+            let push = Value((weakness * (top_score - rm.score).0 + delta.0 * rng.gen_range(0..weakness)) / 128);
+            if rm.score + push >= max_score {
+                max_score = rm.score + push;
+                best = rm.pv[0];
+            }
+        }
+        best
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Stack {
     pub continuation_history: *mut PieceToHistory,
     pub ply: i32,
     pub current_move: Option<Move>,
     pub excluded_move: Option<Move>,
-    pub killers: [Option<Move>; 2],
+    pub killers: [Option<Move>; MAX_KILLER_SLOTS], // The most recent first. Only the first "KillerSlots" are used.
     pub static_eval: Value,
     #[cfg(feature = "kppt")]
     pub static_eval_raw: EvalSum,
+    #[cfg(feature = "nnue")]
+    pub static_eval_raw: Accumulator,
     pub stat_score: i32,
     pub move_count: i32,
 }
@@ -147,14 +236,26 @@ impl Stack {
             ply: 0,
             current_move: None,
             excluded_move: None,
-            killers: [None, None],
+            killers: [None; MAX_KILLER_SLOTS],
             static_eval: Value::ZERO,
             #[cfg(feature = "kppt")]
             static_eval_raw: EvalSum::new(),
+            #[cfg(feature = "nnue")]
+            static_eval_raw: Accumulator::new(),
             stat_score: 0,
             move_count: 0,
         }
     }
+    // m moves to the first slot. The others shift down and the last one is dropped unless m is already stored.
+    pub fn update_killers(&mut self, m: Move, slots: usize) {
+        debug_assert!((1..=MAX_KILLER_SLOTS).contains(&slots));
+        let end = self.killers[..slots]
+            .iter()
+            .position(|&killer| killer == Some(m))
+            .unwrap_or(slots - 1);
+        self.killers.copy_within(0..end, 1);
+        self.killers[0] = Some(m);
+    }
 }
 
 pub fn get_stack(stack: &[Stack], i: i64) -> &Stack {
@@ -193,6 +294,56 @@ pub fn value_mated_in(ply: i32) -> Value {
     -Value::MATE + Value(ply)
 }
 
+// If the best move has been unchanged for this many iterations, it's an easy move.
+pub const EASY_MOVE_STABLE_ITERATIONS: i32 = 6;
+// An easy move is played after this fraction of the optimum time is used.
+pub const EASY_MOVE_TIME_RATIO: f64 = 0.25;
+
+// Singular extension. If all the moves but the tt move fail low against singular_beta at the reduced depth,
+// the tt move is extended by one ply. The excluded move is in Stack::excluded_move of that ply,
+// and the tt key is changed by it (excluded_move_key) so that the reduced search doesn't overwrite the entry of the full search.
+// The minimum depth is SearchParams::singular_extension_min_ply.
+// The tt entry must be searched this shallower at most. Otherwise the tt value isn't reliable.
+pub const SINGULAR_EXTENSION_TT_DEPTH_MARGIN: Depth = Depth(3 * Depth::ONE_PLY.0);
+
+pub fn singular_beta(tt_value: Value, depth: Depth, tt_pv: bool, pv_node: bool) -> Value {
+    Value(tt_value.0 - ((i32::from(tt_pv && !pv_node) + 4) * depth.0) / Depth::ONE_PLY.0)
+}
+
+pub fn singular_depth(depth: Depth) -> Depth {
+    Depth(depth.0 / (2 * Depth::ONE_PLY.0) * Depth::ONE_PLY.0)
+}
+
+// The tt key of the node searched without the excluded move. No excluded move keeps the key of the position.
+// Option<Move> isn't converted into Move, because the niche of None isn't a valid Move.
+pub fn excluded_move_key(key: Key, excluded_move: Option<Move>) -> Key {
+    Key(key.0 ^ (u64::from(excluded_move.map_or(0, |m| m.0.get())) << 16))
+}
+
+// The aspiration window at the root is [previous_score - delta, previous_score + delta].
+pub const ASPIRATION_DELTA: Value = Value(21);
+
+// The larger scores are less stable, so the initial window is wider.
+pub fn aspiration_delta(previous_score: Value) -> Value {
+    ASPIRATION_DELTA + Value(previous_score.0.abs() / 256)
+}
+
+// On each fail low or fail high, the failed side is widened geometrically instead of jumping to the infinity.
+pub fn widen_aspiration_delta(delta: Value) -> Value {
+    delta + delta / 4 + Value(5)
+}
+
+// The score is only a bound if the search at the root failed high or failed low.
+pub fn score_bound(v: Value, alpha: Value, beta: Value) -> Bound {
+    if v >= beta {
+        Bound::LOWER
+    } else if v <= alpha {
+        Bound::UPPER
+    } else {
+        Bound::EXACT
+    }
+}
+
 pub const TEMPO: Value = Value(28);
 
 pub fn stat_bonus(depth: Depth) -> i32 {
@@ -219,35 +370,134 @@ pub fn update_continuation_histories(stack: &mut [Stack], pc: Piece, to: Square,
     }
 }
 
-pub const RAZOR_MARGIN: Value = Value(531);
+// The pruning margins. They can be changed by "setoption name Param.<name>" with the feature "tune".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchParams {
+    pub razor_margin: i32,
+    pub futility_margin_per_ply: i32, // reverse futility pruning (static null move pruning).
+    pub futility_max_ply: i32,
+    pub futility_pruning_base: i32, // futility pruning of the quiet moves.
+    pub futility_pruning_per_ply: i32,
+    pub futility_pruning_max_lmr_ply: i32,
+    pub futility_pruning_history_limit: i32,
+    pub qsearch_futility_margin: i32,
+    pub prob_cut_min_ply: i32, // MAX_PLY disables ProbCut.
+    pub prob_cut_margin: i32,
+    pub prob_cut_improving_margin: i32,
+    pub prob_cut_reduction_ply: i32,
+    pub singular_extension_min_ply: i32, // MAX_PLY disables the singular extension.
+}
 
-pub fn futility_margin(depth: Depth) -> Value {
-    Value(75 * depth.0 / Depth::ONE_PLY.0)
+impl SearchParams {
+    pub const DEFAULT: SearchParams = SearchParams {
+        razor_margin: 531,
+        futility_margin_per_ply: 75,
+        futility_max_ply: 6,
+        futility_pruning_base: 235,
+        futility_pruning_per_ply: 172,
+        futility_pruning_max_lmr_ply: 6,
+        futility_pruning_history_limit: 25000,
+        qsearch_futility_margin: 154,
+        prob_cut_min_ply: 5,
+        prob_cut_margin: 189,
+        prob_cut_improving_margin: 45,
+        prob_cut_reduction_ply: 4,
+        singular_extension_min_ply: 6,
+    };
+    pub fn new() -> SearchParams {
+        Self::DEFAULT
+    }
+    pub fn futility_margin(&self, depth: Depth) -> Value {
+        Value(self.futility_margin_per_ply * depth.0 / Depth::ONE_PLY.0)
+    }
+    // false if the name doesn't exist.
+    #[allow(dead_code)]
+    pub fn set(&mut self, name: &str, value: i32) -> bool {
+        let param = match name {
+            "razor_margin" => &mut self.razor_margin,
+            "futility_margin_per_ply" => &mut self.futility_margin_per_ply,
+            "futility_max_ply" => &mut self.futility_max_ply,
+            "futility_pruning_base" => &mut self.futility_pruning_base,
+            "futility_pruning_per_ply" => &mut self.futility_pruning_per_ply,
+            "futility_pruning_max_lmr_ply" => &mut self.futility_pruning_max_lmr_ply,
+            "futility_pruning_history_limit" => &mut self.futility_pruning_history_limit,
+            "qsearch_futility_margin" => &mut self.qsearch_futility_margin,
+            "prob_cut_min_ply" => &mut self.prob_cut_min_ply,
+            "prob_cut_margin" => &mut self.prob_cut_margin,
+            "prob_cut_improving_margin" => &mut self.prob_cut_improving_margin,
+            "prob_cut_reduction_ply" => &mut self.prob_cut_reduction_ply,
+            "singular_extension_min_ply" => &mut self.singular_extension_min_ply,
+            _ => return false,
+        };
+        *param = value;
+        true
+    }
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub fn futility_move_count(improving: bool, depth_per_one_ply: i32) -> i32 {
     (4 + depth_per_one_ply * depth_per_one_ply) / (2 - i32::from(improving))
 }
 
+// reduction = (base + ln(threads)) * ln(depth) * (base + ln(threads)) * ln(move_count) / divisor
+// base and divisor are scaled by 100 for the USI options LMR_Base and LMR_Divisor.
 pub struct Reductions {
     values: [i32; ExtMove::MAX_LEGAL_MOVES],
+    thread_num: usize,
+    base: i64,
+    divisor: i64,
+    rounded_divisor: i32,
+    threshold: i32, // one more ply is reduced if the product exceeds this and not improving.
 }
 
 impl Reductions {
+    pub const DEFAULT_BASE: i64 = 2480;
+    pub const DEFAULT_DIVISOR: i64 = 102_400;
+
     pub fn new(thread_num: usize) -> Reductions {
         let mut r = Reductions {
             values: [0; ExtMove::MAX_LEGAL_MOVES],
+            thread_num,
+            base: Reductions::DEFAULT_BASE,
+            divisor: Reductions::DEFAULT_DIVISOR,
+            rounded_divisor: 0,
+            threshold: 0,
         };
-        for (i, v) in r.values.iter_mut().enumerate().skip(1) {
-            *v = ((24.8 + f64::from(thread_num as i32).ln()) * f64::from(i as i32).ln()) as i32;
-        }
+        r.init();
         r
     }
+    fn init(&mut self) {
+        let base = self.base as f64 / 100.0 + f64::from(self.thread_num as i32).ln();
+        for (i, v) in self.values.iter_mut().enumerate().skip(1) {
+            *v = (base * f64::from(i as i32).ln()) as i32;
+        }
+        // Zero or negative divisors are meaningless. They are treated as the minimum.
+        self.rounded_divisor = std::cmp::max(1, (self.divisor + 50) / 100) as i32;
+        self.threshold = (i64::from(self.rounded_divisor) * 1007 / 1024) as i32;
+    }
+    pub fn set_thread_num(&mut self, thread_num: usize) {
+        self.thread_num = thread_num;
+        self.init();
+    }
+    pub fn set_base(&mut self, base: i64) {
+        self.base = base;
+        self.init();
+    }
+    pub fn set_divisor(&mut self, divisor: i64) {
+        self.divisor = divisor;
+        self.init();
+    }
     pub fn get(&self, improving: bool, depth: Depth, move_count: i32) -> Depth {
         let r = unsafe {
             self.values.get_unchecked((depth.0 / Depth::ONE_PLY.0) as usize) * self.values.get_unchecked(move_count as usize)
         };
-        Depth(((r + 511) / 1024 + i32::from(!improving && r > 1007)) * Depth::ONE_PLY.0)
+        let d = self.rounded_divisor;
+        Depth(((r + d / 2 - 1) / d + i32::from(!improving && r > self.threshold)) * Depth::ONE_PLY.0)
     }
 }
 
@@ -266,53 +516,232 @@ impl Perft {
     }
     pub fn go(&mut self, depth: u32) {
         let timeins1 = std::time::Instant::now();
-        let searched_nodes = self.perft::<True>(depth);
+        let mut searched_nodes = 0;
+        for (m, cnt) in self.position.perft_divide(depth) {
+            usi_println!("{} : {}", m.to_usi_string(), cnt);
+            searched_nodes += cnt;
+        }
         let timeins2 = std::time::Instant::now();
         let timedur = timeins2 - timeins1;
         let timedur_nanos = std::cmp::max(timedur.as_nanos(), 1);
         let moved_nodes = self.position.nodes_searched();
-        println!();
-        println!("Time duration: {:?}", timedur);
-        println!(
+        usi_println!();
+        usi_println!("Time duration: {:?}", timedur);
+        usi_println!(
             "Searched: {} nodes : {} nps",
             searched_nodes,
             (searched_nodes as u128) * 1_000_000_000 / timedur_nanos
         );
-        println!(
+        usi_println!(
             "(Moved: {} nodes : {} nps)",
             moved_nodes,
             (moved_nodes as u128) * 1_000_000_000 / timedur_nanos
         );
     }
-    // perft() is our utility to verify move generation. All the leaf nodes up
-    // to the given depth are generated and counted, and the sum is returned.
-    pub fn perft<Root: Bool>(&mut self, depth: u32) -> u64 {
-        let leaf: bool = depth == 2;
-        let mut nodes: u64 = 0;
-        let mut mlist = MoveList::new();
-        mlist.generate::<LegalAllType>(&self.position, 0);
-        for i in 0..mlist.size {
-            let cnt: u64;
-            if Root::BOOL && depth <= 1 {
-                cnt = 1;
-                nodes += 1;
-            } else {
-                let m = mlist.ext_moves[i].mv;
-                self.position.do_move(m, self.position.gives_check(m));
-                if leaf {
-                    let mut leaf_mlist = MoveList::new();
-                    leaf_mlist.generate::<LegalAllType>(&self.position, 0);
-                    cnt = leaf_mlist.size as u64;
-                } else {
-                    cnt = self.perft::<False>(depth - 1);
-                }
-                nodes += cnt;
-                self.position.undo_move(m);
-            }
-            if Root::BOOL {
-                println!("{} : {}", mlist.ext_moves[i].mv.to_usi_string(), cnt);
-            }
-        }
-        nodes
+}
+
+#[test]
+fn test_skill_pick_best() {
+    use rand::SeedableRng;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::new();
+            let root_moves = [("2g2f", 100), ("7g7f", 90), ("6i7h", 60), ("1g1f", -1000)]
+                .iter()
+                .map(|&(usi_move, score)| {
+                    let mut rm = RootMove::new(Move::new_from_usi_str(usi_move, &pos).unwrap());
+                    rm.score = Value(score);
+                    rm
+                })
+                .collect::<Vec<_>>();
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+            assert!(!Skill::new(Skill::MAX_LEVEL).enabled());
+            assert!(Skill::new(Skill::MAX_LEVEL - 1).enabled());
+            assert!(Skill::new(0).time_to_pick(Depth::ONE_PLY));
+            assert!(!Skill::new(0).time_to_pick(Depth(2 * Depth::ONE_PLY.0)));
+
+            let skill = Skill::new(0);
+            let picked = (0..1000)
+                .map(|_| skill.pick_best(&root_moves, root_moves.len(), &mut rng).to_usi_string())
+                .collect::<Vec<_>>();
+            assert!(picked.iter().any(|m| m != "2g2f"));
+            // The blunder is over the margin.
+            assert!(picked.iter().all(|m| m != "1g1f"));
+
+            // Only one move is in the multi pv.
+            let skill = Skill::new(Skill::MAX_LEVEL - 1);
+            assert!((0..100).all(|_| skill.pick_best(&root_moves, 1, &mut rng).to_usi_string() == "2g2f"));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_root_move_ponder_move() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::new();
+            let usi_move = |s: &str, pos: &Position| Move::new_from_usi_str(s, pos).unwrap();
+            let best_move = usi_move("7g7f", &pos);
+            let mut root_move = RootMove::new(best_move);
+            assert!(root_move.ponder_move(&pos).is_none());
+            let pos_after = {
+                let mut pos = Position::new();
+                pos.do_move(best_move, false);
+                pos
+            };
+            let ponder_move = usi_move("3c3d", &pos_after);
+            root_move.pv.push(ponder_move);
+            assert_eq!(root_move.ponder_move(&pos), Some(ponder_move));
+            // Black moves again.
+            root_move.pv[1] = usi_move("2g2f", &pos);
+            assert!(root_move.ponder_move(&pos).is_none());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_stack_update_killers() {
+    let m = |i: i32| Move::new_unpromote(Square(i), Square(i + 1), Piece::B_GOLD);
+    let mut ss = Stack::new();
+    ss.update_killers(m(0), 2);
+    assert_eq!(ss.killers[..2], [Some(m(0)), None]);
+    ss.update_killers(m(1), 2);
+    assert_eq!(ss.killers[..2], [Some(m(1)), Some(m(0))]);
+    ss.update_killers(m(2), 2);
+    assert_eq!(ss.killers[..2], [Some(m(2)), Some(m(1))]);
+    // Already stored.
+    ss.update_killers(m(1), 2);
+    assert_eq!(ss.killers[..2], [Some(m(1)), Some(m(2))]);
+    ss.update_killers(m(1), 2);
+    assert_eq!(ss.killers[..2], [Some(m(1)), Some(m(2))]);
+
+    let mut ss = Stack::new();
+    for i in 0..4 {
+        ss.update_killers(m(i), 3);
     }
+    assert_eq!(ss.killers, [Some(m(3)), Some(m(2)), Some(m(1)), None]);
+    ss.update_killers(m(1), 3);
+    assert_eq!(ss.killers, [Some(m(1)), Some(m(3)), Some(m(2)), None]);
+}
+
+#[test]
+fn test_aspiration_window() {
+    assert_eq!(aspiration_delta(Value(0)), ASPIRATION_DELTA);
+    assert!(aspiration_delta(Value(-1000)) > aspiration_delta(Value(100)));
+    let mut delta = aspiration_delta(Value(300));
+    let mut count = 0;
+    while delta < Value::INFINITE {
+        let widened = widen_aspiration_delta(delta);
+        assert!(widened > delta + delta / 5);
+        delta = widened;
+        count += 1;
+    }
+    // Not too many re-searches before the full width.
+    assert!(count < 40);
+
+    let (alpha, beta) = (Value(-10), Value(10));
+    assert_eq!(score_bound(Value(10), alpha, beta).to_usi_str(), "lowerbound ");
+    assert_eq!(score_bound(Value(-10), alpha, beta).to_usi_str(), "upperbound ");
+    assert_eq!(score_bound(Value(0), alpha, beta).to_usi_str(), "");
+}
+
+#[test]
+fn test_reductions() {
+    let default = Reductions::new(1);
+    let depth = Depth(10 * Depth::ONE_PLY.0);
+    let move_count = 20;
+    assert!(default.get(false, depth, move_count) > Depth::ZERO);
+
+    let mut r = Reductions::new(1);
+    r.set_divisor(Reductions::DEFAULT_DIVISOR * 4);
+    assert!(r.get(false, depth, move_count) < default.get(false, depth, move_count));
+    r.set_divisor(Reductions::DEFAULT_DIVISOR);
+    assert_eq!(r.get(false, depth, move_count), default.get(false, depth, move_count));
+    r.set_base(Reductions::DEFAULT_BASE / 2);
+    assert!(r.get(false, depth, move_count) < default.get(false, depth, move_count));
+
+    // Zero divisor doesn't panic.
+    r.set_divisor(0);
+    r.get(true, depth, move_count);
+    r.set_divisor(-100);
+    r.get(true, depth, move_count);
+}
+
+#[test]
+fn test_singular_extension_params() {
+    let depth = Depth(8 * Depth::ONE_PLY.0);
+    assert_eq!(singular_beta(Value(100), depth, false, false), Value(100 - 4 * 8));
+    // The tt pv nodes out of the pv have a larger margin.
+    assert_eq!(singular_beta(Value(100), depth, true, false), Value(100 - 5 * 8));
+    assert_eq!(singular_beta(Value(100), depth, true, true), Value(100 - 4 * 8));
+    assert_eq!(singular_depth(depth), Depth(4 * Depth::ONE_PLY.0));
+    assert_eq!(singular_depth(Depth(7 * Depth::ONE_PLY.0)), Depth(3 * Depth::ONE_PLY.0));
+    let min_depth = Depth(SearchParams::DEFAULT.singular_extension_min_ply * Depth::ONE_PLY.0);
+    assert!(singular_depth(min_depth) >= Depth::ONE_PLY);
+}
+
+#[test]
+fn test_excluded_move_key() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let pos = Position::new();
+            let key = pos.key();
+            assert!(excluded_move_key(key, None) == key);
+            let m0 = Move::new_from_usi_str("7g7f", &pos).unwrap();
+            let m1 = Move::new_from_usi_str("2g2f", &pos).unwrap();
+            let key0 = excluded_move_key(key, Some(m0));
+            let key1 = excluded_move_key(key, Some(m1));
+            assert!(key0 != key && key1 != key && key0 != key1);
+            // The same excluded move gives the same key.
+            assert!(excluded_move_key(key, Some(m0)) == key0);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_insert_pv_in_tt() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut thread_pool = crate::thread::ThreadPool::new();
+            let mut tt = TranspositionTable::new();
+            tt.resize(1, &mut thread_pool).unwrap();
+            let mut pos = Position::new();
+            let sfen = pos.to_sfen();
+            let pv = ["2g2f", "8c8d", "2f2e"]
+                .iter()
+                .map(|s| {
+                    let m = Move::new_from_usi_str(s, &pos).unwrap();
+                    let gives_check = pos.gives_check(m);
+                    pos.do_move(m, gives_check);
+                    m
+                })
+                .collect::<Vec<_>>();
+            let mut pos = Position::new();
+            // The last move is illegal and isn't stored.
+            let mut moves = pv.clone();
+            moves.push(pv[0]);
+            insert_pv_in_tt(&moves, &mut pos, &mut tt);
+            assert_eq!(pos.to_sfen(), sfen);
+            let mut root_move = RootMove::new(pv[0]);
+            root_move.extract_pv_from_tt(&mut pos, &mut tt, MAX_PLY as usize);
+            assert_eq!(root_move.pv, pv);
+            root_move.extract_pv_from_tt(&mut pos, &mut tt, 2);
+            assert_eq!(root_move.pv, pv[..2]);
+            root_move.extract_pv_from_tt(&mut pos, &mut tt, MAX_PLY as usize);
+            assert_eq!(root_move.pv, pv);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
 }
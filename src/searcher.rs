@@ -0,0 +1,278 @@
+use crate::book::*;
+#[cfg(feature = "kppt")]
+use crate::evaluate::kppt::*;
+#[cfg(feature = "nnue")]
+use crate::evaluate::nnue::*;
+use crate::search::*;
+use crate::thread::*;
+use crate::tt::*;
+use crate::usioption::*;
+
+pub use crate::bitboard::Bitboard;
+pub use crate::movetypes::Move;
+pub use crate::position::Position;
+pub use crate::search::LimitsType as Limits;
+pub use crate::thread::{InfoSink, JsonInfoSink, SearchInfo};
+pub use crate::types::{Square, Value};
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub best_move: Move, // Move::RESIGN or Move::WIN if the search wasn't needed.
+    pub ponder_move: Option<Move>,
+    pub score: Value,
+    pub depth: i32,
+    pub pv: Vec<Move>,
+    pub resign: bool, // No legal moves, or the score stayed at -ResignThreshold or less for ResignMoveCount timed searches.
+}
+
+// The engine for the library use. It doesn't read stdin and doesn't write stdout.
+// The hash tables are boxed because the threads have the pointers to them.
+pub struct Searcher {
+    pub(crate) thread_pool: ThreadPool,
+    pub(crate) tt: Box<TranspositionTable>,
+    #[cfg(feature = "kppt")]
+    pub(crate) ehash: Box<EvalHash>,
+    pub(crate) breadcrumbs: Box<Breadcrumbs>,
+    pub(crate) reductions: Box<Reductions>,
+    pub(crate) usi_options: UsiOptions,
+    position: Position,
+    limits: Limits,
+    has_info_callback: bool,
+}
+
+struct CallbackInfoSink<F>(F);
+
+impl<F: FnMut(SearchInfo) + Send> InfoSink for CallbackInfoSink<F> {
+    fn info(&mut self, info: &SearchInfo) {
+        (self.0)(info.clone());
+    }
+    fn println(&mut self, _s: &str) {}
+}
+
+impl Searcher {
+    // The evaluation files are loaded from the default Eval_Dir.
+    pub fn new() -> Result<Searcher, String> {
+        Self::new_with_options(&[])
+    }
+    // The options are set in order before the evaluation files and the hash tables are loaded,
+    // e.g. &[("Eval_Dir", "eval/20190617"), ("USI_Hash", "256")].
+    pub fn new_with_options(options: &[(&str, &str)]) -> Result<Searcher, String> {
+        let mut searcher = Self::new_unloaded(UsiOptions::new());
+        for &(name, value) in options {
+            searcher.set_option(name, value);
+        }
+        searcher.load()?;
+        Ok(searcher)
+    }
+    // The hash sizes, the number of threads and the evaluation files are taken from usi_options.
+    pub(crate) fn new_with_usi_options(usi_options: UsiOptions) -> Result<Searcher, String> {
+        let mut searcher = Self::new_unloaded(usi_options);
+        searcher.load()?;
+        Ok(searcher)
+    }
+    // Nothing is loaded until load() is called. The USI loop calls it at "isready".
+    pub(crate) fn new_unloaded(usi_options: UsiOptions) -> Searcher {
+        let mut searcher = Searcher {
+            thread_pool: ThreadPool::new(),
+            tt: Box::new(TranspositionTable::new()),
+            #[cfg(feature = "kppt")]
+            ehash: Box::new(EvalHash::new()),
+            breadcrumbs: Box::new(Breadcrumbs::new()),
+            reductions: Box::new(Reductions::new(1)),
+            usi_options,
+            position: Position::new(),
+            limits: Limits::new(),
+            has_info_callback: false,
+        };
+        searcher.thread_pool.set(
+            searcher.usi_options.get_i64(UsiOptions::THREADS) as usize,
+            &mut searcher.tt,
+            #[cfg(feature = "kppt")]
+            &mut searcher.ehash,
+            &mut searcher.breadcrumbs,
+            &mut searcher.reductions,
+        );
+        searcher
+    }
+    // Loads the evaluation files and the book, and allocates the hash tables by the current options.
+    pub(crate) fn load(&mut self) -> Result<(), String> {
+        #[cfg(any(feature = "kppt", feature = "nnue"))]
+        load_evaluate_files(&self.usi_options.get_string(UsiOptions::EVAL_DIR))?;
+        // The book is optional.
+        if let Ok(book) = OpeningBook::from_file(self.usi_options.get_filename(UsiOptions::BOOK_FILE)) {
+            self.thread_pool.book = Some(book);
+        }
+        self.tt
+            .resize(self.usi_options.get_i64(UsiOptions::USI_HASH) as usize, &mut self.thread_pool)
+            .map_err(|err| format!("Error: {}", err))?;
+        #[cfg(feature = "kppt")]
+        self.ehash.resize(
+            self.usi_options.get_i64(UsiOptions::EVAL_HASH) as usize,
+            &mut self.thread_pool,
+        );
+        crate::endgame::init();
+        Ok(())
+    }
+    // Same as "setoption name <name> value <value>".
+    pub fn set_option(&mut self, name: &str, value: &str) {
+        let mut is_ready = true;
+        self.set_usi_option(name, value, &mut is_ready);
+    }
+    // is_ready becomes false if the option needs the reload of the evaluation files.
+    pub(crate) fn set_usi_option(&mut self, name: &str, value: &str, is_ready: &mut bool) {
+        self.usi_options.set(
+            name,
+            value,
+            &mut self.thread_pool,
+            &mut self.tt,
+            #[cfg(feature = "kppt")]
+            &mut self.ehash,
+            &mut self.breadcrumbs,
+            &mut self.reductions,
+            is_ready,
+        );
+    }
+    pub(crate) fn push_button(&mut self, name: &str) {
+        self.usi_options.push_button(
+            name,
+            &mut self.thread_pool,
+            &mut self.tt,
+            #[cfg(feature = "kppt")]
+            &mut self.ehash,
+        );
+    }
+    pub fn set_position(&mut self, pos: Position) {
+        self.position = pos;
+    }
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+    // Receives the progress of the search. The other output such as "bestmove" isn't sent.
+    pub fn set_info_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(SearchInfo) + Send + 'static,
+    {
+        self.set_info_sink(Box::new(CallbackInfoSink(callback)));
+    }
+    pub fn set_info_sink(&mut self, sink: Box<dyn InfoSink>) {
+        self.has_info_callback = true;
+        self.thread_pool.set_info_sink(sink);
+    }
+    // Blocks until the search finishes by the limits.
+    pub fn search(&mut self) -> Result<SearchResult, String> {
+        let mut limits = self.limits.clone();
+        limits.start_time = Some(std::time::Instant::now());
+        let ponder_mode = false;
+        let hide_all_output = !self.has_info_callback;
+        self.thread_pool.start_thinking(
+            &self.position,
+            &mut self.tt,
+            limits,
+            &self.usi_options,
+            ponder_mode,
+            hide_all_output,
+        );
+        self.thread_pool.wait_for_search_finished();
+        let root_move = self
+            .thread_pool
+            .last_best_root_move
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| "Error: The search has no result.".to_string())?;
+        Ok(SearchResult {
+            best_move: root_move.pv[0],
+            ponder_move: root_move.ponder_move(&self.position),
+            score: root_move.score,
+            depth: self
+                .thread_pool
+                .last_completed_depth
+                .load(std::sync::atomic::Ordering::Relaxed),
+            resign: root_move.pv[0] == Move::RESIGN || self.thread_pool.last_resign.load(std::sync::atomic::Ordering::Relaxed),
+            pv: root_move.pv,
+        })
+    }
+    pub(crate) fn usi_options(&self) -> &UsiOptions {
+        &self.usi_options
+    }
+    // Same as "usinewgame".
+    pub fn new_game(&mut self) {
+        self.thread_pool.wait_for_search_finished();
+        self.thread_pool.clear();
+        if self.usi_options.get_bool(UsiOptions::CLEAR_HASH_ON_NEW_GAME) {
+            self.tt.clear();
+        } else {
+            self.tt.new_search();
+        }
+    }
+    // The hash can be loaded only with the same USI_Hash as it was saved.
+    pub fn save_hash(&self, path: &std::path::Path) -> std::io::Result<()> {
+        self.tt.save_to_path(path)
+    }
+    pub fn load_hash(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        self.tt.load_from_path(path, &mut self.thread_pool)
+    }
+}
+
+#[cfg(test)]
+impl Searcher {
+    // The hash tables are 16 MB. The evaluation files aren't loaded, so the searches mustn't evaluate the positions.
+    pub(crate) fn new_for_test_unloaded(options: &[(&str, &str)]) -> Searcher {
+        let mut searcher = Self::new_unloaded(UsiOptions::new());
+        searcher.set_option(UsiOptions::USI_HASH, "16");
+        #[cfg(feature = "kppt")]
+        searcher.set_option(UsiOptions::EVAL_HASH, "16");
+        for &(name, value) in options {
+            searcher.set_option(name, value);
+        }
+        searcher
+    }
+    // Without the evaluation function binaries, the searches use the material evaluation. So the tests always run.
+    pub(crate) fn new_for_test(options: &[(&str, &str)]) -> Searcher {
+        let mut searcher = Self::new_for_test_unloaded(options);
+        if searcher.load().is_err() {
+            searcher.set_option(UsiOptions::EVAL_MODE, "material");
+        }
+        searcher
+    }
+}
+
+#[test]
+fn test_searcher() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = match Searcher::new() {
+                Ok(mut searcher) => {
+                    searcher.set_option(UsiOptions::USI_HASH, "16");
+                    searcher
+                }
+                // No evaluation function binaries.
+                Err(_) => Searcher::new_for_test(&[]),
+            };
+            let infos = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+            {
+                let infos = infos.clone();
+                searcher.set_info_callback(move |info| infos.lock().unwrap().push(info));
+            }
+            let pos = Position::new();
+            searcher.set_position(Position::new());
+            let mut limits = Limits::new();
+            limits.depth = Some(6);
+            searcher.set_limits(limits);
+            let result = searcher.search().unwrap();
+            assert!(pos.legal_moves().contains(&result.best_move));
+            assert_eq!(result.pv[0], result.best_move);
+            assert_eq!(result.ponder_move, result.pv.get(1).copied());
+            assert!(-Value::MATE_IN_MAX_PLY < result.score && result.score < Value::MATE_IN_MAX_PLY);
+            assert_eq!(result.depth, 6);
+            let infos = infos.lock().unwrap();
+            assert!(!infos.is_empty());
+            assert!(infos
+                .iter()
+                .all(|info| !info.pv.is_empty() && info.to_usi_string().starts_with("info depth ")));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -0,0 +1,156 @@
+use crate::movetypes::*;
+use crate::position::*;
+use crate::searcher::*;
+use crate::types::*;
+use crate::usioption::*;
+use rand::prelude::*;
+use std::io::prelude::*;
+
+const RANDOM_OPENING_PLIES: u32 = 8;
+const MAX_PLIES: usize = 320; // The game is a draw after this many plies.
+const RESIGN_VALUE: Value = Value(3000); // The side to move resigns at this score or less.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GameResult {
+    BlackWin,
+    WhiteWin,
+    Draw,
+}
+
+impl GameResult {
+    fn new_win(c: Color) -> GameResult {
+        if c == Color::BLACK {
+            GameResult::BlackWin
+        } else {
+            GameResult::WhiteWin
+        }
+    }
+    // From the side to move of the position.
+    fn to_str(self, side_to_move: Color) -> &'static str {
+        match self {
+            GameResult::Draw => "draw",
+            GameResult::BlackWin if side_to_move == Color::BLACK => "win",
+            GameResult::WhiteWin if side_to_move == Color::WHITE => "win",
+            _ => "lose",
+        }
+    }
+}
+
+fn random_opening(pos: &mut Position, rng: &mut StdRng) {
+    for _ in 0..RANDOM_OPENING_PLIES {
+        let moves = pos.legal_moves();
+        match moves.choose(rng) {
+            Some(&m) => {
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+            }
+            None => break,
+        }
+    }
+}
+
+// The positions of the game and the result.
+fn play_game(searcher: &mut Searcher, depth: u32, rng: &mut StdRng) -> Result<(Vec<(String, Color)>, GameResult), String> {
+    let mut pos = Position::new();
+    random_opening(&mut pos, rng);
+    searcher.new_game();
+    let mut positions = vec![];
+    let result = loop {
+        let us = pos.side_to_move();
+        match pos.is_repetition() {
+            Repetition::Draw => break GameResult::Draw,
+            Repetition::Win => break GameResult::new_win(us),
+            Repetition::Lose => break GameResult::new_win(us.inverse()),
+            _ => {}
+        }
+        if positions.len() >= MAX_PLIES {
+            break GameResult::Draw;
+        }
+        positions.push((pos.to_sfen(), us));
+        let mut limits = Limits::new();
+        limits.depth = Some(depth);
+        searcher.set_position(Position::new_from_position(&pos, Default::default()));
+        searcher.set_limits(limits);
+        let result = searcher.search()?;
+        if result.best_move == Move::RESIGN || result.score <= -RESIGN_VALUE {
+            break GameResult::new_win(us.inverse());
+        }
+        if result.best_move == Move::WIN {
+            break GameResult::new_win(us);
+        }
+        let m = result.best_move;
+        let gives_check = pos.gives_check(m);
+        pos.do_move(m, gives_check);
+    };
+    Ok((positions, result))
+}
+
+// "selfplay <games> <depth> <output_file_path>"
+// Each line of the output is "sfen <sfen> result <win|lose|draw>". The result is from the side to move.
+pub fn selfplay(usi_options: &UsiOptions, args: &[&str]) -> Result<(), String> {
+    let mut searcher = Searcher::new_with_usi_options(usi_options.clone())?;
+    selfplay_with_searcher(&mut searcher, args)
+}
+
+fn selfplay_with_searcher(searcher: &mut Searcher, args: &[&str]) -> Result<(), String> {
+    if args.len() != 3 {
+        return Err("Error: expected: selfplay <games> <depth> <output_file_path>".to_string());
+    }
+    let games = args[0]
+        .parse::<u32>()
+        .map_err(|_| format!("Error: Parse error: {}", args[0]))?;
+    let depth = args[1]
+        .parse::<u32>()
+        .map_err(|_| format!("Error: Parse error: {}", args[1]))?;
+    let path = args[2];
+    let mut writer = std::io::BufWriter::new(
+        std::fs::File::create(path).map_err(|err| format!(r#"Error: Cannot create file "{}". {}"#, path, err))?,
+    );
+    let mut rng = match searcher.usi_options().get_i64(UsiOptions::SEED) {
+        0 => StdRng::from_entropy(),
+        seed => StdRng::seed_from_u64(seed as u64),
+    };
+    for i in 0..games {
+        let (positions, result) = play_game(searcher, depth, &mut rng)?;
+        for (sfen, side_to_move) in positions.iter() {
+            writeln!(writer, "sfen {} result {}", sfen, result.to_str(*side_to_move)).map_err(|err| err.to_string())?;
+        }
+        eprintln!("Game: {}/{} {:?} plies: {}", i + 1, games, result, positions.len());
+    }
+    writer.flush().map_err(|err| err.to_string())
+}
+
+#[test]
+fn test_selfplay() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[(UsiOptions::SEED, "1")]);
+            let dir = std::env::temp_dir().join(format!("apery_test_selfplay_{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("selfplay.sfen");
+            let path = path.to_str().unwrap();
+            selfplay_with_searcher(&mut searcher, &["1", "4", path]).unwrap();
+            let text = std::fs::read_to_string(path).unwrap();
+            let lines = text.lines().collect::<Vec<_>>();
+            assert!(!lines.is_empty());
+            let results = ["win", "lose", "draw"];
+            for line in lines.iter() {
+                let tokens = line.split_whitespace().collect::<Vec<_>>();
+                assert_eq!(tokens.len(), 7);
+                assert_eq!(tokens[0], "sfen");
+                assert!(Position::new_from_sfen_args(&tokens[1..5]).is_ok());
+                assert_eq!(tokens[5], "result");
+                assert!(results.contains(&tokens[6]));
+            }
+            // The result is from the side to move, so it alternates unless it's a draw.
+            let first_result = lines[0].split_whitespace().last().unwrap();
+            let second_result = lines[1].split_whitespace().last().unwrap();
+            assert_eq!(first_result == "draw", second_result == "draw");
+            assert!(first_result == "draw" || first_result != second_result);
+            std::fs::remove_dir_all(&dir).unwrap();
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -2,6 +2,61 @@ use crate::types::*;
 
 pub const START_SFEN: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
 
+// Komaochi. The upper side (white) removes the pieces and moves first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handicap {
+    Lance, // The lance on 1a.
+    RightLance,
+    Bishop,
+    Rook,
+    RookLance,
+    TwoPiece, // The rook and the bishop.
+    FourPiece,
+    SixPiece,
+    EightPiece,
+    TenPiece,
+}
+
+impl Handicap {
+    pub fn to_sfen(self) -> String {
+        let (rank1, rank2) = match self {
+            Handicap::Lance => ("lnsgkgsn1", "1r5b1"),
+            Handicap::RightLance => ("1nsgkgsnl", "1r5b1"),
+            Handicap::Bishop => ("lnsgkgsnl", "1r7"),
+            Handicap::Rook => ("lnsgkgsnl", "7b1"),
+            Handicap::RookLance => ("lnsgkgsn1", "7b1"),
+            Handicap::TwoPiece => ("lnsgkgsnl", "9"),
+            Handicap::FourPiece => ("1nsgkgsn1", "9"),
+            Handicap::SixPiece => ("2sgkgs2", "9"),
+            Handicap::EightPiece => ("3gkg3", "9"),
+            Handicap::TenPiece => ("4k4", "9"),
+        };
+        format!("{}/{}/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1", rank1, rank2)
+    }
+}
+
+impl std::str::FromStr for Handicap {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Handicap, String> {
+        match s {
+            "lance" => Ok(Handicap::Lance),
+            "right_lance" => Ok(Handicap::RightLance),
+            "bishop" => Ok(Handicap::Bishop),
+            "rook" => Ok(Handicap::Rook),
+            "rook_lance" => Ok(Handicap::RookLance),
+            "2piece" => Ok(Handicap::TwoPiece),
+            "4piece" => Ok(Handicap::FourPiece),
+            "6piece" => Ok(Handicap::SixPiece),
+            "8piece" => Ok(Handicap::EightPiece),
+            "10piece" => Ok(Handicap::TenPiece),
+            _ => Err(format!(
+                r#"expected: "lance", "right_lance", "bishop", "rook", "rook_lance", "2piece", "4piece", "6piece", "8piece" or "10piece". found: "{}""#,
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum SfenError {
     InvalidNumberOfSections { sections: usize },
@@ -22,4 +77,38 @@ pub enum SfenError {
     InvalidGamePly { chars: String },
     SameHandPieceTwice { pt: PieceType },
     KingIsNothing { c: Color },
+    TooManyKings { c: Color },
+    KingCanBeCaptured { c: Color },
+    PieceCannotMove { pc: Piece, sq: Square },
+}
+
+// The position that can't arise in a game.
+#[derive(Debug)]
+pub enum IllegalPosition {
+    KingIsNothing { c: Color },
+    TooManyKings { c: Color },
+    KingCanBeCaptured { c: Color },
+    PieceCannotMove { pc: Piece, sq: Square }, // The pawn or the lance on the last rank, or the knight on the last 2 ranks.
+    TooManyPieces { pt: PieceType, number: i64 },
+}
+
+impl From<IllegalPosition> for SfenError {
+    fn from(err: IllegalPosition) -> SfenError {
+        match err {
+            IllegalPosition::KingIsNothing { c } => SfenError::KingIsNothing { c },
+            IllegalPosition::TooManyKings { c } => SfenError::TooManyKings { c },
+            IllegalPosition::KingCanBeCaptured { c } => SfenError::KingCanBeCaptured { c },
+            IllegalPosition::PieceCannotMove { pc, sq } => SfenError::PieceCannotMove { pc, sq },
+            IllegalPosition::TooManyPieces { pt, number } => match pt {
+                PieceType::PAWN => SfenError::InvalidNumberOfPawns { number },
+                PieceType::LANCE => SfenError::InvalidNumberOfLances { number },
+                PieceType::KNIGHT => SfenError::InvalidNumberOfKnights { number },
+                PieceType::SILVER => SfenError::InvalidNumberOfSilvers { number },
+                PieceType::GOLD => SfenError::InvalidNumberOfGolds { number },
+                PieceType::BISHOP => SfenError::InvalidNumberOfBishops { number },
+                PieceType::ROOK => SfenError::InvalidNumberOfRooks { number },
+                _ => unreachable!(),
+            },
+        }
+    }
 }
@@ -3,19 +3,168 @@ use crate::book::*;
 use crate::evaluate::kppt::*;
 #[cfg(feature = "material")]
 use crate::evaluate::material::*;
+#[cfg(feature = "nnue")]
+use crate::evaluate::nnue::*;
+use crate::evaluate::*;
+use crate::mate::*;
 use crate::movegen::*;
 use crate::movepick::*;
 use crate::movetypes::*;
-use crate::piecevalue::*;
 use crate::position::*;
 use crate::search::*;
+#[cfg(test)]
+use crate::searcher::Searcher;
 use crate::timeman::*;
 use crate::tt::*;
 use crate::types::*;
 use crate::usioption::*;
-use std::sync::atomic::{AtomicBool, AtomicI64, AtomicPtr, AtomicU64, Ordering};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicPtr, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
+// One line of the "info depth ..." output.
+#[derive(Debug, Clone)]
+pub struct SearchInfo {
+    pub depth: i32,
+    pub seldepth: i32,
+    pub score: Value,
+    pub bound: Bound,
+    pub wdl: Option<(i32, i32, i32)>,
+    pub nodes: i64,
+    pub nps: i64,
+    pub time_ms: i64,
+    pub hashfull: usize,
+    pub pv: Vec<Move>,
+    pub multipv_index: usize, // 1 origin.
+}
+
+impl SearchInfo {
+    // Nodes per second. It's nodes itself at the time 0.
+    pub fn nps(nodes: i64, time_ms: i64) -> i64 {
+        if time_ms <= 0 {
+            nodes
+        } else {
+            nodes * 1000 / time_ms
+        }
+    }
+    pub fn to_usi_string(&self) -> String {
+        format!(
+            "info depth {depth} seldepth {seldepth} multipv {multipv} score {score} {bound}{wdl}nodes {nodes} nps {nps} time {time} hashfull {hashfull} pv {pv}",
+            depth = self.depth,
+            seldepth = self.seldepth,
+            multipv = self.multipv_index,
+            score = self.score.to_usi(),
+            bound = self.bound.to_usi_str(),
+            wdl = match self.wdl {
+                Some((w, d, l)) => format!("wdl {} {} {} ", w, d, l),
+                None => "".to_string(),
+            },
+            nodes = self.nodes,
+            nps = self.nps,
+            time = self.time_ms,
+            hashfull = self.hashfull,
+            pv = self.pv.iter().map(|m| m.to_usi_string()).collect::<Vec<_>>().join(" ")
+        )
+    }
+    // The same contents as to_usi_string(). The score is "score_cp" or "score_mate".
+    pub fn to_json_string(&self) -> String {
+        let mut json = serde_json::json!({
+            "depth": self.depth,
+            "seldepth": self.seldepth,
+            "multipv": self.multipv_index,
+            "nodes": self.nodes,
+            "nps": self.nps,
+            "time": self.time_ms,
+            "hashfull": self.hashfull,
+            "pv": self.pv.iter().map(|m| m.to_usi_string()).collect::<Vec<_>>(),
+        });
+        let score = self.score.to_usi();
+        let mut score = score.split_whitespace();
+        let score_key = format!("score_{}", score.next().unwrap());
+        json[score_key] = score.next().unwrap().parse::<i64>().unwrap().into();
+        match self.bound {
+            Bound::LOWER => json["bound"] = "lowerbound".into(),
+            Bound::UPPER => json["bound"] = "upperbound".into(),
+            _ => {}
+        }
+        if let Some((w, d, l)) = self.wdl {
+            json["wdl"] = serde_json::json!([w, d, l]);
+        }
+        json.to_string()
+    }
+}
+
+// Receives the output of the search.
+pub trait InfoSink: Send {
+    // The periodic progress.
+    fn info(&mut self, info: &SearchInfo);
+    // The other usi output such as "info string".
+    fn println(&mut self, s: &str);
+    // best_move is a usi move, "resign" or "win".
+    fn bestmove(&mut self, best_move: &str, ponder_move: Option<&str>) {
+        match ponder_move {
+            Some(ponder_move) => self.println(&format!("bestmove {} ponder {}", best_move, ponder_move)),
+            None => self.println(&format!("bestmove {}", best_move)),
+        }
+    }
+}
+
+pub struct UsiInfoSink;
+
+impl InfoSink for UsiInfoSink {
+    fn info(&mut self, info: &SearchInfo) {
+        usi_println!("{}", info.to_usi_string());
+    }
+    fn println(&mut self, s: &str) {
+        usi_println!("{}", s);
+    }
+}
+
+// Each output is a line of JSON for the programs which don't parse USI.
+pub struct JsonInfoSink<W: std::io::Write + Send>(pub W);
+
+impl<W: std::io::Write + Send> JsonInfoSink<W> {
+    fn write_line(&mut self, s: &str) {
+        // The failure of the output can't be reported anywhere.
+        let _ = writeln!(self.0, "{}", s);
+        let _ = self.0.flush();
+    }
+}
+
+impl<W: std::io::Write + Send> InfoSink for JsonInfoSink<W> {
+    fn info(&mut self, info: &SearchInfo) {
+        self.write_line(&info.to_json_string());
+    }
+    fn println(&mut self, s: &str) {
+        self.write_line(&serde_json::json!({ "message": s }).to_string());
+    }
+    fn bestmove(&mut self, best_move: &str, ponder_move: Option<&str>) {
+        self.write_line(&serde_json::json!({ "bestmove": best_move, "ponder": ponder_move }).to_string());
+    }
+}
+
+#[derive(Clone)]
+pub struct Output(Arc<Mutex<Box<dyn InfoSink>>>);
+
+impl Output {
+    fn new() -> Output {
+        Output(Arc::new(Mutex::new(Box::new(UsiInfoSink))))
+    }
+    fn println(&self, s: &str) {
+        self.0.lock().unwrap().println(s);
+    }
+    fn bestmove(&self, best_move: &str, ponder_move: Option<&str>) {
+        self.0.lock().unwrap().bestmove(best_move, ponder_move);
+    }
+    fn infos(&self, infos: &[SearchInfo]) {
+        let mut sink = self.0.lock().unwrap();
+        for info in infos.iter() {
+            sink.info(info);
+        }
+    }
+}
+
 struct Breadcrumb {
     thread: AtomicPtr<*mut Thread>,
     key: AtomicU64,
@@ -106,9 +255,16 @@ struct Thread {
     idx: usize,
     pv_idx: usize,
     tt_hit_average: u64,
-    sel_depth: i32,
+    sel_depth: Arc<AtomicI32>, // max ply of the pv nodes including qsearch in the current iteration.
     null_move_pruning_min_ply: i32,
     null_move_pruning_color: Color,
+    repetition_ply: i32, // The lowest ply which the repetitions found in the subtree of the current node return to.
+    draw_value: [Value; Color::NUM], // -Contempt for the side to move at the root.
+    qsearch_max_ply: i32,
+    qsearch_check_plies: i32, // The quiet checks are generated only in the first plies of qsearch.
+    eval_mode: EvalMode,
+    killer_slots: usize,
+    max_pv_length: usize, // Of the info output.
     position: Position,
     root_moves: RootMoves,
     root_depth: Depth,
@@ -128,6 +284,7 @@ struct Thread {
     usi_options: UsiOptions,
     best_move_changes: Arc<AtomicU64>,
     best_move_changess: Vec<Arc<AtomicU64>>,
+    sel_depths: Vec<Arc<AtomicI32>>,
 
     nodes: Arc<AtomicI64>,
     // following variables are shared one object that ThreadPool has.
@@ -137,11 +294,15 @@ struct Thread {
     // following variables are used only main thread.
     previous_time_reduction: f64,
     calls_count: i32,
+    calls_per_check: i32,
+    last_check_time: i64,
     stop_on_ponderhit: Arc<AtomicBool>,
     ponder: Arc<AtomicBool>,
     stop: Arc<AtomicBool>,
     hide_all_output: Arc<AtomicBool>,
     nodess: Vec<Arc<AtomicI64>>,
+    rng: Arc<Mutex<StdRng>>,
+    output: Output,
 }
 
 unsafe impl std::marker::Send for Thread {} // for Thread::tt and Thread::ehash
@@ -150,21 +311,85 @@ struct ThreadPoolBase {
     threads: Vec<Arc<Mutex<Thread>>>,
 }
 
+// How the search threads are placed on the cpus. "none" leaves it to the OS.
+// "pin" binds the i-th thread to the i-th allowed cpu, so the threads don't move across the NUMA nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumaPolicy {
+    None,
+    Pin,
+}
+
+impl std::str::FromStr for NumaPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<NumaPolicy, String> {
+        match s {
+            "none" => Ok(NumaPolicy::None),
+            "pin" => Ok(NumaPolicy::Pin),
+            _ => Err(format!(r#"expected: "none" or "pin". found: "{}""#, s)),
+        }
+    }
+}
+
+impl NumaPolicy {
+    // The cpus which the threads are bound to. Empty means not binding.
+    // This must be called before binding any thread, because the threads inherit the affinity.
+    fn cpus(self) -> Vec<usize> {
+        match self {
+            NumaPolicy::None => vec![],
+            #[cfg(target_os = "linux")]
+            NumaPolicy::Pin => unsafe {
+                let mut set: libc::cpu_set_t = std::mem::zeroed();
+                if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                    return vec![];
+                }
+                (0..libc::CPU_SETSIZE as usize)
+                    .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+                    .collect()
+            },
+            #[cfg(not(target_os = "linux"))]
+            NumaPolicy::Pin => vec![],
+        }
+    }
+}
+
+// Binds the current thread to the cpu. false if it failed.
+#[cfg(target_os = "linux")]
+fn bind_current_thread(cpu: usize) -> bool {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_current_thread(_cpu: usize) -> bool {
+    false
+}
+
 pub struct ThreadPool {
     thread_pool_base: Arc<Mutex<ThreadPoolBase>>,
     nodess: Vec<Arc<AtomicI64>>,
-    pub book: Option<Book>,
+    pub book: Option<OpeningBook>,
     timeman: Arc<Mutex<TimeManagement>>,
     previous_score: Arc<Mutex<Value>>,
+    losing_count: Arc<AtomicU32>, // The number of the consecutive timed searches below the resign threshold.
     iter_values: Arc<Mutex<[Value; 4]>>,
     best_move_changess: Vec<Arc<AtomicU64>>,
+    sel_depths: Vec<Arc<AtomicI32>>,
     stop_on_ponderhit: Arc<AtomicBool>,
     pub ponder: Arc<AtomicBool>,
     pub stop: Arc<AtomicBool>,
+    searched: Arc<AtomicBool>, // The result is ready and only "stop" or "ponderhit" is waited for.
     increase_depth: Arc<AtomicBool>,
     pub hide_all_output: Arc<AtomicBool>,
     pub limits: LimitsType,
+    rng: Arc<Mutex<StdRng>>, // Reseeded from the Seed option at each "go" for the reproducibility.
     pub last_best_root_move: Arc<Mutex<Option<RootMove>>>, // Not for usi engine. For debug or some tools.
+    pub last_completed_depth: Arc<AtomicI32>, // The depth of last_best_root_move.
+    pub last_resign: Arc<AtomicBool>, // The last search resigned by ResignThreshold.
+    output: Output,
     handle: Option<std::thread::JoinHandle<()>>,
 }
 
@@ -174,6 +399,8 @@ impl Thread {
     }
     fn clear(&mut self) {
         self.calls_count = 0;
+        self.calls_per_check = 1;
+        self.last_check_time = 0;
         self.counter_moves.fill(None);
         self.main_history.fill(0);
         self.low_ply_history.fill(0);
@@ -188,9 +415,23 @@ impl Thread {
     }
     fn iterative_deepening_loop(&mut self) {
         let mut stack = [Stack::new(); MAX_PLY as usize + 10];
+        // The contempt is far smaller than the mate values, so it doesn't affect the mate distance.
+        let contempt = Value(self.usi_options.get_i64(UsiOptions::CONTEMPT) as i32);
+        let us = self.position.side_to_move();
+        self.draw_value[us.0 as usize] = Value::DRAW - contempt;
+        self.draw_value[us.inverse().0 as usize] = Value::DRAW + contempt;
+        self.qsearch_max_ply = self.usi_options.get_i64(UsiOptions::QSEARCH_MAX_PLY) as i32;
+        self.qsearch_check_plies = self.usi_options.get_i64(UsiOptions::QSEARCH_CHECK_PLIES) as i32;
+        self.eval_mode = eval_mode(&self.usi_options);
+        self.killer_slots = self.usi_options.get_i64(UsiOptions::KILLER_SLOTS) as usize;
+        self.max_pv_length = match self.usi_options.get_i64(UsiOptions::MAX_PV_LENGTH) {
+            0 => MAX_PLY as usize,
+            len => len as usize,
+        };
         let mut best_value = -Value::INFINITE;
         let mut last_best_move = None;
         let mut last_best_move_depth = Depth::ZERO; // not Option<Depth>
+        let mut best_move_stability = 0; // The number of the consecutive iterations that kept the same best move.
         let mut delta = -Value::INFINITE;
         let mut alpha = -Value::INFINITE;
         let mut beta = Value::INFINITE;
@@ -213,12 +454,20 @@ impl Thread {
                 }
             }
         }
-        let multi_pv = std::cmp::min(self.usi_options.get_i64(UsiOptions::MULTI_PV) as usize, self.root_moves.len());
+        let skill = Skill::new(self.usi_options.get_i64(UsiOptions::SKILL_LEVEL) as i32);
+        let mut skill_best_move = None;
+        let multi_pv = self.usi_options.get_i64(UsiOptions::MULTI_PV) as usize;
+        let multi_pv = if skill.enabled() {
+            std::cmp::max(multi_pv, Skill::MIN_MULTI_PV)
+        } else {
+            multi_pv
+        };
+        let multi_pv = std::cmp::min(multi_pv, self.root_moves.len());
         self.tt_hit_average = TT_HIT_AVERAGE_WINDOW * TT_HIT_AVERAGE_RESOLUTION / 2;
 
         let mut search_again_counter = 0;
 
-        evaluate_at_root(&self.position, &mut stack);
+        static_evaluate_at_root(&self.position, &mut stack, self.eval_mode);
         while {
             self.root_depth += Depth::ONE_PLY;
             self.root_depth
@@ -249,10 +498,10 @@ impl Thread {
                 search_again_counter += 1;
             }
             while self.pv_idx < multi_pv && !self.stop.load(Ordering::Relaxed) {
-                self.sel_depth = 0;
+                self.sel_depth.store(0, Ordering::Relaxed);
                 if self.root_depth >= Depth(4 * Depth::ONE_PLY.0) {
                     let previous_score = self.root_moves[self.pv_idx].previous_score;
-                    delta = Value(21 + previous_score.0.abs() / 256);
+                    delta = aspiration_delta(previous_score);
                     alpha = std::cmp::max(previous_score - delta, -Value::INFINITE);
                     beta = std::cmp::min(previous_score + delta, Value::INFINITE);
                 }
@@ -278,10 +527,14 @@ impl Thread {
                     {
                         last_info_time = Some(std::time::Instant::now());
                         if !self.hide_all_output.load(Ordering::Relaxed) {
-                            println!(
-                                "{}",
-                                self.pv_info_to_usi_string(self.nodes_searched(), multi_pv, self.root_depth, alpha, beta, false,)
-                            );
+                            self.output.infos(&self.pv_infos(
+                                self.nodes_searched(),
+                                multi_pv,
+                                self.root_depth,
+                                alpha,
+                                beta,
+                                false,
+                            ));
                         }
                     }
                     if best_value <= alpha {
@@ -300,7 +553,7 @@ impl Thread {
                         break;
                     }
 
-                    delta += delta / 4 + Value(5);
+                    delta = widen_aspiration_delta(delta);
                     debug_assert!(-Value::INFINITE <= alpha && beta <= Value::INFINITE);
                 }
 
@@ -316,10 +569,8 @@ impl Thread {
                 {
                     last_info_time = Some(std::time::Instant::now());
                     if !self.hide_all_output.load(Ordering::Relaxed) {
-                        println!(
-                            "{}",
-                            self.pv_info_to_usi_string(self.nodes_searched(), multi_pv, self.root_depth, alpha, beta, false,)
-                        );
+                        self.output
+                            .infos(&self.pv_infos(self.nodes_searched(), multi_pv, self.root_depth, alpha, beta, false));
                     }
                 }
 
@@ -330,9 +581,12 @@ impl Thread {
                 self.completed_depth = self.root_depth;
             }
 
-            if last_best_move.is_none() || last_best_move.unwrap_unchecked() != self.root_moves[0].pv[0] {
+            if last_best_move.is_none() || last_best_move.unwrap_unchecked_move() != self.root_moves[0].pv[0] {
                 last_best_move = Some(self.root_moves[0].pv[0]);
                 last_best_move_depth = self.root_depth;
+                best_move_stability = 0;
+            } else if !self.stop.load(Ordering::Relaxed) {
+                best_move_stability += 1;
             }
 
             if let Some(mate) = self.limits.mate {
@@ -345,6 +599,10 @@ impl Thread {
                 continue;
             }
 
+            if skill.enabled() && skill.time_to_pick(self.root_depth) {
+                skill_best_move = Some(skill.pick_best(&self.root_moves, multi_pv, &mut *self.rng.lock().unwrap()));
+            }
+
             if self.limits.use_time_management()
                 && !self.stop.load(Ordering::Relaxed)
                 && !self.stop_on_ponderhit.load(Ordering::Relaxed)
@@ -367,8 +625,11 @@ impl Thread {
                 let best_move_instability = 1.0 + total_best_move_changes / self.best_move_changess.len() as f64;
                 if self.root_moves.len() == 1 || {
                     let timeman = self.timeman.lock().unwrap();
-                    timeman.elapsed()
-                        > (timeman.optimum_millis() as f64 * falling_eval * reduction * best_move_instability) as i64
+                    let elapsed = timeman.elapsed();
+                    let optimum = timeman.optimum_millis() as f64;
+                    elapsed > (optimum * falling_eval * reduction * best_move_instability) as i64
+                        || (best_move_stability >= EASY_MOVE_STABLE_ITERATIONS
+                            && elapsed > (optimum * EASY_MOVE_TIME_RATIO) as i64)
                 } {
                     if self.ponder.load(Ordering::Relaxed) {
                         self.stop_on_ponderhit.store(true, Ordering::Relaxed);
@@ -396,8 +657,35 @@ impl Thread {
         }
 
         self.previous_time_reduction = time_reduction;
+
+        if skill.enabled() && !self.root_moves.is_empty() {
+            let m =
+                skill_best_move.unwrap_or_else(|| skill.pick_best(&self.root_moves, multi_pv, &mut *self.rng.lock().unwrap()));
+            let i = self.root_moves.iter().position(|rm| rm.pv[0] == m).unwrap();
+            self.root_moves.swap(0, i);
+        }
+    }
+    // The "material" mode skips the difference calculation of the evaluation.
+    fn static_evaluate(&mut self, stack: &mut [Stack]) -> Value {
+        match self.eval_mode {
+            EvalMode::Full => evaluate(
+                &mut self.position,
+                stack,
+                #[cfg(feature = "kppt")]
+                self.ehash,
+            ),
+            EvalMode::Material => material_value(&self.position),
+        }
     }
+    // The entry saved by the node is marked if the value comes from a repetition of a position before the node.
+    // The position may not be on the other paths to the node. (The graph history interaction.)
     fn search<IsPv: Bool>(&mut self, stack: &mut [Stack], alpha: Value, beta: Value, depth: Depth, cut_node: bool) -> Value {
+        let repetition_ply = std::mem::replace(&mut self.repetition_ply, MAX_PLY);
+        let value = self.search_node::<IsPv>(stack, alpha, beta, depth, cut_node);
+        self.repetition_ply = std::cmp::min(self.repetition_ply, repetition_ply);
+        value
+    }
+    fn search_node<IsPv: Bool>(&mut self, stack: &mut [Stack], alpha: Value, beta: Value, depth: Depth, cut_node: bool) -> Value {
         let pv_node: bool = IsPv::BOOL;
         let root_node = pv_node && get_stack(stack, 0).ply == 0;
 
@@ -417,35 +705,35 @@ impl Thread {
         let us = self.position.side_to_move();
         let mut best_value = -Value::INFINITE;
         let max_value = Value::INFINITE;
+        let params = self.usi_options.search_params;
 
         if self.is_main() {
             self.check_time();
         }
 
-        if pv_node && self.sel_depth < get_stack(stack, 0).ply + 1 {
-            self.sel_depth = get_stack(stack, 0).ply + 1;
+        if pv_node {
+            self.update_sel_depth(get_stack(stack, 0).ply + 1);
         }
 
         let mut alpha = alpha;
         let mut beta = beta;
         if !root_node {
             // Step 2
-            match self.position.is_repetition() {
+            let (repetition, repetition_plies) = self.position.is_repetition_with_plies();
+            if repetition != Repetition::Not {
+                self.repetition_ply = std::cmp::min(self.repetition_ply, get_stack(stack, 0).ply - repetition_plies);
+            }
+            match repetition {
                 Repetition::Not => {
                     if self.stop.load(Ordering::Relaxed) || get_stack(stack, 0).ply >= MAX_PLY {
                         return if get_stack(stack, 0).ply >= MAX_PLY && !in_check {
-                            evaluate(
-                                &mut self.position,
-                                stack,
-                                #[cfg(feature = "kppt")]
-                                self.ehash,
-                            )
+                            self.static_evaluate(stack)
                         } else {
-                            value_draw(self.nodes.load(Ordering::Relaxed))
+                            value_draw(self.position.nodes_searched())
                         };
                     }
                 }
-                Repetition::Draw => return Value::DRAW,
+                Repetition::Draw => return self.draw_value[us.0 as usize],
                 Repetition::Win => return value_mate_in(get_stack(stack, 0).ply),
                 Repetition::Lose => return value_mated_in(get_stack(stack, 0).ply),
                 Repetition::Superior => {
@@ -473,11 +761,10 @@ impl Thread {
         get_stack_mut(stack, 1).ply = get_stack(stack, 0).ply + 1;
         let mut best_move: Option<Move> = None;
         get_stack_mut(stack, 1).excluded_move = None;
-        get_stack_mut(stack, 2).killers[0] = None;
-        get_stack_mut(stack, 2).killers[1] = None;
+        get_stack_mut(stack, 2).killers = [None; MAX_KILLER_SLOTS];
 
         // get_stack(stack, -1).current_move can be None. None => prev_sq: Square(0)
-        let prev_sq = get_stack(stack, -1).current_move.unwrap_unchecked().to(); // todo: Move::NULL
+        let prev_sq = get_stack(stack, -1).current_move.unwrap_unchecked_move().to(); // todo: Move::NULL
 
         if root_node {
             get_stack_mut(stack, 4).stat_score = 0;
@@ -487,8 +774,7 @@ impl Thread {
 
         // Step 4
         let excluded_move = get_stack(stack, 0).excluded_move;
-        let key = self.position.key().0 ^ (u64::from(excluded_move.unwrap_unchecked().0.get()) << 16);
-        let key = Key(key);
+        let key = excluded_move_key(self.position.key(), excluded_move);
         let (mut tte, mut tt_hit) = unsafe { (*self.tt).probe(key) };
         let mut tt_value = if tt_hit {
             value_from_tt(tte.value(), get_stack(stack, 0).ply)
@@ -510,6 +796,7 @@ impl Thread {
             && tt_hit
             && tte.depth() >= depth
             && tt_value != Value::NONE
+            && !tte.depends_on_repetition()
             && if tt_value >= beta {
                 tte.bound().include_lower()
             } else {
@@ -533,7 +820,7 @@ impl Thread {
                         && !(prior_capture == Piece::EMPTY // prev is capture
                              || get_stack(stack, -1)
                                 .current_move
-                                .unwrap_unchecked()
+                                .unwrap_unchecked_move()
                                 .is_pawn_promotion())
                     {
                         update_continuation_histories(
@@ -555,12 +842,13 @@ impl Thread {
         // Step 5
         if self.position.is_entering_king_win() {
             best_value = Value::mate_in(get_stack(stack, 0).ply);
-            if tt_move.is_none() || tt_move.unwrap_unchecked() != Move::WIN {
+            if tt_move.is_none() || tt_move.unwrap_unchecked_move() != Move::WIN {
                 get_stack_mut(stack, 0).static_eval = best_value; // is this necessary?
                 tte.save(
                     key,
                     value_to_tt(best_value, get_stack(stack, 0).ply),
                     tt_pv,
+                    false,
                     Bound::EXACT,
                     depth,
                     Some(Move::WIN),
@@ -579,6 +867,7 @@ impl Thread {
                     key,
                     value_to_tt(best_value, get_stack(stack, 0).ply),
                     tt_pv,
+                    false,
                     Bound::EXACT,
                     depth,
                     Some(mate_move),
@@ -590,14 +879,9 @@ impl Thread {
         }
 
         let pure_static_eval = if root_node {
-            evaluate_at_root(&self.position, stack)
+            static_evaluate_at_root(&self.position, stack, self.eval_mode)
         } else {
-            evaluate(
-                &mut self.position,
-                stack,
-                #[cfg(feature = "kppt")]
-                self.ehash,
-            )
+            self.static_evaluate(stack)
         };
         let mut eval;
         let improving;
@@ -615,9 +899,10 @@ impl Thread {
                     get_stack_mut(stack, 0).static_eval = eval;
                 }
                 if eval == Value::NONE {
-                    eval = value_draw(self.nodes.load(Ordering::Relaxed));
+                    eval = value_draw(self.position.nodes_searched());
                 }
                 if tt_value != Value::NONE
+                    && !tte.depends_on_repetition()
                     && if tt_value > eval {
                         tte.bound().include_lower()
                     } else {
@@ -635,13 +920,21 @@ impl Thread {
                     eval = -get_stack(stack, -1).static_eval + Value(2 * TEMPO.0);
                     get_stack_mut(stack, 0).static_eval = eval;
                 }
-                tte.save(key, Value::NONE, tt_pv, Bound::BOUND_NONE, Depth::NONE, None, eval, unsafe {
-                    (*self.tt).generation()
-                });
+                tte.save(
+                    key,
+                    Value::NONE,
+                    tt_pv,
+                    false,
+                    Bound::BOUND_NONE,
+                    Depth::NONE,
+                    None,
+                    eval,
+                    unsafe { (*self.tt).generation() },
+                );
             }
 
             // Step 7
-            if !root_node && depth == Depth::ONE_PLY && eval <= alpha - RAZOR_MARGIN {
+            if !root_node && depth == Depth::ONE_PLY && eval <= alpha - Value(params.razor_margin) {
                 return self.qsearch::<IsPv>(stack, alpha, beta, Depth::ZERO);
             }
             improving = if get_stack(stack, -2).static_eval == Value::NONE {
@@ -652,7 +945,11 @@ impl Thread {
             };
 
             // Step 8
-            if !pv_node && depth.0 < 6 * Depth::ONE_PLY.0 && eval - futility_margin(depth) >= beta && eval < Value::KNOWN_WIN {
+            if !pv_node
+                && depth.0 < params.futility_max_ply * Depth::ONE_PLY.0
+                && eval - params.futility_margin(depth) >= beta
+                && eval < Value::KNOWN_WIN
+            {
                 return eval;
             }
 
@@ -676,7 +973,7 @@ impl Thread {
                 get_stack_mut(stack, 0).continuation_history = self.continuation_history[0][0].sentinel();
 
                 self.position.do_null_move();
-                #[cfg(feature = "kppt")]
+                #[cfg(any(feature = "kppt", feature = "nnue"))]
                 {
                     // key is wrong. but it's no problem.
                     get_stack_mut(stack, 1).static_eval_raw = get_stack(stack, 0).static_eval_raw;
@@ -709,8 +1006,12 @@ impl Thread {
             }
 
             // Step 10
-            if !pv_node && depth.0 >= 5 * Depth::ONE_PLY.0 && beta.0.abs() < Value::MATE_IN_MAX_PLY.0 {
-                let raised_beta = std::cmp::min(Value(beta.0 + 189 - 45 * i32::from(improving)), Value::INFINITE);
+            // ProbCut. The result isn't saved to the tt because it's from the reduced depth.
+            if !pv_node && depth.0 >= params.prob_cut_min_ply * Depth::ONE_PLY.0 && beta.0.abs() < Value::MATE_IN_MAX_PLY.0 {
+                let raised_beta = std::cmp::min(
+                    Value(beta.0 + params.prob_cut_margin - params.prob_cut_improving_margin * i32::from(improving)),
+                    Value::INFINITE,
+                );
                 let mut mp = MovePickerForProbCut::new(
                     &self.position,
                     tt_move,
@@ -722,17 +1023,16 @@ impl Thread {
                     if prob_cut_count >= 2 + 2 * i32::from(cut_node) {
                         break;
                     }
-                    if m != excluded_move.unwrap_unchecked() && self.position.legal(m) {
+                    if m != excluded_move.unwrap_unchecked_move() && self.position.legal(m) {
                         prob_cut_count += 1;
                         get_stack_mut(stack, 0).current_move = Some(m);
                         get_stack_mut(stack, 0).continuation_history = self.continuation_history[usize::from(in_check)]
                             [(prior_capture != Piece::EMPTY) as usize]
                             .get_mut(m.piece_moved_after_move(), m.to());
-                        debug_assert!(depth.0 >= 5 * Depth::ONE_PLY.0);
 
                         let gives_check = self.position.gives_check(m);
                         self.position.do_move(m, gives_check);
-                        #[cfg(feature = "kppt")]
+                        #[cfg(any(feature = "kppt", feature = "nnue"))]
                         get_stack_mut(stack, 1).static_eval_raw.set_not_evaluated();
                         let mut value =
                             -self.qsearch::<NonPv>(&mut stack[1..], -raised_beta, -raised_beta + Value(1), Depth::ZERO);
@@ -741,7 +1041,7 @@ impl Thread {
                                 &mut stack[1..],
                                 -raised_beta,
                                 -raised_beta + Value(1),
-                                Depth(depth.0 - 4 * Depth::ONE_PLY.0),
+                                Depth(depth.0 - params.prob_cut_reduction_ply * Depth::ONE_PLY.0),
                                 !cut_node,
                             );
                         }
@@ -790,7 +1090,7 @@ impl Thread {
             &self.capture_history,
             &cont_hists,
             counter_move,
-            &get_stack(stack, 0).killers,
+            &get_stack(stack, 0).killers[..self.killer_slots],
             if depth > Depth(12 * Depth::ONE_PLY.0) {
                 get_stack(stack, 0).ply
             } else {
@@ -800,7 +1100,7 @@ impl Thread {
 
         let mut value = best_value;
         let mut move_count_pruning = false;
-        let tt_capture = tt_move.is_some() && tt_move.unwrap_unchecked().is_capture_or_pawn_promotion(&self.position);
+        let tt_capture = tt_move.is_some() && tt_move.unwrap_unchecked_move().is_capture_or_pawn_promotion(&self.position);
         let mut singular_lmr = false;
 
         let th = ThreadHolding::new(self, key, get_stack(stack, 0).ply);
@@ -814,7 +1114,7 @@ impl Thread {
         while let Some(m) = mp.next_move(&self.position, move_count_pruning) {
             debug_assert!(Some(m).is_normal_move());
 
-            if m == excluded_move.unwrap_unchecked() {
+            if m == excluded_move.unwrap_unchecked_move() {
                 continue;
             }
 
@@ -825,6 +1125,12 @@ impl Thread {
             move_count += 1;
             get_stack_mut(stack, 0).move_count = move_count;
 
+            if root_node && self.is_main() && !self.hide_all_output.load(Ordering::Relaxed) {
+                if let Some(s) = self.currmove_info_to_usi_string(depth, m, move_count as usize + self.pv_idx) {
+                    self.output.println(&s);
+                }
+            }
+
             let mut extension = Depth::ZERO;
             let is_capture_or_pawn_promotion = m.is_capture_or_pawn_promotion(&self.position);
             let piece_moved_after_move = m.piece_moved_after_move();
@@ -848,14 +1154,17 @@ impl Thread {
                     {
                         continue;
                     }
-                    if lmr_depth < Depth(6)
+                    if lmr_depth < Depth(params.futility_pruning_max_lmr_ply)
                         && !in_check
-                        && get_stack(stack, 0).static_eval.0 + 235 + 172 * lmr_depth.0 <= alpha.0
+                        && get_stack(stack, 0).static_eval.0
+                            + params.futility_pruning_base
+                            + params.futility_pruning_per_ply * lmr_depth.0
+                            <= alpha.0
                         && self.main_history.get(us, m)
                             + unsafe { (*cont_hists[0]).get(to, piece_moved_after_move) }
                             + unsafe { (*cont_hists[1]).get(to, piece_moved_after_move) }
                             + unsafe { (*cont_hists[3]).get(to, piece_moved_after_move) }
-                            < 25000
+                            < params.futility_pruning_history_limit
                     {
                         continue;
                     }
@@ -873,19 +1182,17 @@ impl Thread {
             }
 
             // Step 14
-            if depth.0 >= 6 * Depth::ONE_PLY.0
-                && m == tt_move.unwrap_unchecked()
+            if depth.0 >= params.singular_extension_min_ply * Depth::ONE_PLY.0
+                && m == tt_move.unwrap_unchecked_move()
                 && !root_node
                 && excluded_move.is_none()
                 && tt_value.0.abs() < Value::KNOWN_WIN.0
                 && tte.bound().include_lower()
-                && tte.depth().0 >= depth.0 - 3 * Depth::ONE_PLY.0
+                && tte.depth().0 >= depth.0 - SINGULAR_EXTENSION_TT_DEPTH_MARGIN.0
                 && self.position.legal(m)
             {
-                let singular_beta = Value(
-                    tt_value.0 - ((i32::from(tt_pv && !pv_node) + 4) * depth.0) / Depth::ONE_PLY.0, /*2 * depth.0 / Depth::ONE_PLY.0*/
-                );
-                let half_depth = Depth(depth.0 / (2 * Depth::ONE_PLY.0) * Depth::ONE_PLY.0);
+                let singular_beta = singular_beta(tt_value, depth, tt_pv, pv_node);
+                let half_depth = singular_depth(depth);
                 get_stack_mut(stack, 0).excluded_move = Some(m);
                 value = self.search::<NonPv>(stack, singular_beta - Value(1), singular_beta, half_depth, cut_node);
                 get_stack_mut(stack, 0).excluded_move = None;
@@ -916,8 +1223,9 @@ impl Thread {
                 .get_mut(piece_moved_after_move, to);
 
             // Step 15
+            unsafe { (*self.tt).prefetch(self.position.key_after(m)) };
             self.position.do_move(m, gives_check);
-            #[cfg(feature = "kppt")]
+            #[cfg(any(feature = "kppt", feature = "nnue"))]
             get_stack_mut(stack, 1).static_eval_raw.set_not_evaluated();
 
             // Step 16
@@ -926,7 +1234,12 @@ impl Thread {
                 && (!root_node || self.best_move_count(m) == 0)
                 && (!is_capture_or_pawn_promotion
                     || move_count_pruning
-                    || get_stack(stack, 0).static_eval + capture_piece_value(self.position.captured_piece()) <= alpha
+                    || get_stack(stack, 0).static_eval
+                        + self
+                            .position
+                            .piece_values()
+                            .capture_piece_value(self.position.captured_piece())
+                        <= alpha
                     || cut_node
                     || self.tt_hit_average < 375 * TT_HIT_AVERAGE_RESOLUTION * TT_HIT_AVERAGE_WINDOW / 1024)
             {
@@ -956,9 +1269,10 @@ impl Thread {
                     if tt_capture {
                         r += Depth::ONE_PLY;
                     }
+                    // A drop has no move back. Its reverse() would be the zero move if it's dropped on the square 0.
                     if cut_node {
                         r += Depth(2 * Depth::ONE_PLY.0);
-                    } else if !self.position.see_ge(m.reverse(), Value::ZERO) {
+                    } else if !m.is_drop() && !self.position.see_ge(m.reverse(), Value::ZERO) {
                         r -= Depth((2 + i32::from(tt_pv)) * Depth::ONE_PLY.0);
                     }
 
@@ -1018,9 +1332,10 @@ impl Thread {
                 let rm: &mut RootMove = self.root_moves.iter_mut().find(|x| x.pv[0] == m).unwrap();
                 if move_count == 1 || value > alpha {
                     rm.score = value;
-                    rm.sel_depth = self.sel_depth;
+                    rm.sel_depth = self.sel_depth.load(Ordering::Relaxed);
                     rm.pv.truncate(1);
-                    rm.extract_pv_from_tt(&mut self.position, self.tt);
+                    // The ponder move is kept even if MaxPvLength is 1.
+                    rm.extract_pv_from_tt(&mut self.position, self.tt, std::cmp::max(self.max_pv_length, 2));
                     if move_count > 1 {
                         self.best_move_changes.fetch_add(1, Ordering::Relaxed);
                     }
@@ -1046,7 +1361,7 @@ impl Thread {
                 }
             }
 
-            if m != best_move.unwrap_unchecked() {
+            if m != best_move.unwrap_unchecked_move() {
                 if is_capture_or_pawn_promotion {
                     let _ = captures_searched.try_push(m);
                 } else if !is_capture_or_pawn_promotion {
@@ -1093,6 +1408,7 @@ impl Thread {
                 key,
                 value_to_tt(best_value, get_stack(stack, 0).ply),
                 tt_pv,
+                self.repetition_ply < get_stack(stack, 0).ply,
                 if best_value >= beta {
                     Bound::LOWER
                 } else if pv_node && best_move.is_some() {
@@ -1111,10 +1427,20 @@ impl Thread {
 
         best_value
     }
+    fn update_sel_depth(&self, sel_depth: i32) {
+        // Only this thread writes it, so no need for fetch_max().
+        if self.sel_depth.load(Ordering::Relaxed) < sel_depth {
+            self.sel_depth.store(sel_depth, Ordering::Relaxed);
+        }
+    }
     fn qsearch<IsPv: Bool>(&mut self, stack: &mut [Stack], alpha: Value, beta: Value, depth: Depth) -> Value {
         let pv_node: bool = IsPv::BOOL;
         let mut alpha = alpha;
 
+        if pv_node {
+            self.update_sel_depth(get_stack(stack, 0).ply + 1);
+        }
+
         let old_alpha = if pv_node {
             alpha
         } else {
@@ -1132,11 +1458,17 @@ impl Thread {
         // Because qsearch use only capture-moves, promotion-moves, and evasion-moves.
         // Their moves don't reach repetition positions.
         if get_stack_mut(stack, 0).ply >= MAX_PLY {
-            return Value::DRAW;
+            return self.draw_value[self.position.side_to_move().0 as usize];
         }
 
         debug_assert!(0 <= get_stack(stack, 0).ply && get_stack(stack, 0).ply < MAX_PLY);
 
+        // depth is 0 at the first ply of qsearch. The evasions are searched beyond the limit
+        // because the static evaluation in check can't tell a mate.
+        if self.qsearch_max_ply != 0 && !in_check && depth.0 <= -self.qsearch_max_ply * Depth::ONE_PLY.0 {
+            return self.static_evaluate(stack);
+        }
+
         let tt_depth = if in_check || depth >= Depth::QS_CHECKS {
             Depth::QS_CHECKS
         } else {
@@ -1156,6 +1488,7 @@ impl Thread {
             && tt_hit
             && tte.depth() >= tt_depth
             && tt_value != Value::NONE // Only in case of TT access race
+            && !tte.depends_on_repetition()
             && if tt_value >= beta {
                 tte.bound().include_lower()
             } else {
@@ -1179,15 +1512,11 @@ impl Thread {
                 best_value = tte.eval();
                 get_stack_mut(stack, 0).static_eval = best_value;
                 if best_value == Value::NONE {
-                    best_value = evaluate(
-                        &mut self.position,
-                        stack,
-                        #[cfg(feature = "kppt")]
-                        self.ehash,
-                    );
+                    best_value = self.static_evaluate(stack);
                     get_stack_mut(stack, 0).static_eval = best_value;
                 }
                 if tt_value != Value::NONE
+                    && !tte.depends_on_repetition()
                     && if tt_value > best_value {
                         tte.bound().include_lower()
                     } else {
@@ -1197,13 +1526,8 @@ impl Thread {
                     best_value = tt_value;
                 }
             } else {
-                best_value = if get_stack(stack, -1).current_move.unwrap_unchecked() != Move::NULL {
-                    evaluate(
-                        &mut self.position,
-                        stack,
-                        #[cfg(feature = "kppt")]
-                        self.ehash,
-                    )
+                best_value = if get_stack(stack, -1).current_move.unwrap_unchecked_move() != Move::NULL {
+                    self.static_evaluate(stack)
                 } else {
                     -get_stack(stack, -1).static_eval + Value(2 * TEMPO.0)
                 };
@@ -1216,6 +1540,7 @@ impl Thread {
                         key,
                         value_to_tt(best_value, get_stack(stack, 0).ply),
                         false,
+                        false,
                         Bound::LOWER,
                         Depth::NONE,
                         None,
@@ -1230,7 +1555,7 @@ impl Thread {
                 alpha = best_value;
             }
 
-            futility_base = best_value + Value(154);
+            futility_base = best_value + Value(self.usi_options.search_params.qsearch_futility_margin);
         }
 
         let cont_hists = [
@@ -1246,26 +1571,29 @@ impl Thread {
             &self.capture_history,
             &cont_hists,
             &self.position,
-            get_stack(stack, -1).current_move.unwrap_unchecked().to(),
+            get_stack(stack, -1).current_move.unwrap_unchecked_move().to(),
             tt_move,
-            depth,
+            QSearchLimits {
+                depth,
+                checks: depth.0 > -self.qsearch_check_plies * Depth::ONE_PLY.0,
+            },
         );
 
-        evaluate(
-            &mut self.position,
-            stack,
-            #[cfg(feature = "kppt")]
-            self.ehash,
-        ); // for difference calculation
+        self.static_evaluate(stack); // for difference calculation
         while let Some(m) = mp.next_move(&self.position) {
             debug_assert!(m != Move::NULL);
             let gives_check = self.position.gives_check(m);
             move_count += 1;
             if !in_check && !gives_check && futility_base > -Value::KNOWN_WIN {
                 let futility_value = futility_base
-                    + capture_piece_value(self.position.piece_on(m.to()))
+                    + self
+                        .position
+                        .piece_values()
+                        .capture_piece_value(self.position.piece_on(m.to()))
                     + if m.is_promotion() {
-                        promote_piece_type_value(PieceType::new(m.piece_moved_before_move()))
+                        self.position
+                            .piece_values()
+                            .promote_piece_type_value(PieceType::new(m.piece_moved_before_move()))
                     } else {
                         Value::ZERO
                     };
@@ -1300,8 +1628,9 @@ impl Thread {
                 [(prior_capture != Piece::EMPTY) as usize]
                 .get_mut(m.piece_moved_after_move(), m.to());
 
+            unsafe { (*self.tt).prefetch(self.position.key_after(m)) };
             self.position.do_move(m, gives_check);
-            #[cfg(feature = "kppt")]
+            #[cfg(any(feature = "kppt", feature = "nnue"))]
             get_stack_mut(stack, 1).static_eval_raw.set_not_evaluated();
             let value = -self.qsearch::<IsPv>(&mut stack[1..], -beta, -alpha, depth - Depth::ONE_PLY);
             self.position.undo_move(m);
@@ -1334,6 +1663,7 @@ impl Thread {
             key,
             value_to_tt(best_value, get_stack(stack, 0).ply),
             pv_hit,
+            false,
             if best_value >= beta {
                 Bound::LOWER
             } else if pv_node && best_value > old_alpha {
@@ -1358,8 +1688,10 @@ impl Thread {
             None => 0,
         }
     }
+    // The other threads publish their counts periodically, so the sum lags behind a little while searching.
     fn nodes_searched(&self) -> i64 {
         debug_assert!(self.is_main());
+        self.position.publish_nodes();
         self.nodess.iter().fold(0, |sum, nodes| sum + nodes.load(Ordering::Relaxed))
     }
     fn check_time(&mut self) {
@@ -1367,21 +1699,39 @@ impl Thread {
         if self.calls_count > 0 {
             return;
         }
+        // The time of pondering isn't counted. TimeManagement restarts the clock at ponderhit.
+        let (elapsed, maximum) = {
+            let timeman = self.timeman.lock().unwrap();
+            (timeman.elapsed(), timeman.maximum_millis())
+        };
+
+        let max_calls = self.usi_options.get_i64(UsiOptions::NODES_BETWEEN_TIME_CHECKS) as i32;
         self.calls_count = match self.limits.nodes {
-            Some(nodes) => std::cmp::min(1024, nodes / 1024) as i32,
-            None => 1024,
+            // Not depending on the clock. "go nodes" searches the same nodes every time.
+            Some(nodes) => std::cmp::min(max_calls as u64, nodes / 1024) as i32,
+            None => {
+                // The interval is doubled or halved to check the time about every TIME_CHECK_INTERVAL_MILLIS.
+                let interval = elapsed - self.last_check_time; // This is negative or 0 at the beginning of the search.
+                self.last_check_time = elapsed;
+                self.calls_per_check = if interval > TIME_CHECK_INTERVAL_MILLIS {
+                    self.calls_per_check / 2
+                } else if interval < TIME_CHECK_INTERVAL_MILLIS {
+                    self.calls_per_check.saturating_mul(2)
+                } else {
+                    self.calls_per_check
+                }
+                .max(1)
+                .min(max_calls);
+                self.calls_per_check
+            }
         };
 
         if self.ponder.load(Ordering::Relaxed) {
             return;
         }
 
-        let elapsed = self.limits.start_time.unwrap().elapsed();
-
-        if (self.limits.use_time_management()
-            && (elapsed.as_millis() as i64 > self.timeman.lock().unwrap().maximum_millis() - 10
-                || self.stop_on_ponderhit.load(Ordering::Relaxed)))
-            || (self.limits.movetime.is_some() && elapsed >= self.limits.movetime.unwrap())
+        if (self.limits.use_time_management() && (elapsed > maximum - 10 || self.stop_on_ponderhit.load(Ordering::Relaxed)))
+            || (self.limits.movetime.is_some() && elapsed >= self.limits.movetime.unwrap().as_millis() as i64)
             || (self.limits.nodes.is_some() && self.nodes_searched() >= self.limits.nodes.unwrap() as i64)
         {
             self.stop.store(true, Ordering::Relaxed);
@@ -1402,7 +1752,7 @@ impl Thread {
         let moved_piece = best_move.piece_moved_after_move();
         let captured = PieceType::new(self.position.piece_on(best_move.to()));
         let bonus1 = stat_bonus(depth + Depth::ONE_PLY);
-        let bonus2 = if best_value > beta + piece_type_value(PieceType::PAWN) {
+        let bonus2 = if best_value > beta + self.position.piece_values().piece_type_value(PieceType::PAWN) {
             bonus1
         } else {
             stat_bonus(depth)
@@ -1431,10 +1781,10 @@ impl Thread {
         }
     }
     fn update_quiet_stats(&mut self, stack: &mut [Stack], m: Move, bonus: i32, depth: Depth) {
-        if get_stack(stack, 0).killers[0].unwrap_unchecked() != m {
-            let ss = get_stack_mut(stack, 0);
-            ss.killers[1] = ss.killers[0];
-            ss.killers[0] = Some(m);
+        // The drops aren't the killers.
+        if !m.is_drop() {
+            let killer_slots = self.killer_slots;
+            get_stack_mut(stack, 0).update_killers(m, killer_slots);
         }
         let us = self.position.side_to_move();
         self.main_history.update(us, m, bonus);
@@ -1446,7 +1796,7 @@ impl Thread {
 
         let prev_move = get_stack(stack, -1).current_move;
         if prev_move.is_normal_move() {
-            let prev_sq = prev_move.unwrap_unchecked().to();
+            let prev_sq = prev_move.unwrap_unchecked_move().to();
             self.counter_moves.set(prev_sq, self.position.piece_on(prev_sq), m);
         }
         if depth.0 > 12 && get_stack(stack, 0).ply < LowPlyHistory::MAX_LPH as i32 {
@@ -1454,7 +1804,19 @@ impl Thread {
                 .update(get_stack(stack, 0).ply, m, stat_bonus(depth - Depth(7)));
         }
     }
-    fn pv_info_to_usi_string(
+    // Only after Currmove_Delay milliseconds not to output too much at the fast iterations.
+    fn currmove_info_to_usi_string(&self, depth: Depth, m: Move, move_number: usize) -> Option<String> {
+        if self.timeman.lock().unwrap().elapsed() < self.usi_options.get_i64(UsiOptions::CURRMOVE_DELAY) {
+            return None;
+        }
+        Some(format!(
+            "info depth {} currmove {} currmovenumber {}",
+            depth.0 / Depth::ONE_PLY.0,
+            m.to_usi_string(),
+            move_number
+        ))
+    }
+    fn pv_infos(
         &self,
         nodes_searched: i64,
         multi_pv: usize,
@@ -1462,9 +1824,11 @@ impl Thread {
         alpha: Value,
         beta: Value,
         reverse: bool, // for Shogidokoro Graph
-    ) -> String {
-        let elapsed_millis = self.limits.start_time.unwrap().elapsed().as_millis() as i64 + 1; // "+ 1": avoid dividing by 0
-        let info_with_multi_pv_index = |i: usize, rm: &RootMove| -> Option<String> {
+    ) -> Vec<SearchInfo> {
+        let elapsed_millis = self.limits.start_time.unwrap().elapsed().as_millis() as i64;
+        // The max of all the threads.
+        let sel_depth = self.sel_depths.iter().map(|x| x.load(Ordering::Relaxed)).max().unwrap_or(0);
+        let info_with_multi_pv_index = |i: usize, rm: &RootMove| -> Option<SearchInfo> {
             let updated = rm.score != -Value::INFINITE;
             if depth == Depth::ONE_PLY && !updated {
                 return None;
@@ -1474,25 +1838,30 @@ impl Thread {
             } else {
                 (depth - Depth::ONE_PLY, rm.previous_score)
             };
-            let line = format!(
-                "info depth {depth} seldepth {seldepth} multipv {multipv} score {score} {bound}nodes {nodes} nps {nps} time {time} pv {pv}",
-                depth = d.0 / Depth::ONE_PLY.0,
-                seldepth = rm.sel_depth,
-                multipv = i + 1,
-                score = v.to_usi(),
-                bound = if v >= beta {
-                    "lowerbound "
-                } else if v <= alpha {
-                    "upperbound "
-                } else {""},
-                nodes = nodes_searched,
-                nps = nodes_searched * 1000 / elapsed_millis,
-                time = elapsed_millis,
-                pv = rm.pv.iter().map(|m| m.to_usi_string()).collect::<Vec<_>>().join(" ")
-            );
-            Some(line)
+            Some(SearchInfo {
+                depth: d.0 / Depth::ONE_PLY.0,
+                seldepth: std::cmp::max(rm.sel_depth, sel_depth),
+                score: v,
+                // alpha and beta are the window of the current pv line.
+                bound: if i == self.pv_idx {
+                    score_bound(v, alpha, beta)
+                } else {
+                    Bound::EXACT
+                },
+                wdl: if self.usi_options.get_bool(UsiOptions::SHOW_WDL) {
+                    Some(v.to_wdl(self.position.ply()))
+                } else {
+                    None
+                },
+                nodes: nodes_searched,
+                nps: SearchInfo::nps(nodes_searched, elapsed_millis),
+                time_ms: elapsed_millis,
+                hashfull: unsafe { (*self.tt).hashfull() },
+                pv: rm.pv.iter().take(self.max_pv_length).copied().collect(),
+                multipv_index: i + 1,
+            })
         };
-        let mut lines = self
+        let mut infos = self
             .root_moves
             .iter()
             .take(multi_pv)
@@ -1500,12 +1869,61 @@ impl Thread {
             .flat_map(|(i, rm)| info_with_multi_pv_index(i, rm))
             .collect::<Vec<_>>();
         if reverse {
-            lines.reverse();
+            infos.reverse();
         }
-        lines.join("\n")
+        infos
+    }
+    #[cfg(test)]
+    fn pv_info_to_usi_string(
+        &self,
+        nodes_searched: i64,
+        multi_pv: usize,
+        depth: Depth,
+        alpha: Value,
+        beta: Value,
+        reverse: bool,
+    ) -> String {
+        self.pv_infos(nodes_searched, multi_pv, depth, alpha, beta, reverse)
+            .iter()
+            .map(|info| info.to_usi_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+pub(crate) fn eval_mode(usi_options: &UsiOptions) -> EvalMode {
+    usi_options
+        .get_string(UsiOptions::EVAL_MODE)
+        .parse::<EvalMode>()
+        .unwrap_or(EvalMode::Full)
+}
+
+pub(crate) fn static_evaluate_at_root(pos: &Position, stack: &mut [Stack], eval_mode: EvalMode) -> Value {
+    match eval_mode {
+        EvalMode::Full => evaluate_at_root(pos, stack),
+        EvalMode::Material => material_value(pos),
     }
 }
 
+// The static evaluation of the position, and the move of the best static evaluation after one ply.
+fn static_best_move(pos: &Position, root_moves: &[RootMove], eval_mode: EvalMode) -> (Value, Move) {
+    let mut stack = vec![Stack::new(); CURRENT_STACK_INDEX + 1];
+    let score = static_evaluate_at_root(pos, &mut stack, eval_mode);
+    let mut pos = Position::new_from_position(pos, Arc::new(AtomicI64::new(0)));
+    let best_move = root_moves
+        .iter()
+        .map(|rm| rm.pv[0])
+        .max_by_key(|&m| {
+            let gives_check = pos.gives_check(m);
+            pos.do_move(m, gives_check);
+            let value = -static_evaluate_at_root(&pos, &mut stack, eval_mode);
+            pos.undo_move(m);
+            value
+        })
+        .unwrap();
+    (score, best_move)
+}
+
 impl ThreadPool {
     pub fn new() -> ThreadPool {
         ThreadPool {
@@ -1514,15 +1932,22 @@ impl ThreadPool {
             book: None,
             timeman: Arc::new(Mutex::new(TimeManagement::new())),
             previous_score: Arc::new(Mutex::new(Value::INFINITE)),
+            losing_count: Arc::new(AtomicU32::new(0)),
             iter_values: Arc::new(Mutex::new([Value::ZERO; 4])),
             best_move_changess: vec![],
+            sel_depths: vec![],
             stop_on_ponderhit: Arc::new(AtomicBool::new(false)),
             ponder: Arc::new(AtomicBool::new(false)),
             stop: Arc::new(AtomicBool::new(false)),
+            searched: Arc::new(AtomicBool::new(false)),
             increase_depth: Arc::new(AtomicBool::new(true)),
             hide_all_output: Arc::new(AtomicBool::new(false)),
             limits: LimitsType::new(),
+            rng: Arc::new(Mutex::new(StdRng::from_entropy())),
             last_best_root_move: Arc::new(Mutex::new(None)),
+            last_completed_depth: Arc::new(AtomicI32::new(0)),
+            last_resign: Arc::new(AtomicBool::new(false)),
+            output: Output::new(),
             handle: None,
         }
     }
@@ -1531,6 +1956,7 @@ impl ThreadPool {
             th.lock().unwrap().clear();
         }
         *self.last_best_root_move.lock().unwrap() = None;
+        self.losing_count.store(0, Ordering::Relaxed);
 
         let thread_pool_base = self.thread_pool_base.lock().unwrap();
         let mut main_thread = thread_pool_base.threads[0].lock().unwrap();
@@ -1554,16 +1980,24 @@ impl ThreadPool {
         self.thread_pool_base.lock().unwrap().threads.clear();
         self.nodess = (0..requested).map(|_| Arc::new(AtomicI64::new(0))).collect();
         self.best_move_changess = (0..requested).map(|_| Arc::new(AtomicU64::new(0))).collect();
-        *reductions = Reductions::new(requested);
+        self.sel_depths = (0..requested).map(|_| Arc::new(AtomicI32::new(0))).collect();
+        reductions.set_thread_num(requested);
         self.thread_pool_base.lock().unwrap().threads = (0..requested)
             .map(|i| {
                 Arc::new(Mutex::new(Thread {
                     idx: i,
                     pv_idx: 0,
                     tt_hit_average: 0,
-                    sel_depth: 0,
+                    sel_depth: self.sel_depths[i].clone(),
                     null_move_pruning_min_ply: 0,
                     null_move_pruning_color: Color::BLACK,
+                    repetition_ply: MAX_PLY,
+                    draw_value: [Value::DRAW; Color::NUM],
+                    qsearch_max_ply: 0,
+                    qsearch_check_plies: 0,
+                    eval_mode: EvalMode::Full,
+                    killer_slots: 2,
+                    max_pv_length: MAX_PLY as usize,
                     position: Position::new(),
                     root_moves: RootMoves::new(),
                     root_depth: Depth::ZERO,
@@ -1586,17 +2020,22 @@ impl ThreadPool {
                     usi_options: UsiOptions::new(),
                     best_move_changes: self.best_move_changess[i].clone(),
                     best_move_changess: self.best_move_changess.clone(),
+                    sel_depths: self.sel_depths.clone(),
                     nodes: self.nodess[i].clone(),
                     previous_score: self.previous_score.clone(),
                     iter_values: self.iter_values.clone(),
                     increase_depth: self.increase_depth.clone(),
                     previous_time_reduction: 1.0,
                     calls_count: 0,
+                    calls_per_check: 1,
+                    last_check_time: 0,
                     stop_on_ponderhit: self.stop_on_ponderhit.clone(),
                     ponder: self.ponder.clone(),
                     stop: self.stop.clone(),
                     hide_all_output: self.hide_all_output.clone(),
                     nodess: vec![],
+                    rng: self.rng.clone(),
+                    output: self.output.clone(),
                 }))
             })
             .collect();
@@ -1617,9 +2056,17 @@ impl ThreadPool {
             Perft::new(pos).go(limits.perft.unwrap());
             return;
         }
+        // The positions of the search have the piece values of this searcher.
+        #[cfg(feature = "tune")]
+        let pos = &{
+            let mut pos = Position::new_from_position(pos, Arc::new(AtomicI64::new(0)));
+            pos.set_piece_values(&usi_options.piece_values);
+            pos
+        };
         self.wait_for_search_finished();
         self.stop.store(false, Ordering::Relaxed);
         self.stop_on_ponderhit.store(false, Ordering::Relaxed);
+        self.searched.store(false, Ordering::Relaxed);
         self.ponder.store(ponder_mode, Ordering::Relaxed);
         self.hide_all_output.store(hide_all_output, Ordering::Relaxed);
         self.timeman
@@ -1632,56 +2079,127 @@ impl ThreadPool {
             let mut mlist = MoveList::new();
             mlist.generate::<LegalType>(pos, 0);
             let mut root_moves = RootMoves::new();
-            let book_move = if usi_options.get_bool(UsiOptions::BOOK_ENABLE) {
-                match &self.book {
-                    Some(book) => book.probe(pos, &mut rand::thread_rng()),
-                    None => None,
-                }
-            } else {
-                None
-            };
-            match book_move {
-                Some(book_move) => {
-                    root_moves.push(RootMove::new(book_move));
-                }
-                None => {
-                    for m in mlist.slice(0) {
-                        root_moves.push(RootMove::new(m.mv));
-                    }
+            for m in mlist.slice(0) {
+                if limits.searchmoves.is_empty() || limits.searchmoves.contains(&m.mv) {
+                    root_moves.push(RootMove::new(m.mv));
                 }
             }
+            // The first move of root_pv is searched first, and the rest are tried first by the tt moves.
+            if let Some(i) = limits
+                .root_pv
+                .first()
+                .and_then(|&m| root_moves.iter().position(|x| x.pv[0] == m))
+            {
+                root_moves[..=i].rotate_right(1);
+                let mut pos = Position::new_from_position(pos, Arc::new(AtomicI64::new(0)));
+                insert_pv_in_tt(&limits.root_pv, &mut pos, tt);
+            }
             root_moves
         };
+        *self.rng.lock().unwrap() = match usi_options.get_i64(UsiOptions::SEED) {
+            0 => StdRng::from_entropy(),
+            seed => StdRng::seed_from_u64(seed as u64),
+        };
+        let book_move = if usi_options.get_bool(UsiOptions::BOOK_ENABLE)
+            && limits.searchmoves.is_empty()
+            && i64::from(pos.ply()) <= usi_options.get_i64(UsiOptions::BOOK_MOVES)
+        {
+            match &self.book {
+                Some(book) => book.probe(pos, &mut *self.rng.lock().unwrap()),
+                None => None,
+            }
+        } else {
+            None
+        };
+        // The perfect play of the trivial endings.
+        let endgame = if limits.searchmoves.is_empty() && book_move.is_none() {
+            crate::endgame::probe(pos)
+        } else {
+            None
+        };
+        // (best move, depth, score, pv) of the endgame move, or the move by the static evaluation for "go depth 0".
+        let no_search_result = match endgame {
+            Some(endgame) => Some((endgame.best_move, endgame.pv.len() as i32, endgame.score, endgame.pv)),
+            None if limits.depth == Some(0) && book_move.is_none() && !root_moves.is_empty() => {
+                let (score, m) = static_best_move(pos, &root_moves, eval_mode(usi_options));
+                Some((m, 0, score, vec![m]))
+            }
+            None => None,
+        };
         let dummy_nodes = Arc::new(AtomicI64::new(0)); // This isn't used.
         let pos = Position::new_from_position(pos, dummy_nodes);
         let nodess_cloned = self.nodess.clone();
         let timeman_cloned = self.timeman.clone();
         let previous_score_cloned = self.previous_score.clone();
+        let losing_count_cloned = self.losing_count.clone();
         let thread_pool_base_cloned = self.thread_pool_base.clone();
         let stop_cloned = self.stop.clone();
         let ponder_cloned = self.ponder.clone();
+        let searched_cloned = self.searched.clone();
         let hide_all_output_cloned = self.hide_all_output.clone();
         let usi_options_cloned = usi_options.clone();
         let last_best_root_move_cloned = self.last_best_root_move.clone();
+        let last_completed_depth_cloned = self.last_completed_depth.clone();
+        let last_resign_cloned = self.last_resign.clone();
+        last_resign_cloned.store(false, Ordering::Relaxed);
+        let output_cloned = self.output.clone();
+        let cpus = usi_options
+            .get_string(UsiOptions::NUMA_POLICY)
+            .parse::<NumaPolicy>()
+            .unwrap_or(NumaPolicy::None)
+            .cpus();
         self.handle = Some(
             std::thread::Builder::new()
                 .stack_size(crate::stack_size::STACK_SIZE)
                 .spawn(move || {
-                    if root_moves.is_empty() || pos.is_entering_king_win() {
+                    // The book move, the endgame move and the move of "go depth 0" are played without searching.
+                    if root_moves.is_empty() || pos.is_entering_king_win() || book_move.is_some() || no_search_result.is_some() {
+                        searched_cloned.store(true, Ordering::Relaxed);
                         while !stop_cloned.load(Ordering::Relaxed)
                             && (ponder_cloned.load(Ordering::Relaxed) || limits.infinite.is_some())
                         {
                             std::thread::sleep(std::time::Duration::from_millis(1));
                         }
-                        let m = if root_moves.is_empty() {
-                            *last_best_root_move_cloned.lock().unwrap() = Some(RootMove::new(Move::RESIGN));
-                            "resign"
+                        let (m, s) = if root_moves.is_empty() {
+                            (Move::RESIGN, "resign".to_string())
+                        } else if pos.is_entering_king_win() {
+                            (Move::WIN, "win".to_string())
+                        } else if let Some(m) = book_move {
+                            (m, m.to_usi_string())
                         } else {
-                            *last_best_root_move_cloned.lock().unwrap() = Some(RootMove::new(Move::WIN));
-                            "win"
+                            let m = no_search_result.as_ref().unwrap().0;
+                            (m, m.to_usi_string())
                         };
+                        let mut root_move = RootMove::new(m);
+                        if root_moves.is_empty() {
+                            // No legal moves is a loss even if it isn't check.
+                            root_move.score = Value::mated_in(0);
+                        }
+                        let mut ponder_move = None;
+                        if let Some((_, depth, score, pv)) = &no_search_result {
+                            root_move.score = *score;
+                            root_move.pv = pv.clone();
+                            ponder_move = pv.get(1).map(|m| m.to_usi_string());
+                            if !hide_all_output_cloned.load(Ordering::Relaxed) {
+                                output_cloned.infos(&[SearchInfo {
+                                    depth: *depth,
+                                    seldepth: *depth,
+                                    score: *score,
+                                    bound: Bound::EXACT,
+                                    wdl: None,
+                                    nodes: 0,
+                                    nps: 0,
+                                    time_ms: limits.start_time.unwrap().elapsed().as_millis() as i64,
+                                    hashfull: 0,
+                                    pv: pv.clone(),
+                                    multipv_index: 1,
+                                }]);
+                            }
+                        }
+                        *last_best_root_move_cloned.lock().unwrap() = Some(root_move);
+                        last_completed_depth_cloned.store(0, Ordering::Relaxed);
                         if !hide_all_output_cloned.load(Ordering::Relaxed) {
-                            println!("bestmove {}", m);
+                            output_cloned.bestmove(&s, ponder_move.as_deref());
                         }
                         return;
                     }
@@ -1703,7 +2221,11 @@ impl ThreadPool {
                         let limits_cloned = limits.clone();
                         let usi_options_cloned = usi_options_cloned.clone();
                         let timeman_cloned = timeman_cloned.clone();
+                        let cpu = if cpus.is_empty() { None } else { Some(cpus[i % cpus.len()]) };
                         let worker = move || {
+                            if let Some(cpu) = cpu {
+                                bind_current_thread(cpu);
+                            }
                             let mut th = thread_cloned.lock().unwrap();
                             th.best_move_changes.store(0, Ordering::Relaxed);
                             th.limits = limits_cloned;
@@ -1715,6 +2237,7 @@ impl ThreadPool {
                             th.timeman = timeman_cloned;
                             th.low_ply_history.fill(0);
                             th.iterative_deepening_loop();
+                            th.position.publish_nodes();
                         };
                         if i == 0 {
                             worker(); // The main thread doesn't use std::thread::spawn().
@@ -1727,10 +2250,14 @@ impl ThreadPool {
                             );
                         }
                     }
+                    // The search of the main thread has finished by the depth limit or a mate while pondering.
+                    // "ponderhit" sends the bestmove at once, and "stop" sends it too because the GUI waits for it.
+                    searched_cloned.store(true, Ordering::Relaxed);
                     while !stop_cloned.load(Ordering::Relaxed)
                         && (ponder_cloned.load(Ordering::Relaxed) || limits.infinite.is_some())
                     {
-                        // nop
+                        // Wait for "stop" or "ponderhit" without using up a cpu core.
+                        std::thread::sleep(std::time::Duration::from_millis(1));
                     }
                     // main thread finished.
                     // stop the other threads.
@@ -1740,7 +2267,8 @@ impl ThreadPool {
                     }
 
                     let multi_pv = std::cmp::min(usi_options_cloned.get_i64(UsiOptions::MULTI_PV) as usize, root_moves.len());
-                    let best_thread = if multi_pv == 1 && limits.depth.is_none() && !root_moves.is_empty() {
+                    let skill = Skill::new(usi_options_cloned.get_i64(UsiOptions::SKILL_LEVEL) as i32);
+                    let best_thread = if multi_pv == 1 && limits.depth.is_none() && !skill.enabled() && !root_moves.is_empty() {
                         let mut votes = std::collections::BTreeMap::new();
                         let min_score: Value = thread_pool_base_cloned
                             .lock()
@@ -1781,6 +2309,21 @@ impl ThreadPool {
                     };
 
                     *previous_score_cloned.lock().unwrap() = best_thread.lock().unwrap().root_moves[0].score;
+                    // Only the searches of the game moves count. They are limited by the time and not stopped while pondering.
+                    // "go infinite", "go depth" etc. and "go ponder" without "ponderhit" don't change the count.
+                    let resign = if !limits.is_timed() || ponder_cloned.load(Ordering::Relaxed) {
+                        false
+                    } else {
+                        let resign_threshold = usi_options_cloned.get_i64(UsiOptions::RESIGN_THRESHOLD);
+                        let score = best_thread.lock().unwrap().root_moves[0].score;
+                        if resign_threshold != 0 && i64::from(score.0) <= -resign_threshold {
+                            let losing_count = losing_count_cloned.fetch_add(1, Ordering::Relaxed) + 1;
+                            i64::from(losing_count) >= usi_options_cloned.get_i64(UsiOptions::RESIGN_MOVE_COUNT)
+                        } else {
+                            losing_count_cloned.store(0, Ordering::Relaxed);
+                            false
+                        }
+                    };
 
                     let nodes_searched = thread_pool_base_cloned.lock().unwrap().threads[0]
                         .lock()
@@ -1789,36 +2332,84 @@ impl ThreadPool {
                     if let Ok(best_thread) = best_thread.lock() {
                         if !hide_all_output_cloned.load(Ordering::Relaxed) {
                             // Always send again PV info.
-                            println!(
-                                "{}",
-                                best_thread.pv_info_to_usi_string(
-                                    nodes_searched,
-                                    multi_pv,
-                                    best_thread.completed_depth,
-                                    -Value::INFINITE,
-                                    Value::INFINITE,
-                                    true,
-                                )
-                            );
-                            let mut s = format!("bestmove {}", best_thread.root_moves[0].pv[0].to_usi_string(),);
-                            if usi_options_cloned.get_bool(UsiOptions::USI_PONDER) && best_thread.root_moves[0].pv.len() >= 2 {
-                                s += &format!(" ponder {}", best_thread.root_moves[0].pv[1].to_usi_string());
+                            output_cloned.infos(&best_thread.pv_infos(
+                                nodes_searched,
+                                multi_pv,
+                                best_thread.completed_depth,
+                                -Value::INFINITE,
+                                Value::INFINITE,
+                                true,
+                            ));
+                            let pv = &best_thread.root_moves[0].pv;
+                            let ponder_move = if usi_options_cloned.get_bool(UsiOptions::USI_PONDER) {
+                                best_thread.root_moves[0].ponder_move(&pos).map(|m| m.to_usi_string())
+                            } else {
+                                None
+                            };
+                            if resign {
+                                output_cloned.bestmove("resign", None);
+                            } else {
+                                output_cloned.bestmove(&pv[0].to_usi_string(), ponder_move.as_deref());
                             }
-                            println!("{}", s);
                         }
                     }
                     *last_best_root_move_cloned.lock().unwrap() = Some(best_thread.lock().unwrap().root_moves[0].clone());
+                    last_resign_cloned.store(resign, Ordering::Relaxed);
+                    last_completed_depth_cloned.store(
+                        best_thread.lock().unwrap().completed_depth.0 / Depth::ONE_PLY.0,
+                        Ordering::Relaxed,
+                    );
+                })
+                .unwrap(),
+        );
+    }
+    // "go mate". limits.movetime is the time limit. no limit if limits.infinite is set.
+    pub fn start_mate_search(&mut self, pos: &Position, limits: LimitsType, hide_all_output: bool) {
+        self.wait_for_search_finished();
+        self.stop.store(false, Ordering::Relaxed);
+        self.ponder.store(false, Ordering::Relaxed);
+        self.hide_all_output.store(hide_all_output, Ordering::Relaxed);
+        self.limits = limits.clone();
+        let mut pos = Position::new_from_position(pos, self.nodess[0].clone());
+        let stop_cloned = self.stop.clone();
+        let hide_all_output_cloned = self.hide_all_output.clone();
+        let output_cloned = self.output.clone();
+        self.handle = Some(
+            std::thread::Builder::new()
+                .stack_size(crate::stack_size::STACK_SIZE)
+                .spawn(move || {
+                    let time_limit = if limits.infinite.is_some() { None } else { limits.movetime };
+                    let mut searcher = MateSearcher::new(&stop_cloned, limits.start_time.unwrap(), time_limit);
+                    let result = searcher.search(&mut pos);
+                    if !hide_all_output_cloned.load(Ordering::Relaxed) {
+                        output_cloned.println(&result.to_usi_string());
+                    }
                 })
                 .unwrap(),
         );
     }
+    // UsiInfoSink prints the output to stdout.
+    pub fn set_info_sink(&mut self, sink: Box<dyn InfoSink>) {
+        *self.output.0.lock().unwrap() = sink;
+    }
+    pub fn ponderhit(&mut self) {
+        if !self.is_searched() {
+            // Our clock starts now.
+            self.timeman.lock().unwrap().restart();
+        }
+        self.ponder.store(false, Ordering::Relaxed);
+    }
+    // The main thread has the result. While pondering, the bestmove is sent at "ponderhit" or "stop".
+    pub fn is_searched(&self) -> bool {
+        self.searched.load(Ordering::Relaxed)
+    }
     pub fn wait_for_search_finished(&mut self) {
         if let Some(handle) = self.handle.take() {
             handle.join().unwrap();
         }
     }
     #[allow(dead_code)]
-    fn nodes_searched(&self) -> i64 {
+    pub fn nodes_searched(&self) -> i64 {
         self.nodess.iter().fold(0, |sum, nodes| sum + nodes.load(Ordering::Relaxed))
     }
 }
@@ -1829,51 +2420,1893 @@ impl Drop for ThreadPool {
     }
 }
 
+// Collects the output for the tests. The "info" lines are collected only by LinesSink::with_info().
+#[cfg(test)]
+pub struct LinesSink {
+    lines: Arc<Mutex<Vec<String>>>,
+    with_info: bool,
+}
+
+#[cfg(test)]
+impl LinesSink {
+    pub fn new(lines: Arc<Mutex<Vec<String>>>) -> LinesSink {
+        LinesSink { lines, with_info: false }
+    }
+    pub fn with_info(lines: Arc<Mutex<Vec<String>>>) -> LinesSink {
+        LinesSink { lines, with_info: true }
+    }
+}
+
+#[cfg(test)]
+impl InfoSink for LinesSink {
+    fn info(&mut self, info: &SearchInfo) {
+        if self.with_info {
+            self.lines.lock().unwrap().push(info.to_usi_string());
+        }
+    }
+    fn println(&mut self, s: &str) {
+        self.lines.lock().unwrap().push(s.to_string());
+    }
+}
+
 #[test]
 fn test_start_thinking() {
     std::thread::Builder::new()
         .stack_size(crate::stack_size::STACK_SIZE)
         .spawn(|| {
-            let mut thread_pool = ThreadPool::new();
-            let mut tt = TranspositionTable::new();
-            #[cfg(feature = "kppt")]
-            let usi_options = UsiOptions::new();
-            #[cfg(feature = "kppt")]
-            let mut ehash = EvalHash::new();
-            tt.resize(16, &mut thread_pool);
-            #[cfg(feature = "kppt")]
-            ehash.resize(16, &mut thread_pool);
-            #[cfg(feature = "kppt")]
-            match load_evaluate_files(&usi_options.get_string(UsiOptions::EVAL_DIR)) {
-                Ok(_) => {
-                    let limits = {
-                        let mut limits = LimitsType::new();
-                        limits.depth = Some(1);
-                        limits.start_time = Some(std::time::Instant::now());
-                        limits
-                    };
-                    let mut breadcrumbs = Breadcrumbs::new();
-                    let mut reductions = Reductions::new(1);
-                    thread_pool.set(
-                        3,
-                        &mut tt,
-                        #[cfg(feature = "kppt")]
-                        &mut ehash,
-                        &mut breadcrumbs,
-                        &mut reductions,
-                    );
-                    let ponder_mode = false;
-                    let hide_all_output = false;
-                    thread_pool.start_thinking(&Position::new(), &mut tt, limits, &usi_options, ponder_mode, hide_all_output);
-                    thread_pool.wait_for_search_finished();
-                }
-                Err(_) => {
-                    // No evaluation funciton binaries.
-                    // We want to do "cargo test" without evaluation function binaries.
-                    // Then we do nothing and pass this test.
-                    // todo: Is there a more better way?
-                }
+            let mut searcher = Searcher::new_for_test(&[(UsiOptions::THREADS, "3")]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(1);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = false;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_nodes() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let search_nodes = |thread_pool: &mut ThreadPool, tt: &mut TranspositionTable| {
+                thread_pool.clear();
+                tt.clear();
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.nodes = Some(100_000);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                thread_pool.nodes_searched()
+            };
+            let nodes0 = search_nodes(thread_pool, tt);
+            let nodes1 = search_nodes(thread_pool, tt);
+            assert!(nodes0 >= 100_000);
+            assert_eq!(nodes0, nodes1);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_multi_pv() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[(UsiOptions::MULTI_PV, "3")]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(6);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+
+            let root_moves = thread_pool.thread_pool_base.lock().unwrap().threads[0]
+                .lock()
+                .unwrap()
+                .root_moves
+                .clone();
+            let lines = &root_moves[..3];
+            assert!(lines.iter().all(|rm| rm.score != -Value::INFINITE));
+            assert!(lines[0].pv[0] != lines[1].pv[0] && lines[1].pv[0] != lines[2].pv[0] && lines[0].pv[0] != lines[2].pv[0]);
+            assert!(lines[0].score >= lines[1].score && lines[1].score >= lines[2].score);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_ponderhit() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            thread_pool.clear();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.movetime = Some(std::time::Duration::from_millis(100));
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = true;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            // The byoyomi has passed, but the search continues while pondering.
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            assert!(thread_pool.last_best_root_move.lock().unwrap().is_none());
+
+            let ponderhit_time = std::time::Instant::now();
+            thread_pool.ponderhit();
+            thread_pool.wait_for_search_finished();
+            assert!(ponderhit_time.elapsed() >= std::time::Duration::from_millis(100));
+            let best_root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+            assert!(Position::new().legal(best_root_move.pv[0]));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_ponderhit_after_search_finished() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let lines = Arc::new(Mutex::new(vec![]));
+            thread_pool.set_info_sink(Box::new(LinesSink::new(lines.clone())));
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(3);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = true;
+            let hide_all_output = false;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            let start = std::time::Instant::now();
+            while !thread_pool.is_searched() {
+                assert!(start.elapsed() < std::time::Duration::from_secs(10));
+                std::thread::sleep(std::time::Duration::from_millis(1));
             }
+            // The bestmove waits for "ponderhit".
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            assert!(lines.lock().unwrap().is_empty());
+            assert!(thread_pool.last_best_root_move.lock().unwrap().is_none());
+
+            let ponderhit_time = std::time::Instant::now();
+            thread_pool.ponderhit();
+            thread_pool.wait_for_search_finished();
+            assert!(ponderhit_time.elapsed() < std::time::Duration::from_millis(100));
+            let best_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0];
+            let lines = lines.lock().unwrap();
+            assert_eq!(lines.len(), 1);
+            assert!(lines[0].starts_with(&format!("bestmove {}", best_move.to_usi_string())));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_searchmoves() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let pos = Position::new();
+            // a bad move. the engine never chooses it without searchmoves.
+            let m = Move::new_from_usi_str("1g1f", &pos).unwrap();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(6);
+                limits.searchmoves = vec![m];
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let best_root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+            assert_eq!(best_root_move.pv[0], m);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_root_pv() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            // The pawn takes the free rook.
+            let pos = Position::from_sfen("lnsgkgsnl/7b1/ppppppppp/9/9/2r6/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1").unwrap();
+            let mut root_pv = vec![];
+            {
+                let mut pos = Position::new_from_position(&pos, Arc::new(AtomicI64::new(0)));
+                for s in ["7g7f", "3c3d", "7f7e"].iter() {
+                    let m = Move::new_from_usi_str(s, &pos).unwrap();
+                    let gives_check = pos.gives_check(m);
+                    pos.do_move(m, gives_check);
+                    root_pv.push(m);
+                }
+            }
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(1);
+                limits.root_pv = root_pv.clone();
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let best_root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+            assert_eq!(best_root_move.pv[0], root_pv[0]);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_entering_king_win() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            // The declaration doesn't need the evaluation function.
+            let pos = Position::from_sfen("1p7/KRRBPPPPP/NN7/9/9/9/9/9/8k b BP 1").unwrap();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(1);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let best_root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+            assert_eq!(best_root_move.pv[0], Move::WIN);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[cfg(feature = "kppt")]
+#[test]
+fn test_eval_hash_disabled() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let search = |thread_pool: &mut ThreadPool, tt: &mut TranspositionTable| {
+                thread_pool.clear();
+                tt.clear();
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.nodes = Some(50_000);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                let best_root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+                (thread_pool.nodes_searched(), best_root_move.pv[0], best_root_move.score)
+            };
+            // The eval hash never changes the evaluation values, so the search is the same.
+            ehash.resize(0, thread_pool);
+            let without_eval_hash = search(thread_pool, tt);
+            ehash.resize(16, thread_pool);
+            let with_eval_hash = search(thread_pool, tt);
+            assert!(without_eval_hash == with_eval_hash);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_book_move() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::BOOK_ENABLE, "true")]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            thread_pool.book = Some(OpeningBook::from_file("test/book.json").unwrap());
+            // The book move doesn't need the evaluation function.
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(1);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let best_root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+            assert!(["2g2f", "6i7h", "7g7f"].contains(&best_root_move.pv[0].to_usi_string().as_str()));
+            assert_eq!(thread_pool.nodes_searched(), 0);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_endgame() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            // "isready" starts building them in the background.
+            crate::endgame::build_tables();
+            // K + rook in hand vs lone K. The endgame move doesn't need the evaluation function.
+            let pos = Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 b R 1").unwrap();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(1);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let best_root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+            assert!(Value::MATE_IN_MAX_PLY <= best_root_move.score);
+            assert_eq!(best_root_move.score, Value::mate_in(best_root_move.pv.len() as i32));
+            assert!(pos.legal_moves().contains(&best_root_move.pv[0]));
+            assert_eq!(thread_pool.nodes_searched(), 0);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[cfg(feature = "tune")]
+#[test]
+fn test_go_piece_values() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            use crate::piecevalue::*;
+            // The material evaluation doesn't need the evaluation function binaries.
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::EVAL_MODE, "material")]);
+            searcher.set_option("PieceValue.Pawn", "10000");
+            assert_eq!(searcher.usi_options.piece_values.pawn, 10000);
+            // The other searchers keep the default values.
+            let other = Searcher::new_for_test_unloaded(&[]);
+            assert_eq!(other.usi_options.piece_values, PieceValues::DEFAULT);
+
+            // The bishop takes the pawn defended twice.
+            let pos = Position::from_sfen("4k4/9/3g1g3/4p4/9/9/4L4/B8/4K4 b - 1").unwrap();
+            let m = Move::new_from_usi_str("9h5d", &pos).unwrap();
+            assert!(!pos.see_ge(m, Value(0)));
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(1);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, false, true);
+            thread_pool.wait_for_search_finished();
+            // The search thread had the values of the searcher.
+            let th = thread_pool.thread_pool_base.lock().unwrap().threads[0].clone();
+            let th = th.lock().unwrap();
+            assert!(th.position.key() == pos.key());
+            assert_eq!(th.position.piece_values().piece_type_value(PieceType::PAWN), Value(10000));
+            // The pawn is worth more than the bishop.
+            assert!(th.position.see_ge(m, Value(0)));
+            // The white pawn on the board was made again by the value.
+            assert_eq!(th.position.material(), pos.material() - Value(10000 - PAWN_VALUE));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_no_legal_moves() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            // Checkmate, and no legal moves without check.
+            for sfen in ["4k3l/9/9/9/9/9/9/8g/8K b - 1", "4k2r1/9/9/4b4/9/9/9/9/8K b - 1"].iter() {
+                let pos = Position::from_sfen(sfen).unwrap();
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.depth = Some(1);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                let best_root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+                assert_eq!(best_root_move.pv[0], Move::RESIGN);
+                assert_eq!(best_root_move.score, Value::mated_in(0));
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_sel_depth() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let depth = 6;
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(depth);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let best_root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+            // The qsearch goes deeper than the nominal depth.
+            assert!(best_root_move.sel_depth > depth as i32);
+            assert!(thread_pool
+                .sel_depths
+                .iter()
+                .all(|x| x.load(Ordering::Relaxed) > depth as i32));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_currmove_info() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                breadcrumbs,
+                reductions,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let search = |thread_pool: &mut ThreadPool, tt: &mut TranspositionTable, usi_options: &UsiOptions| {
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.depth = Some(4);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                let thread_pool_base = thread_pool.thread_pool_base.lock().unwrap();
+                let main_thread = thread_pool_base.threads[0].lock().unwrap();
+                let m = Move::new_from_usi_str("7g7f", &Position::new()).unwrap();
+                main_thread.currmove_info_to_usi_string(Depth(4 * Depth::ONE_PLY.0), m, 3)
+            };
+            // The search is fast. It doesn't output currmove.
+            assert_eq!(search(thread_pool, tt, usi_options), None);
+            let mut is_ready = true;
+            usi_options.set(
+                UsiOptions::CURRMOVE_DELAY,
+                "0",
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                breadcrumbs,
+                reductions,
+                &mut is_ready,
+            );
+            assert_eq!(
+                search(thread_pool, tt, usi_options),
+                Some("info depth 4 currmove 7g7f currmovenumber 3".to_string())
+            );
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_contempt() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[(UsiOptions::CONTEMPT, "100")]);
+            let Searcher {
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                breadcrumbs,
+                reductions,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let mut is_ready = true;
+            // "5h5i" repeats the position.
+            let mut pos = Position::new();
+            for m in ["5i5h", "5a5b", "5h5i", "5b5a", "5i5h", "5a5b"].iter() {
+                let m = Move::new_from_usi_str(m, &pos).unwrap();
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+            }
+            let search = |usi_move: &str, thread_pool: &mut ThreadPool, tt: &mut TranspositionTable, usi_options: &UsiOptions| {
+                thread_pool.clear();
+                tt.clear();
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.depth = Some(4);
+                    limits.searchmoves = vec![Move::new_from_usi_str(usi_move, &pos).unwrap()];
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().score
+            };
+            // The draw is scored as losing by the contempt for the side to move at the root.
+            assert_eq!(search("5h5i", thread_pool, tt, usi_options), Value(-100));
+            usi_options.set(
+                UsiOptions::CONTEMPT,
+                "0",
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                breadcrumbs,
+                reductions,
+                &mut is_ready,
+            );
+            assert_eq!(search("5h5i", thread_pool, tt, usi_options), Value::DRAW);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_skill_level() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                breadcrumbs,
+                reductions,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let search = |thread_pool: &mut ThreadPool, tt: &mut TranspositionTable, usi_options: &UsiOptions| {
+                thread_pool.clear();
+                tt.clear();
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.depth = Some(4);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0]
+            };
+            // The full strength is deterministic.
+            let m = search(thread_pool, tt, usi_options);
+            assert_eq!(m, search(thread_pool, tt, usi_options));
+
+            let mut is_ready = true;
+            usi_options.set(
+                UsiOptions::SKILL_LEVEL,
+                "0",
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                breadcrumbs,
+                reductions,
+                &mut is_ready,
+            );
+            let legal_moves = Position::new().legal_moves();
+            for _ in 0..10 {
+                assert!(legal_moves.contains(&search(thread_pool, tt, usi_options)));
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_seed() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[(UsiOptions::SKILL_LEVEL, "0"), (UsiOptions::SEED, "12345")]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let mut search = || {
+                thread_pool.clear();
+                tt.clear();
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.depth = Some(4);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0]
+            };
+            // The weakened play picks a random move, but it is the same one with the same seed.
+            let m = search();
+            for _ in 0..5 {
+                assert_eq!(m, search());
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_pv_info_bound() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            // Black can capture the rook for free.
+            let pos = Position::from_sfen("lnsgkgsnl/9/ppppppppp/9/9/4r4/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1").unwrap();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(3);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            thread_pool.stop.store(false, Ordering::Relaxed);
+
+            let thread_pool_base = thread_pool.thread_pool_base.lock().unwrap();
+            let mut th = thread_pool_base.threads[0].lock().unwrap();
+            let mut stack = [Stack::new(); MAX_PLY as usize + 10];
+            for item in stack.iter_mut().take(CURRENT_STACK_INDEX) {
+                item.continuation_history = th.continuation_history[0][0].sentinel();
+            }
+            let depth = Depth(3 * Depth::ONE_PLY.0);
+            th.pv_idx = 0;
+            th.root_depth = depth;
+            // A narrow window far below the real score fails high.
+            let (alpha, beta) = (Value(-1), Value(1));
+            let best_value = th.search::<Pv>(&mut stack, alpha, beta, depth, false);
+            th.root_moves.sort_by(|x, y| y.cmp(x));
+            assert!(best_value >= beta);
+            let info = th.pv_info_to_usi_string(th.nodes_searched(), 1, depth, alpha, beta, false);
+            assert!(info.contains(" lowerbound nodes "), "{}", info);
+            // The final report of an exact score has no bound flag.
+            let info = th.pv_info_to_usi_string(th.nodes_searched(), 1, depth, -Value::INFINITE, Value::INFINITE, false);
+            assert!(!info.contains("bound"), "{}", info);
+            assert!(info.contains(" score cp "), "{}", info);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_easy_move() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            // Black obviously captures the rook. The best move is stable, so the search stops before the maximum time.
+            let pos = Position::from_sfen("4k4/9/9/9/9/4r4/4P4/9/4K4 b - 1").unwrap();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.time = [std::time::Duration::from_millis(10000); Color::NUM];
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let elapsed = thread_pool.timeman.lock().unwrap().elapsed();
+            let maximum = thread_pool.timeman.lock().unwrap().maximum_millis();
+            assert!(elapsed < maximum, "elapsed: {}, maximum: {}", elapsed, maximum);
+            let best_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0];
+            assert_eq!(best_move.to_usi_string(), "5g5f");
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_multi_threads() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let thread_num = 4;
+            let mut searcher = Searcher::new_for_test(&[(UsiOptions::THREADS, &thread_num.to_string())]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            assert_eq!(thread_pool.thread_pool_base.lock().unwrap().threads.len(), thread_num);
+            let pos = Position::new();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.movetime = Some(std::time::Duration::from_millis(300));
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            assert!(thread_pool.handle.is_none());
+            // The helper threads have been joined and release their states.
+            for th in thread_pool.thread_pool_base.lock().unwrap().threads.iter() {
+                let th = th.try_lock().unwrap();
+                assert!(th.nodes.load(Ordering::Relaxed) > 0);
+                assert!(th.completed_depth >= Depth::ONE_PLY);
+            }
+            let best_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0];
+            assert!(pos.legal_moves().contains(&best_move));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_max_pv_length() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                breadcrumbs,
+                reductions,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let mut search = |max_pv_length: &str| -> (Vec<String>, RootMove) {
+                let mut is_ready = true;
+                usi_options.set(
+                    UsiOptions::MAX_PV_LENGTH,
+                    max_pv_length,
+                    thread_pool,
+                    tt,
+                    #[cfg(feature = "kppt")]
+                    ehash,
+                    breadcrumbs,
+                    reductions,
+                    &mut is_ready,
+                );
+                thread_pool.clear();
+                tt.clear();
+                let lines = Arc::new(Mutex::new(vec![]));
+                thread_pool.set_info_sink(Box::new(LinesSink::with_info(lines.clone())));
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.depth = Some(8);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = false;
+                thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                let root_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+                let lines = lines.lock().unwrap().clone();
+                (lines, root_move)
+            };
+            let pv_len = |line: &str| {
+                let tokens = line.split_whitespace().collect::<Vec<_>>();
+                tokens.len() - 1 - tokens.iter().position(|&token| token == "pv").unwrap()
+            };
+            let (unlimited_lines, unlimited_root_move) = search("0");
+            assert!(unlimited_lines
+                .iter()
+                .any(|line| line.starts_with("info ") && pv_len(line) > 3));
+            let (lines, root_move) = search("3");
+            let (bestmove_line, info_lines) = lines.split_last().unwrap();
+            assert!(!info_lines.is_empty());
+            assert!(info_lines.iter().all(|line| pv_len(line) <= 3));
+            assert!(info_lines.iter().any(|line| pv_len(line) == 3));
+            assert_eq!(root_move.pv[..2], unlimited_root_move.pv[..2]);
+            assert_eq!(bestmove_line, unlimited_lines.last().unwrap());
+            assert_eq!(
+                *bestmove_line,
+                format!(
+                    "bestmove {} ponder {}",
+                    root_move.pv[0].to_usi_string(),
+                    root_move.pv[1].to_usi_string()
+                )
+            );
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_bestmove_ponder() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let lines = Arc::new(Mutex::new(vec![]));
+            thread_pool.set_info_sink(Box::new(LinesSink::new(lines.clone())));
+            let sfen = "lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 3";
+            let mut pos = Position::from_sfen(sfen).unwrap();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(8);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = false;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let lines = lines.lock().unwrap();
+            assert_eq!(lines.len(), 1);
+            let tokens = lines[0].split_whitespace().collect::<Vec<_>>();
+            assert_eq!(tokens.len(), 4, "{}", lines[0]);
+            assert_eq!(tokens[0], "bestmove");
+            assert_eq!(tokens[2], "ponder");
+            let best_move = Move::new_from_usi_str(tokens[1], &pos).unwrap();
+            assert!(pos.legal_moves().contains(&best_move));
+            let gives_check = pos.gives_check(best_move);
+            pos.do_move(best_move, gives_check);
+            let ponder_move = Move::new_from_usi_str(tokens[3], &pos).unwrap();
+            assert!(pos.legal_moves().contains(&ponder_move));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_resign() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[
+                (UsiOptions::EVAL_MODE, "material"),
+                (UsiOptions::RESIGN_THRESHOLD, "1000"),
+                (UsiOptions::RESIGN_MOVE_COUNT, "3"),
+            ]);
+            let limits = |timed: bool| {
+                let mut limits = LimitsType::new();
+                if timed {
+                    limits.movetime = Some(std::time::Duration::from_millis(20));
+                } else {
+                    limits.depth = Some(2);
+                }
+                limits
+            };
+            // The lone king against all the pieces.
+            let hopeless = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/9/9/4K4 b - 1";
+            let go = |searcher: &mut Searcher, sfen: &str, timed: bool| {
+                searcher.set_position(Position::from_sfen(sfen).unwrap());
+                searcher.set_limits(limits(timed));
+                searcher.search().unwrap().resign
+            };
+            // "go ponder" stopped before "ponderhit".
+            let go_ponder = |searcher: &mut Searcher, sfen: &str| {
+                let Searcher {
+                    thread_pool,
+                    tt,
+                    usi_options,
+                    ..
+                } = searcher;
+                let mut limits = limits(true);
+                limits.start_time = Some(std::time::Instant::now());
+                let pos = Position::from_sfen(sfen).unwrap();
+                let ponder_mode = true;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                thread_pool.stop.store(true, Ordering::Relaxed);
+                thread_pool.wait_for_search_finished();
+                thread_pool.last_resign.load(Ordering::Relaxed)
+            };
+            assert!(!go(&mut searcher, hopeless, true));
+            assert!(!go(&mut searcher, hopeless, true));
+            // The searches not limited by the time don't count.
+            assert!(!go(&mut searcher, hopeless, false));
+            assert!(!go(&mut searcher, hopeless, false));
+            assert!(go(&mut searcher, hopeless, true));
+            assert!(go(&mut searcher, hopeless, true));
+
+            // The count is reset by the better score.
+            assert!(!go(&mut searcher, crate::sfen::START_SFEN, true));
+            assert!(!go(&mut searcher, hopeless, true));
+            // The ponder searches don't count.
+            assert!(!go_ponder(&mut searcher, hopeless));
+            assert!(!go_ponder(&mut searcher, hopeless));
+            assert!(!go(&mut searcher, hopeless, true));
+            assert!(go(&mut searcher, hopeless, true));
+
+            // No legal moves.
+            let mated = "4k4/4G4/4P4/9/9/9/9/9/4K4 w - 1";
+            searcher.set_position(Position::from_sfen(mated).unwrap());
+            searcher.set_limits(limits(false));
+            let result = searcher.search().unwrap();
+            assert_eq!(result.best_move, Move::RESIGN);
+            assert!(result.resign);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_movetime_overshoot() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let movetime = 20;
+            for &nodes_between_time_checks in ["1", "1024", "1000000"].iter() {
+                searcher.set_option(UsiOptions::NODES_BETWEEN_TIME_CHECKS, nodes_between_time_checks);
+                let start_time = std::time::Instant::now();
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.movetime = Some(std::time::Duration::from_millis(movetime));
+                    limits.start_time = Some(start_time);
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                searcher.thread_pool.start_thinking(
+                    &Position::new(),
+                    &mut searcher.tt,
+                    limits,
+                    &searcher.usi_options,
+                    ponder_mode,
+                    hide_all_output,
+                );
+                searcher.thread_pool.wait_for_search_finished();
+                // Even the large interval is shortened by the measured speed.
+                let elapsed = start_time.elapsed().as_millis() as u64;
+                assert!(
+                    elapsed < movetime + 500,
+                    "NodesBetweenTimeChecks: {}, elapsed: {}",
+                    nodes_between_time_checks,
+                    elapsed
+                );
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_nodes_searched_multi_threads() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            struct NodesSink(Arc<Mutex<Vec<i64>>>);
+            impl InfoSink for NodesSink {
+                fn info(&mut self, info: &SearchInfo) {
+                    self.0.lock().unwrap().push(info.nodes);
+                }
+                fn println(&mut self, _s: &str) {}
+            }
+            let mut searcher = Searcher::new_for_test(&[(UsiOptions::THREADS, "4")]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let nodes = Arc::new(Mutex::new(vec![]));
+            thread_pool.set_info_sink(Box::new(NodesSink(nodes.clone())));
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(8);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = false;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let locals = thread_pool
+                .thread_pool_base
+                .lock()
+                .unwrap()
+                .threads
+                .iter()
+                .map(|th| th.lock().unwrap().position.nodes_searched())
+                .collect::<Vec<_>>();
+            assert_eq!(locals.len(), 4);
+            // A helper thread might not search any node before the main thread finishes.
+            assert!(locals[0] > 0);
+            let total = locals.iter().sum::<i64>();
+            assert_eq!(thread_pool.nodes_searched(), total);
+            // The last info is sent after all the threads finished.
+            assert_eq!(*nodes.lock().unwrap().last().unwrap(), total);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_infinite_stop() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[(UsiOptions::THREADS, "2")]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let pos = Position::new();
+            for &wait_millis in [0, 50].iter() {
+                // "stop" before "go" doesn't affect the next search.
+                thread_pool.stop.store(true, Ordering::Relaxed);
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.infinite = Some(());
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+                std::thread::sleep(std::time::Duration::from_millis(wait_millis));
+                // "stop" may come before the first iteration is completed.
+                let start = std::time::Instant::now();
+                thread_pool.stop.store(true, Ordering::Relaxed);
+                thread_pool.wait_for_search_finished();
+                assert!(start.elapsed() < std::time::Duration::from_secs(1));
+                let best_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0];
+                assert!(pos.legal_moves().contains(&best_move));
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_info_sink() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            struct CapturingSink {
+                infos: Arc<Mutex<Vec<SearchInfo>>>,
+                lines: Arc<Mutex<Vec<String>>>,
+            }
+            impl InfoSink for CapturingSink {
+                fn info(&mut self, info: &SearchInfo) {
+                    self.infos.lock().unwrap().push(info.clone());
+                }
+                fn println(&mut self, s: &str) {
+                    self.lines.lock().unwrap().push(s.to_string());
+                }
+            }
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let infos = Arc::new(Mutex::new(vec![]));
+            let lines = Arc::new(Mutex::new(vec![]));
+            thread_pool.set_info_sink(Box::new(CapturingSink {
+                infos: infos.clone(),
+                lines: lines.clone(),
+            }));
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(6);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = false;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let infos = infos.lock().unwrap();
+            assert!(!infos.is_empty());
+            assert!(infos.windows(2).all(|w| w[0].depth <= w[1].depth));
+            assert_eq!(infos.last().unwrap().depth, 6);
+            assert!(infos.iter().all(|info| info.multipv_index == 1 && !info.pv.is_empty()));
+            let best_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0];
+            assert_eq!(infos.last().unwrap().pv[0], best_move);
+            // Only the other output goes to println.
+            let lines = lines.lock().unwrap();
+            assert_eq!(lines.len(), 1);
+            assert!(lines[0].starts_with(&format!("bestmove {}", best_move.to_usi_string())));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_info_nodes_nps_time() {
+    assert_eq!(SearchInfo::nps(12345, 0), 12345);
+    assert_eq!(SearchInfo::nps(12345, 1000), 12345);
+    assert_eq!(SearchInfo::nps(3000, 1500), 2000);
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let lines = Arc::new(Mutex::new(vec![]));
+            thread_pool.set_info_sink(Box::new(LinesSink::with_info(lines.clone())));
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.movetime = Some(std::time::Duration::from_millis(3000));
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = false;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let lines = lines.lock().unwrap();
+            let info_lines = lines.iter().filter(|line| line.starts_with("info depth")).collect::<Vec<_>>();
+            assert!(info_lines.len() >= 2);
+            let mut times = vec![];
+            for line in info_lines {
+                let tokens = line.split_whitespace().collect::<Vec<_>>();
+                let value_of = |name: &str| -> i64 {
+                    let i = tokens
+                        .iter()
+                        .position(|&token| token == name)
+                        .unwrap_or_else(|| panic!("{}", line));
+                    tokens[i + 1].parse::<i64>().unwrap()
+                };
+                let (time, nodes, nps) = (value_of("time"), value_of("nodes"), value_of("nps"));
+                assert_eq!(nps, SearchInfo::nps(nodes, time), "{}", line);
+                times.push(time);
+            }
+            assert!(times.windows(2).all(|w| w[0] <= w[1]));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_search_params() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let pos = Position::from_sfen("l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1").unwrap();
+            let mut search = |usi_options: &UsiOptions| {
+                thread_pool.clear();
+                tt.clear();
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.depth = Some(8);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                thread_pool.nodes_searched()
+            };
+            assert_eq!(usi_options.search_params, SearchParams::DEFAULT);
+            let nodes = search(usi_options);
+
+            let mut params = SearchParams::new();
+            assert!(params.set("razor_margin", SearchParams::DEFAULT.razor_margin));
+            assert!(!params.set("unknown_param", 0));
+            assert_eq!(params, SearchParams::DEFAULT);
+            assert_eq!(SearchParams::default(), SearchParams::DEFAULT);
+            usi_options.search_params = params;
+            assert_eq!(search(usi_options), nodes);
+
+            // The futility pruning never prunes by the large margin, so the search needs more nodes.
+            assert!(usi_options.search_params.set("futility_margin_per_ply", 10000));
+            let nodes_without_futility_pruning = search(usi_options);
+            assert!(nodes_without_futility_pruning > nodes);
+            assert!(usi_options.search_params.set("futility_margin_per_ply", 1000000));
+            assert_eq!(search(usi_options), nodes_without_futility_pruning);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_prob_cut() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            // The node savings of one position depend on the details of the search, so the total of some positions is compared.
+            let sfens = [
+                "lnsgkgsnl/1r7/p1ppp1bpp/1p3pp2/7P1/2P6/PP1PPPP1P/1B3S1R1/LNSGKG1NL b - 9",
+                "1n3gs1l/lr2sk1b1/ppgpppnpp/2p3p2/3P5/8P/PPP1PPPPN/1B1R1KS1L/LNSG1G3 b - 21",
+                "ln2g2nl/1r1gk2s1/pspp1p1pp/4p1p2/1N7/4PP3/PP1S2PPP/L2BG1SRL/3GK2N1 b B3p 33",
+                "l2gk1sn1/1rsg3bl/ppnp2p1p/2p1p4/7P1/1P2PPG2/PSPP2P1P/LB3KR1L/1N1GS2N1 b 2P 45",
+                "1n3kg2/b8/ps2ppnp1/1pg3g1p/4P4/2P1G1P1P/4SP1PR/2+p1K1S1L/1+l1PBS1NL b L4Prn 111",
+            ];
+            let mut search = |usi_options: &UsiOptions| -> i64 {
+                sfens
+                    .iter()
+                    .map(|sfen| {
+                        let pos = Position::from_sfen(sfen).unwrap();
+                        thread_pool.clear();
+                        tt.clear();
+                        let limits = {
+                            let mut limits = LimitsType::new();
+                            limits.depth = Some(10);
+                            limits.start_time = Some(std::time::Instant::now());
+                            limits
+                        };
+                        let ponder_mode = false;
+                        let hide_all_output = true;
+                        thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+                        thread_pool.wait_for_search_finished();
+                        thread_pool.nodes_searched()
+                    })
+                    .sum()
+            };
+            let nodes = search(usi_options);
+            usi_options.search_params.prob_cut_min_ply = MAX_PLY;
+            let nodes_without_prob_cut = search(usi_options);
+            assert!(nodes < nodes_without_prob_cut);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_counter_moves_after_search() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(8);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let th = thread_pool.thread_pool_base.lock().unwrap().threads[0].clone();
+            let th = th.lock().unwrap();
+            let mut count = 0;
+            for &sq in Square::ALL.iter() {
+                for pc_index in 0..Piece::NUM {
+                    let pc = Piece(pc_index as i32);
+                    if let Some(m) = th.counter_moves.get(sq, pc) {
+                        count += 1;
+                        // The reply to the previous move of the opponent.
+                        assert_eq!(Color::new(m.piece_moved_after_move()), Color::new(pc).inverse());
+                        assert!(!m.is_drop() || m.to() != sq);
+                        assert!(m.is_drop() || m.from() != m.to());
+                    }
+                }
+            }
+            // beta cutoffs by the quiet moves occur in the 8 plies search.
+            assert!(count > 0);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_update_all_stats_counter_move() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let searcher = Searcher::new_for_test_unloaded(&[]);
+            let th = searcher.thread_pool.thread_pool_base.lock().unwrap().threads[0].clone();
+            let mut th = th.lock().unwrap();
+            th.clear();
+            // The previous move 7g7f is refuted by the quiet move 3c3d.
+            th.position = Position::from_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2").unwrap();
+            let prev_move = Move::new_unpromote(Square::SQ77, Square::SQ76, Piece::B_PAWN);
+            let best_move = Move::new_from_usi_str("3c3d", &th.position).unwrap();
+            let mut stack = [Stack::new(); MAX_PLY as usize + 10];
+            for item in stack.iter_mut() {
+                item.continuation_history = th.continuation_history[0][0].sentinel();
+            }
+            get_stack_mut(&mut stack, -1).current_move = Some(prev_move);
+            assert_eq!(th.counter_moves.get(Square::SQ76, Piece::B_PAWN), None);
+            let depth = Depth(3 * Depth::ONE_PLY.0);
+            th.update_all_stats(&mut stack, best_move, Value(100), Value(0), Square::SQ76, &[], &[], depth);
+            assert_eq!(th.counter_moves.get(Square::SQ76, Piece::B_PAWN), Some(best_move));
+            assert_eq!(get_stack(&stack, 0).killers[0], Some(best_move));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_update_all_stats_capture_history() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let searcher = Searcher::new_for_test_unloaded(&[]);
+            let th = searcher.thread_pool.thread_pool_base.lock().unwrap().threads[0].clone();
+            let mut th = th.lock().unwrap();
+            th.clear();
+            // The pawn and the gold can take the rook. The pawn capture causes the cutoff.
+            th.position = Position::from_sfen("4k4/9/9/9/9/4r4/3GP4/9/4K4 b - 1").unwrap();
+            let best_move = Move::new_from_usi_str("5g5f", &th.position).unwrap();
+            let other_capture = Move::new_from_usi_str("6g5f", &th.position).unwrap();
+            let mut stack = [Stack::new(); MAX_PLY as usize + 10];
+            for item in stack.iter_mut() {
+                item.continuation_history = th.continuation_history[0][0].sentinel();
+            }
+            let depth = Depth(3 * Depth::ONE_PLY.0);
+            th.update_all_stats(
+                &mut stack,
+                best_move,
+                Value(100),
+                Value(0),
+                Square::SQ59,
+                &[],
+                &[other_capture],
+                depth,
+            );
+            assert!(th.capture_history.get(Piece::B_PAWN, Square::SQ56, PieceType::ROOK) > 0);
+            assert!(th.capture_history.get(Piece::B_GOLD, Square::SQ56, PieceType::ROOK) < 0);
+            // The capture isn't a counter move.
+            assert!(Square::ALL
+                .iter()
+                .all(|&sq| (0..Piece::NUM).all(|pc| th.counter_moves.get(sq, Piece(pc as i32)).is_none())));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_qsearch_max_ply() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                breadcrumbs,
+                reductions,
+                usi_options,
+                ..
+            } = &mut searcher;
+            // Both sides have many pieces in hand, so the checks and the captures of the checking pieces go on.
+            let pos = Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 b RBGSNLPrbgsnlp 1").unwrap();
+            let mut search = |pos: &Position, max_ply: i32, check_plies: i32| {
+                let mut is_ready = true;
+                for (name, value) in [
+                    (UsiOptions::QSEARCH_MAX_PLY, max_ply),
+                    (UsiOptions::QSEARCH_CHECK_PLIES, check_plies),
+                ]
+                .iter()
+                {
+                    usi_options.set(
+                        name,
+                        &value.to_string(),
+                        thread_pool,
+                        tt,
+                        #[cfg(feature = "kppt")]
+                        ehash,
+                        breadcrumbs,
+                        reductions,
+                        &mut is_ready,
+                    );
+                }
+                thread_pool.clear();
+                tt.clear();
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    limits.depth = Some(1);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                let rm = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap();
+                (rm.sel_depth, thread_pool.nodes_searched(), rm.score)
+            };
+            let (unlimited_sel_depth, unlimited_nodes, _) = search(&pos, 0, 2);
+            let (limited_sel_depth, limited_nodes, _) = search(&pos, 1, 2);
+            assert!(limited_sel_depth <= unlimited_sel_depth);
+            assert!(limited_nodes < unlimited_nodes);
+            // Without the quiet checks, qsearch has only the captures and stand pat.
+            let (_, no_checks_nodes, _) = search(&pos, 0, 0);
+            assert!(no_checks_nodes < unlimited_nodes);
+            // The captures give checks. The side in check at the limit doesn't stand pat, so the short lines
+            // are searched as without the limit.
+            #[cfg(feature = "material")]
+            {
+                let sfen = "1n1gk1snl/ls1r1g1b1/ppp2ppp1/4p3p/5P3/1P1p5/P1PPP1PPP/1B1RK3L/LNSG1GSN1 w - 22";
+                let pos = Position::from_sfen(sfen).unwrap();
+                assert_eq!(search(&pos, 1, 0).2, search(&pos, 0, 0).2);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_numa_policy() {
+    assert_eq!("none".parse::<NumaPolicy>(), Ok(NumaPolicy::None));
+    assert_eq!("pin".parse::<NumaPolicy>(), Ok(NumaPolicy::Pin));
+    assert!("interleave".parse::<NumaPolicy>().is_err());
+    assert!(NumaPolicy::None.cpus().is_empty());
+    #[cfg(target_os = "linux")]
+    {
+        let cpus = NumaPolicy::Pin.cpus();
+        assert!(!cpus.is_empty());
+        std::thread::spawn(move || {
+            assert!(bind_current_thread(cpus[cpus.len() - 1]));
+            assert_eq!(NumaPolicy::Pin.cpus(), vec![cpus[cpus.len() - 1]]);
+        })
+        .join()
+        .unwrap();
+    }
+    #[cfg(not(target_os = "linux"))]
+    assert!(NumaPolicy::Pin.cpus().is_empty());
+
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[(UsiOptions::NUMA_POLICY, "pin"), (UsiOptions::THREADS, "3")]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            assert_eq!(usi_options.get_string(UsiOptions::NUMA_POLICY), "pin");
+            let cpus_before = NumaPolicy::Pin.cpus();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(6);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let pos = Position::new();
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let best_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0];
+            assert!(pos.legal_moves().contains(&best_move));
+            // Only the search threads are bound.
+            assert_eq!(NumaPolicy::Pin.cpus(), cpus_before);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_json_info_sink() {
+    #[derive(Clone)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                #[cfg(feature = "kppt")]
+                ehash,
+                breadcrumbs,
+                reductions,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let mut is_ready = true;
+            for &value in ["json", "xml"].iter() {
+                usi_options.set(
+                    UsiOptions::OUTPUT_FORMAT,
+                    value,
+                    thread_pool,
+                    tt,
+                    #[cfg(feature = "kppt")]
+                    ehash,
+                    breadcrumbs,
+                    reductions,
+                    &mut is_ready,
+                );
+            }
+            // The invalid value isn't set.
+            assert_eq!(usi_options.get_string(UsiOptions::OUTPUT_FORMAT), "json");
+
+            let buffer = SharedBuffer(Arc::new(Mutex::new(vec![])));
+            thread_pool.set_info_sink(Box::new(JsonInfoSink(buffer.clone())));
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(6);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = false;
+            thread_pool.start_thinking(&Position::new(), tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let best_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0];
+            let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            let lines = output
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect::<Vec<_>>();
+            let (last, infos) = lines.split_last().unwrap();
+            assert_eq!(last["bestmove"], best_move.to_usi_string());
+            assert!(last.get("ponder").is_some());
+            assert!(!infos.is_empty());
+            for info in infos.iter() {
+                assert!(info["depth"].as_i64().unwrap() >= 1);
+                assert!(info["nodes"].as_i64().unwrap() > 0);
+                assert!(info["score_cp"].is_i64() || info["score_mate"].is_i64());
+                assert!(!info["pv"].as_array().unwrap().is_empty());
+            }
+            assert_eq!(infos.last().unwrap()["depth"], 6);
+            assert_eq!(infos.last().unwrap()["pv"][0], best_move.to_usi_string());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_repetition_in_tt_of_other_path() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let search = |thread_pool: &mut ThreadPool, pos: &Position, tt: &mut TranspositionTable| {
+                let limits = {
+                    let mut limits = LimitsType::new();
+                    // The other moves take the pawn only after the depth.
+                    limits.depth = Some(3);
+                    limits.start_time = Some(std::time::Instant::now());
+                    limits
+                };
+                let ponder_mode = false;
+                let hide_all_output = true;
+                thread_pool.start_thinking(pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+                thread_pool.wait_for_search_finished();
+                thread_pool.last_best_root_move.lock().unwrap().clone().unwrap()
+            };
+            // The rook takes the pawn after 3h2h. The white king can't defend it.
+            let mut pos = Position::from_sfen("4k4/9/9/7p1/9/9/6P2/5G1RP/4K1GS1 b - 1").unwrap();
+            for m in ["2h3h", "5a4a"].iter() {
+                let m = Move::new_from_usi_str(m, &pos).unwrap();
+                pos.do_move(m, pos.gives_check(m));
+            }
+            let best_move = Move::new_from_usi_str("3h2h", &pos).unwrap();
+            // 3h2h 4a5a is the repetition of the first position. The entries of the nodes after 3h2h have the draws.
+            let root_move = search(thread_pool, &pos, tt);
+            assert_ne!(root_move.pv[0], best_move);
+            let repetition_score = root_move.score;
+
+            // The same position without the positions before it. The entries of the first search are in the TT.
+            let pos = Position::from_sfen(&pos.to_sfen()).unwrap();
+            let root_move = search(thread_pool, &pos, tt);
+            assert_eq!(root_move.pv[0], best_move);
+            assert!(root_move.score > repetition_score);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_aspiration_bound_info() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            // Black can capture the rook for free, so the score is far from the windows below.
+            let pos = Position::from_sfen("lnsgkgsnl/9/ppppppppp/9/9/4r4/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1").unwrap();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(3);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            thread_pool.stop.store(false, Ordering::Relaxed);
+
+            let thread_pool_base = thread_pool.thread_pool_base.lock().unwrap();
+            let mut th = thread_pool_base.threads[0].lock().unwrap();
+            let depth = Depth(3 * Depth::ONE_PLY.0);
+            th.pv_idx = 0;
+            th.root_depth = depth;
+            // The aspiration search by the window reports the failed score with the bound flag.
+            let mut search_with_window = |alpha: Value, beta: Value| -> SearchInfo {
+                let mut stack = [Stack::new(); MAX_PLY as usize + 10];
+                for item in stack.iter_mut().take(CURRENT_STACK_INDEX) {
+                    item.continuation_history = th.continuation_history[0][0].sentinel();
+                }
+                th.search::<Pv>(&mut stack, alpha, beta, depth, false);
+                th.root_moves.sort_by(|x, y| y.cmp(x));
+                th.pv_infos(th.nodes_searched(), 1, depth, alpha, beta, false).remove(0)
+            };
+            let info = search_with_window(Value(-1), Value(1));
+            assert_eq!(info.bound, Bound::LOWER);
+            assert!(info
+                .to_usi_string()
+                .contains(&format!("score {} lowerbound ", info.score.to_usi())));
+            let info = search_with_window(Value(10000), Value(10001));
+            assert_eq!(info.bound, Bound::UPPER);
+            assert!(info
+                .to_usi_string()
+                .contains(&format!("score {} upperbound ", info.score.to_usi())));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+// The other evaluation functions don't have to find the move by the depth 12.
+#[cfg(feature = "material")]
+#[test]
+fn test_singular_extension() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            // The gold drop defends the king only after the long sequence.
+            let pos = Position::from_sfen("lnsg5/1r1psS1k1/p1p3ppl/1p1P4p/9/2P6/L3PPP1P/1S1G1RK1L/4N2N1 w 2B3P2gn2p 64").unwrap();
+            let m = Move::new_from_usi_str("G*3b", &pos).unwrap();
+            searcher.set_position(pos);
+            let mut found_depth = |singular_extension_min_ply: i32| {
+                searcher.usi_options.search_params.singular_extension_min_ply = singular_extension_min_ply;
+                (1..=12)
+                    .find(|&depth| {
+                        searcher.thread_pool.clear();
+                        searcher.tt.clear();
+                        let mut limits = LimitsType::new();
+                        limits.depth = Some(depth);
+                        searcher.set_limits(limits);
+                        searcher.search().unwrap().best_move == m
+                    })
+                    .unwrap()
+            };
+            assert!(found_depth(SearchParams::DEFAULT.singular_extension_min_ply) < found_depth(MAX_PLY));
         })
         .unwrap()
         .join()
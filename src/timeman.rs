@@ -54,6 +54,11 @@ impl TimeManagement {
             slow_mover,
         )
     }
+    // The time budget is computed from the main time and the increment (Fischer) as if the remaining moves are MOVE_HORIZON.
+    // In sudden death (no byoyomi and no increment), it is the ratio of the main time only.
+    // byoyomi (limits.movetime) is added to the budget because it isn't consumed from the main time.
+    // If the main time is exhausted, limits.movetime remains and the search uses the whole byoyomi minus Byoyomi_Margin.
+    // Otherwise Move_Overhead is subtracted from the budget for the latency of the communication.
     pub fn init(&mut self, usi_optoins: &UsiOptions, limits: &mut LimitsType, us: Color, ply: i32) {
         self.start_time = limits.start_time;
         let min_thinking_time = usi_optoins.get_i64(UsiOptions::MINIMUM_THINKING_TIME) as u64;
@@ -64,12 +69,9 @@ impl TimeManagement {
         self.maximum_time_milli = self.optimum_time_milli;
         let slow_mover = usi_optoins.get_i64(UsiOptions::SLOW_MOVER);
         let max_moves_to_go = TimeManagement::MOVE_HORIZON;
-        let move_overhead = 0;
         for hypothetical_moves_to_go in 1..max_moves_to_go {
-            let hypothetical_my_time = limits.time[us.0 as usize]
-                + limits.inc[us.0 as usize] * (hypothetical_moves_to_go - 1) as u32
-                - move_overhead * std::time::Duration::from_millis(2 + std::cmp::min(hypothetical_moves_to_go, 40));
-            let hypothetical_my_time = std::cmp::max(hypothetical_my_time, std::time::Duration::from_millis(0));
+            let hypothetical_my_time =
+                limits.time[us.0 as usize] + limits.inc[us.0 as usize] * (hypothetical_moves_to_go - 1) as u32;
             let t1 = std::time::Duration::from_millis(min_thinking_time)
                 + TimeManagement::remaining_optimum(hypothetical_my_time, hypothetical_moves_to_go, ply, slow_mover);
             let t2 = std::time::Duration::from_millis(min_thinking_time)
@@ -86,8 +88,16 @@ impl TimeManagement {
         }
         if limits.time[us.0 as usize] != std::time::Duration::from_millis(0) {
             limits.movetime = None;
+            // The budget is never 0 even if Move_Overhead is larger than it.
+            let move_overhead = std::time::Duration::from_millis(usi_optoins.get_i64(UsiOptions::MOVE_OVERHEAD) as u64);
+            let min_budget = std::time::Duration::from_millis(1);
+            self.optimum_time_milli = std::cmp::max(self.optimum_time_milli.saturating_sub(move_overhead), min_budget);
+            self.maximum_time_milli = std::cmp::max(self.maximum_time_milli.saturating_sub(move_overhead), min_budget);
         }
     }
+    pub fn restart(&mut self) {
+        self.start_time = Some(std::time::Instant::now());
+    }
     pub fn optimum_millis(&self) -> i64 {
         self.optimum_time_milli.as_millis() as i64
     }
@@ -99,3 +109,96 @@ impl TimeManagement {
         (duration.as_secs() * 1000 + u64::from(duration.subsec_millis())) as i64
     }
 }
+
+#[test]
+fn test_time_management_init() {
+    let usi_options = UsiOptions::new();
+    let min_thinking_time = usi_options.get_i64(UsiOptions::MINIMUM_THINKING_TIME);
+    let ms = std::time::Duration::from_millis;
+    let us = Color::BLACK;
+    let ply = 40;
+    let init = |time: u64, inc: u64, byoyomi: Option<u64>| {
+        let mut limits = LimitsType::new();
+        limits.start_time = Some(std::time::Instant::now());
+        limits.time[us.0 as usize] = ms(time);
+        limits.inc[us.0 as usize] = ms(inc);
+        limits.movetime = byoyomi.map(ms);
+        let mut timeman = TimeManagement::new();
+        timeman.init(&usi_options, &mut limits, us, ply);
+        (timeman, limits)
+    };
+
+    // byoyomi only. the main time is exhausted.
+    let (timeman, limits) = init(0, 0, Some(10000));
+    assert!(!limits.use_time_management());
+    assert_eq!(limits.movetime, Some(ms(10000)));
+    assert_eq!(timeman.optimum_millis(), 10000 + min_thinking_time);
+    assert_eq!(timeman.maximum_millis(), 10000 + min_thinking_time);
+
+    // main time and byoyomi.
+    let (timeman, limits) = init(60000, 0, Some(10000));
+    assert!(limits.use_time_management());
+    assert!(limits.movetime.is_none());
+    assert!(10000 < timeman.optimum_millis());
+    assert!(timeman.optimum_millis() <= timeman.maximum_millis());
+    assert!(timeman.maximum_millis() < 60000 + 10000);
+
+    // sudden death.
+    let (sudden_death, limits) = init(60000, 0, None);
+    assert!(limits.use_time_management());
+    assert!(0 < sudden_death.optimum_millis());
+    assert!(sudden_death.optimum_millis() <= sudden_death.maximum_millis());
+    assert!(sudden_death.maximum_millis() < 60000);
+
+    // Fischer. the increment gives more time than sudden death.
+    let (fischer, _limits) = init(60000, 5000, None);
+    assert!(sudden_death.optimum_millis() < fischer.optimum_millis());
+    assert!(fischer.maximum_millis() < 60000 + 5000);
+}
+
+#[test]
+fn test_time_management_move_overhead() {
+    #[cfg(feature = "kppt")]
+    use crate::evaluate::kppt::EvalHash;
+    use crate::thread::{Breadcrumbs, ThreadPool};
+    use crate::tt::TranspositionTable;
+    let ms = std::time::Duration::from_millis;
+    let us = Color::BLACK;
+    let ply = 40;
+    let init = |time: u64, byoyomi: Option<u64>, move_overhead: i64| {
+        let mut usi_options = UsiOptions::new();
+        usi_options.set(
+            UsiOptions::MOVE_OVERHEAD,
+            &move_overhead.to_string(),
+            &mut ThreadPool::new(),
+            &mut TranspositionTable::new(),
+            #[cfg(feature = "kppt")]
+            &mut EvalHash::new(),
+            &mut Breadcrumbs::new(),
+            &mut Reductions::new(1),
+            &mut true,
+        );
+        let mut limits = LimitsType::new();
+        limits.start_time = Some(std::time::Instant::now());
+        limits.time[us.0 as usize] = ms(time);
+        limits.movetime = byoyomi.map(ms);
+        let mut timeman = TimeManagement::new();
+        timeman.init(&usi_options, &mut limits, us, ply);
+        timeman
+    };
+    for &(time, byoyomi) in [(60000, None), (60000, Some(10000)), (600_000, Some(30000))].iter() {
+        let without_overhead = init(time, byoyomi, 0);
+        let with_overhead = init(time, byoyomi, 100);
+        assert_eq!(without_overhead.optimum_millis() - 100, with_overhead.optimum_millis());
+        assert_eq!(without_overhead.maximum_millis() - 100, with_overhead.maximum_millis());
+    }
+    // The budget is never 0.
+    let timeman = init(10, None, 5000);
+    assert_eq!(timeman.optimum_millis(), 1);
+    assert_eq!(timeman.maximum_millis(), 1);
+    // byoyomi only. Byoyomi_Margin is used instead.
+    assert_eq!(
+        init(0, Some(10000), 0).optimum_millis(),
+        init(0, Some(10000), 100).optimum_millis()
+    );
+}
@@ -3,9 +3,31 @@ use crate::position::*;
 use crate::thread::*;
 use crate::types::*;
 use rayon::prelude::*;
+use std::io::{Read, Write};
+
+// The upper bits of Key are stored in TTEntry to verify the entry. The lower bits are used for cluster index.
+#[cfg(not(feature = "tt32"))]
+type TTKey = u16;
+#[cfg(feature = "tt32")]
+type TTKey = u32;
+
+fn tt_key(key: Key) -> TTKey {
+    (key.0 >> (64 - 8 * std::mem::size_of::<TTKey>())) as TTKey
+}
+
+// genbound8: the bound (2 bits), the pv flag, the repetition flag and the generation (4 bits) from the lowest bit.
+// The generation wraps around every 16 searches, so the entries age by at most 15 generations (31 before the repetition flag).
+// An entry left 16 searches ago looks as new as the current ones, and one left 17 searches ago looks 1 generation old.
+const GENERATION_DELTA: u8 = 0x10;
+const GENERATION_MASK: i32 = 0xf0;
+// It keeps the lower bits of genbound8 from carrying into the generation bits.
+const GENERATION_CYCLE: i32 = 255 + GENERATION_DELTA as i32;
 
 pub struct TTEntry {
+    #[cfg(not(feature = "tt32"))]
     key16: u16,
+    #[cfg(feature = "tt32")]
+    key32: u32,
     mv16: u16,
     value16: i16,
     eval16: i16,
@@ -14,8 +36,34 @@ pub struct TTEntry {
 }
 
 impl TTEntry {
+    // depth8 is (depth - Depth::OFFSET) / Depth::ONE_PLY. The deeper depths are stored as this.
+    pub const MAX_DEPTH: Depth = Depth(Depth::OFFSET.0 + u8::MAX as i32 * Depth::ONE_PLY.0);
+
+    #[cfg(not(feature = "tt32"))]
+    #[inline]
+    fn stored_key(&self) -> TTKey {
+        self.key16
+    }
+    #[cfg(feature = "tt32")]
+    #[inline]
+    fn stored_key(&self) -> TTKey {
+        self.key32
+    }
+    #[cfg(not(feature = "tt32"))]
+    #[inline]
+    fn set_stored_key(&mut self, key: TTKey) {
+        self.key16 = key;
+    }
+    #[cfg(feature = "tt32")]
+    #[inline]
+    fn set_stored_key(&mut self, key: TTKey) {
+        self.key32 = key;
+    }
     pub fn mv(&self, pos: &Position) -> Option<Move> {
         // This can be illegal move.
+        if self.mv16 == 0 {
+            return None;
+        }
         let m = Move(unsafe { std::num::NonZeroU32::new_unchecked(u32::from(self.mv16)) });
         let m = if !Some(m).is_normal_move() || m.is_drop() {
             m
@@ -24,6 +72,8 @@ impl TTEntry {
                 std::num::NonZeroU32::new_unchecked(m.0.get() | ((pos.piece_on(m.from()).0 as u32) << Move::MOVED_PIECE_SHIFT))
             })
         };
+        // The move of the other position by the key collision is rejected here. For example, the from square is
+        // empty or has the piece of the opponent, or the dropped piece isn't in hand.
         if pos.pseudo_legal::<SearchingType>(m) {
             Some(m)
         } else {
@@ -42,18 +92,23 @@ impl TTEntry {
     pub fn is_pv(&self) -> bool {
         (self.genbound8 & 0x4) != 0
     }
+    // The value depends on the repetition of a position before this node. It can be wrong on the other paths.
+    pub fn depends_on_repetition(&self) -> bool {
+        (self.genbound8 & 0x8) != 0
+    }
     pub fn bound(&self) -> Bound {
         Bound(i32::from(self.genbound8) & 0x3)
     }
     #[allow(dead_code)]
     pub fn generation(&self) -> u8 {
-        self.genbound8 & 0xf8
+        self.genbound8 & GENERATION_MASK as u8
     }
     pub fn save(
         &mut self,
         key: Key,
         value: Value,
         pv: bool,
+        repetition: bool,
         bound: Bound,
         depth: Depth,
         mv: Option<Move>,
@@ -61,59 +116,240 @@ impl TTEntry {
         generation: u8,
     ) {
         debug_assert!(depth.0 / Depth::ONE_PLY.0 * Depth::ONE_PLY.0 == depth.0);
+        // The deeper depth would wrap around to a shallow depth in depth8.
+        let depth = std::cmp::min(depth, Self::MAX_DEPTH);
 
         if let Some(mv) = mv {
             self.mv16 = u32::from(mv.0) as u16;
-        } else if (key.0 >> 48) as u16 != self.key16 {
+        } else if tt_key(key) != self.stored_key() {
             self.mv16 = 0;
         }
 
-        if (key.0 >> 48) as u16 != self.key16
+        if tt_key(key) != self.stored_key()
             || (depth.0 - Depth::OFFSET.0) / Depth::ONE_PLY.0 > i32::from(self.depth8) - 4
             || bound.0 == Bound::EXACT.0
         {
             debug_assert!((depth.0 - Depth::OFFSET.0) / Depth::ONE_PLY.0 >= 0);
-            self.key16 = (key.0 >> 48) as u16;
+            self.set_stored_key(tt_key(key));
             self.value16 = value.0 as i16;
             self.eval16 = eval.0 as i16;
-            self.genbound8 = (i32::from(generation) | (i32::from(pv) << 2) | bound.0) as u8;
+            self.genbound8 = (i32::from(generation) | (i32::from(pv) << 2) | (i32::from(repetition) << 3) | bound.0) as u8;
             self.depth8 = ((depth.0 - Depth::OFFSET.0) / Depth::ONE_PLY.0) as u8;
         }
     }
 }
 
+#[cfg(not(feature = "tt32"))]
 const CLUSTER_SIZE: usize = 3;
+#[cfg(feature = "tt32")]
+const CLUSTER_SIZE: usize = 5;
+
+#[cfg(not(feature = "tt32"))]
+const TT_FILE_MAGIC: [u8; 8] = *b"APERYTT\0";
+#[cfg(feature = "tt32")]
+const TT_FILE_MAGIC: [u8; 8] = *b"APERYT32";
+// 1: GENERATION_DELTA was 0x08 and genbound8 had no repetition flag.
+// 2: the repetition flag and the 4-bit generation. The files of version 1 are rejected because their entries would be misread.
+const TT_FILE_VERSION: u32 = 2;
 
+#[cfg(not(feature = "tt32"))]
 #[repr(align(32))]
 struct TTCluster {
     entry: [TTEntry; CLUSTER_SIZE],
     _padding: [u8; 2],
 }
 
+// 12 bytes * 5 entries + 4 bytes padding = 64 bytes (one cache line).
+#[cfg(feature = "tt32")]
+#[repr(align(64))]
+struct TTCluster {
+    entry: [TTEntry; CLUSTER_SIZE],
+    _padding: [u8; 4],
+}
+
+#[derive(Debug)]
+pub struct TtResizeError {
+    pub requested_cluster_count: usize,
+    pub allocated_cluster_count: usize,
+}
+
+impl std::fmt::Display for TtResizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "The hash of {} clusters can't be allocated. {} clusters are allocated instead.",
+            self.requested_cluster_count, self.allocated_cluster_count
+        )
+    }
+}
+
+// The memory of the clusters. The rest of the code sees it as a slice of TTCluster.
+enum ClusterBuffer {
+    Vec(Vec<TTCluster>),
+    #[cfg(target_os = "linux")]
+    Mmap {
+        ptr: *mut TTCluster,
+        len: usize,
+        byte_size: usize,
+    },
+}
+
+// The mmapped memory is owned by ClusterBuffer like Vec.
+unsafe impl Send for ClusterBuffer {}
+unsafe impl Sync for ClusterBuffer {}
+
+impl ClusterBuffer {
+    // The memory is uninitialized. It must be cleared before use.
+    fn new_vec(cluster_count: usize) -> Option<ClusterBuffer> {
+        let mut v = Vec::new();
+        v.try_reserve_exact(cluster_count).ok()?;
+        unsafe {
+            v.set_len(cluster_count);
+        }
+        Some(ClusterBuffer::Vec(v))
+    }
+    // 2MB huge pages are tried first. If they aren't reserved by the system, the transparent huge pages are requested.
+    #[cfg(target_os = "linux")]
+    fn new_huge_pages(cluster_count: usize) -> Option<ClusterBuffer> {
+        const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+        let byte_size = cluster_count
+            .checked_mul(std::mem::size_of::<TTCluster>())?
+            .checked_add(HUGE_PAGE_SIZE - 1)?
+            / HUGE_PAGE_SIZE
+            * HUGE_PAGE_SIZE;
+        if byte_size == 0 {
+            return None;
+        }
+        let mmap = |flags: libc::c_int| unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                byte_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
+                -1,
+                0,
+            )
+        };
+        let mut ptr = mmap(libc::MAP_HUGETLB);
+        if ptr == libc::MAP_FAILED {
+            ptr = mmap(0);
+            if ptr == libc::MAP_FAILED {
+                return None;
+            }
+            // The failure only means the normal pages are used.
+            unsafe {
+                libc::madvise(ptr, byte_size, libc::MADV_HUGEPAGE);
+            }
+        }
+        Some(ClusterBuffer::Mmap {
+            ptr: ptr as *mut TTCluster,
+            len: cluster_count,
+            byte_size,
+        })
+    }
+}
+
+impl Drop for ClusterBuffer {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        {
+            if let ClusterBuffer::Mmap { ptr, byte_size, .. } = *self {
+                unsafe {
+                    libc::munmap(ptr as *mut libc::c_void, byte_size);
+                }
+            }
+        }
+    }
+}
+
+impl std::ops::Deref for ClusterBuffer {
+    type Target = [TTCluster];
+    fn deref(&self) -> &[TTCluster] {
+        match self {
+            ClusterBuffer::Vec(v) => v,
+            #[cfg(target_os = "linux")]
+            ClusterBuffer::Mmap { ptr, len, .. } => unsafe { std::slice::from_raw_parts(*ptr, *len) },
+        }
+    }
+}
+
+impl std::ops::DerefMut for ClusterBuffer {
+    fn deref_mut(&mut self) -> &mut [TTCluster] {
+        match self {
+            ClusterBuffer::Vec(v) => v,
+            #[cfg(target_os = "linux")]
+            ClusterBuffer::Mmap { ptr, len, .. } => unsafe { std::slice::from_raw_parts_mut(*ptr, *len) },
+        }
+    }
+}
+
 pub struct TranspositionTable {
-    table: Vec<TTCluster>,
+    table: ClusterBuffer,
     generation8: u8,
+    large_pages: bool,
 }
 
 impl TranspositionTable {
     pub fn new() -> TranspositionTable {
         TranspositionTable {
-            table: vec![],
+            table: ClusterBuffer::Vec(vec![]),
             generation8: 0,
+            large_pages: false,
         }
     }
-    pub fn resize(&mut self, mega_byte_size: usize, thread_pool: &mut ThreadPool) {
+    // It takes effect from the next resize().
+    pub fn set_large_pages(&mut self, large_pages: bool) {
+        self.large_pages = large_pages;
+    }
+    pub fn resize(&mut self, mega_byte_size: usize, thread_pool: &mut ThreadPool) -> Result<(), TtResizeError> {
         thread_pool.wait_for_search_finished();
-        let mega_byte_size = (mega_byte_size + 1).next_power_of_two() >> 1;
-        let cluster_count = mega_byte_size * 1024 * 1024 / std::mem::size_of::<TTCluster>();
+        let cluster_count = Self::cluster_count(mega_byte_size);
+        // The old table is freed first, so the peak memory is the larger one of the old and the new table.
+        // If the requested table can't be allocated, the table of the old size is allocated again. It fitted just before.
+        let old_cluster_count = self.table.len();
+        self.table = ClusterBuffer::Vec(vec![]);
+        let fallback_cluster_count = if old_cluster_count != 0 {
+            old_cluster_count
+        } else {
+            Self::cluster_count(1)
+        };
+        let (table, allocated_cluster_count) = match self.new_buffer(cluster_count) {
+            Some(table) => (table, cluster_count),
+            None => match self.new_buffer(fallback_cluster_count) {
+                Some(table) => (table, fallback_cluster_count),
+                // probe() needs at least one cluster.
+                None => std::alloc::handle_alloc_error(std::alloc::Layout::new::<TTCluster>()),
+            },
+        };
+        self.table = table;
         // self.table can be very large and takes much time to clear, so parallelize self.clear().
-        self.table.clear();
-        self.table.shrink_to_fit();
-        self.table = Vec::<TTCluster>::with_capacity(cluster_count);
-        unsafe {
-            self.table.set_len(cluster_count);
-        }
+        // All the clusters are overwritten by self.clear().
         self.clear();
+        if allocated_cluster_count != cluster_count {
+            return Err(TtResizeError {
+                requested_cluster_count: cluster_count,
+                allocated_cluster_count,
+            });
+        }
+        Ok(())
+    }
+    // The clusters of the largest power of two megabytes not more than mega_byte_size.
+    fn cluster_count(mega_byte_size: usize) -> usize {
+        (mega_byte_size.checked_add(1))
+            .and_then(usize::checked_next_power_of_two)
+            .and_then(|x| (x >> 1).checked_mul(1024 * 1024))
+            .map_or(usize::MAX, |byte_size| byte_size / std::mem::size_of::<TTCluster>())
+    }
+    fn new_buffer(&self, cluster_count: usize) -> Option<ClusterBuffer> {
+        #[cfg(target_os = "linux")]
+        {
+            if self.large_pages {
+                if let Some(table) = ClusterBuffer::new_huge_pages(cluster_count) {
+                    return Some(table);
+                }
+            }
+        }
+        ClusterBuffer::new_vec(cluster_count)
     }
     // parallel zero clearing.
     pub fn clear(&mut self) {
@@ -121,45 +357,168 @@ impl TranspositionTable {
             *x = unsafe { std::mem::zeroed() };
         });
     }
+    // parallel zero clearing of the entries saved keep_generations or more searches ago.
+    // keep_generations == 0 clears all the entries.
+    pub fn clear_stale(&mut self, keep_generations: u8) {
+        let generation8 = self.generation8;
+        self.table.par_iter_mut().for_each(|cluster| {
+            for entry in cluster.entry.iter_mut() {
+                let age = ((GENERATION_CYCLE + i32::from(generation8) - i32::from(entry.genbound8)) & GENERATION_MASK)
+                    / i32::from(GENERATION_DELTA);
+                if age >= i32::from(keep_generations) {
+                    *entry = unsafe { std::mem::zeroed() };
+                }
+            }
+        });
+    }
     pub fn new_search(&mut self) {
-        self.generation8 = self.generation8.wrapping_add(8);
+        self.generation8 = self.generation8.wrapping_add(GENERATION_DELTA);
     }
     fn cluster_index(&self, key: Key) -> usize {
         let mask = self.table.len() - 1;
         key.0 as usize & mask
     }
+    // load the cluster into cache before probe() needs it.
+    #[inline]
+    pub fn prefetch(&self, key: Key) {
+        let index = self.cluster_index(key);
+        debug_assert!(index < self.table.len());
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            use std::arch::x86_64::*;
+            let addr = self.table.as_ptr().add(index) as *const i8;
+            _mm_prefetch(addr, _MM_HINT_T0);
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            let _ = index;
+        }
+    }
     fn get_mut_cluster(&mut self, index: usize) -> &mut TTCluster {
         debug_assert!(index < self.table.len());
         unsafe { self.table.get_unchecked_mut(index) }
     }
     pub fn probe(&mut self, key: Key) -> (&mut TTEntry, bool) {
         let generation8 = self.generation8;
-        let key16 = (key.0 >> 48) as u16;
+        let tt_key = tt_key(key);
         let cluster = self.get_mut_cluster(self.cluster_index(key));
         for i in 0..cluster.entry.len() {
-            if cluster.entry[i].key16 == 0 || cluster.entry[i].key16 == key16 {
-                cluster.entry[i].genbound8 = generation8 | (cluster.entry[i].genbound8 & 0x7); // refresh
-                let found = cluster.entry[i].key16 != 0;
+            if cluster.entry[i].stored_key() == 0 || cluster.entry[i].stored_key() == tt_key {
+                cluster.entry[i].genbound8 = generation8 | (cluster.entry[i].genbound8 & !(GENERATION_MASK as u8)); // refresh
+                let found = cluster.entry[i].stored_key() != 0;
                 return (&mut cluster.entry[i], found);
             }
         }
-        let replace = cluster
-            .entry
-            .iter_mut()
-            .min_by(|x, y| {
-                let left = i32::from(x.depth8) - ((263 + i32::from(generation8) - i32::from(x.genbound8)) & 0xf8);
-                let right = i32::from(y.depth8) - ((263 + i32::from(generation8) - i32::from(y.genbound8)) & 0xf8);
-                left.cmp(&right)
-            })
-            .unwrap();
+        // An entry is worth 8 plies less per generation.
+        let worth = |x: &TTEntry| {
+            i32::from(x.depth8)
+                - ((GENERATION_CYCLE + i32::from(generation8) - i32::from(x.genbound8)) & GENERATION_MASK) * 8
+                    / i32::from(GENERATION_DELTA)
+        };
+        let replace = cluster.entry.iter_mut().min_by(|x, y| worth(x).cmp(&worth(y))).unwrap();
         let found = false;
         (replace, found)
     }
     pub fn generation(&self) -> u8 {
         self.generation8
     }
+    // File format: magic (8 bytes), version (u32), cluster count (u64), generation8 (u8), then raw clusters.
+    // Header integers and entries are written in native byte order, so the file can be read only on the same endianness machine.
+    pub fn save_to_path(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writer.write_all(&TT_FILE_MAGIC)?;
+        writer.write_all(&TT_FILE_VERSION.to_ne_bytes())?;
+        writer.write_all(&(self.table.len() as u64).to_ne_bytes())?;
+        writer.write_all(&[self.generation8])?;
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                self.table.as_ptr() as *const u8,
+                self.table.len() * std::mem::size_of::<TTCluster>(),
+            )
+        };
+        writer.write_all(bytes)?;
+        writer.flush()
+    }
+    pub fn load_from_path(&mut self, path: &std::path::Path, thread_pool: &mut ThreadPool) -> std::io::Result<()> {
+        thread_pool.wait_for_search_finished();
+        let invalid_data = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut magic = [0_u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != TT_FILE_MAGIC {
+            return Err(invalid_data(format!("{} is not a transposition table file.", path.display())));
+        }
+        let mut version = [0_u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_ne_bytes(version);
+        if version != TT_FILE_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported transposition table file version: {}",
+                version
+            )));
+        }
+        let mut cluster_count = [0_u8; 8];
+        reader.read_exact(&mut cluster_count)?;
+        let cluster_count = u64::from_ne_bytes(cluster_count);
+        if cluster_count != self.table.len() as u64 {
+            return Err(invalid_data(format!(
+                "cluster count mismatch. file: {}, table: {}",
+                cluster_count,
+                self.table.len()
+            )));
+        }
+        let mut generation8 = [0_u8; 1];
+        reader.read_exact(&mut generation8)?;
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.table.as_mut_ptr() as *mut u8,
+                self.table.len() * std::mem::size_of::<TTCluster>(),
+            )
+        };
+        if let Err(e) = reader.read_exact(bytes) {
+            // the table is partially overwritten. don't leave broken entries.
+            self.clear();
+            return Err(e);
+        }
+        self.generation8 = generation8[0];
+        Ok(())
+    }
+    // The moves of the entries from pos. It stops at no tt move, an illegal move, a repetition or max_len moves.
+    // pos is restored.
+    pub fn extract_pv(&mut self, pos: &mut Position, max_len: usize) -> Vec<Move> {
+        let mut pv = vec![];
+        while pv.len() < max_len && pos.is_repetition() == Repetition::Not {
+            let (tte, tt_hit) = self.probe(pos.key());
+            let m = match tte.mv(pos) {
+                Some(m) if tt_hit && pos.legal(m) => m,
+                _ => break,
+            };
+            let gives_check = pos.gives_check(m);
+            pos.do_move(m, gives_check);
+            pv.push(m);
+        }
+        for &m in pv.iter().rev() {
+            pos.undo_move(m);
+        }
+        pv
+    }
+    // approximate occupancy in per mille. sample only the first clusters to avoid scanning a huge table.
+    pub fn hashfull(&self) -> usize {
+        const SAMPLE_CLUSTER_NUM: usize = 1000;
+        let sample_num = std::cmp::min(SAMPLE_CLUSTER_NUM, self.table.len());
+        if sample_num == 0 {
+            return 0;
+        }
+        let count = self.table[..sample_num]
+            .iter()
+            .flat_map(|cluster| cluster.entry.iter())
+            .filter(|entry| entry.stored_key() != 0 && entry.genbound8 & GENERATION_MASK as u8 == self.generation8)
+            .count();
+        count * 1000 / (sample_num * CLUSTER_SIZE)
+    }
 }
 
+#[cfg(not(feature = "tt32"))]
 #[test]
 fn test_size() {
     assert_eq!(std::mem::size_of::<TTEntry>(), 10);
@@ -167,31 +526,25 @@ fn test_size() {
     assert_eq!(std::mem::size_of::<[TTCluster; 4]>(), 128);
 }
 
+#[cfg(feature = "tt32")]
+#[test]
+fn test_size() {
+    assert_eq!(std::mem::size_of::<TTEntry>(), 12);
+    assert_eq!(std::mem::size_of::<TTCluster>(), 64);
+    assert_eq!(std::mem::size_of::<[TTCluster; 4]>(), 256);
+}
+
+// The replacement order depends on CLUSTER_SIZE.
+#[cfg(not(feature = "tt32"))]
 #[test]
 fn test_probe() {
-    #[cfg(feature = "kppt")]
-    use crate::evaluate::kppt::*;
-    use crate::search::*;
+    use crate::searcher::Searcher;
+    use crate::usioption::UsiOptions;
     std::thread::Builder::new()
         .stack_size(crate::stack_size::STACK_SIZE)
         .spawn(|| {
-            let mut thread_pool = ThreadPool::new();
-            let mut tt = TranspositionTable::new();
-            #[cfg(feature = "kppt")]
-            let mut ehash = EvalHash::new();
-            let mut breadcrumbs = Breadcrumbs::new();
-            let mut reductions = Reductions::new(1);
-            thread_pool.set(
-                1,
-                &mut tt,
-                #[cfg(feature = "kppt")]
-                &mut ehash,
-                &mut breadcrumbs,
-                &mut reductions,
-            );
-            tt.resize(1, &mut thread_pool);
-            #[cfg(feature = "kppt")]
-            ehash.resize(1, &mut thread_pool);
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { tt, .. } = &mut searcher;
             let pv = false;
             let gen8 = tt.generation8;
 
@@ -199,19 +552,19 @@ fn test_probe() {
             let (tte, found) = tt.probe(key);
             assert!(!found);
             let (d2_val, d2) = (Value(20), Depth(2));
-            tte.save(key, d2_val, pv, Bound::EXACT, d2, None, Value(0), gen8); // cluster: [(d2, gen_old), 0, 0]
+            tte.save(key, d2_val, pv, false, Bound::EXACT, d2, None, Value(0), gen8); // cluster: [(d2, gen_old), 0, 0]
 
             let key = Key(0x7fff_ffff_ffff_ffff);
             let (tte, found) = tt.probe(key);
             assert!(!found);
             let (d1_val, d1) = (Value(10), Depth(1));
-            tte.save(key, d1_val, pv, Bound::EXACT, d1, None, Value(0), gen8); // cluster: [(d2, gen_old), (d1, gen_old), 0]
+            tte.save(key, d1_val, pv, false, Bound::EXACT, d1, None, Value(0), gen8); // cluster: [(d2, gen_old), (d1, gen_old), 0]
 
             let key = Key(0x3fff_ffff_ffff_ffff);
             let (tte, found) = tt.probe(key);
             assert!(!found);
             let (d9_val, d9) = (Value(90), Depth(9));
-            tte.save(key, d9_val, pv, Bound::EXACT, d9, None, Value(0), gen8); // cluster: [(d2, gen_old), (d1, gen_old), (d9, gen_old)]
+            tte.save(key, d9_val, pv, false, Bound::EXACT, d9, None, Value(0), gen8); // cluster: [(d2, gen_old), (d1, gen_old), (d9, gen_old)]
 
             tt.new_search();
             let gen8 = tt.generation8;
@@ -221,21 +574,513 @@ fn test_probe() {
             assert!(!found);
             assert_eq!(tte.value(), d1_val); // the entry is most shallow depth
             let (d1_val, d1) = (Value(10), Depth(1));
-            tte.save(key, d1_val, pv, Bound::EXACT, d1, None, Value(0), gen8); // cluster: [(d2, gen_old), (d1, gen_new), (d9, gen_old)]
+            tte.save(key, d1_val, pv, false, Bound::EXACT, d1, None, Value(0), gen8); // cluster: [(d2, gen_old), (d1, gen_new), (d9, gen_old)]
 
             let key = Key(0x0fff_ffff_ffff_ffff);
             let (tte, found) = tt.probe(key);
             assert!(!found);
             assert_eq!(tte.value(), d2_val); // old and shallow entry.
             let (d3_val, d3) = (Value(30), Depth(3));
-            tte.save(key, d3_val, pv, Bound::EXACT, d3, None, Value(0), gen8); // cluster: [d3, gen_new), (d1, gen_new), (d9, gen_old)]
+            tte.save(key, d3_val, pv, false, Bound::EXACT, d3, None, Value(0), gen8); // cluster: [d3, gen_new), (d1, gen_new), (d9, gen_old)]
 
             let key = Key(0x07ff_ffff_ffff_ffff);
             let (tte, found) = tt.probe(key);
             assert!(!found);
             assert_eq!(tte.value(), d1_val); // d9 entry has very deep depth. d9 isn't chosen.
             let (d2_val, d2) = (Value(20), Depth(2));
-            tte.save(key, d2_val, pv, Bound::EXACT, d2, None, Value(0), gen8); // cluster: [d3, gen_new), (d2, gen_new), (d9, gen_old)]
+            tte.save(key, d2_val, pv, false, Bound::EXACT, d2, None, Value(0), gen8);
+            // cluster: [d3, gen_new), (d2, gen_new), (d9, gen_old)]
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_hashfull() {
+    use crate::searcher::Searcher;
+    use crate::usioption::UsiOptions;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { tt, .. } = &mut searcher;
+            assert_eq!(tt.hashfull(), 0);
+
+            // fill one entry of each of the first 500 clusters. 500 / 3000 entries.
+            let gen8 = tt.generation();
+            for i in 0..500 {
+                let key = Key(0xffff_0000_0000_0000 | i);
+                let (tte, found) = tt.probe(key);
+                assert!(!found);
+                tte.save(key, Value(0), false, false, Bound::EXACT, Depth(1), None, Value(0), gen8);
+            }
+            assert_eq!(tt.hashfull(), 500 * 1000 / (1000 * CLUSTER_SIZE));
+
+            // entries of the previous search aren't counted.
+            tt.new_search();
+            assert_eq!(tt.hashfull(), 0);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_save_and_load() {
+    use crate::searcher::Searcher;
+    use crate::usioption::UsiOptions;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { thread_pool, tt, .. } = &mut searcher;
+            tt.new_search();
+            let gen8 = tt.generation();
+            let keys = [
+                Key(0xffff_ffff_ffff_ffff),
+                Key(0x1234_5678_9abc_def0),
+                Key(0x0fed_cba9_8765_4321),
+            ];
+            for (i, &key) in keys.iter().enumerate() {
+                let (tte, _found) = tt.probe(key);
+                let i = i as i32;
+                tte.save(
+                    key,
+                    Value(10 + i),
+                    false,
+                    false,
+                    Bound::EXACT,
+                    Depth(i + 1),
+                    None,
+                    Value(20 + i),
+                    gen8,
+                );
+            }
+
+            let path = std::env::temp_dir().join(format!("apery_test_tt_{}.bin", std::process::id()));
+            tt.save_to_path(&path).unwrap();
+            tt.clear();
+            tt.load_from_path(&path, thread_pool).unwrap();
+            assert_eq!(tt.generation(), gen8);
+            for (i, &key) in keys.iter().enumerate() {
+                let (tte, found) = tt.probe(key);
+                let i = i as i32;
+                assert!(found);
+                assert_eq!(tte.value(), Value(10 + i));
+                assert_eq!(tte.eval(), Value(20 + i));
+                assert_eq!(tte.depth(), Depth(i + 1));
+                assert_eq!(tte.bound().0, Bound::EXACT.0);
+            }
+
+            // The files of the old version have the old genbound8.
+            let mut bytes = std::fs::read(&path).unwrap();
+            bytes[TT_FILE_MAGIC.len()..TT_FILE_MAGIC.len() + 4].copy_from_slice(&1_u32.to_ne_bytes());
+            let old_path = std::env::temp_dir().join(format!("apery_test_tt_v1_{}.bin", std::process::id()));
+            std::fs::write(&old_path, &bytes).unwrap();
+            let err = tt.load_from_path(&old_path, thread_pool).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+            std::fs::remove_file(&old_path).unwrap();
+
+            // cluster count mismatch.
+            tt.resize(2, thread_pool).unwrap();
+            assert!(tt.load_from_path(&path, thread_pool).is_err());
+            std::fs::remove_file(&path).unwrap();
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_prefetch() {
+    use crate::searcher::Searcher;
+    use crate::usioption::UsiOptions;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { tt, .. } = &mut searcher;
+            let keys = (0..1000_u64)
+                .map(|i| Key(i.wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+                .collect::<Vec<_>>();
+            let gen8 = tt.generation();
+            for (i, &key) in keys.iter().enumerate() {
+                let (tte, _found) = tt.probe(key);
+                tte.save(
+                    key,
+                    Value(i as i32),
+                    false,
+                    false,
+                    Bound::EXACT,
+                    Depth(1),
+                    None,
+                    Value(0),
+                    gen8,
+                );
+            }
+            let probe_all = |tt: &mut TranspositionTable| {
+                keys.iter()
+                    .map(|&key| {
+                        let (tte, found) = tt.probe(key);
+                        (found, tte.value(), tte.depth())
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let before = probe_all(tt);
+            assert!(before.iter().any(|&(found, _, _)| found));
+            for &key in keys.iter() {
+                tt.prefetch(key);
+            }
+            // prefetch doesn't change the table contents.
+            assert_eq!(probe_all(tt), before);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { tt, .. } = &mut searcher;
+            // The search prefetches key_after(m) before do_move(m). It's the cluster that the child node probes.
+            let sfens = [
+                crate::sfen::START_SFEN,
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2",
+                // The captures and the drops.
+                "lnsgkgsnl/1r5+B1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/7R1/LNSGKGSNL w b 4",
+            ];
+            let gen8 = tt.generation();
+            for sfen in sfens.iter() {
+                let mut pos = Position::from_sfen(sfen).unwrap();
+                for m in pos.legal_moves() {
+                    let key = pos.key_after(m);
+                    let gives_check = pos.gives_check(m);
+                    pos.do_move(m, gives_check);
+                    assert!(key == pos.key());
+                    assert_eq!(tt.cluster_index(key), tt.cluster_index(pos.key()));
+                    let (tte, _found) = tt.probe(key);
+                    tte.save(key, Value(1), false, false, Bound::EXACT, Depth(1), Some(m), Value(0), gen8);
+                    let (tte, found) = tt.probe(pos.key());
+                    assert!(found);
+                    assert_eq!(tte.value(), Value(1));
+                    pos.undo_move(m);
+                }
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_probe_after_generation_wraparound() {
+    use crate::searcher::Searcher;
+    use crate::usioption::UsiOptions;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { tt, .. } = &mut searcher;
+            // The keys of the same cluster. The last one doesn't fit in the cluster.
+            let keys = (0..=CLUSTER_SIZE).map(|i| Key(u64::MAX >> i)).collect::<Vec<_>>();
+            // The last generation before generation8 wraps around to 0.
+            while tt.generation() != GENERATION_MASK as u8 {
+                tt.new_search();
+            }
+            let gen8 = tt.generation();
+            // The lower bits of genbound8 are all set. They must not carry into the generation.
+            let (tte, found) = tt.probe(keys[0]);
+            assert!(!found);
+            tte.save(keys[0], Value(10), true, true, Bound::EXACT, Depth(10), None, Value(0), gen8);
+
+            tt.new_search();
+            tt.new_search();
+            assert_eq!(tt.generation(), GENERATION_DELTA);
+            let gen8 = tt.generation();
+            for &key in keys[1..CLUSTER_SIZE].iter() {
+                let (tte, found) = tt.probe(key);
+                assert!(!found);
+                tte.save(key, Value(1), false, false, Bound::EXACT, Depth(1), None, Value(0), gen8);
+            }
+            // The deep entry of 2 generations ago is worth less than the shallow entries of this search.
+            let (tte, found) = tt.probe(keys[CLUSTER_SIZE]);
+            assert!(!found);
+            assert_eq!(tte.value(), Value(10));
+            assert_eq!(tte.generation(), GENERATION_MASK as u8);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_clear_stale() {
+    use crate::searcher::Searcher;
+    use crate::usioption::UsiOptions;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { tt, .. } = &mut searcher;
+            // save one entry per generation. the oldest entry is keys[0].
+            let keys = [
+                Key(0xffff_0000_0000_0000),
+                Key(0xffff_0000_0000_0001),
+                Key(0xffff_0000_0000_0002),
+                Key(0xffff_0000_0000_0003),
+            ];
+            for &key in keys.iter() {
+                tt.new_search();
+                let gen8 = tt.generation();
+                let (tte, found) = tt.probe(key);
+                assert!(!found);
+                tte.save(key, Value(0), false, false, Bound::EXACT, Depth(1), None, Value(0), gen8);
+            }
+            tt.clear_stale(2);
+            let found = keys
+                .iter()
+                .map(|&key| {
+                    let cluster = &tt.table[tt.cluster_index(key)];
+                    cluster.entry.iter().any(|x| x.stored_key() == tt_key(key))
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(found, vec![false, false, true, true]);
+
+            tt.clear_stale(0);
+            assert!(keys
+                .iter()
+                .all(|&key| tt.table[tt.cluster_index(key)].entry.iter().all(|x| x.stored_key() == 0)));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[cfg(feature = "tt32")]
+#[test]
+fn test_probe_wide_key() {
+    use crate::searcher::Searcher;
+    use crate::usioption::UsiOptions;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { tt, .. } = &mut searcher;
+            let gen8 = tt.generation();
+            // same cluster index and same upper 16 bits. they differ only in bits 32..48.
+            let key0 = Key(0xffff_0001_0000_0000);
+            let key1 = Key(0xffff_0002_0000_0000);
+
+            let (tte, found) = tt.probe(key0);
+            assert!(!found);
+            tte.save(key0, Value(10), false, false, Bound::EXACT, Depth(1), None, Value(0), gen8);
+
+            let (tte, found) = tt.probe(key1);
+            assert!(!found);
+            tte.save(key1, Value(20), false, false, Bound::EXACT, Depth(1), None, Value(0), gen8);
+
+            let (tte, found) = tt.probe(key0);
+            assert!(found);
+            assert_eq!(tte.value(), Value(10));
+            let (tte, found) = tt.probe(key1);
+            assert!(found);
+            assert_eq!(tte.value(), Value(20));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_save_max_depth() {
+    use crate::searcher::Searcher;
+    use crate::usioption::UsiOptions;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { tt, .. } = &mut searcher;
+            let gen8 = tt.generation();
+            let key = Key(0x1234_5678_9abc_def0);
+            assert_eq!(TTEntry::MAX_DEPTH, Depth(249 * Depth::ONE_PLY.0));
+            let (tte, _found) = tt.probe(key);
+            tte.save(
+                key,
+                Value(0),
+                false,
+                false,
+                Bound::EXACT,
+                TTEntry::MAX_DEPTH,
+                None,
+                Value(0),
+                gen8,
+            );
+            assert_eq!(tte.depth(), TTEntry::MAX_DEPTH);
+            // 256 + Depth::OFFSET would be stored as 0 if it wrapped around.
+            let depth = Depth((256 + 6 + 1) * Depth::ONE_PLY.0) + Depth::OFFSET;
+            tte.save(key, Value(0), false, false, Bound::EXACT, depth, None, Value(0), gen8);
+            assert_eq!(tte.depth(), TTEntry::MAX_DEPTH);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_resize_error() {
+    use crate::searcher::Searcher;
+    use crate::usioption::UsiOptions;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test_unloaded(&[(UsiOptions::USI_HASH, "1")]);
+            let Searcher { thread_pool, tt, .. } = &mut searcher;
+            let cluster_count = tt.table.len();
+            match tt.resize(usize::MAX / 2, thread_pool) {
+                Err(TtResizeError {
+                    requested_cluster_count,
+                    allocated_cluster_count,
+                }) => {
+                    assert!(allocated_cluster_count < requested_cluster_count);
+                    assert_eq!(allocated_cluster_count, cluster_count);
+                }
+                Ok(()) => panic!("resize must fail."),
+            }
+            // The table of the old size is allocated again.
+            assert_eq!(tt.table.len(), cluster_count);
+            let (_tte, found) = tt.probe(Key(1));
+            assert!(!found);
+            assert!(tt.resize(1, thread_pool).is_ok());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_large_pages() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut thread_pool = ThreadPool::new();
+            let mut tt = TranspositionTable::new();
+            // The table is usable whether the huge pages are available or not.
+            tt.set_large_pages(true);
+            tt.resize(4, &mut thread_pool).unwrap();
+            assert_eq!(tt.table.len(), 4 * 1024 * 1024 / std::mem::size_of::<TTCluster>());
+            assert_eq!(tt.hashfull(), 0);
+            let gen8 = tt.generation8;
+            let key = Key(0x0123_4567_89ab_cdef);
+            let (tte, found) = tt.probe(key);
+            assert!(!found);
+            tte.save(key, Value(42), false, false, Bound::EXACT, Depth(3), None, Value(7), gen8);
+            let (tte, found) = tt.probe(key);
+            assert!(found);
+            assert_eq!(tte.value(), Value(42));
+            assert_eq!(tte.eval(), Value(7));
+            assert_eq!(tte.depth(), Depth(3));
+            tt.clear();
+            assert!(!tt.probe(key).1);
+
+            tt.set_large_pages(false);
+            tt.resize(1, &mut thread_pool).unwrap();
+            assert!(matches!(tt.table, ClusterBuffer::Vec(_)));
+            assert_eq!(tt.table.len(), 1024 * 1024 / std::mem::size_of::<TTCluster>());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_tt_move_of_other_position() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut thread_pool = ThreadPool::new();
+            let mut tt = TranspositionTable::new();
+            tt.resize(1, &mut thread_pool).unwrap();
+            let generation = tt.generation();
+            let mut tte_mv = |key: Key, m: Move, pos: &Position| {
+                let (tte, _found) = tt.probe(key);
+                tte.save(
+                    key,
+                    Value::ZERO,
+                    false,
+                    false,
+                    Bound::EXACT,
+                    Depth::ONE_PLY,
+                    Some(m),
+                    Value::ZERO,
+                    generation,
+                );
+                tte.mv(pos)
+            };
+            // The key of the entry collides with the key of the other position.
+            let key = Key(0x1234_5678_9abc_def0);
+            let pos = Position::new();
+            let m = Move::new_from_usi_str("7g7f", &pos).unwrap();
+            assert_eq!(tte_mv(key, m, &pos), Some(m));
+            // The from square is empty.
+            let pos = Position::from_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2").unwrap();
+            assert_eq!(tte_mv(key, m, &pos), None);
+            // The from square has the piece of the opponent.
+            let pos = Position::from_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1").unwrap();
+            assert_eq!(tte_mv(key, m, &pos), None);
+            // The dropped piece isn't in hand.
+            let pos = Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 b P 1").unwrap();
+            let m = Move::new_from_usi_str("P*5e", &pos).unwrap();
+            assert_eq!(tte_mv(key, m, &pos), Some(m));
+            let pos = Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 b L 1").unwrap();
+            assert_eq!(tte_mv(key, m, &pos), None);
+            let pos = Position::from_sfen("4k4/9/9/9/9/9/9/9/4K4 w p 1").unwrap();
+            assert_eq!(tte_mv(key, m, &pos), None);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_extract_pv() {
+    use crate::search::*;
+    use crate::searcher::Searcher;
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let Searcher {
+                thread_pool,
+                tt,
+                usi_options,
+                ..
+            } = &mut searcher;
+            let mut pos = Position::new();
+            let limits = {
+                let mut limits = LimitsType::new();
+                limits.depth = Some(8);
+                limits.start_time = Some(std::time::Instant::now());
+                limits
+            };
+            let ponder_mode = false;
+            let hide_all_output = true;
+            thread_pool.start_thinking(&pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+            thread_pool.wait_for_search_finished();
+            let best_move = thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0];
+            let sfen = pos.to_sfen();
+            let max_len = 4;
+            let pv = tt.extract_pv(&mut pos, max_len);
+            assert_eq!(pos.to_sfen(), sfen);
+            assert!(!pv.is_empty() && pv.len() <= max_len);
+            assert_eq!(pv[0], best_move);
+            for &m in pv.iter() {
+                assert!(pos.pseudo_legal::<NotSearchingType>(m) && pos.legal(m));
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+            }
+            assert!(tt.extract_pv(&mut pos, 0).is_empty());
         })
         .unwrap()
         .join()
@@ -518,7 +518,6 @@ impl Square {
     pub fn inverse(self) -> Square {
         Square(Square::NUM as i32 - 1 - self.0)
     }
-    #[allow(dead_code)]
     pub fn inverse_file(self) -> Square {
         Square::new(File::new(self).inverse(), Rank::new(self))
     }
@@ -660,6 +659,7 @@ pub fn is_aligned_and_sq2_is_not_between_sq0_and_sq1(sq0: Square, sq1: Square, s
     result
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Bound(pub i32);
 
 impl Bound {
@@ -674,6 +674,14 @@ impl Bound {
     pub fn include_upper(&self) -> bool {
         (self.0 & Bound::UPPER.0) != 0
     }
+    // For the score of "info". BOUND_NONE isn't used.
+    pub fn to_usi_str(self) -> &'static str {
+        match self {
+            Bound::LOWER => "lowerbound ",
+            Bound::UPPER => "upperbound ",
+            _ => "",
+        }
+    }
 }
 
 #[derive(
@@ -727,7 +735,6 @@ impl Value {
             )
         }
     }
-    #[allow(dead_code)]
     pub fn to_win_rate(self) -> f64 {
         if Value::MATED_IN_MAX_PLY < self && self < Value::MATE_IN_MAX_PLY {
             1.0 / (1.0 + (f64::from(-self.0) / 600.0).exp())
@@ -737,6 +744,17 @@ impl Value {
             0.0
         }
     }
+    // Win, draw and loss probabilities in per mille. The sum is always 1000.
+    // Draws (mostly by repetition) are rare in shogi. The draw rate has a peak at the even score,
+    // and the peak is higher in the long games where the repetitions are likely.
+    pub fn to_wdl(self, game_ply: i32) -> (i32, i32, i32) {
+        let win_rate = self.to_win_rate();
+        let draw_peak = 0.02 + 0.06 * f64::from(game_ply.clamp(0, 300)) / 300.0;
+        let draw_rate = draw_peak * 4.0 * win_rate * (1.0 - win_rate);
+        let win = ((win_rate - draw_rate / 2.0) * 1000.0).round() as i32;
+        let loss = ((1.0 - win_rate - draw_rate / 2.0) * 1000.0).round() as i32;
+        (win, 1000 - win - loss, loss)
+    }
     pub fn mate_in(ply: i32) -> Value {
         Value::MATE - Value(ply)
     }
@@ -867,6 +885,25 @@ impl PieceType {
             _ => unreachable!(),
         }
     }
+    pub fn to_kif_str(self) -> &'static str {
+        match self {
+            PieceType::PAWN => "歩",
+            PieceType::LANCE => "香",
+            PieceType::KNIGHT => "桂",
+            PieceType::SILVER => "銀",
+            PieceType::BISHOP => "角",
+            PieceType::ROOK => "飛",
+            PieceType::GOLD => "金",
+            PieceType::KING => "玉",
+            PieceType::PRO_PAWN => "と",
+            PieceType::PRO_LANCE => "杏",
+            PieceType::PRO_KNIGHT => "圭",
+            PieceType::PRO_SILVER => "全",
+            PieceType::HORSE => "馬",
+            PieceType::DRAGON => "龍",
+            _ => unreachable!(),
+        }
+    }
     pub fn new_from_str_for_drop_move(s: &str) -> Option<PieceType> {
         match s {
             "P" => Some(PieceType::PAWN),
@@ -988,8 +1025,6 @@ impl Piece {
             "r" => Some(Piece::W_ROOK),
             "G" => Some(Piece::B_GOLD),
             "g" => Some(Piece::W_GOLD),
-            "K" => Some(Piece::B_KING),
-            "k" => Some(Piece::W_KING),
             _ => None,
         }
     }
@@ -1516,3 +1551,27 @@ fn test_bound() {
     assert!(Bound::EXACT.include_lower());
     assert!(Bound::EXACT.include_upper());
 }
+
+#[test]
+fn test_value_to_wdl() {
+    for &game_ply in [1, 100, 500].iter() {
+        let mut prev = (0, 0, 1000);
+        for v in (-3000..=3000).step_by(50) {
+            let (w, d, l) = Value(v).to_wdl(game_ply);
+            assert_eq!(w + d + l, 1000);
+            assert!(0 <= w && 0 <= d && 0 <= l);
+            // monotonic in score.
+            assert!(prev.0 <= w && l <= prev.2);
+            prev = (w, d, l);
+        }
+        let (w, d, l) = Value(0).to_wdl(game_ply);
+        assert_eq!(w, l);
+        assert!(d < 100);
+        // "score cp 500"
+        let (w, _, l) = Value(500 * PAWN_VALUE / 100).to_wdl(game_ply);
+        assert!(w > 2 * l);
+    }
+    assert!(Value(0).to_wdl(1).1 < Value(0).to_wdl(300).1);
+    assert_eq!(Value::mate_in(3).to_wdl(1), (1000, 0, 0));
+    assert_eq!(Value::mated_in(2).to_wdl(1), (0, 0, 1000));
+}
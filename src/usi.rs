@@ -1,8 +1,9 @@
-use crate::book::*;
 #[cfg(feature = "kppt")]
 use crate::evaluate::kppt::*;
 #[cfg(feature = "material")]
 use crate::evaluate::material::*;
+#[cfg(feature = "nnue")]
+use crate::evaluate::nnue::*;
 use crate::file_to_vec::*;
 use crate::huffman_code::*;
 use crate::learn::*;
@@ -10,20 +11,20 @@ use crate::movegen::*;
 use crate::movetypes::*;
 use crate::position::*;
 use crate::search::*;
-use crate::sfen::START_SFEN;
+use crate::searcher::Searcher;
+use crate::selfplay::*;
+use crate::sfen::{SfenError, START_SFEN};
+#[cfg(test)]
 use crate::thread::*;
+#[cfg(test)]
 use crate::tt::*;
 use crate::types::*;
 use crate::usioption::*;
+use rand::prelude::*;
 use std::io::prelude::*;
 
-fn go(
-    thread_pool: &mut ThreadPool,
-    tt: &mut TranspositionTable,
-    usi_options: &UsiOptions,
-    pos: &Position,
-    args: &[&str],
-) -> Result<(), String> {
+fn go(searcher: &mut Searcher, pos: &Position, args: &[&str]) -> Result<(), String> {
+    let usi_options = &searcher.usi_options;
     let mut limits = LimitsType::new();
     limits.start_time = Some(std::time::Instant::now());
     let mut iter = args.iter();
@@ -33,11 +34,14 @@ fn go(
         Ok(n)
     };
     let mut ponder_mode = false;
+    let mut mate_mode = false;
+    let mut has_clock = false;
     while let Some(&limit_type) = iter.next() {
         match limit_type {
             "btime" | "wtime" => {
                 let color = if limit_type == "btime" { Color::BLACK } else { Color::WHITE };
                 let n = next_num(limit_type, &mut iter)?;
+                has_clock = true;
                 let time_margin = usi_options.get_i64(UsiOptions::TIME_MARGIN) as u64;
                 limits.time[color.0 as usize] = if time_margin <= n {
                     std::time::Duration::from_millis(n - time_margin)
@@ -48,6 +52,7 @@ fn go(
             "binc" | "winc" => {
                 let color = if limit_type == "binc" { Color::BLACK } else { Color::WHITE };
                 let n = next_num(limit_type, &mut iter)?;
+                has_clock = true;
                 limits.inc[color.0 as usize] = std::time::Duration::from_millis(n);
             }
             "byoyomi" | "movetime" => {
@@ -64,6 +69,17 @@ fn go(
                 limits.depth = Some(n);
             }
             "infinite" => limits.infinite = Some(()),
+            "mate" => {
+                mate_mode = true;
+                match iter.next() {
+                    Some(&"infinite") => limits.infinite = Some(()),
+                    Some(item) => {
+                        let n = item.parse().map_err(|_| "Error: Parse error.".to_string())?;
+                        limits.movetime = Some(std::time::Duration::from_millis(n));
+                    }
+                    None => return Err(format!("Error: No token after {}.", limit_type)),
+                }
+            }
             "nodes" => {
                 let n = next_num(limit_type, &mut iter)?;
                 limits.nodes = Some(n);
@@ -71,6 +87,25 @@ fn go(
             "ponder" => {
                 ponder_mode = true;
             }
+            "searchmoves" => {
+                // "searchmoves" is the last item of "go".
+                for &usi_move in iter.by_ref() {
+                    match Move::new_from_usi_str(usi_move, pos) {
+                        Some(m) => limits.searchmoves.push(m),
+                        None => eprintln!("Warning: Illegal move in searchmoves: {}", usi_move),
+                    }
+                }
+            }
+            "rootpv" => {
+                // The moves continue while they are legal in the sequence.
+                let mut pos = Position::new_from_position(pos, Default::default());
+                while let Some(m) = iter.clone().next().and_then(|s| Move::new_from_usi_str(s, &pos)) {
+                    iter.next();
+                    let gives_check = pos.gives_check(m);
+                    pos.do_move(m, gives_check);
+                    limits.root_pv.push(m);
+                }
+            }
             "perft" => {
                 let n = next_num(limit_type, &mut iter)?;
                 limits.perft = Some(n);
@@ -78,26 +113,60 @@ fn go(
             invalid_token => return Err(format!("Error: Invalid token: {}", invalid_token)),
         }
     }
+    // "go infinite" ignores DefaultDepth.
+    let default_depth = usi_options.get_i64(UsiOptions::DEFAULT_DEPTH) as u32;
+    if default_depth != 0
+        && !has_clock
+        && limits.depth.is_none()
+        && limits.nodes.is_none()
+        && limits.movetime.is_none()
+        && limits.infinite.is_none()
+        && limits.perft.is_none()
+    {
+        limits.depth = Some(default_depth);
+    }
     let hide_all_output = false;
-    thread_pool.start_thinking(pos, tt, limits, usi_options, ponder_mode, hide_all_output);
+    if mate_mode {
+        searcher.thread_pool.start_mate_search(pos, limits, hide_all_output);
+    } else {
+        searcher
+            .thread_pool
+            .start_thinking(pos, &mut searcher.tt, limits, usi_options, ponder_mode, hide_all_output);
+    }
     Ok(())
 }
 
-fn usi_new_game(thread_pool: &mut ThreadPool, _tt: &mut TranspositionTable) {
-    thread_pool.wait_for_search_finished();
-    thread_pool.clear();
-    // Is tt.clear() disturbed at the continuous match?
-    //_tt.clear();
+// "gameover win", "gameover lose" or "gameover draw". Nothing is printed, not even the bestmove of the stopped search.
+// The search is stopped and the states of the game are reset for the next game.
+fn gameover(searcher: &mut Searcher, pos: &mut Position, args: &[&str]) {
+    match args.first() {
+        Some(&"win") | Some(&"lose") | Some(&"draw") => {}
+        _ => eprintln!(r#"Invalid gameover command. expected: "win", "lose" or "draw"."#),
+    }
+    searcher
+        .thread_pool
+        .hide_all_output
+        .store(true, std::sync::atomic::Ordering::Relaxed);
+    searcher.thread_pool.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    searcher.thread_pool.wait_for_search_finished();
+    searcher.thread_pool.clear();
+    searcher
+        .tt
+        .clear_stale(searcher.usi_options.get_i64(UsiOptions::GAMEOVER_HASH_KEEP) as u8);
+    #[cfg(feature = "kppt")]
+    searcher.ehash.clear();
+    // The repetition history of the game is discarded.
+    *pos = Position::new();
 }
 
-fn self_move(thread_pool: &mut ThreadPool, tt: &mut TranspositionTable, usi_options: &UsiOptions, pos: &Position) {
+fn self_move(searcher: &mut Searcher, pos: &Position) {
     let start_sfen = &pos.to_sfen();
     loop {
-        let mut pos = Position::new_from_sfen(start_sfen).unwrap();
+        let mut pos = Position::from_sfen(start_sfen).unwrap();
         let mut record = pos.to_sfen();
         let mut pos_map = std::collections::HashMap::new();
         loop {
-            println!("position sfen {}", record);
+            usi_println!("position sfen {}", record);
             let key = pos.key().0;
             *pos_map.entry(key).or_insert(0) += 1;
             if *pos_map.get(&key).unwrap() == 4 {
@@ -108,9 +177,16 @@ fn self_move(thread_pool: &mut ThreadPool, tt: &mut TranspositionTable, usi_opti
             limits.movetime = Some(std::time::Duration::from_millis(4000));
             let ponder_mode = false;
             let hide_all_output = false;
-            thread_pool.start_thinking(&pos, tt, limits, &usi_options, ponder_mode, hide_all_output);
-            thread_pool.wait_for_search_finished();
-            let m = thread_pool.last_best_root_move.lock().unwrap().as_ref().unwrap().pv[0];
+            searcher.thread_pool.start_thinking(
+                &pos,
+                &mut searcher.tt,
+                limits,
+                &searcher.usi_options,
+                ponder_mode,
+                hide_all_output,
+            );
+            searcher.thread_pool.wait_for_search_finished();
+            let m = searcher.thread_pool.last_best_root_move.lock().unwrap().as_ref().unwrap().pv[0];
             if m == Move::RESIGN {
                 break;
             } else {
@@ -121,10 +197,54 @@ fn self_move(thread_pool: &mut ThreadPool, tt: &mut TranspositionTable, usi_opti
     }
 }
 
-fn position(pos: &mut Position, args: &[&str]) {
+#[derive(Debug)]
+pub enum UsiParseError {
+    NoPositionType,
+    InvalidPositionType {
+        token: String,
+    },
+    Sfen(SfenError),
+    Handicap(String),
+    NoMovesToken {
+        token: String,
+    },
+    Move {
+        usi_move: String,
+        sfen: String,
+        err: MoveParseError,
+    },
+}
+
+impl std::fmt::Display for UsiParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsiParseError::NoPositionType => {
+                write!(
+                    f,
+                    r#"Invalid postion command. expected: "startpos", "sfen" or "handicap". but found nothing"#
+                )
+            }
+            UsiParseError::InvalidPositionType { token } => write!(
+                f,
+                r#"Invalid postion command. expected: "startpos", "sfen" or "handicap". found: "{}""#,
+                token
+            ),
+            UsiParseError::Sfen(err) => write!(f, "sfen error: {:?}", err),
+            UsiParseError::Handicap(err) => write!(f, "handicap error: {}", err),
+            UsiParseError::NoMovesToken { token } => {
+                write!(f, r#"Invalid position command. expected: "moves". found: "{}""#, token)
+            }
+            UsiParseError::Move { usi_move, sfen, err } => {
+                write!(f, "Invalid move: {}, position: {}, error: {:?}", usi_move, sfen, err)
+            }
+        }
+    }
+}
+
+// pos isn't changed if args is invalid.
+fn position(pos: &mut Position, args: &[&str]) -> Result<(), UsiParseError> {
     if args.is_empty() {
-        eprintln!(r#"Invalid postion command. expected: "startpos" or "sfen". but found nothing"#,);
-        return;
+        return Err(UsiParseError::NoPositionType);
     }
     let mut tmp_pos;
     let args = match args[0] {
@@ -134,55 +254,113 @@ fn position(pos: &mut Position, args: &[&str]) {
         }
         "sfen" => {
             // &args[1..]:  skip "sfen".
-            match Position::new_from_sfen_args(&args[1..]) {
-                Ok(new_pos) => tmp_pos = new_pos,
-                Err(err) => {
-                    println!("sfen error: {:?}", err);
-                    return;
-                }
-            }
+            tmp_pos = Position::new_from_sfen_args(&args[1..]).map_err(UsiParseError::Sfen)?;
             &args[5..]
         }
+        "handicap" => {
+            let name = args.get(1).copied().unwrap_or("");
+            tmp_pos = Position::handicap(name.parse().map_err(UsiParseError::Handicap)?);
+            &args[2..]
+        }
         _ => {
-            eprintln!(
-                r#"Invalid postion command. expected: "startpos" or "sfen". found: "{}""#,
-                args[0]
-            );
-            return;
+            return Err(UsiParseError::InvalidPositionType {
+                token: args[0].to_string(),
+            });
         }
     };
     if args.is_empty() {
         *pos = tmp_pos;
         pos.reserve_states();
-        return;
+        return Ok(());
     }
     if args[0] != "moves" {
-        eprintln!(r#"Invalid position command. expected: "moves". found: "{}""#, args[0]);
-        return;
+        return Err(UsiParseError::NoMovesToken {
+            token: args[0].to_string(),
+        });
     }
     for arg in &args[1..] {
-        if let Some(m) = Move::new_from_usi_str(arg, &tmp_pos) {
-            let gives_check = tmp_pos.gives_check(m);
-            tmp_pos.do_move(m, gives_check);
-        } else {
-            eprintln!("Invalid move: {}, position: {}", arg, tmp_pos.to_sfen());
-            return;
+        match Move::from_usi(&tmp_pos, arg) {
+            Ok(m) => {
+                let gives_check = tmp_pos.gives_check(m);
+                tmp_pos.do_move(m, gives_check);
+            }
+            Err(err) => {
+                return Err(UsiParseError::Move {
+                    usi_move: arg.to_string(),
+                    sfen: tmp_pos.to_sfen(),
+                    err,
+                });
+            }
         }
     }
     *pos = tmp_pos;
     pos.reserve_states();
+    Ok(())
+}
+
+// Remembers the last "position" command. GUIs send the whole movelist every time,
+// so only the appended moves are applied if the new movelist extends the previous one.
+struct PositionCommand {
+    base: Vec<String>,  // "startpos" or "sfen ..."
+    moves: Vec<String>, // the usi moves after "moves".
+}
+
+impl PositionCommand {
+    fn new() -> PositionCommand {
+        PositionCommand {
+            base: vec![],
+            moves: vec![],
+        }
+    }
+    // Call this when pos is changed by anything other than set().
+    fn clear(&mut self) {
+        self.base.clear();
+        self.moves.clear();
+    }
+    // pos isn't changed if args is invalid.
+    fn set(&mut self, pos: &mut Position, args: &[&str]) -> Result<(), UsiParseError> {
+        let (base, moves) = match args.iter().position(|&arg| arg == "moves") {
+            Some(i) => (&args[..i], &args[i + 1..]),
+            None => (args, &[][..]),
+        };
+        let is_extended = !self.base.is_empty()
+            && self.base.iter().eq(base.iter())
+            && self.moves.len() <= moves.len()
+            && self.moves.iter().eq(moves[..self.moves.len()].iter());
+        if !is_extended {
+            position(pos, args)?;
+            self.base = base.iter().map(|arg| arg.to_string()).collect();
+            self.moves = moves.iter().map(|arg| arg.to_string()).collect();
+            return Ok(());
+        }
+        let mut applied_moves = vec![];
+        for arg in &moves[self.moves.len()..] {
+            match Move::from_usi(pos, arg) {
+                Ok(m) => {
+                    let gives_check = pos.gives_check(m);
+                    pos.do_move(m, gives_check);
+                    applied_moves.push(m);
+                }
+                Err(err) => {
+                    let err = UsiParseError::Move {
+                        usi_move: arg.to_string(),
+                        sfen: pos.to_sfen(),
+                        err,
+                    };
+                    for &m in applied_moves.iter().rev() {
+                        pos.undo_move(m);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        self.moves.extend(moves[self.moves.len()..].iter().map(|arg| arg.to_string()));
+        pos.reserve_states();
+        Ok(())
+    }
 }
 
-pub fn setoption(
-    args: &[&str],
-    usi_options: &mut UsiOptions,
-    thread_pool: &mut ThreadPool,
-    tt: &mut TranspositionTable,
-    #[cfg(feature = "kppt")] ehash: &mut EvalHash,
-    breadcrumbs: &mut Breadcrumbs,
-    reductions: &mut Reductions,
-    is_ready: &mut bool,
-) {
+pub fn setoption(args: &[&str], searcher: &mut Searcher, is_ready: &mut bool) {
     if !args.is_empty() && args[0] != "name" {
         eprintln!(r#"Error: expected: "name", found: "{}""#, args[0]);
         return;
@@ -190,7 +368,7 @@ pub fn setoption(
     match args.len() {
         2 => {
             let name = args[1];
-            usi_options.push_button(name, tt);
+            searcher.push_button(name);
         }
         4 => {
             if args[2] != "value" {
@@ -199,17 +377,7 @@ pub fn setoption(
             }
             let name = args[1];
             let value = args[3];
-            usi_options.set(
-                name,
-                value,
-                thread_pool,
-                tt,
-                #[cfg(feature = "kppt")]
-                ehash,
-                breadcrumbs,
-                reductions,
-                is_ready,
-            );
+            searcher.set_usi_option(name, value, is_ready);
         }
         _ => {
             let mut s = "Error: invalid number of sections.".to_string();
@@ -223,19 +391,207 @@ pub fn setoption(
 fn legal_moves(pos: &Position) {
     let mut mlist = MoveList::new();
     mlist.generate::<LegalType>(&pos, 0);
+    let mut s = String::new();
     for i in 0..mlist.size {
-        print!("{} ", mlist.ext_moves[i].mv.to_usi_string());
+        s += &format!("{} ", mlist.ext_moves[i].mv.to_usi_string());
     }
-    println!();
+    usi_println!("{}", s);
 }
 
 fn legal_all_moves(pos: &Position) {
     let mut mlist = MoveList::new();
     mlist.generate::<LegalAllType>(&pos, 0);
+    let mut s = String::new();
     for i in 0..mlist.size {
-        print!("{} ", mlist.ext_moves[i].mv.to_usi_string());
+        s += &format!("{} ", mlist.ext_moves[i].mv.to_usi_string());
+    }
+    usi_println!("{}", s);
+}
+
+// The static evaluation from the side to move, and its breakdown into the material and the rest.
+// It's calculated from scratch, not from the differences.
+fn static_evaluation(pos: &Position) -> String {
+    let mut stack = vec![Stack::new(); CURRENT_STACK_INDEX + 1];
+    let value = evaluate_at_root(pos, &mut stack);
+    let material = if pos.side_to_move() == Color::BLACK {
+        pos.material()
+    } else {
+        -pos.material()
+    };
+    format!("{} (material: {}, positional: {})", value.0, material.0, (value - material).0)
+}
+
+// The sfen of the first position whose static evaluation differs from the one of the color-flipped position.
+// Both are from the side to move, so they are the same if the evaluation is symmetric.
+// The positions are reached by the random moves from the start position.
+fn first_asymmetric_position(num_positions: u32, rng: &mut StdRng) -> Option<String> {
+    const MAX_GAME_PLY: i32 = 256;
+    let mut stack = vec![Stack::new(); CURRENT_STACK_INDEX + 1];
+    let mut pos = Position::new();
+    for _ in 0..num_positions {
+        if evaluate_at_root(&pos, &mut stack) != evaluate_at_root(&pos.flip_colors(), &mut stack) {
+            return Some(pos.to_sfen());
+        }
+        match pos.legal_moves().choose(rng) {
+            Some(&m) if pos.ply() < MAX_GAME_PLY => {
+                let gives_check = pos.gives_check(m);
+                pos.do_move(m, gives_check);
+            }
+            _ => pos = Position::new(),
+        }
+    }
+    None
+}
+
+// "evaltest [<positions>]"
+fn evaltest(usi_options: &UsiOptions, args: &[&str]) -> Result<(), String> {
+    let num_positions = match args.first() {
+        Some(s) => s.parse::<u32>().map_err(|_| format!("Error: Parse error: {}", s))?,
+        None => 10000,
+    };
+    let mut rng = match usi_options.get_i64(UsiOptions::SEED) {
+        0 => StdRng::from_entropy(),
+        seed => StdRng::seed_from_u64(seed as u64),
+    };
+    match first_asymmetric_position(num_positions, &mut rng) {
+        Some(sfen) => Err(format!("Error: The evaluation isn't symmetric: sfen {}", sfen)),
+        None => {
+            usi_println!("evaltest: {} positions are symmetric.", num_positions);
+            Ok(())
+        }
+    }
+}
+
+// Positions for "bench". Changing them changes the node count which is used as a signature of the search.
+const BENCH_SFENS: [&str; 20] = [
+    "lnsgkgsnl/1r7/p1ppp1bpp/1p3pp2/7P1/2P6/PP1PPPP1P/1B3S1R1/LNSGKG1NL b - 9",
+    "l4S2l/4g1gs1/5p1p1/pr2N1pkp/4Gn3/PP3PPPP/2GPP4/1K7/L3r+s2L w BS2N5Pb 1",
+    "6n1l/2+S1k4/2lp4p/1np1B2b1/3PP4/1N1S3rP/1P2+pPP+p1/1p1G5/3KG2r1 b GSN2L4Pgs2p 1",
+    "l6nl/5+P1gk/2np1S3/p1p4Pp/3P2Sp1/1PPb2P1P/P5GS1/R8/LN4bKL w RGgsn5p 1",
+    "1n3gs1l/lr2sk1b1/ppgpppnpp/2p3p2/3P5/8P/PPP1PPPPN/1B1R1KS1L/LNSG1G3 b - 21",
+    "l1s3sn1/1rg1kg1bl/p1np2ppp/1pp6/4pp3/P6PP/NPPPPPP1L/L1GSKS1R1/B5GN1 b - 27",
+    "ln2g2nl/1r1gk2s1/pspp1p1pp/4p1p2/1N7/4PP3/PP1S2PPP/L2BG1SRL/3GK2N1 b B3p 33",
+    "2s2g2l/lrgb1k3/npppp1ns1/p7p/2P4pN/5P3/PPGPPKPPP/2L5R/2S1G1SNL b 2Pb 39",
+    "l2gk1sn1/1rsg3bl/ppnp2p1p/2p1p4/7P1/1P2PPG2/PSPP2P1P/LB3KR1L/1N1GS2N1 b 2P 45",
+    "1n2gks2/l2s3b1/pppppgppn/9/9/P1l1PRP2/1PPPK2Pp/L1GSG1r+l1/1NB6 b Psn2p 51",
+    "lnsg5/1r5k1/p1pG1sppl/1p6p/3P5/2P6/+b3PPP1P/1S1G1RK1L/L3N2N1 b B3Pgsn3p 57",
+    "lnsks2nl/3g3b1/2p1+P1ppp/pr5S1/1p1Gp3P/7P1/PPP1+p1P1N/LB4R2/1N1KGGS1L b 2Pp 63",
+    "r2g2s1b/l1s3g1l/2pp2p1n/pP3kgpp/6n2/P3P1P2/NSPPRS1PP/LB1GK4/7NL b 3Pp 69",
+    "1g2kg3/1s5L1/ll1+B1+B3/2p1p2p1/3P2p2/2N6/1G+n1PPPP1/9/3K3NS b RGS6Prsnl3p 75",
+    "6+b2/1+L1k2g2/2Pp3p1/l3pr2p/L2gsp1n1/3Pn1g1P/2K2P1PL/6SSR/6GN1 b SN2Pb6p 81",
+    "1g1+Ps1g2/l8/+N5kgp/2p6/N2P1n1L1/PPP1P1p2/2+b2PNPP/L3G1SRL/2S3K2 b BS5Prp 87",
+    "l2kp1g2/3g4P/2ns1G2S/ppp3p2/5p3/P2LRPbS1/2NP3NN/LP1K2R2/S7L b BG5P2p 93",
+    "2r3sll/G6b1/3k1g1p1/1KN3p1L/1p5N1/9/lP1PP2P1/p2sG1bR1/1+s1S1P1N1 b 9Pgn 99",
+    "ln2pk3/n1s2p1rl/+L1p2n3/1p1p4P/3P3p1/P8/1PP1G4/1BK1P1+B1L/SNS6 b R5P3gsp 105",
+    "1n3kg2/b8/ps2ppnp1/1pg3g1p/4P4/2P1G1P1P/4SP1PR/2+p1K1S1L/1+l1PBS1NL b L4Prn 111",
+];
+
+// The positions for "bench" from the file. Each line is a sfen. If the line has the token "sfen",
+// the 4 tokens after it are used, so "position sfen ..." or "sfen ... bm 7g7f" can be read too. The moves are ignored.
+// The malformed lines are skipped.
+fn read_bench_sfens(path: &str) -> Result<Vec<String>, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("Error: {}: {}", path, err))?;
+    let mut sfens = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        if tokens.is_empty() || tokens[0].starts_with('#') {
+            continue;
+        }
+        if tokens.contains(&"startpos") {
+            sfens.push(START_SFEN.to_string());
+            continue;
+        }
+        let tokens = match tokens.iter().position(|&token| token == "sfen") {
+            Some(j) => &tokens[j + 1..],
+            None => &tokens[..],
+        };
+        let tokens = &tokens[..std::cmp::min(4, tokens.len())];
+        match Position::new_from_sfen_args(tokens) {
+            Ok(_) => sfens.push(tokens.join(" ")),
+            Err(err) => eprintln!("Warning: {}:{} is skipped. {:?}", path, i + 1, err),
+        }
+    }
+    Ok(sfens)
+}
+
+// "bench [depth] [threads] [hash] [file]" searches the fixed positions, or the positions of the file,
+// and prints the total nodes and NPS.
+// The node count is deterministic for the same arguments if the number of threads is 1.
+fn bench(searcher: &mut Searcher, args: &[&str]) -> Result<i64, String> {
+    let arg = |i: usize, default: u32| -> Result<u32, String> {
+        match args.get(i) {
+            Some(s) => s.parse().map_err(|_| format!("Error: Parse error: {}", s)),
+            None => Ok(default),
+        }
+    };
+    let depth = arg(0, 13)?;
+    let threads = std::cmp::max(arg(1, 1)?, 1);
+    let hash = std::cmp::max(arg(2, 16)?, 1);
+    let sfens = match args.get(3) {
+        Some(path) => read_bench_sfens(path)?,
+        None => BENCH_SFENS.iter().map(|sfen| sfen.to_string()).collect(),
+    };
+    searcher.thread_pool.wait_for_search_finished();
+    searcher.thread_pool.set(
+        threads as usize,
+        &mut searcher.tt,
+        #[cfg(feature = "kppt")]
+        &mut searcher.ehash,
+        &mut searcher.breadcrumbs,
+        &mut searcher.reductions,
+    );
+    searcher
+        .tt
+        .resize(hash as usize, &mut searcher.thread_pool)
+        .map_err(|err| format!("Error: {}", err))?;
+    // The book move isn't searched.
+    let book = searcher.thread_pool.book.take();
+    let start = std::time::Instant::now();
+    let mut nodes = 0;
+    for (i, sfen) in sfens.iter().enumerate() {
+        eprintln!("Position: {}/{}", i + 1, sfens.len());
+        let pos = Position::from_sfen(sfen).unwrap();
+        searcher.thread_pool.clear();
+        searcher.tt.clear();
+        let mut limits = LimitsType::new();
+        limits.depth = Some(depth);
+        limits.start_time = Some(std::time::Instant::now());
+        let ponder_mode = false;
+        let hide_all_output = true;
+        searcher.thread_pool.start_thinking(
+            &pos,
+            &mut searcher.tt,
+            limits,
+            &searcher.usi_options,
+            ponder_mode,
+            hide_all_output,
+        );
+        searcher.thread_pool.wait_for_search_finished();
+        nodes += searcher.thread_pool.nodes_searched();
     }
-    println!();
+    let elapsed = start.elapsed().as_millis() as i64;
+    usi_println!("===========================");
+    usi_println!("Total time (ms) : {}", elapsed);
+    usi_println!("Nodes searched : {}", nodes);
+    usi_println!("Nodes/second : {}", if elapsed == 0 { 0 } else { nodes * 1000 / elapsed });
+    // Restore the settings of the USI options.
+    searcher.thread_pool.book = book;
+    searcher.thread_pool.set(
+        searcher.usi_options.get_i64(UsiOptions::THREADS) as usize,
+        &mut searcher.tt,
+        #[cfg(feature = "kppt")]
+        &mut searcher.ehash,
+        &mut searcher.breadcrumbs,
+        &mut searcher.reductions,
+    );
+    searcher
+        .tt
+        .resize(
+            searcher.usi_options.get_i64(UsiOptions::USI_HASH) as usize,
+            &mut searcher.thread_pool,
+        )
+        .map_err(|err| format!("Error: {}", err))?;
+    Ok(nodes)
 }
 
 fn bench_movegen(pos: &Position) {
@@ -250,9 +606,9 @@ fn bench_movegen(pos: &Position) {
     }
     let end = start.elapsed();
     let elapsed = (end.as_secs() * 1000) as i64 + i64::from(end.subsec_millis());
-    println!("elapsed: {} [msec]", elapsed);
-    println!("times/s: {} [times/sec]", if elapsed == 0 { 0 } else { max * 1000 / elapsed });
-    println!("num of moves: {}", mlist.size);
+    usi_println!("elapsed: {} [msec]", elapsed);
+    usi_println!("times/s: {} [times/sec]", if elapsed == 0 { 0 } else { max * 1000 / elapsed });
+    usi_println!("num of moves: {}", mlist.size);
 }
 
 fn read_sfen_and_output_hcp(args: &[&str]) {
@@ -301,7 +657,7 @@ fn read_sfen_and_output_hcp(args: &[&str]) {
             continue;
         }
         if args[0] != "moves" {
-            println!(r#"Invalid position command. expected: "moves". found: "{}""#, args[0]);
+            usi_println!(r#"Invalid position command. expected: "moves". found: "{}""#, args[0]);
             continue;
         }
 
@@ -344,7 +700,7 @@ fn read_hcp(args: &[&str]) {
     for item in v {
         match Position::new_from_huffman_coded_position(&item) {
             Ok(pos) => {
-                println!("{}", pos.to_sfen());
+                usi_println!("{}", pos.to_sfen());
             }
             Err(_) => {
                 eprintln!("cannot decode");
@@ -362,7 +718,7 @@ fn read_csa_dirs_and_output_sfen(dir_paths: &[&str]) {
             let mut buf = Vec::new();
             f.read_to_end(&mut buf).unwrap();
             if let Ok(sfen) = csa_record_to_sfen(&buf) {
-                println!("{}", sfen);
+                usi_println!("{}", sfen);
             }
         }
     }
@@ -448,23 +804,72 @@ fn csa_record_to_sfen(csa: &[u8]) -> Result<String, String> {
     Ok(s)
 }
 
-pub fn cmd_loop() {
-    let mut tt = TranspositionTable::new();
+#[cfg(feature = "kppt")]
+const EVAL_FILE_NAMES: &[&str] = &["KPP.bin", "KKP.bin"];
+#[cfg(feature = "nnue")]
+const EVAL_FILE_NAMES: &[&str] = &["nn.bin"];
+
+// FNV-1a. It only tells whether the same evaluation file is loaded.
+#[cfg(any(feature = "kppt", feature = "nnue"))]
+fn eval_file_hash(path: &std::path::Path) -> std::io::Result<u64> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        for &b in buf {
+            hash = (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        let len = buf.len();
+        reader.consume(len);
+    }
+    Ok(hash)
+}
+
+// The "info string" lines sent before "readyok".
+fn isready_info_strings(usi_options: &UsiOptions) -> Vec<String> {
+    let mut lines = vec![];
     #[cfg(feature = "kppt")]
-    let mut ehash = EvalHash::new();
-    let mut breadcrumbs = Breadcrumbs::new();
-    let mut reductions = Reductions::new(1);
-    let mut thread_pool = ThreadPool::new();
-    thread_pool.set(
-        1,
-        &mut tt,
-        #[cfg(feature = "kppt")]
-        &mut ehash,
-        &mut breadcrumbs,
-        &mut reductions,
-    );
-    let mut usi_options = UsiOptions::new();
+    lines.push("info string evaluation kppt".to_string());
+    #[cfg(feature = "nnue")]
+    lines.push("info string evaluation nnue".to_string());
+    #[cfg(feature = "material")]
+    lines.push("info string evaluation material".to_string());
+    #[cfg(any(feature = "kppt", feature = "nnue"))]
+    for file_name in EVAL_FILE_NAMES.iter() {
+        let mut path = std::path::PathBuf::from(usi_options.get_string(UsiOptions::EVAL_DIR));
+        path.push(file_name);
+        let hash = match eval_file_hash(&path) {
+            Ok(hash) => format!("{:016x}", hash),
+            Err(_) => "none".to_string(),
+        };
+        lines.push(format!("info string eval_file {} hash {}", path.display(), hash));
+    }
+    lines.push(format!(
+        "info string {} {}",
+        UsiOptions::USI_HASH,
+        usi_options.get_i64(UsiOptions::USI_HASH)
+    ));
+    #[cfg(feature = "kppt")]
+    lines.push(format!(
+        "info string {} {}",
+        UsiOptions::EVAL_HASH,
+        usi_options.get_i64(UsiOptions::EVAL_HASH)
+    ));
+    lines.push(format!(
+        "info string {} {}",
+        UsiOptions::THREADS,
+        usi_options.get_i64(UsiOptions::THREADS)
+    ));
+    lines
+}
+
+pub fn cmd_loop() {
+    let mut searcher = Searcher::new_unloaded(UsiOptions::new());
     let mut pos = Position::new();
+    let mut position_command = PositionCommand::new();
     let mut is_ready = false;
     loop {
         let cmd = if std::env::args().len() == 1 {
@@ -474,6 +879,7 @@ pub fn cmd_loop() {
                 Ok(0) | Err(_) => cmd = String::from("quit"), // if read EOF, be Ok(0).
                 Ok(_) => cmd = cmd.trim().to_string(),
             }
+            crate::usi_log::log_input(&cmd);
             cmd
         } else {
             let mut cmd = String::new();
@@ -488,83 +894,77 @@ pub fn cmd_loop() {
 
         match token {
             // Required commands as USI protocol.
-            "gameover" | "quit" | "stop" => {
-                thread_pool.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+            "gameover" => {
+                gameover(&mut searcher, &mut pos, &args[1..]);
+                position_command.clear();
+            }
+            "quit" | "stop" => {
+                searcher.thread_pool.stop.store(true, std::sync::atomic::Ordering::Relaxed);
             }
             "go" => {
                 if is_ready {
-                    if let Err(err) = go(&mut thread_pool, &mut tt, &usi_options, &pos, &args[1..]) {
+                    if let Err(err) = go(&mut searcher, &pos, &args[1..]) {
                         eprintln!("{}", err);
                     }
                 } else {
-                    println!(r#"We need "isready" command in advance."#);
+                    usi_println!(r#"We need "isready" command in advance."#);
                 }
             }
             "isready" => {
                 if !is_ready {
-                    #[cfg(feature = "kppt")]
-                    let mut all_ok = true;
-                    #[cfg(feature = "material")]
-                    let all_ok = true;
-                    #[cfg(feature = "kppt")]
-                    match load_evaluate_files(&usi_options.get_string(UsiOptions::EVAL_DIR)) {
-                        Ok(_) => {}
-                        Err(err) => {
-                            eprintln!("{}", err);
-                            all_ok = false;
-                        }
-                    }
-                    match Book::from_file(&usi_options.get_filename(UsiOptions::BOOK_FILE)) {
-                        Ok(book) => {
-                            thread_pool.book = Some(book);
-                        }
-                        Err(_err) => {
-                            //eprintln!("{}", err);
-                            //all_ok = false;
+                    match searcher.load() {
+                        Ok(()) => {
+                            is_ready = true;
+                            for line in isready_info_strings(&searcher.usi_options) {
+                                usi_println!("{}", line);
+                            }
                         }
-                    }
-                    if all_ok {
-                        tt.resize(usi_options.get_i64(UsiOptions::USI_HASH) as usize, &mut thread_pool);
-                        #[cfg(feature = "kppt")]
-                        ehash.resize(usi_options.get_i64(UsiOptions::EVAL_HASH) as usize, &mut thread_pool);
-
-                        is_ready = true;
+                        Err(err) => eprintln!("{}", err),
                     }
                 }
                 if is_ready {
-                    println!("readyok");
+                    usi_println!("readyok");
                 }
             }
-            "ponderhit" => {
-                thread_pool.ponder.store(false, std::sync::atomic::Ordering::Relaxed);
-            }
-            "position" => position(&mut pos, &args[1..]),
-            "setoption" => setoption(
-                &args[1..],
-                &mut usi_options,
-                &mut thread_pool,
-                &mut tt,
-                #[cfg(feature = "kppt")]
-                &mut ehash,
-                &mut breadcrumbs,
-                &mut reductions,
-                &mut is_ready,
-            ),
+            "ponderhit" => searcher.thread_pool.ponderhit(),
+            "position" => {
+                if let Err(err) = position_command.set(&mut pos, &args[1..]) {
+                    eprintln!("{}", err);
+                }
+            }
+            "setoption" => setoption(&args[1..], &mut searcher, &mut is_ready),
             "usi" => {
                 let mut s = format!("id name {}", crate::engine_name::ENGINE_NAME);
                 s += &format!("\nid author {}", crate::authors::AUTHORS);
-                s += &format!("\n{}", usi_options.to_usi_string());
+                s += &format!("\n{}", searcher.usi_options.to_usi_string());
                 s += "\nusiok";
-                println!("{}", s);
+                usi_println!("{}", s);
             }
-            "usinewgame" => usi_new_game(&mut thread_pool, &mut tt),
+            "usinewgame" => searcher.new_game(),
             // Not required commands as USI protocol.
+            "bench" => {
+                if is_ready {
+                    if let Err(err) = bench(&mut searcher, &args[1..]) {
+                        eprintln!("{}", err);
+                    }
+                } else {
+                    eprintln!(r#"We need "isready" command in advance."#);
+                }
+            }
             "bench_movegen" => bench_movegen(&pos),
             "d" => pos.print(),
             "eval" => {
                 if is_ready {
-                    let mut stack = vec![Stack::new(); CURRENT_STACK_INDEX + 1];
-                    println!("{}", evaluate_at_root(&pos, &mut stack).0);
+                    usi_println!("{}", static_evaluation(&pos));
+                } else {
+                    eprintln!(r#"We need "isready" command in advance."#);
+                }
+            }
+            "evaltest" => {
+                if is_ready {
+                    if let Err(err) = evaltest(&searcher.usi_options, &args[1..]) {
+                        eprintln!("{}", err);
+                    }
                 } else {
                     eprintln!(r#"We need "isready" command in advance."#);
                 }
@@ -576,14 +976,23 @@ pub fn cmd_loop() {
                     eprintln!(r#"We need "isready" command in advance."#);
                 }
             }
-            "key" => println!("{}", pos.key().0),
+            "key" => usi_println!("{}", pos.key().0),
             "legal_moves" => legal_moves(&pos),
             "legal_all_moves" => legal_all_moves(&pos),
-            "self_move" => self_move(&mut thread_pool, &mut tt, &usi_options, &pos),
+            "selfplay" => {
+                if is_ready {
+                    if let Err(err) = selfplay(&searcher.usi_options, &args[1..]) {
+                        eprintln!("{}", err);
+                    }
+                } else {
+                    eprintln!(r#"We need "isready" command in advance."#);
+                }
+            }
+            "self_move" => self_move(&mut searcher, &pos),
             "read_csa_dirs_and_output_sfen" => read_csa_dirs_and_output_sfen(&args[1..]),
             "read_hcp" => read_hcp(&args[1..]),
             "read_sfen_and_output_hcp" => read_sfen_and_output_hcp(&args[1..]),
-            "wait" => thread_pool.wait_for_search_finished(),
+            "wait" => searcher.thread_pool.wait_for_search_finished(),
             "write_eval" => {
                 if is_ready {
                     #[cfg(feature = "kppt")]
@@ -620,3 +1029,492 @@ fn test_csa_record_to_sfne() {
 
 #[test]
 fn test_usi() {}
+
+// The material builds always run it. The others need "cargo test -- --ignored" with the evaluation function binaries.
+#[test]
+#[cfg_attr(any(feature = "kppt", feature = "nnue"), ignore = "needs the evaluation function binaries")]
+fn test_static_evaluation() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            #[cfg(any(feature = "kppt", feature = "nnue"))]
+            load_evaluate_files(&UsiOptions::new().get_string(UsiOptions::EVAL_DIR)).unwrap();
+            let mut pos = Position::new();
+            position(&mut pos, &["startpos", "moves", "7g7f", "3c3d", "8h2b+"]).unwrap();
+            let s = static_evaluation(&pos);
+            let numbers = s
+                .split(|c: char| !c.is_ascii_digit() && c != '-')
+                .filter_map(|x| x.parse::<i32>().ok())
+                .collect::<Vec<_>>();
+            let (value, material, positional) = (numbers[0], numbers[1], numbers[2]);
+            assert_eq!(value, material + positional);
+            // White is a bishop down.
+            assert!(material < 0);
+            #[cfg(feature = "material")]
+            assert_eq!(positional, 0);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+// The material builds always run it. The others need "cargo test -- --ignored" with the evaluation function binaries.
+#[test]
+#[cfg_attr(any(feature = "kppt", feature = "nnue"), ignore = "needs the evaluation function binaries")]
+fn test_evaluation_symmetry() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            #[cfg(any(feature = "kppt", feature = "nnue"))]
+            load_evaluate_files(&UsiOptions::new().get_string(UsiOptions::EVAL_DIR)).unwrap();
+            let mut rng = StdRng::seed_from_u64(1);
+            assert_eq!(first_asymmetric_position(300, &mut rng), None);
+            let usi_options = UsiOptions::new();
+            assert!(evaltest(&usi_options, &["10"]).is_ok());
+            assert!(evaltest(&usi_options, &["ten"]).is_err());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_bench() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            // The search of 1 thread is deterministic.
+            let mut run = || bench(&mut searcher, &["6", "1", "16"]).unwrap();
+            let nodes = run();
+            assert_eq!(nodes, run());
+            // The signature of the search. Update it if the search is changed intentionally.
+            #[cfg(feature = "material")]
+            assert_eq!(nodes, 27986);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_bench_file() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let dir = std::env::temp_dir().join(format!("apery_test_bench_file_{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let write = |name: &str, lines: &[&str]| {
+                let path = dir.join(name);
+                std::fs::write(&path, lines.join("\n")).unwrap();
+                path.to_str().unwrap().to_string()
+            };
+            let sfen0 = BENCH_SFENS[0];
+            let sfen1 = format!("position sfen {} moves 7g7f", BENCH_SFENS[3]);
+            let both = write("both.sfen", &[sfen0, "", "# comment", "sfen invalid b - 1", &sfen1]);
+            let first = write("first.sfen", &[sfen0]);
+            let second = write("second.sfen", &[&sfen1]);
+            assert_eq!(read_bench_sfens(&both).unwrap(), vec![BENCH_SFENS[0], BENCH_SFENS[3]]);
+            assert!(read_bench_sfens(dir.join("no_such_file").to_str().unwrap()).is_err());
+
+            let mut run = |path: &str| bench(&mut searcher, &["4", "1", "16", path]).unwrap();
+            let nodes_first = run(&first);
+            let nodes_second = run(&second);
+            assert!(nodes_first > 0 && nodes_second > 0);
+            // Both positions are searched, and the malformed line is skipped.
+            assert_eq!(run(&both), nodes_first + nodes_second);
+            std::fs::remove_dir_all(&dir).unwrap();
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_gameover() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            let lines = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+            searcher.set_info_sink(Box::new(LinesSink::new(lines.clone())));
+            let mut pos = Position::new();
+            position(&mut pos, &["startpos", "moves", "7g7f", "3c3d"]).unwrap();
+            go(&mut searcher, &pos, &["infinite"]).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            gameover(&mut searcher, &mut pos, &["draw"]);
+            // The search has been stopped without "bestmove", and the result isn't left for the next game.
+            assert!(lines.lock().unwrap().is_empty());
+            assert!(searcher.thread_pool.last_best_root_move.lock().unwrap().is_none());
+            assert_eq!(pos.to_sfen(), Position::new().to_sfen());
+
+            // The next game.
+            position(&mut pos, &["startpos", "moves", "2g2f"]).unwrap();
+            go(&mut searcher, &pos, &["depth", "3"]).unwrap();
+            searcher.thread_pool.wait_for_search_finished();
+            let best_move = searcher.thread_pool.last_best_root_move.lock().unwrap().clone().unwrap().pv[0];
+            assert!(pos.legal_moves().contains(&best_move));
+            let lines = lines.lock().unwrap();
+            assert_eq!(lines.len(), 1);
+            assert!(lines[0].starts_with(&format!("bestmove {}", best_move.to_usi_string())));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_setoption_clear_hash() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_unloaded(UsiOptions::new());
+            let mut is_ready = true;
+            searcher.tt.resize(1, &mut searcher.thread_pool).unwrap();
+            let key = Position::new().key();
+            searcher.tt.new_search();
+            let gen8 = searcher.tt.generation();
+            let (tte, _found) = searcher.tt.probe(key);
+            tte.save(key, Value(0), false, false, Bound::EXACT, Depth(1), None, Value(0), gen8);
+            assert!(searcher.tt.probe(key).1);
+
+            // A button has no value.
+            setoption(&["name", UsiOptions::CLEAR_HASH], &mut searcher, &mut is_ready);
+            assert!(!searcher.tt.probe(key).1);
+            assert!(is_ready);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_parse_error() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut pos = Position::new();
+            position(&mut pos, &["startpos", "moves", "7g7f"]).unwrap();
+            let key = pos.key();
+            let parse = |pos: &mut Position, cmd: &str| {
+                let args = cmd.split_whitespace().collect::<Vec<_>>();
+                let result = position(pos, &args);
+                // The invalid command doesn't change the position.
+                assert!(pos.key() == key);
+                result.unwrap_err()
+            };
+            match parse(&mut pos, "") {
+                UsiParseError::NoPositionType => {}
+                err => panic!("{:?}", err),
+            }
+            match parse(
+                &mut pos,
+                "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL x - 1",
+            ) {
+                UsiParseError::Sfen(SfenError::InvalidSideToMoveCharactors { .. }) => {}
+                err => panic!("{:?}", err),
+            }
+            match parse(&mut pos, "sfen lnsgkgsnl/1r5b1/ppppppppp") {
+                UsiParseError::Sfen(SfenError::InvalidNumberOfSections { .. }) => {}
+                err => panic!("{:?}", err),
+            }
+            match parse(&mut pos, "startpos 7g7f") {
+                UsiParseError::NoMovesToken { .. } => {}
+                err => panic!("{:?}", err),
+            }
+            match parse(&mut pos, "startpos moves 5e5d") {
+                UsiParseError::Move {
+                    err: MoveParseError::EmptySquare { sq },
+                    ..
+                } => assert_eq!(sq, Square::SQ55),
+                err => panic!("{:?}", err),
+            }
+            match parse(&mut pos, "startpos moves 7g7f+") {
+                UsiParseError::Move {
+                    err: MoveParseError::NotPromotable,
+                    ..
+                } => {}
+                err => panic!("{:?}", err),
+            }
+            match parse(&mut pos, "startpos moves 7g7f 3c3d 5i4h+") {
+                UsiParseError::Move {
+                    usi_move,
+                    err: MoveParseError::NotPromotable,
+                    ..
+                } => assert_eq!(usi_move, "5i4h+"),
+                err => panic!("{:?}", err),
+            }
+            match parse(&mut pos, "startpos moves P*5e") {
+                UsiParseError::Move {
+                    err: MoveParseError::NotInHand { pt },
+                    ..
+                } => assert_eq!(pt, PieceType::PAWN),
+                err => panic!("{:?}", err),
+            }
+            match parse(&mut pos, "startpos moves 7g7f 3c3d 8h2b+ 3a2b B*5e G*5d") {
+                UsiParseError::Move {
+                    sfen,
+                    err: MoveParseError::NotInHand { pt },
+                    ..
+                } => {
+                    assert_eq!(pt, PieceType::GOLD);
+                    assert_eq!(sfen, "lnsgkg1nl/1r5s1/pppppp1pp/6p2/4B4/2P6/PP1PPPPPP/7R1/LNSGKGSNL w b 6");
+                }
+                err => panic!("{:?}", err),
+            }
+            match parse(&mut pos, "startpos moves 7g7e") {
+                UsiParseError::Move {
+                    err: MoveParseError::Illegal,
+                    ..
+                } => {}
+                err => panic!("{:?}", err),
+            }
+            match parse(&mut pos, "startpos moves z9z9") {
+                UsiParseError::Move {
+                    err: MoveParseError::InvalidString { chars },
+                    ..
+                } => assert_eq!(chars, "z9z9"),
+                err => panic!("{:?}", err),
+            }
+            // The next command is accepted.
+            position(&mut pos, &["startpos", "moves", "2g2f"]).unwrap();
+            assert_eq!(
+                pos.to_sfen(),
+                "lnsgkgsnl/1r5b1/ppppppppp/9/9/7P1/PPPPPPP1P/1B5R1/LNSGKGSNL w - 2"
+            );
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_command_extended_moves() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            // pos is the position set by the "position" command of args from scratch.
+            let assert_position = |pos: &Position, args: &[&str]| {
+                let mut expected = Position::new();
+                position(&mut expected, args).unwrap();
+                assert_eq!(pos.to_sfen(), expected.to_sfen());
+                assert!(pos.key() == expected.key());
+                assert_eq!(pos.ply(), expected.ply());
+            };
+            let mut pos = Position::new();
+            let mut position_command = PositionCommand::new();
+            let args = ["startpos", "moves", "7g7f", "3c3d"];
+            position_command.set(&mut pos, &args).unwrap();
+            assert_position(&pos, &args);
+
+            // Only the appended move is applied.
+            let args = ["startpos", "moves", "7g7f", "3c3d", "8h2b+"];
+            position_command.set(&mut pos, &args).unwrap();
+            assert_position(&pos, &args);
+
+            // The same movelist.
+            position_command.set(&mut pos, &args).unwrap();
+            assert_position(&pos, &args);
+
+            // The invalid appended moves don't change pos.
+            let result = position_command.set(&mut pos, &["startpos", "moves", "7g7f", "3c3d", "8h2b+", "3a2b", "1a1b"]);
+            assert!(result.is_err());
+            assert_position(&pos, &args);
+
+            // The movelist doesn't extend the previous one.
+            let args = ["startpos", "moves", "2g2f"];
+            position_command.set(&mut pos, &args).unwrap();
+            assert_position(&pos, &args);
+
+            // From no moves.
+            let sfen = Position::new().to_sfen();
+            let mut sfen_args = vec!["sfen"];
+            sfen_args.extend(sfen.split_whitespace());
+            position_command.set(&mut pos, &sfen_args).unwrap();
+            assert_position(&pos, &["startpos"]);
+            sfen_args.extend(["moves", "7g7f"].iter());
+            position_command.set(&mut pos, &sfen_args).unwrap();
+            assert_position(&pos, &["startpos", "moves", "7g7f"]);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_default_depth() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_for_test(&[]);
+            searcher.set_option(UsiOptions::DEFAULT_DEPTH, "8");
+            let pos = Position::new();
+            go(&mut searcher, &pos, &[]).unwrap();
+            searcher.thread_pool.wait_for_search_finished();
+            assert_eq!(
+                searcher
+                    .thread_pool
+                    .last_completed_depth
+                    .load(std::sync::atomic::Ordering::Relaxed),
+                8
+            );
+
+            // An explicit depth is prior to DefaultDepth.
+            go(&mut searcher, &pos, &["depth", "3"]).unwrap();
+            searcher.thread_pool.wait_for_search_finished();
+            assert_eq!(
+                searcher
+                    .thread_pool
+                    .last_completed_depth
+                    .load(std::sync::atomic::Ordering::Relaxed),
+                3
+            );
+
+            // "go infinite" ignores DefaultDepth.
+            go(&mut searcher, &pos, &["infinite"]).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!searcher.thread_pool.stop.load(std::sync::atomic::Ordering::Relaxed));
+            searcher.thread_pool.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+            searcher.thread_pool.wait_for_search_finished();
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_position_handicap_command() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut pos = Position::new();
+            position(&mut pos, &["handicap", "2piece", "moves", "5a4b", "7g7f"]).unwrap();
+            assert_eq!(
+                pos.to_sfen(),
+                "lnsg1gsnl/5k3/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 3"
+            );
+            match position(&mut pos, &["handicap"]) {
+                Err(UsiParseError::Handicap(_)) => {}
+                result => panic!("{:?}", result),
+            }
+            match position(&mut pos, &["handicap", "3piece"]) {
+                Err(UsiParseError::Handicap(_)) => {}
+                result => panic!("{:?}", result),
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_isready_info_strings() {
+    let usi_options = UsiOptions::new();
+    let lines = isready_info_strings(&usi_options);
+    assert!(lines.iter().all(|line| line.starts_with("info string ")));
+    let evaluation = lines
+        .iter()
+        .find_map(|line| line.strip_prefix("info string evaluation "))
+        .unwrap();
+    assert!(["kppt", "nnue", "material"].contains(&evaluation));
+    #[cfg(feature = "material")]
+    assert!(lines.iter().all(|line| !line.contains("eval_file")));
+    #[cfg(any(feature = "kppt", feature = "nnue"))]
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with("info string eval_file ") && line.contains(" hash ")));
+    assert!(lines.contains(&format!("info string USI_Hash {}", usi_options.get_i64(UsiOptions::USI_HASH))));
+    assert!(lines.contains(&format!("info string Threads {}", usi_options.get_i64(UsiOptions::THREADS))));
+}
+
+#[test]
+fn test_usi_new_game_keeps_hash() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            let mut searcher = Searcher::new_unloaded(UsiOptions::new());
+            searcher.tt.resize(1, &mut searcher.thread_pool).unwrap();
+            let pos = Position::new();
+            let m = Move::new_from_usi_str("7g7f", &pos).unwrap();
+            let save = |tt: &mut TranspositionTable| {
+                let generation = tt.generation();
+                let (tte, _found) = tt.probe(pos.key());
+                tte.save(
+                    pos.key(),
+                    Value(100),
+                    false,
+                    false,
+                    Bound::EXACT,
+                    Depth::ONE_PLY,
+                    Some(m),
+                    Value(100),
+                    generation,
+                );
+            };
+            save(&mut searcher.tt);
+            let generation = searcher.tt.generation();
+            searcher.new_game();
+            assert_ne!(searcher.tt.generation(), generation);
+            let (tte, found) = searcher.tt.probe(pos.key());
+            assert!(found);
+            assert_eq!(tte.mv(&pos), Some(m));
+            assert_eq!(tte.value(), Value(100));
+
+            searcher.set_option(UsiOptions::CLEAR_HASH_ON_NEW_GAME, "true");
+            save(&mut searcher.tt);
+            searcher.new_game();
+            assert!(!searcher.tt.probe(pos.key()).1);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_go_depth_zero() {
+    std::thread::Builder::new()
+        .stack_size(crate::stack_size::STACK_SIZE)
+        .spawn(|| {
+            struct CapturingSink {
+                infos: std::sync::Arc<std::sync::Mutex<Vec<SearchInfo>>>,
+                lines: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+            }
+            impl InfoSink for CapturingSink {
+                fn info(&mut self, info: &SearchInfo) {
+                    self.infos.lock().unwrap().push(info.clone());
+                }
+                fn println(&mut self, s: &str) {
+                    self.lines.lock().unwrap().push(s.to_string());
+                }
+            }
+            let mut searcher = Searcher::new_for_test(&[]);
+            let infos = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+            let lines = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+            searcher.set_info_sink(Box::new(CapturingSink {
+                infos: infos.clone(),
+                lines: lines.clone(),
+            }));
+            let pos = Position::new();
+            go(&mut searcher, &pos, &["depth", "0"]).unwrap();
+            searcher.thread_pool.wait_for_search_finished();
+            assert_eq!(searcher.thread_pool.nodes_searched(), 0);
+            let infos = infos.lock().unwrap();
+            assert_eq!(infos.len(), 1);
+            let mut stack = vec![Stack::new(); CURRENT_STACK_INDEX + 1];
+            assert_eq!(
+                infos[0].score,
+                static_evaluate_at_root(&pos, &mut stack, eval_mode(&searcher.usi_options))
+            );
+            assert!(infos[0].to_usi_string().starts_with("info depth 0 seldepth 0 "));
+            assert!(infos[0].to_usi_string().contains(&format!(" score cp {} ", infos[0].score.0)));
+            let lines = lines.lock().unwrap();
+            assert_eq!(lines.len(), 1);
+            let best_move = lines[0].strip_prefix("bestmove ").unwrap();
+            assert_eq!(best_move, infos[0].pv[0].to_usi_string());
+            assert!(Move::new_from_usi_str(best_move, &pos).is_some());
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -0,0 +1,97 @@
+use std::io::Write;
+
+// The transcript of the USI communication for debugging. It is enabled by the DebugLogFile option.
+static LOG_FILE: once_cell::sync::Lazy<std::sync::Mutex<Option<std::fs::File>>> =
+    once_cell::sync::Lazy::new(|| std::sync::Mutex::new(None));
+
+// println!() which also writes the line to the log file.
+macro_rules! usi_println {
+    () => {
+        $crate::usi_log::println("")
+    };
+    ($($arg:tt)*) => {
+        $crate::usi_log::println(&format!($($arg)*))
+    };
+}
+
+// The file is opened in append mode. An empty path disables logging.
+pub fn set_log_file(path: &str) -> std::io::Result<()> {
+    let file = if path.is_empty() {
+        None
+    } else {
+        Some(std::fs::OpenOptions::new().create(true).append(true).open(path)?)
+    };
+    *LOG_FILE.lock().unwrap() = file;
+    Ok(())
+}
+
+pub fn println(s: &str) {
+    println!("{}", s);
+    log_output(s);
+}
+
+// The lines from the GUI.
+pub fn log_input(s: &str) {
+    write(">>", s);
+}
+
+// The lines to the GUI.
+pub fn log_output(s: &str) {
+    write("<<", s);
+}
+
+fn write(direction: &str, s: &str) {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    if let Some(file) = log_file.as_mut() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        // Flushed each line, so the log is usable after a crash.
+        for line in s.lines() {
+            let _ = writeln!(file, "{}.{:03} {} {}", now.as_secs(), now.subsec_millis(), direction, line);
+        }
+        let _ = file.flush();
+    }
+}
+
+#[test]
+fn test_log_file() {
+    let dir = std::env::temp_dir().join(format!("apery_test_log_file_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("usi.log");
+    let path = path.to_str().unwrap();
+    set_log_file(path).unwrap();
+    log_input("usi");
+    usi_println!("id name {}", "test_log_file");
+    usi_println!("option name A type check default true\noption name B type check default false");
+    log_input("isready");
+    usi_println!("readyok");
+    set_log_file("").unwrap();
+    usi_println!("not logged");
+    // Append mode.
+    set_log_file(path).unwrap();
+    log_input("quit");
+    set_log_file("").unwrap();
+
+    let text = std::fs::read_to_string(path).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    let expected = [
+        ">> usi",
+        "<< id name test_log_file",
+        "<< option name A type check default true",
+        "<< option name B type check default false",
+        ">> isready",
+        "<< readyok",
+        ">> quit",
+    ];
+    for expected_line in expected.iter() {
+        assert!(lines.iter().any(|line| line.ends_with(expected_line)), "{}", expected_line);
+    }
+    assert!(!text.contains("not logged"));
+    // The time stamp and the direction.
+    assert!(lines.iter().all(|line| {
+        let (time, rest) = line.split_at(line.find(' ').unwrap());
+        time.contains('.') && (rest.starts_with(" >> ") || rest.starts_with(" << "))
+    }));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
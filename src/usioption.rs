@@ -1,8 +1,12 @@
 #[cfg(feature = "kppt")]
 use crate::evaluate::kppt::*;
+use crate::evaluate::*;
+#[cfg(feature = "tune")]
+use crate::piecevalue::*;
 use crate::search::*;
 use crate::thread::*;
 use crate::tt::*;
+use crate::types::*;
 
 #[derive(Clone)]
 enum UsiOptionValue {
@@ -63,58 +67,143 @@ impl UsiOptionValue {
 #[derive(Clone)]
 pub struct UsiOptions {
     v: std::collections::HashMap<&'static str, UsiOptionValue>,
+    pub search_params: SearchParams, // not shown by "usi".
+    #[cfg(feature = "tune")]
+    pub piece_values: PieceValues, // not shown by "usi".
 }
 
 impl UsiOptions {
     pub const BOOK_ENABLE: &'static str = "Book_Enable";
     pub const BOOK_FILE: &'static str = "Book_File";
+    pub const BOOK_MOVES: &'static str = "Book_Moves";
     pub const BYOYOMI_MARGIN: &'static str = "Byoyomi_Margin";
-    const CLEAR_HASH: &'static str = "Clear_Hash";
+    pub const CONTEMPT: &'static str = "Contempt";
+    pub const CLEAR_HASH: &'static str = "Clear_Hash";
+    pub const CLEAR_HASH_ON_NEW_GAME: &'static str = "ClearHashOnNewGame";
+    pub const CURRMOVE_DELAY: &'static str = "Currmove_Delay";
+    pub const DEFAULT_DEPTH: &'static str = "DefaultDepth"; // 0 means no default.
+    pub const DEBUG_LOG_FILE: &'static str = "DebugLogFile"; // "<empty>" disables the log.
     pub const EVAL_DIR: &'static str = "Eval_Dir";
+    pub const EVAL_MODE: &'static str = "EvalMode"; // "full" or "material".
     #[cfg(feature = "kppt")]
     pub const EVAL_HASH: &'static str = "Eval_Hash";
+    pub const GAMEOVER_HASH_KEEP: &'static str = "Gameover_Hash_Keep";
+    pub const KILLER_SLOTS: &'static str = "KillerSlots";
+    pub const LARGE_PAGES: &'static str = "LargePages";
+    pub const LMR_BASE: &'static str = "LMR_Base";
+    pub const LMR_DIVISOR: &'static str = "LMR_Divisor";
+    pub const MAX_PV_LENGTH: &'static str = "MaxPvLength"; // 0 means no limit.
     pub const MINIMUM_THINKING_TIME: &'static str = "Minimum_Thinking_Time";
+    pub const MOVE_OVERHEAD: &'static str = "Move_Overhead";
     pub const MULTI_PV: &'static str = "MultiPV";
+    pub const NODES_BETWEEN_TIME_CHECKS: &'static str = "NodesBetweenTimeChecks";
+    pub const NUMA_POLICY: &'static str = "NumaPolicy"; // "none" or "pin".
+    pub const OUTPUT_FORMAT: &'static str = "OutputFormat"; // "usi" or "json".
+    pub const QSEARCH_CHECK_PLIES: &'static str = "Qsearch_Check_Plies";
+    pub const QSEARCH_MAX_PLY: &'static str = "Qsearch_Max_Ply";
+    pub const RESIGN_MOVE_COUNT: &'static str = "ResignMoveCount";
+    pub const RESIGN_THRESHOLD: &'static str = "ResignThreshold"; // 0 disables the resignation.
+    pub const SEED: &'static str = "Seed"; // 0 means seeding from the system entropy.
+    pub const SHOW_WDL: &'static str = "Show_WDL";
+    pub const SKILL_LEVEL: &'static str = "Skill_Level";
     pub const SLOW_MOVER: &'static str = "Slow_Mover";
     pub const THREADS: &'static str = "Threads";
     pub const TIME_MARGIN: &'static str = "Time_Margin";
     pub const USI_HASH: &'static str = "USI_Hash";
     pub const USI_PONDER: &'static str = "USI_Ponder";
 
+    const EMPTY: &'static str = "<empty>";
+
     pub fn new() -> UsiOptions {
         let mut options = std::collections::HashMap::new();
 
         // The following are all options.
         options.insert(Self::BOOK_ENABLE, UsiOptionValue::check(false));
         options.insert(Self::BOOK_FILE, UsiOptionValue::filename("book/20191216/book.json"));
+        options.insert(Self::BOOK_MOVES, UsiOptionValue::spin(16, 0, 10000));
         options.insert(Self::BYOYOMI_MARGIN, UsiOptionValue::spin(500, 0, i64::max_value()));
         options.insert(Self::CLEAR_HASH, UsiOptionValue::Button);
+        // false: "usinewgame" only ages the hash entries, and the old entries are replaced first.
+        options.insert(Self::CLEAR_HASH_ON_NEW_GAME, UsiOptionValue::check(false));
+        options.insert(Self::CONTEMPT, UsiOptionValue::spin(0, -1000, 1000));
+        options.insert(Self::CURRMOVE_DELAY, UsiOptionValue::spin(3000, 0, i64::MAX));
+        // The depth limit of "go" without any limits.
+        options.insert(Self::DEFAULT_DEPTH, UsiOptionValue::spin(0, 0, i64::from(MAX_PLY)));
+        options.insert(Self::DEBUG_LOG_FILE, UsiOptionValue::filename(""));
         options.insert(Self::EVAL_DIR, UsiOptionValue::string("eval/20190617"));
+        options.insert(Self::EVAL_MODE, UsiOptionValue::string("full"));
         #[cfg(feature = "kppt")]
-        options.insert(Self::EVAL_HASH, UsiOptionValue::spin(256, 1, 1024 * 1024));
+        options.insert(Self::EVAL_HASH, UsiOptionValue::spin(256, 0, 1024 * 1024));
+        // The hash entries saved this many or more searches ago are cleared at "gameover". 0 clears all.
+        options.insert(Self::GAMEOVER_HASH_KEEP, UsiOptionValue::spin(32, 0, 32));
+        options.insert(Self::KILLER_SLOTS, UsiOptionValue::spin(2, 1, MAX_KILLER_SLOTS as i64));
+        options.insert(Self::LARGE_PAGES, UsiOptionValue::check(false));
+        options.insert(Self::LMR_BASE, UsiOptionValue::spin(Reductions::DEFAULT_BASE, 0, 100_000));
+        options.insert(
+            Self::LMR_DIVISOR,
+            UsiOptionValue::spin(Reductions::DEFAULT_DIVISOR, 1, 100_000_000),
+        );
+        options.insert(Self::MAX_PV_LENGTH, UsiOptionValue::spin(0, 0, i64::from(MAX_PLY)));
         options.insert(Self::MINIMUM_THINKING_TIME, UsiOptionValue::spin(20, 0, 5000));
+        options.insert(Self::MOVE_OVERHEAD, UsiOptionValue::spin(30, 0, 5000));
         options.insert(Self::MULTI_PV, UsiOptionValue::spin(1, 1, 500));
+        // The upper limit. The interval gets shorter on a slow machine to check the time every few milliseconds.
+        options.insert(Self::NODES_BETWEEN_TIME_CHECKS, UsiOptionValue::spin(1024, 1, 1_000_000));
+        options.insert(Self::NUMA_POLICY, UsiOptionValue::string("none"));
+        options.insert(Self::OUTPUT_FORMAT, UsiOptionValue::string("usi"));
+        // 0: qsearch doesn't generate the quiet checks.
+        options.insert(Self::QSEARCH_CHECK_PLIES, UsiOptionValue::spin(0, 0, 2));
+        // 0: qsearch has no ply limit.
+        options.insert(Self::QSEARCH_MAX_PLY, UsiOptionValue::spin(0, 0, i64::from(MAX_PLY)));
+        // "bestmove resign" after ResignMoveCount consecutive scores at -ResignThreshold or less.
+        // Only the timed game searches count.
+        options.insert(Self::RESIGN_MOVE_COUNT, UsiOptionValue::spin(3, 1, 1000));
+        options.insert(
+            Self::RESIGN_THRESHOLD,
+            UsiOptionValue::spin(0, 0, i64::from(Value::INFINITE.0)),
+        );
+        options.insert(Self::SHOW_WDL, UsiOptionValue::check(false));
+        options.insert(Self::SEED, UsiOptionValue::spin(0, 0, i64::MAX));
+        options.insert(
+            Self::SKILL_LEVEL,
+            UsiOptionValue::spin(Skill::MAX_LEVEL as i64, 0, Skill::MAX_LEVEL as i64),
+        );
         options.insert(Self::SLOW_MOVER, UsiOptionValue::spin(84, 10, 1000));
         options.insert(Self::THREADS, UsiOptionValue::spin(1, 1, 8192));
         options.insert(Self::TIME_MARGIN, UsiOptionValue::spin(500, 0, i64::max_value()));
         options.insert(Self::USI_HASH, UsiOptionValue::spin(256, 1, 1024 * 1024));
         options.insert(Self::USI_PONDER, UsiOptionValue::check(true));
 
-        UsiOptions { v: options }
+        UsiOptions {
+            v: options,
+            search_params: SearchParams::new(),
+            #[cfg(feature = "tune")]
+            piece_values: PieceValues::DEFAULT,
+        }
     }
-    pub fn push_button(&self, key: &str, tt: &mut TranspositionTable) {
+    pub fn push_button(
+        &self,
+        key: &str,
+        thread_pool: &mut ThreadPool,
+        tt: &mut TranspositionTable,
+        #[cfg(feature = "kppt")] ehash: &mut EvalHash,
+    ) {
         match self.v.get(key) {
             None => {
-                println!("Error: illegal option name: {}", key);
+                usi_println!("Error: illegal option name: {}", key);
             }
             Some(UsiOptionValue::Button) => match key {
                 Self::CLEAR_HASH => {
+                    // The search may be using the hash tables.
+                    thread_pool.wait_for_search_finished();
                     tt.clear();
+                    #[cfg(feature = "kppt")]
+                    ehash.clear();
                 }
                 _ => unreachable!(),
             },
             _ => {
-                println!(r#"Error: The option "{}" isn't button type"#, key);
+                usi_println!(r#"Error: The option "{}" isn't button type"#, key);
             }
         }
     }
@@ -129,20 +218,70 @@ impl UsiOptions {
         reductions: &mut Reductions,
         is_ready: &mut bool,
     ) {
+        #[cfg(feature = "tune")]
+        {
+            if let Some(name) = key.strip_prefix("Param.") {
+                match value.parse::<i32>() {
+                    Ok(n) if self.search_params.set(name, n) => {}
+                    Ok(_) => eprintln!("Error: illegal parameter name: {}", name),
+                    Err(err) => eprintln!("Error: {:?}", err),
+                }
+                return;
+            }
+            // "PieceValue.Pawn" etc. It's applied to the positions of the next search.
+            if let Some(name) = key.strip_prefix("PieceValue.") {
+                match value.parse::<i32>() {
+                    Ok(n) if self.piece_values.set(name, n) => {}
+                    Ok(_) => eprintln!("Error: illegal piece name: {}", name),
+                    Err(err) => eprintln!("Error: {:?}", err),
+                }
+                return;
+            }
+        }
         match self.v.get_mut(key) {
             None => {
-                println!("Error: illegal option name: {}", key);
+                usi_println!("Error: illegal option name: {}", key);
             }
             Some(UsiOptionValue::String { current, .. }) => {
+                if key == Self::NUMA_POLICY {
+                    if let Err(err) = value.parse::<NumaPolicy>() {
+                        usi_println!("Error: {}", err);
+                        return;
+                    }
+                }
+                if key == Self::EVAL_MODE {
+                    if let Err(err) = value.parse::<EvalMode>() {
+                        usi_println!("Error: {}", err);
+                        return;
+                    }
+                }
+                if key == Self::OUTPUT_FORMAT {
+                    match value {
+                        "usi" => thread_pool.set_info_sink(Box::new(UsiInfoSink)),
+                        "json" => thread_pool.set_info_sink(Box::new(JsonInfoSink(std::io::stdout()))),
+                        _ => {
+                            usi_println!(r#"Error: expected: "usi" or "json". found: "{}""#, value);
+                            return;
+                        }
+                    }
+                }
                 *current = value.to_string();
                 if key == Self::EVAL_DIR {
                     *is_ready = false;
                 }
             }
             Some(UsiOptionValue::Filename { current, .. }) => {
+                // "setoption" can't send an empty value.
+                let value = if value == Self::EMPTY { "" } else { value };
                 *current = value.to_string();
-                if key == Self::BOOK_FILE {
-                    *is_ready = false;
+                match key {
+                    Self::BOOK_FILE => *is_ready = false,
+                    Self::DEBUG_LOG_FILE => {
+                        if let Err(err) = crate::usi_log::set_log_file(value) {
+                            usi_println!("Error: {}: {}", value, err);
+                        }
+                    }
+                    _ => {}
                 }
             }
             Some(UsiOptionValue::Spin { current, min, max, .. }) => match value.parse::<i64>() {
@@ -161,20 +300,39 @@ impl UsiOptions {
                             breadcrumbs,
                             reductions,
                         ),
-                        Self::USI_HASH => tt.resize(n as usize, thread_pool),
+                        Self::LMR_BASE => reductions.set_base(n),
+                        Self::LMR_DIVISOR => reductions.set_divisor(n),
+                        Self::USI_HASH => {
+                            if let Err(err) = tt.resize(n as usize, thread_pool) {
+                                usi_println!("info string Error: {}", err);
+                            }
+                        }
                         _ => {}
                     }
                 }
                 Err(err) => {
-                    println!("{:?}", err);
+                    usi_println!("{:?}", err);
                 }
             },
-            Some(UsiOptionValue::Check { current, .. }) => match value {
-                "true" => *current = true,
-                "false" => *current = false,
-                _ => println!("Error: illegal option value: {}", value),
-            },
-            Some(UsiOptionValue::Button) => println!(r#"Error: The option "{}" is button type. You can't set value to it."#, key),
+            Some(UsiOptionValue::Check { current, .. }) => {
+                match value {
+                    "true" => *current = true,
+                    "false" => *current = false,
+                    _ => {
+                        usi_println!("Error: illegal option value: {}", value);
+                        return;
+                    }
+                }
+                if key == Self::LARGE_PAGES {
+                    tt.set_large_pages(*current);
+                    if let Err(err) = tt.resize(self.get_i64(Self::USI_HASH) as usize, thread_pool) {
+                        usi_println!("info string Error: {}", err);
+                    }
+                }
+            }
+            Some(UsiOptionValue::Button) => {
+                usi_println!(r#"Error: The option "{}" is button type. You can't set value to it."#, key)
+            }
         }
     }
     pub fn to_usi_string(&self) -> String {
@@ -185,9 +343,11 @@ impl UsiOptions {
                 UsiOptionValue::String { default, .. } => {
                     format!("option name {} type string default {}", key, default)
                 }
-                UsiOptionValue::Filename { default, .. } => {
-                    format!("option name {} type filename default {}", key, default)
-                }
+                UsiOptionValue::Filename { default, .. } => format!(
+                    "option name {} type filename default {}",
+                    key,
+                    if default.is_empty() { Self::EMPTY } else { default }
+                ),
                 UsiOptionValue::Spin { default, min, max, .. } => {
                     format!("option name {} type spin default {} min {} max {}", key, default, min, max)
                 }